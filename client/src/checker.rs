@@ -1,7 +1,6 @@
 use std::error::Error;
 use std::fmt::{Display, Formatter};
 use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpStream};
-use std::time::Duration;
 use crate::mc_packet::{MCPacket, PacketParseError};
 
 #[derive(Debug)]
@@ -23,9 +22,9 @@ impl Error for InvalidServerError {}
 
 pub fn validate_server(addr: Ipv4Addr) -> Result<String, InvalidServerError> {
     let socket_addr = SocketAddr::new(IpAddr::V4(addr), 25565);
-    let mut stream = TcpStream::connect_timeout(&socket_addr, Duration::from_secs(crate::TCP_TIMEOUT_SECS))
+    let mut stream = TcpStream::connect_timeout(&socket_addr, crate::config::get_tcp_timeout())
         .map_err(|_| InvalidServerError::new("Timed out connecting to host"))?;
-    stream.set_read_timeout(Some(Duration::from_secs(crate::TCP_TIMEOUT_SECS)))
+    stream.set_read_timeout(Some(crate::config::get_tcp_timeout()))
         .map_err(|_| InvalidServerError::new("Error when trying to set read timeout"))?;
 
     // Initialize MC connection
@@ -1,63 +1,107 @@
-use std::fs;
-use std::error::Error;
-use std::fmt::{Display, Formatter};
-use std::sync::RwLock;
-use lazy_static::lazy_static;
-use yaml_rust::YamlLoader;
-
-struct Config {
-    loaded: bool,
-    dispatcher_base: String
-}
-
-lazy_static! {
-    static ref CONFIG: RwLock<Config> = RwLock::new(Config {
-        loaded: false,
-        dispatcher_base: String::new()
-    });
-}
-
-pub fn load_config(path: &str) -> Result<(), Box<dyn Error>> {
-    let mut config = CONFIG.write().unwrap();
-    let yaml = YamlLoader::load_from_str(&fs::read_to_string(path)?)?;
-    if yaml.is_empty() {
-        return Err(Box::new(ConfigParseError::new("File does not contain valid yaml syntax.")));
+// Settings of the client, see the `worker-config` crate for how they are loaded and reloaded. The
+// settings pushed by the dispatcher (see `/config/worker`) take precedence over the local ones.
+
+use std::sync::{Arc, OnceLock, RwLock};
+use std::time::Duration;
+use protocol::ResolvedConfig;
+use reqwest::blocking::Client;
+use serde::{Deserialize, Serialize};
+use worker_config::{DispatcherSettings, Settings, SharedConfig, Source};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    #[serde(flatten)]
+    pub dispatcher: DispatcherSettings,
+    // Connect and read timeout of a scan, in seconds
+    pub tcp_timeout: u64,
+    // Servers requested from the dispatcher at once
+    pub job_size: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            dispatcher: DispatcherSettings::default(),
+            tcp_timeout: 2,
+            job_size: 256,
+        }
     }
+}
 
-    let yaml = yaml[0].clone();
+impl Settings for Config {
+    const RELOADABLE: &'static [&'static str] = &["api_key", "ca_cert", "client_cert", "client_key",
+        "tcp_timeout", "job_size"];
 
-    config.dispatcher_base = String::from(yaml["dispatcher_base"].as_str()
-        .ok_or_else(|| ConfigParseError::new("dispatcher_base field is missing or invalid."))?);
+    fn validate(&self) -> Vec<String> {
+        let mut errors = self.dispatcher.validate();
+        for (name, value) in [("tcp_timeout", self.tcp_timeout), ("job_size", self.job_size)] {
+            if value == 0 {
+                errors.push(format!("{} must be above 0", name));
+            }
+        }
+        errors
+    }
 
-    config.loaded = true;
+    fn dispatcher(&self) -> &DispatcherSettings {
+        &self.dispatcher
+    }
+}
+
+/// Settings pushed by the dispatcher, they take precedence over the local settings
+#[derive(Default)]
+struct RemoteConfig {
+    version: Option<String>,
+    tcp_timeout: Option<Duration>
+}
+
+static CONFIG: OnceLock<SharedConfig<Config>> = OnceLock::new();
+static REMOTE: RwLock<RemoteConfig> = RwLock::new(RemoteConfig { version: None, tcp_timeout: None });
+
+/// Loads the settings, and reloads them on SIGHUP
+pub fn init(source: Source) -> Result<(), Vec<String>> {
+    let config = SharedConfig::load(source)?;
+    if CONFIG.set(config).is_ok() {
+        CONFIG.get().unwrap().reload_on_sighup();
+    }
     Ok(())
 }
 
-//
-// GETTERS
-//
+/// Local settings, from the config file, the environment and the command line
+pub fn current() -> Arc<Config> {
+    CONFIG.get().expect("Config used before being loaded").get()
+}
 
-pub fn get_dispatcher_base() -> String {
-    let config = CONFIG.read().unwrap();
-    assert!(config.loaded, "Tried to access config field before loading the file.");
-    config.dispatcher_base.clone()
+/// Settings along with the HTTP client of the dispatcher, see `worker_config::registration`
+pub fn shared() -> &'static SharedConfig<Config> {
+    CONFIG.get().expect("Config used before being loaded")
 }
 
-#[derive(Debug, Clone, )]
-struct ConfigParseError {
-    msg: String,
+/// HTTP client of the dispatcher, shared by every request
+pub fn dispatcher_client() -> Client {
+    CONFIG.get().expect("Config used before being loaded").client()
 }
 
-impl ConfigParseError {
-    pub fn new(msg: &str) -> ConfigParseError {
-        ConfigParseError { msg: String::from(msg) }
-    }
+/// Applies the settings pushed by the dispatcher (see `/config/worker`), settings missing from
+/// `resolved` fall back to the local settings
+pub fn apply_remote_config(resolved: &ResolvedConfig) {
+    *REMOTE.write().unwrap() = RemoteConfig {
+        version: Some(resolved.version.clone()),
+        tcp_timeout: resolved.settings.tcp_timeout.filter(|v| *v > 0).map(Duration::from_secs)
+    };
 }
 
-impl Display for ConfigParseError {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Error parsing config: {}", self.msg)
-    }
+/// Goes back to the local settings
+pub fn clear_remote_config() {
+    *REMOTE.write().unwrap() = RemoteConfig::default();
+}
+
+/// Version of the settings pushed by the dispatcher, None if there are none
+pub fn get_remote_version() -> Option<String> {
+    REMOTE.read().unwrap().version.clone()
 }
 
-impl Error for ConfigParseError {}
\ No newline at end of file
+/// Connect and read timeout of a scan
+pub fn get_tcp_timeout() -> Duration {
+    REMOTE.read().unwrap().tcp_timeout.unwrap_or(Duration::from_secs(current().tcp_timeout))
+}
@@ -1,35 +1,48 @@
 mod mc_packet;
 mod checker;
 mod config;
+mod motd;
 
 extern crate pnet;
 
 use std::io::Result;
 use std::net::Ipv4Addr;
-use std::sync::Arc;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
 use std::thread::sleep;
 use std::time::Duration;
 use clap::Parser;
-use serde_json::{json, Value};
+use protocol::{BatchQuery, ClientBatch, ClientJob, ClientResult, JobResult, ResolvedConfig, WorkerKind, WorkerQuery};
+use serde_json::Value;
+use worker_config::{Source, registration};
+use worker_config::dispatcher::check_protocol;
 use crate::checker::validate_server;
 
-pub const JOB_SIZE: usize = 256;
-pub const TCP_TIMEOUT_SECS: u64 = 2;
+/// Servers of a batch scanned at the same time
+const SCAN_THREADS: usize = 32;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
     #[arg(short, long = "config")]
-    config_path: String,
+    config_path: PathBuf,
+
+    /// Overrides a setting of the config file, i.e. `--set tcp_timeout=5`
+    #[arg(short, long = "set", value_name = "SETTING=VALUE", value_parser = worker_config::parse_override)]
+    overrides: Vec<(String, String)>,
 }
 
 
 fn main() -> Result<()> {
     let cli = Cli::parse();
 
-    if let Err(err) = config::load_config(&cli.config_path) {
-        panic!("Received the following error when parsing config file:\n{}", err);
+    // Load the settings from the config file, the environment and the command line
+    if let Err(errors) = config::init(Source { path: cli.config_path, overrides: cli.overrides }) {
+        eprintln!("Invalid configuration:");
+        errors.iter().for_each(|e| eprintln!("  - {}", e));
+        std::process::exit(1);
     }
 
 
@@ -48,43 +61,85 @@ fn main() -> Result<()> {
         }).expect("Error setting SIGINT handler");
     }
 
+    registration::register(config::shared(), WorkerKind::Client, env!("CARGO_PKG_VERSION"), None);
+    registration::start_heartbeats(config::shared(), WorkerKind::Client, env!("CARGO_PKG_VERSION"), None);
+
     while !stop_signal.load(Ordering::Relaxed) {
-        println!("Trying to obtain an IP to scan");
-        let job = get_job();
-        if job.is_none() {
+        println!("Trying to obtain IPs to scan");
+        let jobs = get_jobs();
+        if jobs.is_empty() {
             println!("No IP available yet, waiting...");
             sleep(Duration::from_secs(5));
             continue;
         }
 
-        let (id, ip) = job.unwrap();
-        println!("Scanning {}", ip);
-        if let Ok(json) = validate_server(ip) {
-            send_results(id, ip, Some(&json));
-            if let Ok(json) = serde_json::from_str::<Value>(&json) {
-                println!("SERVER FOUND ({})!\nDesc: {}\nPlayers: {}/{}\n{}", json["version"]["name"],
-                         json["description"], json["players"]["online"], json["players"]["max"],
-                         json["players"]["sample"]);
-            }
-        } else {
-            send_results(id, ip, None);
+        let count = jobs.len();
+        let results = scan_batch(jobs, &stop_signal);
+        if results.len() < count {
+            println!("Stopped after scanning {} of {} servers, sending their results", results.len(), count);
+        }
+        if !results.is_empty() {
+            send_results(&results);
         }
     }
 
     Ok(())
 }
 
-fn get_job() -> Option<(u32, Ipv4Addr)> {
-    let url = format!("{}/client/job", config::get_dispatcher_base());
-    let client = reqwest::blocking::Client::new();
+/// Fetches the settings pushed by the dispatcher if their version changed. The current settings
+/// are kept if the dispatcher can't be reached, and the defaults are used if it has none.
+fn sync_config(version: Option<&str>) {
+    if version == config::get_remote_version().as_deref() {
+        return;
+    }
+    if version.is_none() {
+        println!("Dispatcher stopped managing the settings, using the defaults.");
+        config::clear_remote_config();
+        return;
+    }
+
+    let url = config::current().dispatcher.url("/config/worker");
+    let res = config::dispatcher_client().get(url).query(&worker_query()).send();
+    if let Ok(r) = &res {
+        check_protocol(r);
+    }
+    match res {
+        Ok(r) if r.status() == reqwest::StatusCode::OK => match r.json::<ResolvedConfig>() {
+            Ok(resolved) => {
+                println!("Applying settings version {} from the dispatcher.", resolved.version);
+                config::apply_remote_config(&resolved);
+            }
+            Err(_) => println!("Received invalid settings from the dispatcher, keeping the current ones.")
+        },
+        Ok(r) if r.status() == reqwest::StatusCode::NOT_FOUND => config::clear_remote_config(),
+        _ => println!("Error getting settings from dispatch server, keeping the current ones.")
+    }
+}
+
+/// Identifies the client to the dispatcher, which uses its name and group to find its settings,
+/// and its id to record the scans it produced
+fn worker_query() -> WorkerQuery {
+    WorkerQuery {
+        worker: config::current().dispatcher.name.clone(),
+        group: config::current().dispatcher.group.clone(),
+        worker_id: registration::worker_id(),
+    }
+}
+
+/// Leases a batch of `job_size` servers to scan, returns an empty batch if there are none
+fn get_jobs() -> Vec<ClientJob> {
+    let url = config::current().dispatcher.url("/client/job");
+    let batch = BatchQuery { count: Some(u32::try_from(config::current().job_size).unwrap_or(u32::MAX)) };
     let res;
     loop {
-        match client.get(&url).send() {
+        match config::dispatcher_client().get(&url).query(&worker_query()).query(&batch).send() {
             Ok(r) => {
+                check_protocol(&r);
                 res = r;
                 break;
             }
             Err(_) => {
+                registration::record_error();
                 println!("Error sending request to server, retrying in 5 seconds.");
                 sleep(Duration::from_secs(5));
             }
@@ -92,70 +147,83 @@ fn get_job() -> Option<(u32, Ipv4Addr)> {
     }
 
     if res.status() == reqwest::StatusCode::NOT_FOUND {
-        return None;
+        return Vec::new();
+    } else if res.status() == reqwest::StatusCode::UNAUTHORIZED || res.status() == reqwest::StatusCode::FORBIDDEN {
+        registration::record_error();
+        println!("Dispatcher rejected the API key, check api_key in the config file.");
+        return Vec::new();
     } else if res.status() != reqwest::StatusCode::OK {
+        registration::record_error();
         println!("Unknown status code received from dispatch server.");
-        return None;
+        return Vec::new();
     }
 
-    let t = res.text().unwrap();
-    let v: Value = match serde_json::from_str(&t) {
-        Ok(v) => v,
-        Err(_) => return None
-    };
-
-    // Validate id field
-    let id = match v["id"].as_str() {
-        Some(s) => s,
-        None => return None
-    };
-    let id = match id.parse() {
-        Ok(u) => u,
-        Err(_) => return None
-    };
-
-    // Validate IP field
-    let ip = match v["ip"].as_str() {
-        Some(s) => s,
-        None => return None
-    };
-    let ip = match ip.parse() {
-        Ok(i) => i,
-        Err(_) => return None
+    let batch: ClientBatch = match res.json() {
+        Ok(batch) => batch,
+        Err(e) => {
+            registration::record_error();
+            println!("Received invalid jobs from dispatch server: {}", e);
+            return Vec::new();
+        }
     };
 
-    Some((id, ip))
+    // Applied before the scans, so that they use the new timeout
+    sync_config(batch.config_version.as_deref());
+    batch.jobs
 }
 
-fn send_results(id: u32, ip: Ipv4Addr, response: Option<&str>) {
-    let url = format!("{}/client/job/{}", config::get_dispatcher_base(), id);
-
-    let parsed_response: Option<Value> = match response {
-        Some(s) => serde_json::from_str(s).ok(),
-        None => None
-    };
-
-    let body = match parsed_response {
-        Some(v) => {
-            json!({
-                "status": "up",
-                "ip": ip.to_string(),
-                "response": v
-            })
+/// Scans the servers of a batch, `SCAN_THREADS` at a time. Stops taking new servers once
+/// `stop_signal` is set, and returns the results of the servers that were scanned.
+fn scan_batch(jobs: Vec<ClientJob>, stop_signal: &AtomicBool) -> Vec<JobResult> {
+    let jobs = Mutex::new(jobs.into_iter());
+    let results = Mutex::new(Vec::new());
+    thread::scope(|s| {
+        for _ in 0..SCAN_THREADS {
+            s.spawn(|| {
+                while !stop_signal.load(Ordering::Relaxed) {
+                    let job = jobs.lock().unwrap().next();
+                    match job {
+                        Some(ClientJob { id, ip, .. }) => {
+                            let result = scan(ip);
+                            results.lock().unwrap().push(JobResult { id, result });
+                        }
+                        None => break
+                    }
+                }
+            });
         }
-        None => {
-            json!({
-                "status": "down",
-                "ip": ip.to_string()
-            })
+    });
+    results.into_inner().unwrap()
+}
+
+fn scan(ip: Ipv4Addr) -> ClientResult {
+    println!("Scanning {}", ip);
+    match validate_server(ip).ok().and_then(|json| serde_json::from_str::<Value>(&json).ok()) {
+        Some(json) => {
+            println!("SERVER FOUND ({})!\nDesc: {}\nPlayers: {}/{}\n{}", json["version"]["name"],
+                     motd::to_ansi(&json["description"]), json["players"]["online"], json["players"]["max"],
+                     json["players"]["sample"]);
+            ClientResult::up(ip, json)
         }
-    };
+        None => ClientResult::down(ip)
+    }
+}
 
-    let client = reqwest::blocking::Client::new();
+/// Sends the results of a batch, which may only contain part of its jobs
+fn send_results(results: &[JobResult]) {
+    let url = config::current().dispatcher.url("/client/results");
     loop {
-        match client.post(&url).json(&body).send() {
-            Ok(_) => break,
+        match config::dispatcher_client().post(&url).query(&worker_query()).json(results).send() {
+            Ok(r) => {
+                check_protocol(&r);
+                if !r.status().is_success() {
+                    registration::record_error();
+                    println!("Dispatch server couldn't save the results: {}", r.status());
+                }
+                break;
+            }
             Err(_) => {
+                registration::record_error();
                 println!("Error uploading data to server, retrying in 5 seconds.");
                 sleep(Duration::from_secs(5));
             }
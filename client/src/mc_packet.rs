@@ -2,7 +2,7 @@
 
 use std::error::Error;
 use std::fmt::{Display, Formatter};
-use std::io::{Read, Write};
+use std::io::{ErrorKind, Read, Write};
 
 #[derive(Debug)]
 pub struct PacketParseError(pub String);
@@ -73,6 +73,7 @@ impl MCPacket {
         self.data.extend_from_slice(&i.to_be_bytes());
     }
 
+    #[allow(dead_code)]
     pub fn write_i64(&mut self, i: i64) {
         self.data.extend_from_slice(&i.to_be_bytes());
     }
@@ -87,11 +88,14 @@ impl MCPacket {
     pub fn read_var_int(s: &mut dyn Read) -> Result<u64, PacketParseError> {
         let mut val: u64 = 0;
         let mut i = 0;
-        let mut iter = s.bytes();
+        let mut buf = [0u8; 1];
 
         loop {
-            let byte = iter.next().ok_or_else(|| PacketParseError::new("Ran out of bytes trying to read VarInt"))?
-                .map_err(|err| PacketParseError(format!("OS Error reading VarInt: {}", err)))?;
+            s.read_exact(&mut buf).map_err(|err| match err.kind() {
+                ErrorKind::UnexpectedEof => PacketParseError::new("Ran out of bytes trying to read VarInt"),
+                _ => PacketParseError(format!("OS Error reading VarInt: {}", err))
+            })?;
+            let byte = buf[0];
             val |= ((byte & 0b01111111) as u64) << i;
             i += 7;
 
@@ -0,0 +1,41 @@
+// Conversion of Minecraft chat components (format used by the `description` field of a status
+// response) into ANSI-colored text that can be printed in a terminal. The components are parsed
+// by `protocol::motd`, shared with the dispatcher.
+
+use protocol::motd::{Style, parse_component};
+use rand::Rng;
+use serde_json::Value;
+
+const RESET: &str = "\x1b[0m";
+
+/// ANSI escape sequence that switches the terminal to `style`
+fn ansi_sequence(style: &Style) -> String {
+    let mut seq = String::from("\x1b[0");
+    if style.bold { seq.push_str(";1"); }
+    if style.italic { seq.push_str(";3"); }
+    if style.underlined { seq.push_str(";4"); }
+    if style.strikethrough { seq.push_str(";9"); }
+    if let Some(color) = style.color {
+        seq.push_str(&format!(";38;2;{};{};{}", color.r, color.g, color.b));
+    }
+    seq.push('m');
+    seq
+}
+
+/// Converts a chat component (string, object or array form) into a string containing ANSI
+/// escape sequences for its colors and formatting. Legacy `§` codes are also applied.
+pub fn to_ansi(description: &Value) -> String {
+    let mut rng = rand::thread_rng();
+    let mut out = String::new();
+    for span in parse_component(description) {
+        out.push_str(&ansi_sequence(&span.style));
+        if span.style.obfuscated {
+            // Obfuscated text constantly cycles through random characters in the game
+            out.extend(span.text.chars().map(|c| if c.is_whitespace() { c } else { rng.gen_range('!'..='~') }));
+        } else {
+            out.push_str(&span.text);
+        }
+    }
+    out.push_str(RESET);
+    out
+}
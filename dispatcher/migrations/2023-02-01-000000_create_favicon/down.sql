@@ -0,0 +1,17 @@
+-- This file should undo anything in `up.sql`
+
+-- Inline favicons back into the scan table
+ALTER TABLE scan ADD COLUMN favicon TEXT;
+
+UPDATE scan SET favicon = 'data:image/png;base64,' || replace(encode(favicon.data, 'base64'), E'\n', '')
+FROM favicon
+WHERE scan.favicon_id = favicon.favicon_id;
+
+UPDATE scan SET favicon = rejected_favicon.favicon
+FROM rejected_favicon
+WHERE scan.scan_id = rejected_favicon.scan_id;
+
+DROP TABLE rejected_favicon;
+
+ALTER TABLE scan DROP COLUMN favicon_id;
+DROP TABLE favicon;
@@ -0,0 +1,53 @@
+-- Your SQL goes here
+
+-- Create favicon table, each unique favicon PNG is stored once, keyed by its SHA-256 hash
+CREATE TABLE favicon (
+    favicon_id SERIAL PRIMARY KEY,
+    hash TEXT NOT NULL UNIQUE,
+    data BYTEA NOT NULL
+);
+
+ALTER TABLE scan ADD COLUMN favicon_id INT REFERENCES favicon (favicon_id) ON UPDATE CASCADE ON DELETE SET NULL;
+CREATE INDEX scan_favicon_id_idx ON scan (favicon_id);
+
+-- Move existing favicons over
+CREATE FUNCTION pg_temp.try_decode_favicon(favicon TEXT) RETURNS BYTEA AS $$
+BEGIN
+    RETURN decode(regexp_replace(favicon, '^data:image/png;base64,|\s', '', 'g'), 'base64');
+EXCEPTION WHEN OTHERS THEN
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TEMPORARY TABLE scan_favicon AS
+    SELECT scan_id, pg_temp.try_decode_favicon(favicon) AS data
+    FROM scan WHERE favicon IS NOT NULL;
+
+-- Only keep 64x64 PNGs under the size cap (32 KiB), based on the PNG signature and IHDR chunk
+DELETE FROM scan_favicon
+WHERE data IS NULL
+   OR length(data) > 32768
+   OR substring(data FROM 1 FOR 8) <> '\x89504e470d0a1a0a'::BYTEA
+   OR substring(data FROM 17 FOR 8) <> '\x0000004000000040'::BYTEA;
+
+-- The favicons that were rejected keep their raw data, new scans discard them instead (see
+-- `crate::favicon::decode_favicon`)
+CREATE TABLE rejected_favicon (
+    scan_id INT PRIMARY KEY REFERENCES scan (scan_id) ON UPDATE CASCADE ON DELETE CASCADE,
+    favicon TEXT NOT NULL
+);
+
+INSERT INTO rejected_favicon (scan_id, favicon)
+    SELECT scan_id, favicon FROM scan
+    WHERE favicon IS NOT NULL AND scan_id NOT IN (SELECT scan_id FROM scan_favicon);
+
+INSERT INTO favicon (hash, data)
+    SELECT encode(sha256(data), 'hex'), data FROM scan_favicon
+ON CONFLICT (hash) DO NOTHING;
+
+UPDATE scan SET favicon_id = favicon.favicon_id
+FROM scan_favicon JOIN favicon ON favicon.hash = encode(sha256(scan_favicon.data), 'hex')
+WHERE scan.scan_id = scan_favicon.scan_id;
+
+DROP TABLE scan_favicon;
+ALTER TABLE scan DROP COLUMN favicon;
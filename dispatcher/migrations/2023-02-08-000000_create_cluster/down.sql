@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+
+DROP TABLE server_cluster;
+DROP TABLE cluster;
+ALTER TABLE favicon DROP COLUMN phash;
@@ -0,0 +1,20 @@
+-- Your SQL goes here
+
+-- Perceptual hash of the favicon, used to find visually similar favicons
+ALTER TABLE favicon ADD COLUMN phash BIGINT;
+
+-- Groups of servers that belong to the same network, based on their favicon and MOTD
+CREATE TABLE cluster (
+    cluster_id SERIAL PRIMARY KEY,
+    size INT NOT NULL,
+    favicon_id INT REFERENCES favicon (favicon_id) ON UPDATE CASCADE ON DELETE SET NULL,
+    motd TEXT,
+    updated_at TIMESTAMP NOT NULL DEFAULT now()
+);
+
+-- Only servers that are part of a cluster (2 or more servers) have a row
+CREATE TABLE server_cluster (
+    ip inet PRIMARY KEY,
+    cluster_id INT NOT NULL REFERENCES cluster (cluster_id) ON UPDATE CASCADE ON DELETE CASCADE
+);
+CREATE INDEX server_cluster_cluster_id_idx ON server_cluster (cluster_id);
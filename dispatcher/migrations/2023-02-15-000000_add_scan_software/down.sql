@@ -0,0 +1,12 @@
+-- This file should undo anything in `up.sql`
+
+DROP INDEX scan_ip_scan_id_idx;
+DROP INDEX scan_software_idx;
+
+ALTER TABLE scan
+    DROP COLUMN protocol,
+    DROP COLUMN software,
+    DROP COLUMN is_proxy,
+    DROP COLUMN is_modded,
+    DROP COLUMN game_version_min,
+    DROP COLUMN game_version_max;
@@ -0,0 +1,14 @@
+-- Your SQL goes here
+
+-- Software identified from the status response, NULL until the scan is classified
+ALTER TABLE scan
+    ADD COLUMN protocol INT,
+    ADD COLUMN software TEXT,
+    ADD COLUMN is_proxy BOOLEAN,
+    ADD COLUMN is_modded BOOLEAN,
+    ADD COLUMN game_version_min TEXT,
+    ADD COLUMN game_version_max TEXT;
+
+CREATE INDEX scan_software_idx ON scan (software);
+-- Used to find the latest scan of each server
+CREATE INDEX scan_ip_scan_id_idx ON scan (ip, scan_id);
@@ -0,0 +1,20 @@
+-- Your SQL goes here
+
+-- Mods advertised by modded servers, a row per mod id and version
+CREATE TABLE mod (
+    mod_id SERIAL PRIMARY KEY,
+    modid TEXT NOT NULL,
+    version TEXT
+);
+
+-- Mods without a version are stored once as well, NULLs would be distinct in a unique constraint
+CREATE UNIQUE INDEX mod_modid_version_idx ON mod (modid, coalesce(version, ''));
+
+-- Create many-to-many relation table
+CREATE TABLE mod_scan (
+    mod_id INT NOT NULL REFERENCES mod (mod_id) ON UPDATE CASCADE ON DELETE CASCADE,
+    scan_id INT NOT NULL REFERENCES scan (scan_id) ON UPDATE CASCADE ON DELETE CASCADE,
+    CONSTRAINT mod_scan_pkey PRIMARY KEY (mod_id, scan_id)
+);
+
+CREATE INDEX mod_scan_scan_id_idx ON mod_scan (scan_id);
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+
+ALTER TABLE scan
+    DROP COLUMN sample_text,
+    DROP COLUMN player_count_spoofed;
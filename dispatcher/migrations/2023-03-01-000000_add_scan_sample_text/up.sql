@@ -0,0 +1,6 @@
+-- Your SQL goes here
+
+-- Decorative entries of the player sample (MOTD lines, ads, fake names...), which aren't saved as players
+ALTER TABLE scan
+    ADD COLUMN sample_text TEXT[] NOT NULL DEFAULT '{}',
+    ADD COLUMN player_count_spoofed BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+
+DROP TABLE honeypot;
+DROP TABLE port_probe;
+DROP TABLE netblock_stats;
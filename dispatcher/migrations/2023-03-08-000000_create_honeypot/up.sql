@@ -0,0 +1,25 @@
+-- Your SQL goes here
+
+-- Number of IPs of each /24 sent to scouts, and how many of them answered
+CREATE TABLE netblock_stats (
+    network CIDR PRIMARY KEY,
+    probed INT NOT NULL DEFAULT 0,
+    responded INT NOT NULL DEFAULT 0
+);
+
+-- SYN probes sent to a random port of servers that answered on the Minecraft port
+CREATE TABLE port_probe (
+    ip INET PRIMARY KEY,
+    probes INT NOT NULL DEFAULT 0,
+    hits INT NOT NULL DEFAULT 0
+);
+
+-- IPs (/32) and netblocks (/24) flagged as likely honeypots, recomputed periodically
+CREATE TABLE honeypot (
+    network CIDR PRIMARY KEY,
+    score REAL NOT NULL,
+    reasons TEXT[] NOT NULL,
+    updated_at TIMESTAMP NOT NULL
+);
+
+CREATE INDEX honeypot_network_idx ON honeypot USING gist (network inet_ops);
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+
+DROP INDEX player_scan_scan_id_idx;
+DROP TABLE player_base;
@@ -0,0 +1,16 @@
+-- Your SQL goes here
+
+-- Estimated number of distinct players of each server, from all the player samples of its scans
+CREATE TABLE player_base (
+    ip INET PRIMARY KEY,
+    sampled_scans INT NOT NULL,
+    observed_players INT NOT NULL,
+    -- Number of players seen in exactly one and exactly two scans
+    singletons INT NOT NULL,
+    doubletons INT NOT NULL,
+    estimated_players REAL NOT NULL,
+    updated_at TIMESTAMP NOT NULL
+);
+
+-- Used to aggregate the player samples of each server
+CREATE INDEX player_scan_scan_id_idx ON player_scan (scan_id);
@@ -0,0 +1,18 @@
+-- Your SQL goes here
+
+-- Co-occurrence graph, rebuilt periodically from player_scan. Edges are stored in both directions.
+-- Servers are linked by the number of distinct players seen on both of them
+CREATE TABLE server_link (
+    ip INET NOT NULL,
+    linked_ip INET NOT NULL,
+    shared_players INT NOT NULL,
+    CONSTRAINT server_link_pkey PRIMARY KEY (ip, linked_ip)
+);
+
+-- Players are linked by the number of scans they were both seen in
+CREATE TABLE player_link (
+    player_id INT NOT NULL REFERENCES player (player_id) ON UPDATE CASCADE ON DELETE CASCADE,
+    linked_player_id INT NOT NULL REFERENCES player (player_id) ON UPDATE CASCADE ON DELETE CASCADE,
+    shared_scans INT NOT NULL,
+    CONSTRAINT player_link_pkey PRIMARY KEY (player_id, linked_player_id)
+);
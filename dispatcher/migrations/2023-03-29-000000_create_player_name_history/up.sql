@@ -0,0 +1,16 @@
+-- Your SQL goes here
+
+-- Every username seen for a player, so that players can still be found by their old names
+CREATE TABLE player_name_history (
+    player_id INT NOT NULL REFERENCES player (player_id) ON UPDATE CASCADE ON DELETE CASCADE,
+    username TEXT NOT NULL,
+    first_seen TIMESTAMP NOT NULL DEFAULT now(),
+    last_seen TIMESTAMP NOT NULL DEFAULT now(),
+    CONSTRAINT player_name_history_pkey PRIMARY KEY (player_id, username)
+);
+
+CREATE INDEX player_name_history_username_idx ON player_name_history (lower(username));
+
+-- Current names, the time they were first seen is unknown
+INSERT INTO player_name_history (player_id, username)
+SELECT player_id, username FROM player;
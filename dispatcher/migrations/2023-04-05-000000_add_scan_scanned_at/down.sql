@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+
+DROP INDEX scan_scanned_at_idx;
+DROP INDEX scan_ip_scanned_at_idx;
+ALTER TABLE scan DROP COLUMN scanned_at;
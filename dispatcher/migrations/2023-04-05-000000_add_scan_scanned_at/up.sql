@@ -0,0 +1,8 @@
+-- Your SQL goes here
+
+-- Time of the scan, unknown (NULL) for scans saved before it was recorded
+ALTER TABLE scan ADD COLUMN scanned_at TIMESTAMP;
+ALTER TABLE scan ALTER COLUMN scanned_at SET DEFAULT now();
+
+CREATE INDEX scan_ip_scanned_at_idx ON scan (ip, scanned_at);
+CREATE INDEX scan_scanned_at_idx ON scan (scanned_at);
@@ -0,0 +1,9 @@
+-- This file should undo anything in `up.sql`
+
+DROP MATERIALIZED VIEW stats_discovery;
+DROP MATERIALIZED VIEW stats_auth_mode;
+DROP MATERIALIZED VIEW stats_software;
+DROP MATERIALIZED VIEW stats_protocol;
+DROP MATERIALIZED VIEW stats_version;
+DROP MATERIALIZED VIEW stats_overview;
+DROP MATERIALIZED VIEW stats_server;
@@ -0,0 +1,63 @@
+-- Your SQL goes here
+
+-- Latest scan of every known server, honeypots excluded. Servers scanned in the last day are
+-- considered online. A server is in online mode if the UUIDs it sends match the real UUIDs of
+-- its players, and in offline mode if any of them doesn't.
+CREATE MATERIALIZED VIEW stats_server AS
+SELECT latest.ip, latest.version, latest.protocol, latest.software, latest.is_proxy, latest.is_modded,
+    latest.game_version_max, latest.online_count, latest.player_count_spoofed,
+    latest.scanned_at >= now() - INTERVAL '1 day' AS online,
+    first.first_seen,
+    auth.offline_mode
+FROM (
+    SELECT DISTINCT ON (ip) * FROM scan ORDER BY ip, scan_id DESC
+) latest
+JOIN (
+    SELECT ip, min(scanned_at) AS first_seen FROM scan GROUP BY ip
+) first ON first.ip = latest.ip
+LEFT JOIN (
+    SELECT scan.ip, bool_or(player.player_uuid IS DISTINCT FROM player_scan.player_scan_uuid) AS offline_mode
+    FROM player_scan
+    JOIN scan ON scan.scan_id = player_scan.scan_id
+    JOIN player ON player.player_id = player_scan.player_id
+    GROUP BY scan.ip
+) auth ON auth.ip = latest.ip
+WHERE NOT EXISTS (SELECT 1 FROM honeypot WHERE honeypot.network >>= latest.ip);
+
+CREATE UNIQUE INDEX stats_server_ip_idx ON stats_server (ip);
+
+-- Spoofed player counts are left out of the player totals
+CREATE MATERIALIZED VIEW stats_overview AS
+SELECT count(*) AS total_servers,
+    count(*) FILTER (WHERE online) AS online_servers,
+    coalesce(sum(online_count) FILTER (WHERE online AND NOT player_count_spoofed), 0) AS total_players,
+    avg(online_count) FILTER (WHERE online AND NOT player_count_spoofed)::DOUBLE PRECISION AS average_players,
+    now()::TIMESTAMP AS updated_at
+FROM stats_server;
+
+CREATE MATERIALIZED VIEW stats_version AS
+SELECT game_version_max AS game_version, count(*) AS servers
+FROM stats_server WHERE online
+GROUP BY game_version_max;
+
+CREATE MATERIALIZED VIEW stats_protocol AS
+SELECT protocol, count(*) AS servers
+FROM stats_server WHERE online
+GROUP BY protocol;
+
+CREATE MATERIALIZED VIEW stats_software AS
+SELECT software, is_proxy, is_modded, count(*) AS servers
+FROM stats_server WHERE online
+GROUP BY software, is_proxy, is_modded;
+
+CREATE MATERIALIZED VIEW stats_auth_mode AS
+SELECT CASE WHEN offline_mode IS NULL THEN 'unknown' WHEN offline_mode THEN 'offline' ELSE 'online' END AS mode,
+    count(*) AS servers
+FROM stats_server WHERE online
+GROUP BY 1;
+
+-- Servers seen for the first time each day
+CREATE MATERIALIZED VIEW stats_discovery AS
+SELECT date_trunc('day', first_seen) AS day, count(*) AS servers
+FROM stats_server WHERE first_seen IS NOT NULL
+GROUP BY 1;
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+
+DROP MATERIALIZED VIEW stats_asn;
+DROP MATERIALIZED VIEW stats_country;
+DROP TABLE server_geo;
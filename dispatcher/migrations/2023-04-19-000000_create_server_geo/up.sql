@@ -0,0 +1,27 @@
+-- Your SQL goes here
+
+-- Location and network of server IPs, looked up in the local MaxMind databases
+CREATE TABLE server_geo (
+    ip INET PRIMARY KEY,
+    country_code TEXT,
+    country TEXT,
+    city TEXT,
+    asn INTEGER,
+    as_org TEXT,
+    updated_at TIMESTAMP NOT NULL
+);
+
+CREATE INDEX server_geo_country_code_idx ON server_geo (country_code);
+CREATE INDEX server_geo_asn_idx ON server_geo (asn);
+
+CREATE MATERIALIZED VIEW stats_country AS
+SELECT server_geo.country_code, server_geo.country, count(*) AS servers
+FROM stats_server LEFT JOIN server_geo ON server_geo.ip = stats_server.ip
+WHERE stats_server.online
+GROUP BY server_geo.country_code, server_geo.country;
+
+CREATE MATERIALIZED VIEW stats_asn AS
+SELECT server_geo.asn, server_geo.as_org, count(*) AS servers
+FROM stats_server LEFT JOIN server_geo ON server_geo.ip = stats_server.ip
+WHERE stats_server.online
+GROUP BY server_geo.asn, server_geo.as_org;
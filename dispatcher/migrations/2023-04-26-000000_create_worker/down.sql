@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+
+DROP INDEX scan_worker_id_idx;
+ALTER TABLE scan DROP COLUMN worker_id;
+DROP TABLE worker;
@@ -0,0 +1,27 @@
+-- Your SQL goes here
+
+-- Scouts and clients that registered with the dispatcher. Counters and heartbeat fields are
+-- flushed periodically from the dispatcher's memory.
+CREATE TABLE worker (
+    id UUID PRIMARY KEY,
+    -- scout or client
+    kind TEXT NOT NULL,
+    name TEXT,
+    worker_group TEXT,
+    hostname TEXT,
+    version TEXT,
+    adapter TEXT,
+    -- Packets per second of scouts, as of the last heartbeat
+    send_rate BIGINT,
+    registered_at TIMESTAMP NOT NULL DEFAULT now(),
+    last_seen TIMESTAMP NOT NULL DEFAULT now(),
+    jobs_completed BIGINT NOT NULL DEFAULT 0,
+    -- IPs probed by scouts, servers scanned by clients
+    items_processed BIGINT NOT NULL DEFAULT 0,
+    errors BIGINT NOT NULL DEFAULT 0
+);
+
+-- Worker that produced the scan, unknown (NULL) for scans of unregistered workers
+ALTER TABLE scan ADD COLUMN worker_id UUID REFERENCES worker (id) ON DELETE SET NULL;
+
+CREATE INDEX scan_worker_id_idx ON scan (worker_id);
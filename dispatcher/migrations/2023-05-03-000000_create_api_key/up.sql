@@ -0,0 +1,17 @@
+-- Your SQL goes here
+
+-- API keys of the workers and users of the dispatcher. Only the SHA-256 hash of a key is stored,
+-- its prefix identifies it in listings.
+CREATE TABLE api_key (
+    id SERIAL PRIMARY KEY,
+    name TEXT NOT NULL,
+    -- scout, client, read_only or admin
+    role TEXT NOT NULL,
+    key_hash TEXT NOT NULL UNIQUE,
+    prefix TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT now(),
+    -- Set on the previous key when a key is rotated, it stays valid until then
+    expires_at TIMESTAMP,
+    revoked_at TIMESTAMP,
+    last_used TIMESTAMP
+);
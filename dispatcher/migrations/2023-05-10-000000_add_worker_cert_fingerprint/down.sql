@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+
+DROP INDEX worker_cert_fingerprint_idx;
+ALTER TABLE worker DROP COLUMN cert_fingerprint;
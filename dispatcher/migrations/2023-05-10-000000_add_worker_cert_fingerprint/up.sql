@@ -0,0 +1,5 @@
+-- Your SQL goes here
+
+-- SHA-256 fingerprint of the TLS client certificate identifying the worker
+ALTER TABLE worker ADD COLUMN cert_fingerprint TEXT;
+CREATE UNIQUE INDEX worker_cert_fingerprint_idx ON worker (cert_fingerprint);
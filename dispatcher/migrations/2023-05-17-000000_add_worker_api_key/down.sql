@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+
+ALTER TABLE worker DROP COLUMN api_key_id;
+ALTER TABLE api_key DROP COLUMN origin_id;
@@ -0,0 +1,9 @@
+-- Your SQL goes here
+
+-- First key of the rotations of a key, NULL for keys that weren't rotated from another. Keys
+-- rotated from one another can act for the same workers.
+ALTER TABLE api_key ADD COLUMN origin_id INTEGER REFERENCES api_key (id);
+
+-- Key that registered the worker (the first of its rotations), only that key can act for it.
+-- NULL for the workers that registered before keys were tied to them.
+ALTER TABLE worker ADD COLUMN api_key_id INTEGER REFERENCES api_key (id);
@@ -0,0 +1,248 @@
+// API key authentication. Every request needs a key, sent as `Authorization: Bearer <key>` or
+// `X-Api-Key: <key>`, and the role of the key decides which routes it can use:
+// - scout: `/scout`, `/workers` and `/config`
+// - client: `/client`, `/workers` and `/config`
+// - read_only: every GET route, except the ones of the workers and `/admin`
+// - admin: every route, including key management under `/admin/keys`
+// Routes are checked without their version prefix (see `crate::routes`), and the API
+// documentation (`/api/v1/openapi.json` and `/api/v1/docs`) doesn't need a key.
+// Only the SHA-256 hash of a key is stored, keys are long random strings so a slow hash isn't
+// needed. Keys are rotated by issuing a new key with the same name and role, the previous one
+// stays valid for a grace period. Workers belong to the key that registered them and to the keys
+// rotated from it, other keys can't act for them (see `crate::fleet`). Revoked keys are rejected
+// right away. An admin key is created and printed on startup when there is none, to manage the
+// other keys.
+
+use std::collections::HashMap;
+use std::sync::{Mutex, RwLock};
+use std::time::SystemTime;
+use actix_web::{Error, HttpMessage, error};
+use actix_web::dev::ServiceRequest;
+use actix_web::http::{Method, header};
+use actix_web::web::Data;
+use diesel::prelude::*;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use crate::DbConnection;
+use crate::models::{ApiKey, NewApiKey};
+use crate::openapi;
+use crate::routes::unversioned_path;
+use crate::schema::api_key;
+
+/// Start of every key, makes them easy to spot in config files
+const KEY_PREFIX: &str = "msk_";
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Role {
+    Scout,
+    Client,
+    ReadOnly,
+    Admin,
+}
+
+impl Role {
+    pub fn parse(role: &str) -> Option<Role> {
+        match role {
+            "scout" => Some(Role::Scout),
+            "client" => Some(Role::Client),
+            "read_only" => Some(Role::ReadOnly),
+            "admin" => Some(Role::Admin),
+            _ => None
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Scout => "scout",
+            Role::Client => "client",
+            Role::ReadOnly => "read_only",
+            Role::Admin => "admin",
+        }
+    }
+
+    /// Checks that the role gives access to the route
+    fn allows(&self, method: &Method, path: &str) -> bool {
+        let in_scope = |scope: &str| path.strip_prefix(scope)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'));
+        let worker_route = in_scope("/workers") || in_scope("/config");
+        match self {
+            Role::Scout => in_scope("/scout") || worker_route,
+            Role::Client => in_scope("/client") || worker_route,
+            Role::ReadOnly => method == Method::GET && !worker_route
+                && !in_scope("/scout") && !in_scope("/client") && !in_scope("/admin"),
+            Role::Admin => true,
+        }
+    }
+}
+
+/// Hex-encoded SHA-256 hash of a key
+pub fn hash_key(key: &str) -> String {
+    format!("{:x}", Sha256::digest(key.as_bytes()))
+}
+
+/// Generates a new key, returns the key and the row to save
+pub fn generate_key(name: String, role: Role) -> (String, NewApiKey) {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let key = format!("{}{}", KEY_PREFIX, bytes.iter().map(|b| format!("{:02x}", b)).collect::<String>());
+    let new_key = NewApiKey {
+        name,
+        role: String::from(role.as_str()),
+        key_hash: hash_key(&key),
+        prefix: String::from(&key[..KEY_PREFIX.len() + 8]),
+        origin_id: None,
+    };
+    (key, new_key)
+}
+
+/// Creates an admin key if there is no usable one, returns the new key
+pub fn ensure_admin_key(conn: &mut DbConnection) -> QueryResult<Option<String>> {
+    let now = SystemTime::now();
+    let has_admin = ApiKey::all(conn)?.iter()
+        .any(|k| k.role == Role::Admin.as_str() && k.revoked_at.is_none() && k.expires_at.is_none_or(|e| e > now));
+    if has_admin {
+        return Ok(None);
+    }
+    let (key, new_key) = generate_key(String::from("bootstrap"), Role::Admin);
+    new_key.save_to_db(conn)?;
+    Ok(Some(key))
+}
+
+struct ActiveKey {
+    id: i32,
+    lineage: i32,
+    role: Role,
+    expires_at: Option<SystemTime>,
+}
+
+/// Key a request was authenticated with, available with `HttpRequest::extensions`
+#[derive(Debug, Copy, Clone)]
+pub struct AuthenticatedKey {
+    pub role: Role,
+    // See [`ApiKey::lineage`]
+    pub lineage: i32,
+}
+
+/// Keys that aren't revoked, by hash
+pub struct ApiKeys {
+    keys: RwLock<HashMap<String, ActiveKey>>,
+    // Last use of the keys since they were last saved
+    last_used: Mutex<HashMap<i32, SystemTime>>,
+}
+
+impl ApiKeys {
+    pub fn load(conn: &mut DbConnection) -> QueryResult<Self> {
+        let keys = ApiKeys { keys: RwLock::new(HashMap::new()), last_used: Mutex::new(HashMap::new()) };
+        keys.reload(conn)?;
+        Ok(keys)
+    }
+
+    /// Loads the keys again, after they were changed
+    pub fn reload(&self, conn: &mut DbConnection) -> QueryResult<()> {
+        let keys = ApiKey::all(conn)?.into_iter()
+            .filter(|k| k.revoked_at.is_none())
+            .filter_map(|k| {
+                let role = Role::parse(&k.role)?;
+                let lineage = k.lineage();
+                Some((k.key_hash, ActiveKey { id: k.id, lineage, role, expires_at: k.expires_at }))
+            })
+            .collect();
+        *self.keys.write().unwrap() = keys;
+        Ok(())
+    }
+
+    /// Saves when the keys were last used
+    pub fn save_last_used(&self, conn: &mut DbConnection) -> QueryResult<usize> {
+        let last_used: Vec<(i32, SystemTime)> = self.last_used.lock().unwrap().drain().collect();
+        conn.build_transaction().run(|conn| {
+            for (id, time) in last_used.iter() {
+                diesel::update(api_key::table.find(*id)).set(api_key::last_used.eq(*time)).execute(conn)?;
+            }
+            Ok(last_used.len())
+        })
+    }
+
+    /// Checks a key, None if it is unknown, revoked or expired
+    fn authenticate(&self, key: &str) -> Option<AuthenticatedKey> {
+        let now = SystemTime::now();
+        let keys = self.keys.read().unwrap();
+        let active = keys.get(&hash_key(key))?;
+        if active.expires_at.is_some_and(|e| e <= now) {
+            return None;
+        }
+        self.last_used.lock().unwrap().insert(active.id, now);
+        Some(AuthenticatedKey { role: active.role, lineage: active.lineage })
+    }
+}
+
+/// Key of a request, from the `Authorization` or `X-Api-Key` header
+fn request_key(req: &ServiceRequest) -> Option<&str> {
+    let headers = req.headers();
+    if let Some(value) = headers.get(header::AUTHORIZATION) {
+        return value.to_str().ok()?.strip_prefix("Bearer ").map(str::trim);
+    }
+    headers.get("X-Api-Key")?.to_str().ok().map(str::trim)
+}
+
+/// Checks the API key of a request, see the module documentation
+pub fn authorize(req: &ServiceRequest) -> Result<(), Error> {
+    if openapi::is_public(req.path()) {
+        return Ok(());
+    }
+    let keys = req.app_data::<Data<ApiKeys>>().expect("API keys missing from app data");
+    let key = request_key(req).ok_or_else(|| error::ErrorUnauthorized("Missing API key"))?;
+    let authenticated = keys.authenticate(key).ok_or_else(|| error::ErrorUnauthorized("Invalid API key"))?;
+    let role = authenticated.role;
+    if !role.allows(req.method(), unversioned_path(req.path())) {
+        return Err(error::ErrorForbidden(format!("Route not allowed for the {} role", role.as_str())));
+    }
+    req.extensions_mut().insert(authenticated);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roles_allow_their_scopes() {
+        assert!(Role::Scout.allows(&Method::GET, "/scout/job/100"));
+        assert!(Role::Scout.allows(&Method::POST, "/scout/ips"));
+        assert!(Role::Scout.allows(&Method::POST, "/workers/register"));
+        assert!(Role::Scout.allows(&Method::GET, "/config/worker"));
+        assert!(!Role::Scout.allows(&Method::GET, "/client/job"));
+        assert!(!Role::Scout.allows(&Method::GET, "/servers"));
+        assert!(Role::Client.allows(&Method::POST, "/client/job/1"));
+        assert!(Role::Client.allows(&Method::POST, "/workers/00000000-0000-0000-0000-000000000000/heartbeat"));
+        assert!(!Role::Client.allows(&Method::GET, "/scout/job/100"));
+        assert!(!Role::Client.allows(&Method::GET, "/admin/keys"));
+        for path in ["/admin/keys", "/scout/job/1", "/client/job", "/servers", "/stats/summary"] {
+            assert!(Role::Admin.allows(&Method::GET, path));
+            assert!(Role::Admin.allows(&Method::POST, path));
+        }
+    }
+
+    #[test]
+    fn scopes_match_whole_segments() {
+        assert!(Role::Scout.allows(&Method::GET, "/scout"));
+        assert!(!Role::Scout.allows(&Method::GET, "/scoutx"));
+        assert!(!Role::Scout.allows(&Method::GET, "/scoutx/job/1"));
+        assert!(!Role::Client.allows(&Method::GET, "/clients"));
+        assert!(!Role::Client.allows(&Method::GET, "/workersx"));
+        // Not worker routes, so readable
+        assert!(Role::ReadOnly.allows(&Method::GET, "/scoutx"));
+        assert!(Role::ReadOnly.allows(&Method::GET, "/administrators"));
+    }
+
+    #[test]
+    fn read_only_only_reads() {
+        assert!(Role::ReadOnly.allows(&Method::GET, "/servers"));
+        assert!(Role::ReadOnly.allows(&Method::GET, "/info/progress"));
+        for path in ["/admin", "/admin/keys", "/workers", "/workers/register", "/config/worker", "/scout/job/1", "/client/job"] {
+            assert!(!Role::ReadOnly.allows(&Method::GET, path), "read_only shouldn't read {}", path);
+        }
+        for method in [Method::POST, Method::PUT, Method::DELETE, Method::PATCH] {
+            assert!(!Role::ReadOnly.allows(&method, "/servers"), "read_only shouldn't {} /servers", method);
+        }
+    }
+}
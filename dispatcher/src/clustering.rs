@@ -0,0 +1,399 @@
+// Groups servers that are likely part of the same network (or hosting panel), using the latest
+// scan of every server. Two servers are linked when they share the exact same favicon, visually
+// similar favicons, or similar MOTDs once formatting and numbers are removed. Linked servers
+// are then merged into clusters.
+
+use std::collections::{HashMap, HashSet};
+use std::collections::hash_map::Entry;
+use std::time::SystemTime;
+use diesel::prelude::*;
+use ipnet::IpNet;
+use protocol::motd::{parse_component, plain_text};
+use serde_json::Value;
+use crate::DbConnection;
+use crate::favicon::{perceptual_hash, to_rgba};
+use crate::models::{NewCluster, NewServerCluster};
+use crate::schema::{cluster, favicon, scan, server_cluster};
+
+/// Max hamming distance between the perceptual hashes of two favicons considered similar
+const PHASH_MAX_DISTANCE: u32 = 3;
+/// Min Jaccard similarity between the words of two MOTDs considered similar
+const MOTD_MIN_SIMILARITY: f64 = 0.8;
+/// MOTDs with fewer words aren't distinctive enough to be compared
+const MOTD_MIN_WORDS: usize = 3;
+/// Words present in more MOTDs than this are too common to be used to find similar MOTDs
+const MAX_WORD_FREQUENCY: usize = 200;
+/// Buckets larger than this are skipped, they only contain degenerate hashes (i.e. blank images)
+const MAX_BUCKET_SIZE: usize = 1000;
+/// Default MOTDs of server software, shared by unrelated servers (normalized form)
+const DEFAULT_MOTDS: &[&str] = &[
+    "a minecraft server",
+    "a velocity server",
+    "another bungee server",
+    "just another bungeecord proxy",
+    "a paper server",
+    "a spigot server",
+];
+
+struct Server {
+    ip: IpNet,
+    favicon_id: Option<i32>,
+    // Plain text of the MOTD, and its normalized form used for comparisons
+    motd: Option<String>,
+    motd_key: Option<String>,
+}
+
+/// Recomputes the clusters from the latest scan of every server, and saves them to the database.
+/// Returns the number of clusters found.
+pub fn run_clustering(conn: &mut DbConnection) -> QueryResult<usize> {
+    update_perceptual_hashes(conn)?;
+
+    let servers = load_servers(conn)?;
+    let phashes: HashMap<i32, u64> = favicon::table
+        .filter(favicon::phash.is_not_null())
+        .select((favicon::favicon_id, favicon::phash))
+        .load::<(i32, Option<i64>)>(conn)?
+        .into_iter()
+        .filter_map(|(id, phash)| phash.map(|h| (id, h as u64)))
+        .collect();
+
+    let groups = cluster_servers(&servers, &phashes);
+    save_clusters(&servers, groups, conn)
+}
+
+/// Lowercases the MOTD, replaces numbers with `#` (player counts, dates, server numbers...) and
+/// removes punctuation, so that MOTDs generated from the same template are identical.
+pub fn normalize_motd(motd: &str) -> String {
+    let mut normalized = String::with_capacity(motd.len());
+    let mut last = ' ';
+    for c in motd.chars().flat_map(char::to_lowercase) {
+        let c = match c {
+            c if c.is_ascii_digit() => '#',
+            c if c.is_alphanumeric() => c,
+            _ => ' '
+        };
+        // Collapse whitespace and consecutive digits
+        if (c == ' ' || c == '#') && last == c {
+            continue;
+        }
+        normalized.push(c);
+        last = c;
+    }
+    String::from(normalized.trim())
+}
+
+/// Favicons saved before perceptual hashes were introduced don't have one yet
+fn update_perceptual_hashes(conn: &mut DbConnection) -> QueryResult<()> {
+    let missing = favicon::table
+        .filter(favicon::phash.is_null())
+        .select((favicon::favicon_id, favicon::data))
+        .load::<(i32, Vec<u8>)>(conn)?;
+
+    for (id, data) in missing {
+        // Invalid images get a hash of 0, which is never compared
+        let phash = to_rgba(&data).map_or(0, |rgba| perceptual_hash(&rgba));
+        diesel::update(favicon::table.find(id))
+            .set(favicon::phash.eq(phash as i64))
+            .execute(conn)?;
+    }
+    Ok(())
+}
+
+fn load_servers(conn: &mut DbConnection) -> QueryResult<Vec<Server>> {
+    let latest_scans = scan::table
+        .select((scan::ip, scan::favicon_id, scan::description))
+        .distinct_on(scan::ip)
+        .order((scan::ip, scan::scan_id.desc()))
+        .load::<(IpNet, Option<i32>, Option<String>)>(conn)?;
+
+    Ok(latest_scans.into_iter().map(|(ip, favicon_id, description)| {
+        let motd = description.map(|d| {
+            let value = serde_json::from_str(&d).unwrap_or(Value::String(d));
+            plain_text(&parse_component(&value))
+        });
+        let motd_key = motd.as_deref().and_then(motd_key);
+        Server { ip, favicon_id, motd, motd_key }
+    }).collect())
+}
+
+/// Normalized MOTD compared between servers, None for empty and default MOTDs
+fn motd_key(motd: &str) -> Option<String> {
+    Some(normalize_motd(motd)).filter(|m| !m.is_empty() && !DEFAULT_MOTDS.contains(&m.as_str()))
+}
+
+/// Returns the groups of (2 or more) linked servers, as indices into `servers`
+fn cluster_servers(servers: &[Server], phashes: &HashMap<i32, u64>) -> Vec<Vec<usize>> {
+    let mut sets = DisjointSets::new(servers.len());
+
+    // Exact favicon, keep the first server using each favicon as its representative
+    let mut by_favicon: HashMap<i32, usize> = HashMap::new();
+    for (i, server) in servers.iter().enumerate() {
+        if let Some(favicon_id) = server.favicon_id {
+            match by_favicon.entry(favicon_id) {
+                Entry::Occupied(e) => sets.union(*e.get(), i),
+                Entry::Vacant(e) => { e.insert(i); }
+            }
+        }
+    }
+
+    // Similar favicons. Two hashes within a distance of 3 have at least one identical 16-bit
+    // band out of 4, so only hashes sharing a band need to be compared
+    let mut bands: HashMap<(u32, u16), Vec<u64>> = HashMap::new();
+    let mut by_phash: HashMap<u64, usize> = HashMap::new();
+    for (favicon_id, &server) in &by_favicon {
+        match phashes.get(favicon_id) {
+            Some(&phash) if phash != 0 && phash != u64::MAX => {
+                match by_phash.entry(phash) {
+                    Entry::Occupied(e) => { sets.union(*e.get(), server); continue; }
+                    Entry::Vacant(e) => { e.insert(server); }
+                }
+                for band in 0..4 {
+                    bands.entry((band, (phash >> (band * 16)) as u16)).or_default().push(phash);
+                }
+            }
+            _ => {}
+        }
+    }
+    for bucket in bands.values().filter(|b| b.len() > 1 && b.len() <= MAX_BUCKET_SIZE) {
+        for (i, a) in bucket.iter().enumerate() {
+            for b in &bucket[i + 1..] {
+                if (a ^ b).count_ones() <= PHASH_MAX_DISTANCE {
+                    sets.union(by_phash[a], by_phash[b]);
+                }
+            }
+        }
+    }
+
+    // Identical normalized MOTDs
+    let mut by_motd: HashMap<&str, usize> = HashMap::new();
+    for (i, server) in servers.iter().enumerate() {
+        if let Some(key) = server.motd_key.as_deref() {
+            match by_motd.entry(key) {
+                Entry::Occupied(e) => sets.union(*e.get(), i),
+                Entry::Vacant(e) => { e.insert(i); }
+            }
+        }
+    }
+
+    // Similar MOTDs, only comparing MOTDs that share one of their 2 least common words
+    let words: Vec<(usize, HashSet<&str>)> = by_motd.iter()
+        .map(|(motd, &server)| (server, motd.split(' ').collect::<HashSet<&str>>()))
+        .filter(|(_, w)| w.len() >= MOTD_MIN_WORDS)
+        .collect();
+    let mut frequency: HashMap<&str, usize> = HashMap::new();
+    words.iter().flat_map(|(_, w)| w.iter()).for_each(|w| *frequency.entry(w).or_default() += 1);
+
+    let mut candidates: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (i, (_, w)) in words.iter().enumerate() {
+        let mut rarest: Vec<&str> = w.iter()
+            .copied()
+            .filter(|w| w.chars().count() >= 3 && frequency[w] <= MAX_WORD_FREQUENCY)
+            .collect();
+        rarest.sort_by_key(|w| (frequency[w], *w));
+        rarest.iter().take(2).for_each(|w| candidates.entry(w).or_default().push(i));
+    }
+    for bucket in candidates.values().filter(|b| b.len() > 1) {
+        for (i, &a) in bucket.iter().enumerate() {
+            for &b in &bucket[i + 1..] {
+                let (server_a, words_a) = &words[a];
+                let (server_b, words_b) = &words[b];
+                let shared = words_a.intersection(words_b).count() as f64;
+                let total = words_a.union(words_b).count() as f64;
+                if shared / total >= MOTD_MIN_SIMILARITY {
+                    sets.union(*server_a, *server_b);
+                }
+            }
+        }
+    }
+
+    sets.groups().into_iter().filter(|g| g.len() > 1).collect()
+}
+
+/// Replaces the saved clusters. A new group reuses the id that most of its servers had in the
+/// previous run, so that clusters keep the same id over time even as servers join, leave or
+/// change IP.
+fn save_clusters(servers: &[Server], mut groups: Vec<Vec<usize>>, conn: &mut DbConnection) -> QueryResult<usize> {
+    // Larger clusters get to keep their id first
+    groups.sort_by_key(|g| std::cmp::Reverse(g.len()));
+
+    conn.transaction(|conn| {
+        let previous: HashMap<IpNet, i32> = server_cluster::table
+            .select((server_cluster::ip, server_cluster::cluster_id))
+            .load::<(IpNet, i32)>(conn)?
+            .into_iter()
+            .collect();
+        diesel::delete(server_cluster::table).execute(conn)?;
+
+        let mut cluster_ids = Vec::with_capacity(groups.len());
+        let mut rows = Vec::new();
+        for group in groups {
+            let previous_id = previous_id(&group, servers, &previous, &cluster_ids);
+
+            let values = NewCluster {
+                size: group.len() as i32,
+                favicon_id: most_common(group.iter().filter_map(|&i| servers[i].favicon_id)),
+                motd: most_common(group.iter().filter_map(|&i| servers[i].motd.clone())),
+                updated_at: SystemTime::now()
+            };
+            let id = match previous_id {
+                Some(id) => {
+                    diesel::update(cluster::table.find(id)).set(&values).execute(conn)?;
+                    id
+                }
+                None => diesel::insert_into(cluster::table)
+                    .values(&values)
+                    .returning(cluster::cluster_id)
+                    .get_result(conn)?
+            };
+
+            cluster_ids.push(id);
+            rows.extend(group.iter().map(|&i| NewServerCluster { ip: servers[i].ip, cluster_id: id }));
+        }
+
+        diesel::delete(cluster::table.filter(cluster::cluster_id.ne_all(&cluster_ids))).execute(conn)?;
+        // Stay under the bind parameter limit of postgres
+        for chunk in rows.chunks(10000) {
+            diesel::insert_into(server_cluster::table).values(chunk).execute(conn)?;
+        }
+        Ok(cluster_ids.len())
+    })
+}
+
+/// Id that most servers of the group had in the previous run, among the ids not already reused
+fn previous_id(group: &[usize], servers: &[Server], previous: &HashMap<IpNet, i32>, reused: &[i32]) -> Option<i32> {
+    most_common(group.iter()
+        .filter_map(|i| previous.get(&servers[*i].ip).copied())
+        .filter(|id| !reused.contains(id)))
+}
+
+fn most_common<T: Eq + std::hash::Hash>(values: impl Iterator<Item = T>) -> Option<T> {
+    let mut counts: HashMap<T, usize> = HashMap::new();
+    values.for_each(|v| *counts.entry(v).or_default() += 1);
+    counts.into_iter().max_by_key(|(_, count)| *count).map(|(v, _)| v)
+}
+
+/// Union-find structure used to merge linked servers into clusters
+struct DisjointSets {
+    parent: Vec<usize>,
+}
+
+impl DisjointSets {
+    fn new(size: usize) -> Self {
+        DisjointSets { parent: (0..size).collect() }
+    }
+
+    fn find(&mut self, x: usize) -> usize {
+        let mut root = x;
+        while self.parent[root] != root {
+            root = self.parent[root];
+        }
+        // Path compression
+        let mut x = x;
+        while self.parent[x] != root {
+            let next = self.parent[x];
+            self.parent[x] = root;
+            x = next;
+        }
+        root
+    }
+
+    fn union(&mut self, a: usize, b: usize) {
+        let (a, b) = (self.find(a), self.find(b));
+        if a != b {
+            self.parent[b] = a;
+        }
+    }
+
+    fn groups(&mut self) -> Vec<Vec<usize>> {
+        let mut groups: HashMap<usize, Vec<usize>> = HashMap::new();
+        for i in 0..self.parent.len() {
+            let root = self.find(i);
+            groups.entry(root).or_default().push(i);
+        }
+        groups.into_values().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+    use super::*;
+
+    fn server(i: u8, favicon_id: Option<i32>, motd: Option<&str>) -> Server {
+        Server {
+            ip: IpNet::from(std::net::IpAddr::V4(Ipv4Addr::new(10, 0, 0, i))),
+            favicon_id,
+            motd: motd.map(String::from),
+            motd_key: motd.and_then(motd_key),
+        }
+    }
+
+    /// Groups as sorted lists, in a stable order
+    fn sorted(mut groups: Vec<Vec<usize>>) -> Vec<Vec<usize>> {
+        groups.iter_mut().for_each(|g| g.sort());
+        groups.sort();
+        groups
+    }
+
+    #[test]
+    fn merges_linked_sets() {
+        let mut sets = DisjointSets::new(6);
+        sets.union(0, 1);
+        sets.union(3, 4);
+        sets.union(1, 4);
+        assert_eq!(sets.find(0), sets.find(3));
+        assert_ne!(sets.find(0), sets.find(2));
+        assert_eq!(sorted(sets.groups()), vec![vec![0, 1, 3, 4], vec![2], vec![5]]);
+    }
+
+    #[test]
+    fn normalizes_motds() {
+        assert_eq!(normalize_motd("  My  Server #12 - 150/200 players!"), "my server # # # players");
+        assert_eq!(normalize_motd("Lobby 1 - 20 players"), normalize_motd("lobby 12 -- 150 Players"));
+    }
+
+    #[test]
+    fn links_similar_favicons() {
+        let base: u64 = 0x0123_4567_89AB_CDEF;
+        // 3 bits apart in 3 different bands still share the 4th one
+        let close = base ^ (1 | 1 << 16 | 1 << 32);
+        let far = base ^ (3 | 3 << 16 | 3 << 32 | 3 << 48);
+        let servers = [
+            server(1, Some(1), None),
+            server(2, Some(1), None),
+            server(3, Some(2), None),
+            server(4, Some(3), None),
+            // Degenerate hashes are never compared
+            server(5, Some(4), None),
+            server(6, Some(5), None),
+        ];
+        let phashes = HashMap::from([(1, base), (2, close), (3, far), (4, 0), (5, 1)]);
+        assert_eq!(sorted(cluster_servers(&servers, &phashes)), vec![vec![0, 1, 2]]);
+    }
+
+    #[test]
+    fn links_similar_motds() {
+        let servers = [
+            server(1, None, Some("Welcome to Skyblock Network - 120 online")),
+            server(2, None, Some("welcome to skyblock network - 98 online!")),
+            server(3, None, Some("Welcome to Skyblock Network - 7 online now")),
+            server(4, None, Some("Welcome to Prison Network - 5 online")),
+            // Default MOTDs link nothing
+            server(5, None, Some("A Minecraft Server")),
+            server(6, None, Some("A Minecraft Server")),
+        ];
+        assert_eq!(sorted(cluster_servers(&servers, &HashMap::new())), vec![vec![0, 1, 2]]);
+    }
+
+    #[test]
+    fn reuses_the_id_of_most_servers() {
+        let servers: Vec<Server> = (1..=4).map(|i| server(i, None, None)).collect();
+        let previous = HashMap::from([(servers[0].ip, 7), (servers[1].ip, 5), (servers[2].ip, 5)]);
+        let group = [0, 1, 2, 3];
+        assert_eq!(previous_id(&group, &servers, &previous, &[]), Some(5));
+        // Already taken by a larger cluster
+        assert_eq!(previous_id(&group, &servers, &previous, &[5]), Some(7));
+        assert_eq!(previous_id(&group, &servers, &previous, &[5, 7]), None);
+        assert_eq!(previous_id(&[3], &servers, &previous, &[]), None);
+    }
+}
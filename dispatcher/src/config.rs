@@ -0,0 +1,364 @@
+// Dispatcher settings. They are read from a TOML file (`--config`, or `dispatcher.toml` in the
+// working directory if it exists), then from environment variables and command-line flags, each
+// source overriding the previous one. `.env` is loaded before, so it can hold the variables. See
+// `dispatcher.toml` at the root of the repository for the list of settings and their variables.
+// Every invalid setting is reported at startup, before anything is started.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use clap::Parser;
+use log::LevelFilter;
+use serde::Deserialize;
+use crate::send_budget::BudgetConfig;
+use crate::targeting::TargetMode;
+use crate::tls::TlsConfig;
+
+/// Loaded when `--config` isn't given, if it exists
+const DEFAULT_CONFIG_PATH: &str = "dispatcher.toml";
+
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+pub struct Cli {
+    /// Config file, `dispatcher.toml` by default
+    #[arg(short, long = "config")]
+    config_path: Option<PathBuf>,
+    #[arg(long)]
+    bind_address: Option<String>,
+    #[arg(short, long)]
+    port: Option<u16>,
+    #[arg(long)]
+    state_file: Option<PathBuf>,
+    #[arg(long)]
+    coverage_file: Option<PathBuf>,
+    /// Seconds before an unanswered client job is given to another client
+    #[arg(long)]
+    client_job_expiry_secs: Option<u64>,
+    /// Seconds between two cleanups of the expired client jobs
+    #[arg(long)]
+    cleanup_interval_secs: Option<u64>,
+    /// Days before a worker that stopped sending heartbeats is forgotten, 0 to keep them
+    #[arg(long)]
+    worker_retention_days: Option<u64>,
+    #[arg(long)]
+    database_url: Option<String>,
+    #[arg(long)]
+    database_pool_size: Option<u32>,
+    /// off, error, warn, info, debug or trace
+    #[arg(long)]
+    log_level: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub bind_address: String,
+    pub port: u16,
+    // Outstanding jobs and IPs waiting to be scanned, saved on shutdown
+    pub state_file: PathBuf,
+    // Coverage of the current scan cycle, see `crate::coverage`
+    pub coverage_file: PathBuf,
+    pub client_job_expiry_secs: u64,
+    pub cleanup_interval_secs: u64,
+    // Registered workers offline for longer are removed, 0 keeps them
+    pub worker_retention_days: u64,
+    // Level of the request logs, the other messages are always printed
+    pub log_level: String,
+    pub target_mode: TargetMode,
+    // Settings served to the workers, see `crate::worker_config`
+    pub worker_config: Option<PathBuf>,
+    pub database: DatabaseConfig,
+    pub geoip: GeoIpConfig,
+    pub send_rate: SendRateConfig,
+    pub tls: TlsSettings,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    pub url: Option<String>,
+    pub pool_size: u32,
+    // Idle connections kept open, the pool size by default
+    pub min_idle: Option<u32>,
+    pub connection_timeout_secs: u64,
+}
+
+/// MaxMind database paths, see `crate::geoip`
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GeoIpConfig {
+    pub city_db: Option<PathBuf>,
+    // Used when there is no city database
+    pub country_db: Option<PathBuf>,
+    pub asn_db: Option<PathBuf>,
+}
+
+/// Global send-rate budget of the scouts, see `crate::send_budget`
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SendRateConfig {
+    pub budget: Option<u64>,
+    // `HH:MM-HH:MM=RATE` entries
+    pub schedule: Vec<String>,
+    // `HH:MM-HH:MM` entries
+    pub blackouts: Vec<String>,
+    pub job_duration_secs: u64,
+}
+
+/// Certificate paths, see `crate::tls`
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsSettings {
+    pub cert: Option<PathBuf>,
+    pub key: Option<PathBuf>,
+    pub client_ca: Option<PathBuf>,
+    // required or optional
+    pub client_auth: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            bind_address: String::from("0.0.0.0"),
+            port: 8000,
+            state_file: PathBuf::from("./.dispatcher"),
+            coverage_file: PathBuf::from("./.coverage"),
+            client_job_expiry_secs: 60,
+            cleanup_interval_secs: 60,
+            worker_retention_days: 30,
+            log_level: String::from("warn"),
+            target_mode: TargetMode::Uniform,
+            worker_config: None,
+            database: DatabaseConfig::default(),
+            geoip: GeoIpConfig::default(),
+            send_rate: SendRateConfig::default(),
+            tls: TlsSettings::default(),
+        }
+    }
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        DatabaseConfig { url: None, pool_size: 10, min_idle: None, connection_timeout_secs: 30 }
+    }
+}
+
+impl Default for SendRateConfig {
+    fn default() -> Self {
+        SendRateConfig { budget: None, schedule: Vec::new(), blackouts: Vec::new(), job_duration_secs: 60 }
+    }
+}
+
+/// Looks up an environment variable, the real environment outside of tests
+type Env<'a> = &'a dyn Fn(&str) -> Option<String>;
+
+/// Overrides `value` with the variable `name` if it is set
+fn env_value<T: FromStr>(env: Env, name: &str, value: &mut T, errors: &mut Vec<String>) {
+    if let Some(text) = env(name) {
+        match text.parse() {
+            Ok(parsed) => *value = parsed,
+            Err(_) => errors.push(format!("Invalid {} {}", name, text))
+        }
+    }
+}
+
+/// Same as [`env_value`], for settings without a default
+fn env_option<T: FromStr>(env: Env, name: &str, value: &mut Option<T>, errors: &mut Vec<String>) {
+    if let Some(text) = env(name) {
+        match text.parse() {
+            Ok(parsed) => *value = Some(parsed),
+            Err(_) => errors.push(format!("Invalid {} {}", name, text))
+        }
+    }
+}
+
+/// Overrides `value` with the comma-separated variable `name` if it is set
+fn env_list(env: Env, name: &str, value: &mut Vec<String>) {
+    if let Some(text) = env(name) {
+        *value = text.split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(String::from)
+            .collect();
+    }
+}
+
+impl Config {
+    /// Loads the config from every source and validates it. Returns every error found.
+    pub fn load(cli: Cli) -> Result<Self, Vec<String>> {
+        Config::load_with_env(cli, &|name| env::var(name).ok())
+    }
+
+    fn load_with_env(cli: Cli, env: Env) -> Result<Self, Vec<String>> {
+        let mut config = match &cli.config_path {
+            Some(path) => Config::read_file(path).map_err(|e| vec![e])?,
+            None if Path::new(DEFAULT_CONFIG_PATH).exists() => Config::read_file(Path::new(DEFAULT_CONFIG_PATH)).map_err(|e| vec![e])?,
+            None => Config::default()
+        };
+
+        let mut errors = Vec::new();
+        config.apply_env(env, &mut errors);
+        config.apply_cli(cli);
+        errors.extend(config.validate());
+        if errors.is_empty() {
+            Ok(config)
+        } else {
+            Err(errors)
+        }
+    }
+
+    fn read_file(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("Unable to read {}: {}", path.display(), e))?;
+        toml::from_str(&text).map_err(|e| format!("Invalid config file {}: {}", path.display(), e))
+    }
+
+    fn apply_env(&mut self, env: Env, errors: &mut Vec<String>) {
+        env_value(env, "BIND_ADDRESS", &mut self.bind_address, errors);
+        env_value(env, "PORT", &mut self.port, errors);
+        env_value(env, "STATE_FILE", &mut self.state_file, errors);
+        env_value(env, "COVERAGE_FILE", &mut self.coverage_file, errors);
+        env_value(env, "CLIENT_JOB_EXPIRY_SECS", &mut self.client_job_expiry_secs, errors);
+        env_value(env, "CLEANUP_INTERVAL_SECS", &mut self.cleanup_interval_secs, errors);
+        env_value(env, "WORKER_RETENTION_DAYS", &mut self.worker_retention_days, errors);
+        env_value(env, "LOG_LEVEL", &mut self.log_level, errors);
+        env_value(env, "TARGET_MODE", &mut self.target_mode, errors);
+        env_option(env, "WORKER_CONFIG", &mut self.worker_config, errors);
+
+        env_option(env, "DATABASE_URL", &mut self.database.url, errors);
+        env_value(env, "DATABASE_POOL_SIZE", &mut self.database.pool_size, errors);
+        env_option(env, "DATABASE_MIN_IDLE", &mut self.database.min_idle, errors);
+        env_value(env, "DATABASE_CONNECTION_TIMEOUT_SECS", &mut self.database.connection_timeout_secs, errors);
+
+        env_option(env, "GEOIP_CITY_DB", &mut self.geoip.city_db, errors);
+        env_option(env, "GEOIP_COUNTRY_DB", &mut self.geoip.country_db, errors);
+        env_option(env, "GEOIP_ASN_DB", &mut self.geoip.asn_db, errors);
+
+        env_option(env, "SEND_RATE_BUDGET", &mut self.send_rate.budget, errors);
+        env_list(env, "SEND_RATE_SCHEDULE", &mut self.send_rate.schedule);
+        env_list(env, "SEND_BLACKOUTS", &mut self.send_rate.blackouts);
+        env_value(env, "JOB_DURATION_SECS", &mut self.send_rate.job_duration_secs, errors);
+
+        env_option(env, "TLS_CERT", &mut self.tls.cert, errors);
+        env_option(env, "TLS_KEY", &mut self.tls.key, errors);
+        env_option(env, "TLS_CLIENT_CA", &mut self.tls.client_ca, errors);
+        env_option(env, "TLS_CLIENT_AUTH", &mut self.tls.client_auth, errors);
+    }
+
+    fn apply_cli(&mut self, cli: Cli) {
+        self.bind_address = cli.bind_address.unwrap_or(self.bind_address.clone());
+        self.port = cli.port.unwrap_or(self.port);
+        self.state_file = cli.state_file.unwrap_or(self.state_file.clone());
+        self.coverage_file = cli.coverage_file.unwrap_or(self.coverage_file.clone());
+        self.client_job_expiry_secs = cli.client_job_expiry_secs.unwrap_or(self.client_job_expiry_secs);
+        self.cleanup_interval_secs = cli.cleanup_interval_secs.unwrap_or(self.cleanup_interval_secs);
+        self.worker_retention_days = cli.worker_retention_days.unwrap_or(self.worker_retention_days);
+        self.database.url = cli.database_url.or(self.database.url.take());
+        self.database.pool_size = cli.database_pool_size.unwrap_or(self.database.pool_size);
+        self.log_level = cli.log_level.unwrap_or(self.log_level.clone());
+    }
+
+    /// Checks the values of the settings, returns the errors
+    fn validate(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+        if self.port == 0 {
+            errors.push(String::from("port must be above 0"));
+        }
+        if self.client_job_expiry_secs == 0 {
+            errors.push(String::from("client_job_expiry_secs must be above 0"));
+        }
+        if self.cleanup_interval_secs == 0 {
+            errors.push(String::from("cleanup_interval_secs must be above 0"));
+        }
+        if LevelFilter::from_str(&self.log_level).is_err() {
+            errors.push(format!("Invalid log_level {}, expected off, error, warn, info, debug or trace", self.log_level));
+        }
+        if self.database.url.is_none() {
+            errors.push(String::from("database.url (DATABASE_URL) is required"));
+        }
+        if self.database.pool_size == 0 {
+            errors.push(String::from("database.pool_size must be above 0"));
+        }
+        if self.database.min_idle.is_some_and(|m| m > self.database.pool_size) {
+            errors.push(String::from("database.min_idle can't be above database.pool_size"));
+        }
+        if self.database.connection_timeout_secs == 0 {
+            errors.push(String::from("database.connection_timeout_secs must be above 0"));
+        }
+        if let Err(e) = self.budget_config() {
+            errors.push(e);
+        }
+        if let Err(e) = self.tls_config() {
+            errors.push(e);
+        }
+        errors
+    }
+
+    pub fn log_level(&self) -> LevelFilter {
+        LevelFilter::from_str(&self.log_level).unwrap_or(LevelFilter::Warn)
+    }
+
+    pub fn budget_config(&self) -> Result<BudgetConfig, String> {
+        BudgetConfig::from_config(&self.send_rate)
+    }
+
+    pub fn tls_config(&self) -> Result<Option<TlsConfig>, String> {
+        TlsConfig::from_config(&self.tls)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use super::*;
+
+    /// Loads `file` as the config file, with the variables of `vars` and the flags of `args`
+    fn load(file: &str, vars: &[(&str, &str)], args: &[&str]) -> Result<Config, Vec<String>> {
+        let path = env::temp_dir().join(format!("msearch-dispatcher-{}-{:x}.toml", std::process::id(), rand::random::<u64>()));
+        fs::write(&path, file).unwrap();
+        let path_arg = path.to_string_lossy().into_owned();
+        let cli = Cli::parse_from(["dispatcher", "--config", &path_arg].iter().chain(args));
+        let vars: HashMap<&str, &str> = vars.iter().copied().collect();
+        let config = Config::load_with_env(cli, &|name| vars.get(name).map(|v| v.to_string()));
+        fs::remove_file(&path).unwrap();
+        config
+    }
+
+    #[test]
+    fn sources_override_each_other() {
+        let file = "port = 7000\nbind_address = \"10.0.0.1\"\nstate_file = \"state.json\"\n\n[database]\nurl = \"postgres://file\"\npool_size = 4\n";
+        let vars = [("PORT", "8001"), ("BIND_ADDRESS", "10.0.0.2"), ("DATABASE_POOL_SIZE", "6"), ("SEND_RATE_BUDGET", "5000"),
+                    ("SEND_BLACKOUTS", "01:00-02:00, ,03:00-04:00")];
+        let config = load(file, &vars, &["--port", "9000", "--database-url", "postgres://cli"]).unwrap();
+        // File, then environment, then command line
+        assert_eq!(config.state_file, PathBuf::from("state.json"));
+        assert_eq!(config.bind_address, "10.0.0.2");
+        assert_eq!(config.database.pool_size, 6);
+        assert_eq!(config.port, 9000);
+        assert_eq!(config.database.url.as_deref(), Some("postgres://cli"));
+        assert_eq!(config.send_rate.budget, Some(5000));
+        assert_eq!(config.send_rate.blackouts, ["01:00-02:00", "03:00-04:00"]);
+        // Defaults of the settings missing everywhere
+        assert_eq!(config.coverage_file, PathBuf::from("./.coverage"));
+        assert_eq!(config.database.connection_timeout_secs, 30);
+    }
+
+    #[test]
+    fn reports_every_error() {
+        let file = "log_level = \"loud\"\n\n[database]\npool_size = 2\nmin_idle = 5\n";
+        let vars = [("PORT", "http"), ("CLIENT_JOB_EXPIRY_SECS", "0"), ("TARGET_MODE", "smart")];
+        let errors = load(file, &vars, &[]).unwrap_err();
+        for expected in ["Invalid PORT http", "Invalid TARGET_MODE smart", "client_job_expiry_secs must be above 0", "Invalid log_level loud",
+                         "database.url (DATABASE_URL) is required", "database.min_idle can't be above database.pool_size"] {
+            assert!(errors.iter().any(|e| e.starts_with(expected)), "{:?} should contain {}", errors, expected);
+        }
+        assert_eq!(errors.len(), 6);
+    }
+
+    #[test]
+    fn rejects_unknown_settings() {
+        let errors = load("prot = 8000\n", &[], &[]).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].contains("unknown field `prot`"), "{:?}", errors);
+    }
+}
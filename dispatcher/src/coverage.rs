@@ -0,0 +1,265 @@
+// Coverage of the current scan cycle. A cycle is one pass of the random walk over the whole IPv4
+// space (see `IpChunkIterator`), and an address is covered once a scout reported back on the job
+// it was part of. Legacy scouts don't report the job of their IPs, their jobs are covered as soon
+// as they are given out. Covered addresses are kept in a roaring bitmap, which stays small while the
+// cycle is sparse and tops out at 512 MiB, and saved to `./.coverage`.
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::net::Ipv4Addr;
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use roaring::RoaringBitmap;
+use serde::Serialize;
+use utoipa::ToSchema;
+use crate::ip_chunk_iterator::is_public;
+
+/// Number of coverage samples kept to compute the current rate, taken once a minute
+const MAX_SAMPLES: usize = 60;
+
+/// Number of public addresses in each /8, see [`is_public`]. Computed once, the non-public ranges
+/// are whole /24s except for the broadcast address, so only the /24s whose first and last
+/// addresses disagree are counted address by address.
+fn public_addresses() -> &'static [u64; 256] {
+    static COUNTS: OnceLock<[u64; 256]> = OnceLock::new();
+    COUNTS.get_or_init(|| {
+        let mut counts = [0; 256];
+        for block in 0..1u32 << 24 {
+            let (first, last) = (block << 8, (block << 8) | 0xFF);
+            let public = match (is_public(Ipv4Addr::from(first)), is_public(Ipv4Addr::from(last))) {
+                (true, true) => 256,
+                (false, false) => 0,
+                _ => (first..=last).filter(|ip| is_public(Ipv4Addr::from(*ip))).count() as u64
+            };
+            counts[(block >> 16) as usize] += public;
+        }
+        counts
+    })
+}
+
+struct CoverageState {
+    cycle: u32,
+    started_at: SystemTime,
+    covered: RoaringBitmap,
+    // Time and number of covered addresses
+    samples: VecDeque<(SystemTime, u64)>,
+    // Changed since last saved
+    dirty: bool,
+}
+
+/// Coverage of the current cycle
+pub struct Coverage {
+    state: Mutex<CoverageState>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct RangeProgress {
+    // First byte of the /8
+    pub block: u8,
+    pub covered: u64,
+    pub total: u64,
+    pub percent: f64,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Progress {
+    pub cycle: u32,
+    pub started_at: u64,
+    pub covered: u64,
+    pub total: u64,
+    pub remaining: u64,
+    pub percent: f64,
+    // Addresses covered per second over the last hour (or since the cycle started)
+    pub rate: f64,
+    // None until the rate is known
+    pub eta_secs: Option<u64>,
+    pub heatmap: Vec<RangeProgress>,
+}
+
+impl Coverage {
+    fn new_state(cycle: u32) -> CoverageState {
+        CoverageState {
+            cycle,
+            started_at: SystemTime::now(),
+            covered: RoaringBitmap::new(),
+            samples: VecDeque::new(),
+            dirty: true,
+        }
+    }
+
+    /// Loads the coverage saved at `path`, or starts the first cycle if there is none
+    pub fn load_or_new(path: &Path) -> io::Result<Self> {
+        let state = if path.exists() {
+            let mut reader = BufReader::new(File::open(path)?);
+            let mut header = [0u8; 12];
+            reader.read_exact(&mut header)?;
+            let cycle = u32::from_le_bytes(header[0..4].try_into().unwrap());
+            let started_at = u64::from_le_bytes(header[4..12].try_into().unwrap());
+            CoverageState {
+                cycle,
+                started_at: UNIX_EPOCH + Duration::from_secs(started_at),
+                covered: RoaringBitmap::deserialize_from(reader)?,
+                samples: VecDeque::new(),
+                dirty: false,
+            }
+        } else {
+            Coverage::new_state(0)
+        };
+        Ok(Coverage { state: Mutex::new(state) })
+    }
+
+    /// Saves the coverage to `path`, if it changed since it was last saved
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if !state.dirty {
+            return Ok(());
+        }
+        // Write to a temporary file first, the coverage isn't lost if the dispatcher is killed
+        // while saving
+        let tmp_path = path.with_extension("tmp");
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        let started_at = state.started_at.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+        writer.write_all(&state.cycle.to_le_bytes())?;
+        writer.write_all(&started_at.to_le_bytes())?;
+        state.covered.serialize_into(&mut writer)?;
+        writer.into_inner()?.sync_all()?;
+        std::fs::rename(tmp_path, path)?;
+        state.dirty = false;
+        Ok(())
+    }
+
+    pub fn cycle(&self) -> u32 {
+        self.state.lock().unwrap().cycle
+    }
+
+    /// Starts a new cycle, forgetting the covered addresses
+    pub fn start_cycle(&self) {
+        let mut state = self.state.lock().unwrap();
+        *state = Coverage::new_state(state.cycle + 1);
+    }
+
+    /// Marks the addresses of a completed job as covered, if the job is part of the current cycle
+    pub fn mark(&self, cycle: u32, ips: &[Ipv4Addr]) {
+        let mut state = self.state.lock().unwrap();
+        if state.cycle != cycle {
+            return;
+        }
+        state.covered.extend(ips.iter().map(|ip| u32::from(*ip)));
+        state.dirty = true;
+    }
+
+    /// Records the number of covered addresses, used to compute the current rate
+    pub fn sample(&self) {
+        let mut state = self.state.lock().unwrap();
+        let covered = state.covered.len();
+        state.samples.push_back((SystemTime::now(), covered));
+        if state.samples.len() > MAX_SAMPLES {
+            state.samples.pop_front();
+        }
+    }
+
+    pub fn progress(&self) -> Progress {
+        let state = self.state.lock().unwrap();
+        let covered = state.covered.len();
+        let total: u64 = public_addresses().iter().sum();
+        let remaining = total.saturating_sub(covered);
+
+        let now = SystemTime::now();
+        let (since, covered_since) = state.samples.front().copied().unwrap_or((state.started_at, 0));
+        let elapsed = now.duration_since(since).unwrap_or_default().as_secs_f64();
+        let rate = if elapsed > 0.0 { covered.saturating_sub(covered_since) as f64 / elapsed } else { 0.0 };
+
+        let heatmap = (0..=255u32)
+            .map(|block| {
+                let (start, end) = (block << 24, (block << 24) | 0xFFFFFF);
+                let covered = state.covered.range_cardinality(start..=end);
+                let total = public_addresses()[block as usize];
+                RangeProgress {
+                    block: block as u8,
+                    covered,
+                    total,
+                    percent: if total > 0 { covered as f64 * 100.0 / total as f64 } else { 100.0 },
+                }
+            })
+            .collect();
+
+        Progress {
+            cycle: state.cycle,
+            started_at: state.started_at.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs()),
+            covered,
+            total,
+            remaining,
+            percent: covered as f64 * 100.0 / total as f64,
+            rate,
+            eta_secs: (rate > 0.0).then(|| (remaining as f64 / rate) as u64),
+            heatmap,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Public addresses of a /8, counted address by address
+    fn count_public(block: u8) -> u64 {
+        let start = (block as u32) << 24;
+        (start..=start | 0xFFFFFF).filter(|ip| is_public(Ipv4Addr::from(*ip))).count() as u64
+    }
+
+    #[test]
+    fn counts_public_addresses_like_the_random_walk() {
+        let counts = public_addresses();
+        // /8s with non-public ranges smaller than a /16, down to the broadcast address
+        for block in [192, 198, 203, 255] {
+            assert_eq!(counts[block as usize], count_public(block), "public addresses of {}.0.0.0/8", block);
+        }
+        assert_eq!(counts[8], 1 << 24);
+        assert_eq!(counts[10], 0);
+        assert_eq!(counts[127], 0);
+        assert_eq!(counts[169], (1 << 24) - (1 << 16));
+        assert_eq!(counts[172], (1 << 24) - (1 << 20));
+        assert_eq!(counts[192], (1 << 24) - (1 << 16) - 256);
+        assert!(counts[224..240].iter().all(|count| *count == 0));
+        assert_eq!(counts[255], (1 << 24) - 1);
+    }
+
+    #[test]
+    fn marks_the_current_cycle_only() {
+        let coverage = Coverage { state: Mutex::new(Coverage::new_state(3)) };
+        let ips = [Ipv4Addr::new(8, 8, 8, 8), Ipv4Addr::new(8, 8, 4, 4), Ipv4Addr::new(1, 1, 1, 1)];
+        coverage.mark(3, &ips);
+        coverage.mark(2, &[Ipv4Addr::new(9, 9, 9, 9)]);
+
+        let progress = coverage.progress();
+        assert_eq!(progress.cycle, 3);
+        assert_eq!(progress.covered, 3);
+        assert_eq!(progress.remaining, progress.total - 3);
+        assert_eq!(progress.heatmap[8].covered, 2);
+        assert_eq!(progress.heatmap[1].covered, 1);
+        assert_eq!(progress.heatmap[9].covered, 0);
+
+        coverage.start_cycle();
+        assert_eq!(coverage.cycle(), 4);
+        assert_eq!(coverage.progress().covered, 0);
+    }
+
+    #[test]
+    fn saves_and_loads_the_cycle() {
+        let path = std::env::temp_dir().join(format!("msearch-coverage-{}", std::process::id()));
+        let coverage = Coverage { state: Mutex::new(Coverage::new_state(7)) };
+        coverage.mark(7, &[Ipv4Addr::new(8, 8, 8, 8), Ipv4Addr::new(203, 0, 114, 1)]);
+        coverage.save(&path).unwrap();
+
+        let loaded = Coverage::load_or_new(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let (saved, loaded) = (coverage.state.lock().unwrap(), loaded.state.lock().unwrap());
+        assert_eq!(loaded.cycle, 7);
+        assert_eq!(loaded.started_at, UNIX_EPOCH + Duration::from_secs(saved.started_at.duration_since(UNIX_EPOCH).unwrap().as_secs()));
+        assert_eq!(loaded.covered, saved.covered);
+        assert!(!loaded.dirty);
+    }
+}
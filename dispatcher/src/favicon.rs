@@ -0,0 +1,87 @@
+// Validation and decoding of server favicons. Status responses contain the favicon as a base64
+// encoded PNG (`data:image/png;base64,...`), which must be a 64x64 image.
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use custom_error::custom_error;
+use sha2::{Digest, Sha256};
+
+pub const FAVICON_SIZE: u32 = 64;
+/// Upper bound on the size of the PNG data, larger favicons are discarded
+pub const MAX_FAVICON_BYTES: usize = 32 * 1024;
+
+custom_error! {pub FaviconError
+    InvalidEncoding = "Favicon is not valid base64 data.",
+    TooLarge{size: usize} = "Favicon is too large ({size} bytes).",
+    InvalidImage = "Favicon is not a valid PNG image.",
+    InvalidSize{width: u32, height: u32} = "Favicon must be 64x64, got {width}x{height}."}
+
+/// Decodes a favicon from a status response into PNG data, making sure it is an image of the
+/// right dimensions and within the size cap.
+pub fn decode_favicon(favicon: &str) -> Result<Vec<u8>, FaviconError> {
+    let data = favicon.strip_prefix("data:image/png;base64,").unwrap_or(favicon);
+    let data: String = data.chars().filter(|c| !c.is_whitespace()).collect();
+    let png = BASE64.decode(data).map_err(|_| FaviconError::InvalidEncoding)?;
+
+    if png.len() > MAX_FAVICON_BYTES {
+        return Err(FaviconError::TooLarge { size: png.len() });
+    }
+
+    let decoder = png::Decoder::new(png.as_slice());
+    let reader = decoder.read_info().map_err(|_| FaviconError::InvalidImage)?;
+    let (width, height) = (reader.info().width, reader.info().height);
+    if width != FAVICON_SIZE || height != FAVICON_SIZE {
+        return Err(FaviconError::InvalidSize { width, height });
+    }
+
+    Ok(png)
+}
+
+/// Hex representation of the SHA-256 hash of the PNG data, used as the favicon's key
+pub fn favicon_hash(png: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(png))
+}
+
+/// Decodes PNG data into 64x64 RGBA pixels. Returns `None` if the image is invalid or not the
+/// right size.
+pub fn to_rgba(png: &[u8]) -> Option<Vec<u8>> {
+    let mut decoder = png::Decoder::new(png);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().ok()?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf).ok()?;
+
+    if info.width != FAVICON_SIZE || info.height != FAVICON_SIZE {
+        return None;
+    }
+
+    let pixels = &buf[..info.buffer_size()];
+    let rgba = match info.color_type {
+        png::ColorType::Rgba => pixels.to_vec(),
+        png::ColorType::Rgb => pixels.chunks(3).flat_map(|p| [p[0], p[1], p[2], 0xFF]).collect(),
+        png::ColorType::GrayscaleAlpha => pixels.chunks(2).flat_map(|p| [p[0], p[0], p[0], p[1]]).collect(),
+        png::ColorType::Grayscale => pixels.iter().flat_map(|&p| [p, p, p, 0xFF]).collect(),
+        png::ColorType::Indexed => return None  // Expanded to RGB(A) by the decoder transformations
+    };
+    Some(rgba)
+}
+
+/// Average hash of a favicon: the image is split into 8x8 blocks, and each bit is set if the
+/// block is brighter than the average of all blocks. Visually similar images have hashes with
+/// a small hamming distance, even when re-encoded or slightly edited.
+pub fn perceptual_hash(rgba: &[u8]) -> u64 {
+    const BLOCK_SIZE: usize = FAVICON_SIZE as usize / 8;
+
+    let mut blocks = [0u32; 64];
+    for (i, pixel) in rgba.chunks(4).enumerate() {
+        let (x, y) = (i % FAVICON_SIZE as usize, i / FAVICON_SIZE as usize);
+        // Luminance, with transparent pixels considered black
+        let luma = (pixel[0] as u32 * 299 + pixel[1] as u32 * 587 + pixel[2] as u32 * 114) / 1000;
+        blocks[(y / BLOCK_SIZE) * 8 + x / BLOCK_SIZE] += luma * pixel[3] as u32 / 255;
+    }
+
+    let mean = blocks.iter().sum::<u32>() / 64;
+    blocks.iter().enumerate()
+        .filter(|(_, &b)| b > mean)
+        .fold(0, |hash, (i, _)| hash | (1 << i))
+}
@@ -0,0 +1,331 @@
+// Scouts and clients registered with the dispatcher. Workers register at startup to get an id
+// (see `/workers/register`), which they send with their job requests so that results can be
+// traced back to them, and send a heartbeat every 30 seconds with their version, hostname,
+// adapter, current send rate and the errors they ran into. The fleet is kept in memory, and
+// flushed to the `worker` table every minute. When the dispatcher verifies client certificates
+// (see `crate::tls`), a worker is identified by its certificate: registering again with the same
+// certificate returns the same id, and only that certificate can act for the worker (see
+// `Fleet::identify`). Without certificates, a worker registering again with the same kind, name
+// and hostname from the same API key gets the id it had before. Workers that stopped sending
+// heartbeats are removed after `worker_retention_days`, their scans are kept.
+
+use std::cmp::Reverse;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use diesel::prelude::*;
+use protocol::Heartbeat;
+use serde::Serialize;
+use utoipa::ToSchema;
+use uuid::Uuid;
+use crate::DbConnection;
+use crate::models::{Worker, WorkerUpdate};
+use crate::schema::worker;
+
+/// Window of the throughput of a worker
+const THROUGHPUT_WINDOW: Duration = Duration::from_secs(10 * 60);
+/// Workers are offline after missing a few heartbeats
+const OFFLINE_AFTER: Duration = Duration::from_secs(2 * 60);
+
+struct FleetEntry {
+    worker: Worker,
+    // Completion time and items of the recent jobs
+    recent_jobs: VecDeque<(SystemTime, u64)>,
+    // Changed since last saved
+    dirty: bool,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct WorkerInfo {
+    pub id: Uuid,
+    pub kind: String,
+    pub name: Option<String>,
+    pub group: Option<String>,
+    pub hostname: Option<String>,
+    pub version: Option<String>,
+    pub adapter: Option<String>,
+    pub send_rate: Option<i64>,
+    pub registered_at: u64,
+    pub last_seen: u64,
+    pub online: bool,
+    pub jobs_completed: i64,
+    pub items_processed: i64,
+    // Items per second over the last 10 minutes
+    pub throughput: f64,
+    pub errors: i64,
+    pub cert_fingerprint: Option<String>,
+}
+
+/// Registered workers
+pub struct Fleet {
+    workers: Mutex<HashMap<Uuid, FleetEntry>>,
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+impl Fleet {
+    /// Loads the workers that registered before
+    pub fn load(conn: &mut DbConnection) -> QueryResult<Self> {
+        let workers = Worker::all(conn)?.into_iter()
+            .map(|worker| (worker.id, FleetEntry { worker, recent_jobs: VecDeque::new(), dirty: false }))
+            .collect();
+        Ok(Fleet { workers: Mutex::new(workers) })
+    }
+
+    /// Adds a worker that just registered
+    pub fn add(&self, worker: Worker) {
+        self.workers.lock().unwrap()
+            .insert(worker.id, FleetEntry { worker, recent_jobs: VecDeque::new(), dirty: false });
+    }
+
+    pub fn contains(&self, id: Uuid) -> bool {
+        self.workers.lock().unwrap().contains_key(&id)
+    }
+
+    /// Worker registered with a client certificate
+    pub fn find_by_certificate(&self, fingerprint: &str) -> Option<Uuid> {
+        self.workers.lock().unwrap().values()
+            .find(|entry| entry.worker.cert_fingerprint.as_deref() == Some(fingerprint))
+            .map(|entry| entry.worker.id)
+    }
+
+    /// Worker registered without a certificate with the same kind, name and hostname from the same
+    /// API key, i.e. the same worker after a restart. The most recently seen one if there are
+    /// several, workers without a hostname are never reused.
+    pub fn find_by_identity(&self, kind: &str, name: Option<&str>, hostname: Option<&str>, lineage: Option<i32>) -> Option<Uuid> {
+        hostname?;
+        self.workers.lock().unwrap().values()
+            .map(|entry| &entry.worker)
+            .filter(|w| w.cert_fingerprint.is_none() && w.kind == kind && w.name.as_deref() == name
+                && w.hostname.as_deref() == hostname && w.api_key_id == lineage)
+            .max_by_key(|w| w.last_seen)
+            .map(|w| w.id)
+    }
+
+    /// Checks that a request for worker `id` comes with its certificate. Workers registered
+    /// without a certificate can be used from any connection, as well as unknown workers.
+    pub fn certificate_matches(&self, id: Uuid, fingerprint: Option<&str>) -> bool {
+        match self.workers.lock().unwrap().get(&id) {
+            Some(entry) => entry.worker.cert_fingerprint.as_deref().is_none_or(|f| Some(f) == fingerprint),
+            None => true
+        }
+    }
+
+    /// Checks that a request for worker `id` comes with the API key that registered it, or a key
+    /// rotated from that key (see `crate::auth`). Workers registered before keys were tied to
+    /// them can be used with any key, as well as unknown workers.
+    pub fn key_matches(&self, id: Uuid, lineage: Option<i32>) -> bool {
+        match self.workers.lock().unwrap().get(&id) {
+            Some(entry) => entry.worker.api_key_id.is_none_or(|k| Some(k) == lineage),
+            None => true
+        }
+    }
+
+    /// Worker a request acts for, given the id it sent, the fingerprint of its client certificate
+    /// and the lineage of its API key. A worker connecting with a certificate is the one registered
+    /// with it, even if it didn't send its id. Fails if the worker registered with another
+    /// certificate or API key.
+    pub fn identify(&self, claimed: Option<Uuid>, fingerprint: Option<&str>, lineage: Option<i32>) -> Result<Option<Uuid>, &'static str> {
+        let certified = fingerprint.and_then(|f| self.find_by_certificate(f));
+        let id = match (claimed, certified) {
+            (Some(claimed), Some(certified)) if claimed != certified => return Err("Worker registered with another certificate"),
+            (Some(id), _) | (None, Some(id)) => id,
+            (None, None) => return Ok(None)
+        };
+        if !self.certificate_matches(id, fingerprint) {
+            return Err("Worker registered with another certificate");
+        }
+        if !self.key_matches(id, lineage) {
+            return Err("Worker registered with another API key");
+        }
+        Ok(Some(id))
+    }
+
+    /// Updates a worker from its heartbeat. Returns false if the worker isn't registered.
+    pub fn heartbeat(&self, id: Uuid, heartbeat: Heartbeat) -> bool {
+        let mut workers = self.workers.lock().unwrap();
+        let entry = match workers.get_mut(&id) {
+            Some(entry) => entry,
+            None => return false
+        };
+        let worker = &mut entry.worker;
+        worker.hostname = heartbeat.hostname.or(worker.hostname.take());
+        worker.version = heartbeat.version.or(worker.version.take());
+        worker.adapter = heartbeat.adapter.or(worker.adapter.take());
+        worker.send_rate = heartbeat.send_rate.map(|r| r as i64).or(worker.send_rate);
+        worker.errors += heartbeat.errors as i64;
+        worker.last_seen = SystemTime::now();
+        entry.dirty = true;
+        true
+    }
+
+    /// Records a job completed by `id`, with the number of IPs probed or servers scanned. Jobs of
+    /// unknown workers are ignored.
+    pub fn record_job(&self, id: Uuid, items: u64) {
+        let mut workers = self.workers.lock().unwrap();
+        if let Some(entry) = workers.get_mut(&id) {
+            let now = SystemTime::now();
+            entry.worker.jobs_completed += 1;
+            entry.worker.items_processed += items as i64;
+            entry.worker.last_seen = now;
+            entry.recent_jobs.push_back((now, items));
+            while entry.recent_jobs.front()
+                .is_some_and(|(time, _)| now.duration_since(*time).unwrap_or_default() > THROUGHPUT_WINDOW) {
+                entry.recent_jobs.pop_front();
+            }
+            entry.dirty = true;
+        }
+    }
+
+    /// Saves the workers that changed since they were last saved, returns how many were saved
+    pub fn save(&self, conn: &mut DbConnection) -> QueryResult<usize> {
+        let updates: Vec<(Uuid, WorkerUpdate)> = {
+            let mut workers = self.workers.lock().unwrap();
+            workers.values_mut()
+                .filter(|entry| entry.dirty)
+                .map(|entry| {
+                    entry.dirty = false;
+                    let worker = &entry.worker;
+                    (worker.id, WorkerUpdate {
+                        hostname: worker.hostname.clone(),
+                        version: worker.version.clone(),
+                        adapter: worker.adapter.clone(),
+                        send_rate: worker.send_rate,
+                        last_seen: worker.last_seen,
+                        jobs_completed: worker.jobs_completed,
+                        items_processed: worker.items_processed,
+                        errors: worker.errors,
+                    })
+                })
+                .collect()
+        };
+
+        conn.build_transaction().run(|conn| {
+            for (id, update) in updates.iter() {
+                diesel::update(worker::table.find(*id)).set(update).execute(conn)?;
+            }
+            Ok(updates.len())
+        })
+    }
+
+    /// Removes the workers that weren't seen for `retention`, returns how many were removed. Their
+    /// scans are kept, without their worker.
+    pub fn prune(&self, retention: Duration, conn: &mut DbConnection) -> QueryResult<usize> {
+        let cutoff = match SystemTime::now().checked_sub(retention) {
+            Some(cutoff) => cutoff,
+            None => return Ok(0)
+        };
+        let stale: Vec<Uuid> = self.workers.lock().unwrap().values()
+            .filter(|entry| entry.worker.last_seen < cutoff)
+            .map(|entry| entry.worker.id)
+            .collect();
+        if stale.is_empty() {
+            return Ok(0);
+        }
+        let removed = diesel::delete(worker::table.filter(worker::id.eq_any(&stale))).execute(conn)?;
+        let mut workers = self.workers.lock().unwrap();
+        for id in stale {
+            workers.remove(&id);
+        }
+        Ok(removed)
+    }
+
+    /// Registered workers, most recently seen first
+    pub fn list(&self) -> Vec<WorkerInfo> {
+        let now = SystemTime::now();
+        let workers = self.workers.lock().unwrap();
+        let mut list: Vec<WorkerInfo> = workers.values()
+            .map(|entry| {
+                let worker = &entry.worker;
+                // Workers that registered recently haven't been around for the whole window
+                let window = now.duration_since(worker.registered_at).unwrap_or_default().min(THROUGHPUT_WINDOW);
+                let recent_items: u64 = entry.recent_jobs.iter()
+                    .filter(|(time, _)| now.duration_since(*time).unwrap_or_default() <= THROUGHPUT_WINDOW)
+                    .map(|(_, items)| items)
+                    .sum();
+                WorkerInfo {
+                    id: worker.id,
+                    kind: worker.kind.clone(),
+                    name: worker.name.clone(),
+                    group: worker.worker_group.clone(),
+                    hostname: worker.hostname.clone(),
+                    version: worker.version.clone(),
+                    adapter: worker.adapter.clone(),
+                    send_rate: worker.send_rate,
+                    registered_at: unix_secs(worker.registered_at),
+                    last_seen: unix_secs(worker.last_seen),
+                    online: now.duration_since(worker.last_seen).map_or(true, |d| d <= OFFLINE_AFTER),
+                    jobs_completed: worker.jobs_completed,
+                    items_processed: worker.items_processed,
+                    throughput: if window.as_secs() > 0 { recent_items as f64 / window.as_secs_f64() } else { 0.0 },
+                    errors: worker.errors,
+                    cert_fingerprint: worker.cert_fingerprint.clone(),
+                }
+            })
+            .collect();
+        list.sort_by_key(|w| Reverse(w.last_seen));
+        list
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn worker(certificate: Option<&str>, lineage: Option<i32>) -> Worker {
+        Worker {
+            id: Uuid::new_v4(),
+            kind: String::from("scout"),
+            name: None,
+            worker_group: None,
+            hostname: Some(String::from("host")),
+            version: None,
+            adapter: None,
+            send_rate: None,
+            registered_at: SystemTime::now(),
+            last_seen: SystemTime::now(),
+            jobs_completed: 0,
+            items_processed: 0,
+            errors: 0,
+            cert_fingerprint: certificate.map(String::from),
+            api_key_id: lineage,
+        }
+    }
+
+    /// Fleet of the workers, and their ids
+    fn fleet<const N: usize>(workers: [Worker; N]) -> (Fleet, [Uuid; N]) {
+        let ids = workers.each_ref().map(|w| w.id);
+        let fleet = Fleet { workers: Mutex::new(HashMap::new()) };
+        workers.into_iter().for_each(|w| fleet.add(w));
+        (fleet, ids)
+    }
+
+    #[test]
+    fn identifies_workers_by_certificate() {
+        let (fleet, [certified, other]) = fleet([worker(Some("aa"), None), worker(Some("bb"), None)]);
+        assert_eq!(fleet.identify(Some(certified), Some("aa"), None), Ok(Some(certified)));
+        // Without the id, from the certificate
+        assert_eq!(fleet.identify(None, Some("aa"), None), Ok(Some(certified)));
+        assert_eq!(fleet.identify(None, Some("cc"), None), Ok(None));
+        assert_eq!(fleet.identify(None, None, None), Ok(None));
+
+        // Claiming another worker than the one of the certificate
+        assert!(fleet.identify(Some(other), Some("aa"), None).is_err());
+        assert!(fleet.identify(Some(certified), Some("cc"), None).is_err());
+        assert!(fleet.identify(Some(certified), None, None).is_err());
+    }
+
+    #[test]
+    fn identifies_workers_by_api_key() {
+        let (fleet, [keyed, legacy]) = fleet([worker(None, Some(1)), worker(None, None)]);
+        assert_eq!(fleet.identify(Some(keyed), None, Some(1)), Ok(Some(keyed)));
+        assert!(fleet.identify(Some(keyed), None, Some(2)).is_err());
+        assert!(fleet.identify(Some(keyed), None, None).is_err());
+        // Registered before keys were tied to workers, or unknown
+        assert_eq!(fleet.identify(Some(legacy), None, Some(2)), Ok(Some(legacy)));
+        let unknown = Uuid::new_v4();
+        assert_eq!(fleet.identify(Some(unknown), None, Some(2)), Ok(Some(unknown)));
+    }
+}
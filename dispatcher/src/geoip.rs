@@ -0,0 +1,185 @@
+// Location and network of servers, from MaxMind databases (GeoLite2 or GeoIP2) stored on disk.
+// The paths are read from the `geoip` section of the config, all optional:
+// - city_db (GEOIP_CITY_DB): City database, or country_db (GEOIP_COUNTRY_DB) for a Country
+//   database (country only)
+// - asn_db (GEOIP_ASN_DB): ASN database
+// The files are checked periodically and reloaded when they change (i.e. after running
+// `geoipupdate`), after which every server is looked up again. Lookups never use the network.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::RwLock;
+use std::time::SystemTime;
+use diesel::prelude::*;
+use diesel::upsert::excluded;
+use ipnet::IpNet;
+use maxminddb::{MaxMindDBError, Reader, geoip2};
+use crate::DbConnection;
+use crate::config::GeoIpConfig;
+use crate::models::NewServerGeo;
+use crate::schema::{scan, server_geo};
+
+struct Database {
+    path: PathBuf,
+    // Modification time of the loaded file, None if it couldn't be loaded
+    modified: Option<SystemTime>,
+    reader: Option<Reader<Vec<u8>>>,
+}
+
+impl Database {
+    fn new(path: PathBuf) -> Self {
+        Database { path, modified: None, reader: None }
+    }
+
+    /// Loads the file if it changed since it was last loaded. Returns true if it was reloaded.
+    fn reload_if_changed(&mut self) -> Result<bool, MaxMindDBError> {
+        let modified = fs::metadata(&self.path).and_then(|m| m.modified())?;
+        if self.modified == Some(modified) {
+            return Ok(false);
+        }
+        // The modification time is saved even if the file is invalid, to avoid retrying it
+        // until it changes again. The previous version is kept in the meantime.
+        self.modified = Some(modified);
+        self.reader = Some(Reader::open_readfile(&self.path)?);
+        Ok(true)
+    }
+}
+
+#[derive(Default)]
+struct Databases {
+    // City or Country database
+    location: Option<Database>,
+    asn: Option<Database>,
+}
+
+/// MaxMind databases used to enrich server IPs
+#[derive(Default)]
+pub struct GeoIp {
+    databases: RwLock<Databases>,
+}
+
+/// Location and network of an IP, the fields are None when not found in the databases
+#[derive(Debug, Default)]
+pub struct GeoInfo {
+    pub country_code: Option<String>,
+    pub country: Option<String>,
+    pub city: Option<String>,
+    pub asn: Option<i32>,
+    pub as_org: Option<String>,
+}
+
+impl GeoIp {
+    /// Uses the database paths of the config, see the module documentation. The databases are
+    /// loaded by the first call to [`GeoIp::reload_if_changed`].
+    pub fn new(config: &GeoIpConfig) -> Self {
+        let location = config.city_db.as_ref().or(config.country_db.as_ref());
+        GeoIp {
+            databases: RwLock::new(Databases {
+                location: location.map(|p| Database::new(p.clone())),
+                asn: config.asn_db.as_ref().map(|p| Database::new(p.clone())),
+            })
+        }
+    }
+
+    /// Returns true if at least one database path is set
+    pub fn is_configured(&self) -> bool {
+        let databases = self.databases.read().unwrap();
+        databases.location.is_some() || databases.asn.is_some()
+    }
+
+    /// Reloads the databases whose file changed. Returns true if any was reloaded, errors are
+    /// printed and the previous version of the database is kept.
+    pub fn reload_if_changed(&self) -> bool {
+        let mut guard = self.databases.write().unwrap();
+        let databases = &mut *guard;
+        let mut reloaded = false;
+        for database in [&mut databases.location, &mut databases.asn].into_iter().flatten() {
+            match database.reload_if_changed() {
+                Ok(true) => {
+                    println!("Loaded GeoIP database {}", database.path.display());
+                    reloaded = true;
+                }
+                Ok(false) => {}
+                Err(e) => println!("Error loading GeoIP database {}: {}", database.path.display(), e)
+            }
+        }
+        reloaded
+    }
+
+    pub fn lookup(&self, ip: IpAddr) -> GeoInfo {
+        let databases = self.databases.read().unwrap();
+        let mut info = GeoInfo::default();
+
+        let location = databases.location.as_ref().and_then(|d| d.reader.as_ref());
+        // Country records are a subset of City records, so both databases can be read as City
+        if let Some(city) = location.and_then(|r| r.lookup::<geoip2::City>(ip).ok()) {
+            if let Some(country) = city.country {
+                info.country_code = country.iso_code.map(String::from);
+                info.country = english_name(country.names);
+            }
+            info.city = city.city.and_then(|c| english_name(c.names));
+        }
+
+        let asn = databases.asn.as_ref().and_then(|d| d.reader.as_ref());
+        if let Some(asn) = asn.and_then(|r| r.lookup::<geoip2::Asn>(ip).ok()) {
+            info.asn = asn.autonomous_system_number.map(|n| n as i32);
+            info.as_org = asn.autonomous_system_organization.map(String::from);
+        }
+        info
+    }
+}
+
+fn english_name(names: Option<BTreeMap<&str, &str>>) -> Option<String> {
+    names.and_then(|n| n.get("en").map(|name| String::from(*name)))
+}
+
+/// Looks up the servers missing from `server_geo`, or all of them if `all` is set (after the
+/// databases changed). Returns the number of servers looked up.
+pub fn run_enrichment(geoip: &GeoIp, all: bool, conn: &mut DbConnection) -> QueryResult<usize> {
+    let ips = if all {
+        scan::table.select(scan::ip).distinct().load::<IpNet>(conn)?
+    } else {
+        scan::table
+            .left_join(server_geo::table.on(server_geo::ip.eq(scan::ip)))
+            .filter(server_geo::ip.is_null())
+            .select(scan::ip)
+            .distinct()
+            .load::<IpNet>(conn)?
+    };
+
+    let now = SystemTime::now();
+    let rows: Vec<NewServerGeo> = ips.iter()
+        .map(|ip| {
+            let info = geoip.lookup(ip.addr());
+            NewServerGeo {
+                ip: *ip,
+                country_code: info.country_code,
+                country: info.country,
+                city: info.city,
+                asn: info.asn,
+                as_org: info.as_org,
+                updated_at: now
+            }
+        })
+        .collect();
+
+    // Stay under the bind parameter limit of postgres
+    for chunk in rows.chunks(5000) {
+        diesel::insert_into(server_geo::table)
+            .values(chunk)
+            .on_conflict(server_geo::ip)
+            .do_update()
+            .set((
+                server_geo::country_code.eq(excluded(server_geo::country_code)),
+                server_geo::country.eq(excluded(server_geo::country)),
+                server_geo::city.eq(excluded(server_geo::city)),
+                server_geo::asn.eq(excluded(server_geo::asn)),
+                server_geo::as_org.eq(excluded(server_geo::as_org)),
+                server_geo::updated_at.eq(excluded(server_geo::updated_at))
+            ))
+            .execute(conn)?;
+    }
+    Ok(rows.len())
+}
@@ -0,0 +1,181 @@
+// Player co-occurrence graph. Servers are linked when the same players are seen on them, and
+// players are linked when they are seen together in the same player sample. The graph is rebuilt
+// periodically from `player_scan`, and can be exported as GraphML or GEXF to be explored with
+// tools like Gephi.
+
+use std::fmt::Write;
+use diesel::prelude::*;
+use diesel::sql_query;
+use ipnet::IpNet;
+use crate::DbConnection;
+use crate::schema::{player, player_link, server_link};
+
+/// Players seen on more servers than this are ignored when linking servers. These are mostly
+/// names shared by unrelated offline mode players (or bots), and would link everything together.
+const MAX_PLAYER_SERVERS: i64 = 50;
+
+/// Recomputes the server and player links. Returns the number of (undirected) server and
+/// player edges.
+pub fn rebuild_graph(conn: &mut DbConnection) -> QueryResult<(usize, usize)> {
+    conn.transaction(|conn| {
+        diesel::delete(server_link::table).execute(conn)?;
+        diesel::delete(player_link::table).execute(conn)?;
+
+        let server_links = sql_query(format!(
+            "INSERT INTO server_link (ip, linked_ip, shared_players)
+            WITH seen AS (
+                SELECT DISTINCT scan.ip, player_scan.player_id
+                FROM player_scan JOIN scan ON scan.scan_id = player_scan.scan_id
+            ), common AS (
+                SELECT player_id FROM seen GROUP BY player_id HAVING count(*) BETWEEN 2 AND {}
+            )
+            SELECT a.ip, b.ip, count(*)
+            FROM seen a
+            JOIN seen b ON a.player_id = b.player_id AND a.ip <> b.ip
+            WHERE a.player_id IN (SELECT player_id FROM common)
+            GROUP BY a.ip, b.ip", MAX_PLAYER_SERVERS))
+            .execute(conn)?;
+
+        let player_links = sql_query(
+            "INSERT INTO player_link (player_id, linked_player_id, shared_scans)
+            SELECT a.player_id, b.player_id, count(*)
+            FROM player_scan a
+            JOIN player_scan b ON a.scan_id = b.scan_id AND a.player_id <> b.player_id
+            GROUP BY a.player_id, b.player_id")
+            .execute(conn)?;
+
+        // Edges are stored in both directions
+        Ok((server_links / 2, player_links / 2))
+    })
+}
+
+/// Kind of nodes of an exported graph
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum GraphKind {
+    Servers,
+    Players,
+}
+
+/// File format of an exported graph
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum GraphFormat {
+    GraphMl,
+    Gexf,
+}
+
+struct Graph {
+    // Node id and label
+    nodes: Vec<(String, String)>,
+    // Source and target node ids, weight
+    edges: Vec<(String, String, i32)>,
+}
+
+/// Exports the edges with a weight of at least `min_weight`, and the nodes they connect
+pub fn export_graph(kind: GraphKind, format: GraphFormat, min_weight: i32, conn: &mut DbConnection) -> QueryResult<String> {
+    let graph = match kind {
+        GraphKind::Servers => load_server_graph(min_weight, conn)?,
+        GraphKind::Players => load_player_graph(min_weight, conn)?
+    };
+    Ok(match format {
+        GraphFormat::GraphMl => to_graphml(&graph),
+        GraphFormat::Gexf => to_gexf(&graph)
+    })
+}
+
+fn load_server_graph(min_weight: i32, conn: &mut DbConnection) -> QueryResult<Graph> {
+    let edges = server_link::table
+        .filter(server_link::ip.lt(server_link::linked_ip))
+        .filter(server_link::shared_players.ge(min_weight))
+        .order((server_link::ip, server_link::linked_ip))
+        .load::<(IpNet, IpNet, i32)>(conn)?;
+
+    let mut nodes: Vec<IpNet> = edges.iter().flat_map(|(a, b, _)| [*a, *b]).collect();
+    nodes.sort();
+    nodes.dedup();
+
+    Ok(Graph {
+        nodes: nodes.iter().map(|ip| (ip.addr().to_string(), ip.addr().to_string())).collect(),
+        edges: edges.iter().map(|(a, b, w)| (a.addr().to_string(), b.addr().to_string(), *w)).collect(),
+    })
+}
+
+fn load_player_graph(min_weight: i32, conn: &mut DbConnection) -> QueryResult<Graph> {
+    let edges = player_link::table
+        .filter(player_link::player_id.lt(player_link::linked_player_id))
+        .filter(player_link::shared_scans.ge(min_weight))
+        .order((player_link::player_id, player_link::linked_player_id))
+        .load::<(i32, i32, i32)>(conn)?;
+
+    let mut ids: Vec<i32> = edges.iter().flat_map(|(a, b, _)| [*a, *b]).collect();
+    ids.sort_unstable();
+    ids.dedup();
+    let mut nodes = Vec::with_capacity(ids.len());
+    // Stay under the bind parameter limit of postgres
+    for chunk in ids.chunks(10000) {
+        let players = player::table
+            .filter(player::player_id.eq_any(chunk))
+            .select((player::player_id, player::username))
+            .order(player::player_id)
+            .load::<(i32, String)>(conn)?;
+        nodes.extend(players.into_iter().map(|(id, name)| (id.to_string(), name)));
+    }
+
+    Ok(Graph {
+        nodes,
+        edges: edges.iter().map(|(a, b, w)| (a.to_string(), b.to_string(), *w)).collect(),
+    })
+}
+
+fn to_graphml(graph: &Graph) -> String {
+    let mut out = String::from(concat!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+        "<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n",
+        "  <key id=\"label\" for=\"node\" attr.name=\"label\" attr.type=\"string\"/>\n",
+        "  <key id=\"weight\" for=\"edge\" attr.name=\"weight\" attr.type=\"int\"/>\n",
+        "  <graph id=\"G\" edgedefault=\"undirected\">\n"));
+    for (id, label) in &graph.nodes {
+        let _ = writeln!(out, "    <node id=\"{}\"><data key=\"label\">{}</data></node>", escape(id), escape(label));
+    }
+    for (source, target, weight) in &graph.edges {
+        let _ = writeln!(out, "    <edge source=\"{}\" target=\"{}\"><data key=\"weight\">{}</data></edge>",
+                         escape(source), escape(target), weight);
+    }
+    out.push_str("  </graph>\n</graphml>\n");
+    out
+}
+
+fn to_gexf(graph: &Graph) -> String {
+    let mut out = String::from(concat!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+        "<gexf xmlns=\"http://gexf.net/1.3\" version=\"1.3\">\n",
+        "  <graph mode=\"static\" defaultedgetype=\"undirected\">\n",
+        "    <nodes>\n"));
+    for (id, label) in &graph.nodes {
+        let _ = writeln!(out, "      <node id=\"{}\" label=\"{}\"/>", escape(id), escape(label));
+    }
+    out.push_str("    </nodes>\n    <edges>\n");
+    for (i, (source, target, weight)) in graph.edges.iter().enumerate() {
+        let _ = writeln!(out, "      <edge id=\"{}\" source=\"{}\" target=\"{}\" weight=\"{}\"/>",
+                         i, escape(source), escape(target), weight);
+    }
+    out.push_str("    </edges>\n  </graph>\n</gexf>\n");
+    out
+}
+
+/// Escapes text for use in XML attributes and elements
+fn escape(text: &str) -> String {
+    text.chars()
+        // Control characters aren't allowed in XML 1.0
+        .filter(|c| !c.is_control() || matches!(c, '\t' | '\n' | '\r'))
+        .fold(String::with_capacity(text.len()), |mut out, c| {
+            match c {
+                '<' => out.push_str("&lt;"),
+                '>' => out.push_str("&gt;"),
+                '&' => out.push_str("&amp;"),
+                '"' => out.push_str("&quot;"),
+                '\'' => out.push_str("&apos;"),
+                c => out.push(c)
+            }
+            out
+        })
+}
@@ -0,0 +1,148 @@
+// History of servers over time, built from their timestamped scans. Long histories are
+// downsampled by the database into hourly or daily buckets, so that charting a server over months
+// doesn't require sending every scan.
+
+use std::time::SystemTime;
+use diesel::prelude::*;
+use diesel::sql_query;
+use diesel::sql_types::{BigInt, Bool, Double, Inet, Integer, Nullable, Text, Timestamp};
+use ipnet::IpNet;
+use crate::DbConnection;
+
+/// Max number of points returned for a time series, the oldest points are left out
+pub const MAX_POINTS: i64 = 5000;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Resolution {
+    Raw,
+    Hourly,
+    Daily,
+}
+
+impl Resolution {
+    pub fn from_name(name: &str) -> Option<Resolution> {
+        match name {
+            "raw" => Some(Resolution::Raw),
+            "hourly" => Some(Resolution::Hourly),
+            "daily" => Some(Resolution::Daily),
+            _ => None
+        }
+    }
+
+    /// Unit of `date_trunc` used to group the scans
+    fn bucket(&self) -> Option<&'static str> {
+        match self {
+            Resolution::Raw => None,
+            Resolution::Hourly => Some("hour"),
+            Resolution::Daily => Some("day")
+        }
+    }
+}
+
+/// Player counts over a bucket of time (a single scan at the raw resolution)
+#[derive(QueryableByName)]
+pub struct CountPoint {
+    #[diesel(sql_type = Timestamp)]
+    pub time: SystemTime,
+    #[diesel(sql_type = BigInt)]
+    pub scans: i64,
+    #[diesel(sql_type = Nullable<Double>)]
+    pub online_avg: Option<f64>,
+    #[diesel(sql_type = Nullable<Integer>)]
+    pub online_min: Option<i32>,
+    #[diesel(sql_type = Nullable<Integer>)]
+    pub online_max: Option<i32>,
+    #[diesel(sql_type = Nullable<Integer>)]
+    pub max_count: Option<i32>,
+}
+
+/// Scan where the version, MOTD or favicon of a server differs from its previous scan
+#[derive(QueryableByName)]
+pub struct Change {
+    #[diesel(sql_type = Inet)]
+    pub ip: IpNet,
+    #[diesel(sql_type = Timestamp)]
+    pub time: SystemTime,
+    #[diesel(sql_type = Bool)]
+    pub version_changed: bool,
+    #[diesel(sql_type = Nullable<Text>)]
+    pub version: Option<String>,
+    #[diesel(sql_type = Nullable<Text>)]
+    pub previous_version: Option<String>,
+    #[diesel(sql_type = Bool)]
+    pub motd_changed: bool,
+    #[diesel(sql_type = Nullable<Text>)]
+    pub description: Option<String>,
+    #[diesel(sql_type = Nullable<Text>)]
+    pub previous_description: Option<String>,
+    #[diesel(sql_type = Bool)]
+    pub favicon_changed: bool,
+    #[diesel(sql_type = Nullable<Text>)]
+    pub favicon: Option<String>,
+    #[diesel(sql_type = Nullable<Text>)]
+    pub previous_favicon: Option<String>,
+}
+
+/// Returns the player counts of the server between `from` and `to`, oldest first
+pub fn load_counts(ip: IpNet, resolution: Resolution, from: SystemTime, to: SystemTime, conn: &mut DbConnection) -> QueryResult<Vec<CountPoint>> {
+    let query = match resolution.bucket() {
+        None => String::from(
+            "SELECT scanned_at AS time, 1::BIGINT AS scans, online_count::DOUBLE PRECISION AS online_avg,
+                online_count AS online_min, online_count AS online_max, max_count
+            FROM scan
+            WHERE ip = $1 AND scanned_at >= $2 AND scanned_at < $3
+            ORDER BY scanned_at DESC LIMIT $4"),
+        Some(bucket) => format!(
+            "SELECT date_trunc('{bucket}', scanned_at) AS time, count(*) AS scans,
+                avg(online_count)::DOUBLE PRECISION AS online_avg, min(online_count) AS online_min,
+                max(online_count) AS online_max, max(max_count) AS max_count
+            FROM scan
+            WHERE ip = $1 AND scanned_at >= $2 AND scanned_at < $3
+            GROUP BY date_trunc('{bucket}', scanned_at)
+            ORDER BY time DESC LIMIT $4", bucket = bucket)
+    };
+
+    let mut points = sql_query(query)
+        .bind::<Inet, _>(ip)
+        .bind::<Timestamp, _>(from)
+        .bind::<Timestamp, _>(to)
+        .bind::<BigInt, _>(MAX_POINTS)
+        .load::<CountPoint>(conn)?;
+    points.reverse();
+    Ok(points)
+}
+
+/// Returns the changes of version, MOTD or favicon between `from` and `to`, of a single server
+/// (oldest first) or of all servers (most recent first)
+pub fn load_changes(ip: Option<IpNet>, from: SystemTime, to: SystemTime, limit: i64, offset: i64, conn: &mut DbConnection) -> QueryResult<Vec<Change>> {
+    // Scans are compared to the previous scan of the same server, which can be older than `from`
+    let query = format!(
+        "SELECT ip, time, version_changed, version, previous_version, motd_changed, description,
+            previous_description, favicon_changed, favicon, previous_favicon
+        FROM (
+            SELECT s.ip, s.scanned_at AS time, s.version, s.description, f.hash AS favicon,
+                lag(s.scan_id) OVER w IS NOT NULL AS has_previous,
+                lag(s.version) OVER w AS previous_version,
+                lag(s.description) OVER w AS previous_description,
+                lag(f.hash) OVER w AS previous_favicon,
+                s.version IS DISTINCT FROM lag(s.version) OVER w AS version_changed,
+                s.description IS DISTINCT FROM lag(s.description) OVER w AS motd_changed,
+                s.favicon_id IS DISTINCT FROM lag(s.favicon_id) OVER w AS favicon_changed
+            FROM scan s
+            LEFT JOIN favicon f ON f.favicon_id = s.favicon_id
+            WHERE s.scanned_at < $2 AND ($3::INET IS NULL OR s.ip = $3)
+                AND s.ip IN (SELECT ip FROM scan WHERE scanned_at >= $1 AND scanned_at < $2)
+            WINDOW w AS (PARTITION BY s.ip ORDER BY s.scan_id)
+        ) scans
+        WHERE time >= $1 AND has_previous AND (version_changed OR motd_changed OR favicon_changed)
+        ORDER BY time {} LIMIT $4 OFFSET $5",
+        if ip.is_some() { "ASC" } else { "DESC" });
+
+    sql_query(query)
+        .bind::<Timestamp, _>(from)
+        .bind::<Timestamp, _>(to)
+        .bind::<Nullable<Inet>, _>(ip)
+        .bind::<BigInt, _>(limit)
+        .bind::<BigInt, _>(offset)
+        .load::<Change>(conn)
+}
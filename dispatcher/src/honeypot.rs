@@ -0,0 +1,259 @@
+// Detection of honeypots and scanner traps. These answer on the Minecraft port without running a
+// real server, pollute the results and waste client time. IPs and netblocks are scored with the
+// following heuristics, and the ones above the threshold are flagged:
+// - Share of a /24 answering on the Minecraft port: real servers are sparse, a netblock where
+//   most IPs answer is more likely answering SYN-ACK to everything
+// - SYN-ACK on a random port: servers that answered are probed again by scouts on a random
+//   high port, which nothing should be listening on
+// - Unstable statuses: version, protocol or favicon changing between most consecutive scans of
+//   the same IP (player counts are left out, some servers set the max count from the online count)
+
+use std::collections::{HashMap, HashSet};
+use std::net::Ipv4Addr;
+use std::sync::RwLock;
+use std::time::SystemTime;
+use diesel::prelude::*;
+use diesel::sql_query;
+use diesel::sql_types::{Inet, Int4, Nullable, Text};
+use diesel::upsert::excluded;
+use ipnet::{IpNet, Ipv4Net};
+use crate::DbConnection;
+use crate::models::NewHoneypot;
+use crate::schema::{honeypot, netblock_stats, port_probe};
+
+/// Networks scoring at least this much are flagged
+pub const FLAG_THRESHOLD: f32 = 0.5;
+/// Netblocks need this many probed IPs before their response share is considered
+const MIN_PROBED: i32 = 32;
+/// Number of recent scans of an IP compared to detect unstable statuses
+const STABILITY_WINDOW: i64 = 6;
+const MIN_STABILITY_SCANS: usize = 3;
+
+/// Networks currently flagged as honeypots, kept in memory to filter the client queue
+#[derive(Default)]
+pub struct HoneypotFilter {
+    networks: RwLock<HashSet<Ipv4Net>>,
+}
+
+impl HoneypotFilter {
+    /// Returns true if the IP, or the /24 it is part of, is flagged
+    pub fn contains(&self, ip: Ipv4Addr) -> bool {
+        let networks = self.networks.read().unwrap();
+        networks.contains(&Ipv4Net::from(ip)) || networks.contains(&netblock(ip))
+    }
+
+    /// Replaces the flagged networks with the ones saved in the database
+    pub fn reload(&self, conn: &mut DbConnection) -> QueryResult<()> {
+        let networks = honeypot::table
+            .select(honeypot::network)
+            .load::<IpNet>(conn)?
+            .into_iter()
+            .filter_map(|n| match n {
+                IpNet::V4(n) => Some(n),
+                IpNet::V6(_) => None
+            })
+            .collect();
+        *self.networks.write().unwrap() = networks;
+        Ok(())
+    }
+}
+
+/// /24 containing the IP
+pub fn netblock(ip: Ipv4Addr) -> Ipv4Net {
+    Ipv4Net::new(ip, 24).unwrap().trunc()
+}
+
+/// Adds the IPs to the probed (or responded) counts of their /24
+pub fn record_netblock_stats(ips: &[Ipv4Addr], responded: bool, conn: &mut DbConnection) -> QueryResult<()> {
+    let mut counts: HashMap<Ipv4Net, i32> = HashMap::new();
+    ips.iter().for_each(|ip| *counts.entry(netblock(*ip)).or_default() += 1);
+
+    let rows: Vec<(IpNet, i32)> = counts.into_iter().map(|(n, c)| (IpNet::V4(n), c)).collect();
+    // Stay under the bind parameter limit of postgres
+    for chunk in rows.chunks(10000) {
+        if responded {
+            let values: Vec<_> = chunk.iter()
+                .map(|(n, c)| (netblock_stats::network.eq(n), netblock_stats::responded.eq(c)))
+                .collect();
+            diesel::insert_into(netblock_stats::table)
+                .values(&values)
+                .on_conflict(netblock_stats::network)
+                .do_update()
+                .set(netblock_stats::responded.eq(netblock_stats::responded + excluded(netblock_stats::responded)))
+                .execute(conn)?;
+        } else {
+            let values: Vec<_> = chunk.iter()
+                .map(|(n, c)| (netblock_stats::network.eq(n), netblock_stats::probed.eq(c)))
+                .collect();
+            diesel::insert_into(netblock_stats::table)
+                .values(&values)
+                .on_conflict(netblock_stats::network)
+                .do_update()
+                .set(netblock_stats::probed.eq(netblock_stats::probed + excluded(netblock_stats::probed)))
+                .execute(conn)?;
+        }
+    }
+    Ok(())
+}
+
+/// Saves the result of random-port probes, `hits` being the probed IPs that answered
+pub fn record_port_probes(ips: &[Ipv4Addr], hits: &[Ipv4Addr], conn: &mut DbConnection) -> QueryResult<()> {
+    let hits: HashSet<&Ipv4Addr> = hits.iter().collect();
+    let values: Vec<_> = ips.iter()
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .map(|ip| (
+            port_probe::ip.eq(IpNet::V4(Ipv4Net::from(*ip))),
+            port_probe::probes.eq(1),
+            port_probe::hits.eq(hits.contains(ip) as i32)
+        ))
+        .collect();
+
+    for chunk in values.chunks(10000) {
+        diesel::insert_into(port_probe::table)
+            .values(chunk)
+            .on_conflict(port_probe::ip)
+            .do_update()
+            .set((
+                port_probe::probes.eq(port_probe::probes + excluded(port_probe::probes)),
+                port_probe::hits.eq(port_probe::hits + excluded(port_probe::hits))
+            ))
+            .execute(conn)?;
+    }
+    Ok(())
+}
+
+#[derive(QueryableByName)]
+struct RecentScan {
+    #[diesel(sql_type = Inet)]
+    ip: IpNet,
+    #[diesel(sql_type = Nullable<Text>)]
+    version: Option<String>,
+    #[diesel(sql_type = Nullable<Int4>)]
+    protocol: Option<i32>,
+    #[diesel(sql_type = Nullable<Int4>)]
+    favicon_id: Option<i32>,
+}
+
+/// Scores of the networks flagged so far, with the reasons they were flagged for
+#[derive(Default)]
+struct Scores {
+    networks: HashMap<IpNet, (f32, Vec<String>)>,
+}
+
+impl Scores {
+    /// Flags the network if the score reaches [`FLAG_THRESHOLD`], a network keeps its highest score
+    fn add(&mut self, network: IpNet, score: f32, reason: String) {
+        if score >= FLAG_THRESHOLD {
+            let entry = self.networks.entry(network).or_insert((0.0, Vec::new()));
+            entry.0 = entry.0.max(score);
+            entry.1.push(reason);
+        }
+    }
+}
+
+/// Number of status changes between consecutive scans of an IP, and number of compared pairs.
+/// None with too few scans to tell.
+fn status_changes(scans: &[RecentScan]) -> Option<(usize, usize)> {
+    if scans.len() < MIN_STABILITY_SCANS {
+        return None;
+    }
+    let changes = scans.windows(2)
+        .filter(|w| w[0].version != w[1].version || w[0].protocol != w[1].protocol
+            || w[0].favicon_id != w[1].favicon_id)
+        .count();
+    Some((changes, scans.len() - 1))
+}
+
+/// Recomputes the honeypot scores and saves the flagged networks. Returns the number of
+/// flagged networks.
+pub fn run_detection(conn: &mut DbConnection) -> QueryResult<usize> {
+    let mut scores = Scores::default();
+
+    let netblocks = netblock_stats::table
+        .filter(netblock_stats::probed.ge(MIN_PROBED))
+        .load::<(IpNet, i32, i32)>(conn)?;
+    for (network, probed, responded) in netblocks {
+        let share = (responded as f32 / probed as f32).min(1.0);
+        scores.add(network, share, format!("{} of {} probed IPs responded", responded, probed));
+    }
+
+    let probes = port_probe::table
+        .filter(port_probe::hits.gt(0))
+        .load::<(IpNet, i32, i32)>(conn)?;
+    for (ip, probes, hits) in probes {
+        scores.add(ip, hits as f32 / probes as f32, format!("answered on a random port ({}/{} probes)", hits, probes));
+    }
+
+    let recent_scans = sql_query(format!(
+        "SELECT ip, version, protocol, favicon_id FROM (
+            SELECT *, row_number() OVER (PARTITION BY ip ORDER BY scan_id DESC) AS n FROM scan
+        ) recent WHERE n <= {} ORDER BY ip, scan_id", STABILITY_WINDOW))
+        .load::<RecentScan>(conn)?;
+    for scans in recent_scans.chunk_by(|a, b| a.ip == b.ip) {
+        if let Some((changes, pairs)) = status_changes(scans) {
+            scores.add(scans[0].ip, changes as f32 / pairs as f32, format!("status changed in {} of the last {} scans", changes, pairs));
+        }
+    }
+
+    let now = SystemTime::now();
+    let rows: Vec<NewHoneypot> = scores.networks.into_iter()
+        .map(|(network, (score, reasons))| NewHoneypot {
+            network,
+            score,
+            reasons: reasons.into_iter().map(Some).collect(),
+            updated_at: now
+        })
+        .collect();
+
+    conn.transaction(|conn| {
+        diesel::delete(honeypot::table).execute(conn)?;
+        for chunk in rows.chunks(10000) {
+            diesel::insert_into(honeypot::table).values(chunk).execute(conn)?;
+        }
+        Ok(rows.len())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scan(version: &str, favicon_id: Option<i32>) -> RecentScan {
+        RecentScan { ip: "10.0.0.1/32".parse().unwrap(), version: Some(String::from(version)), protocol: Some(763), favicon_id }
+    }
+
+    #[test]
+    fn flags_scores_above_the_threshold() {
+        let (network, ip): (IpNet, IpNet) = ("10.0.0.0/24".parse().unwrap(), "10.0.1.1/32".parse().unwrap());
+        let mut scores = Scores::default();
+        scores.add(network, FLAG_THRESHOLD - 0.01, String::from("below"));
+        assert!(scores.networks.is_empty());
+        scores.add(network, FLAG_THRESHOLD, String::from("at"));
+        scores.add(network, 0.9, String::from("above"));
+        scores.add(network, 0.6, String::from("lower"));
+        scores.add(ip, 1.0, String::from("other"));
+        assert_eq!(scores.networks[&network], (0.9, vec![String::from("at"), String::from("above"), String::from("lower")]));
+        assert_eq!(scores.networks.len(), 2);
+    }
+
+    #[test]
+    fn counts_status_changes() {
+        assert_eq!(status_changes(&[scan("1.20.1", None), scan("1.20.1", None)]), None);
+        assert_eq!(status_changes(&[scan("1.20.1", None), scan("1.20.1", None), scan("1.20.1", None)]), Some((0, 2)));
+        let flapping = [scan("1.20.1", Some(1)), scan("1.8.8", Some(1)), scan("1.8.8", Some(2)), scan("1.8.8", Some(2))];
+        let (changes, pairs) = status_changes(&flapping).unwrap();
+        assert_eq!((changes, pairs), (2, 3));
+        assert!(changes as f32 / pairs as f32 >= FLAG_THRESHOLD);
+    }
+
+    #[test]
+    fn filters_ips_and_their_netblock() {
+        let filter = HoneypotFilter::default();
+        filter.networks.write().unwrap().extend([Ipv4Net::from(Ipv4Addr::new(8, 8, 8, 8)), netblock(Ipv4Addr::new(9, 9, 9, 9))]);
+        assert!(filter.contains(Ipv4Addr::new(8, 8, 8, 8)));
+        assert!(!filter.contains(Ipv4Addr::new(8, 8, 8, 9)));
+        assert!(filter.contains(Ipv4Addr::new(9, 9, 9, 200)));
+        assert!(!filter.contains(Ipv4Addr::new(9, 9, 10, 1)));
+    }
+}
@@ -55,9 +55,7 @@ impl Iterator for IpChunkIterator {
             let ip = Ipv4Addr::from(self.mapped_x());
             self.x += 1;
 
-            // Check that IP is accessible publicly. Switch to .is_global when stable
-            if !ip.is_loopback() && !ip.is_private() && !ip.is_link_local() && !ip.is_multicast()
-                && !ip.is_broadcast() && !ip.is_documentation() {
+            if is_public(ip) {
                 result = Some(ip);
                 break;
             }
@@ -75,3 +73,9 @@ impl Iterator for IpChunkIterator {
 impl ExactSizeIterator for IpChunkIterator {
 
 }
+
+/// Checks that the IP is accessible publicly. Switch to .is_global when stable
+pub fn is_public(ip: Ipv4Addr) -> bool {
+    !ip.is_loopback() && !ip.is_private() && !ip.is_link_local() && !ip.is_multicast()
+        && !ip.is_broadcast() && !ip.is_documentation()
+}
@@ -2,30 +2,62 @@ extern crate env_logger;
 extern crate r2d2;
 extern crate custom_error;
 
+mod auth;
+mod config;
 mod models;
 mod schema;
 mod routes;
 mod ip_chunk_iterator;
+mod clustering;
+mod coverage;
+mod fleet;
+mod software;
+mod mods;
+mod samples;
+mod honeypot;
+mod player_base;
+mod graph;
+mod history;
+mod send_budget;
+mod stats;
+mod targeting;
+mod tls;
+mod geoip;
+mod worker_config;
+mod favicon;
+mod preview;
+mod openapi;
 
-use std::collections::VecDeque;
-use std::{env, fs, io};
+use std::collections::{HashMap, VecDeque};
+use std::{fs, io, mem, process};
 use std::net::Ipv4Addr;
-use std::path::Path;
 use std::sync::Mutex;
+use std::sync::atomic::AtomicU32;
 use std::time::{Duration, SystemTime};
 use actix_web::{App, HttpServer};
-use actix_web::middleware::Logger;
+use actix_web::dev::Service;
+use actix_web::middleware::{DefaultHeaders, Logger};
 use actix_web::web::Data;
+use clap::Parser;
 use diesel::pg::PgConnection;
 use diesel::r2d2::ConnectionManager;
 use dotenvy::dotenv;
+use protocol::{PROTOCOL_VERSION, VERSION_HEADER};
 use r2d2::PooledConnection;
 use serde::{Deserialize, Serialize};
 use tokio::{task, time};
+use crate::auth::ApiKeys;
+use crate::config::{Cli, Config};
+use crate::coverage::Coverage;
+use crate::fleet::Fleet;
+use crate::geoip::GeoIp;
+use crate::honeypot::HoneypotFilter;
 use crate::ip_chunk_iterator::IpChunkIterator;
-use crate::routes::client_routes::{ClientJob, get_client_scope};
-use crate::routes::info_routes::get_info_scope;
-use crate::routes::scout_routes::get_scout_scope;
+use crate::routes::client_routes::OutstandingJobs;
+use crate::routes::scout_routes::ScoutLease;
+use crate::send_budget::SendBudget;
+use crate::targeting::{TargetLanes, Targeting};
+use crate::worker_config::WorkerConfig;
 
 pub type DbPool = r2d2::Pool<ConnectionManager<PgConnection>>;
 pub type DbConnection = PooledConnection<ConnectionManager<PgConnection>>;
@@ -34,7 +66,20 @@ pub type DbConnection = PooledConnection<ConnectionManager<PgConnection>>;
 pub struct ServerState {
     ip_range: Mutex<IpChunkIterator>,
     valid_ips: Mutex<VecDeque<Ipv4Addr>>,
-    outstanding_client_jobs: Mutex<VecDeque<ClientJob>>
+    outstanding_client_jobs: Mutex<OutstandingJobs>,
+    // Servers that answered, waiting to be probed on a random port by a scout
+    #[serde(default)]
+    probe_queue: Mutex<VecDeque<Ipv4Addr>>,
+    // Priority lanes of the adaptive target mode, see `crate::targeting`
+    #[serde(default)]
+    target_lanes: Mutex<TargetLanes>,
+    // Scout jobs waiting for their results
+    #[serde(default)]
+    outstanding_scout_jobs: Mutex<HashMap<u32, ScoutLease>>,
+    // Id of the next scout job, kept across restarts so that scouts reporting older jobs don't
+    // cover the IPs of newer ones
+    #[serde(default)]
+    next_scout_job: AtomicU32
 }
 
 #[actix_web::main]
@@ -42,86 +87,484 @@ async fn main() -> io::Result<()> {
     // Load vars from .env
     dotenv().ok();
 
+    // Load the config from its file, the environment and the command line
+    let config = match Config::load(Cli::parse()) {
+        Ok(config) => config,
+        Err(errors) => {
+            eprintln!("Invalid configuration:");
+            errors.iter().for_each(|e| eprintln!("  - {}", e));
+            process::exit(1);
+        }
+    };
+
     // Generate server state from saved state, or generate new
     println!("Trying to load previously saved server state");
-    let server_state: Data<ServerState> = if Path::new("./.dispatcher").exists() {
+    let server_state: Data<ServerState> = if config.state_file.exists() {
         println!("Found file, loading it");
-        let data = fs::read_to_string("./.dispatcher").expect("Unable to read file");
+        let data = fs::read_to_string(&config.state_file).expect("Unable to read file");
         Data::new(serde_json::from_str(&data).expect("Invalid file contents"))
     } else {
         println!("No saved state file found.");
         Data::new(ServerState {
             ip_range: Mutex::new(IpChunkIterator::new()),
             valid_ips: Mutex::new(VecDeque::new()),
-            outstanding_client_jobs: Mutex::new(VecDeque::new())
+            outstanding_client_jobs: Mutex::new(OutstandingJobs::default()),
+            probe_queue: Mutex::new(VecDeque::new()),
+            target_lanes: Mutex::new(TargetLanes::default()),
+            outstanding_scout_jobs: Mutex::new(HashMap::new()),
+            next_scout_job: AtomicU32::new(0)
         })
     };
 
     // Connect to database
-    let url = env::var("DATABASE_URL").expect("DATABASE_URL variable not defined!");
+    let url = config.database.url.clone().expect("Database URL missing from validated config");
     let manager = ConnectionManager::<PgConnection>::new(url);
     let pool = r2d2::Pool::builder()
+        .max_size(config.database.pool_size)
+        .min_idle(config.database.min_idle)
+        .connection_timeout(Duration::from_secs(config.database.connection_timeout_secs))
         .build(manager).expect("Failed to create pool.");
 
     // Start recurring job
     {
         let server_state = server_state.clone();
+        let expiry = Duration::from_secs(config.client_job_expiry_secs);
+        let cleanup_interval = Duration::from_secs(config.cleanup_interval_secs);
         task::spawn(async move {
-            let mut interval = time::interval(Duration::from_secs(60));
+            let mut interval = time::interval(cleanup_interval);
 
             loop {
                 interval.tick().await;
                 println!("Cleaning up outstanding client jobs.");
-                let mut outstanding = server_state.outstanding_client_jobs.lock().unwrap();
+                let expired = server_state.outstanding_client_jobs.lock().unwrap().remove_expired(expiry);
                 let mut valid_ips = server_state.valid_ips.lock().unwrap();
+                for job in expired {
+                    // Task expired, add back to valid_ips
+                    println!("Removing job: {} - {}", job.id, job.ip);
+                    valid_ips.push_front(job.ip);
+                }
+                println!("Finished cleaning outstanding client jobs.")
+            }
+        });
+    }
+
+    // Load the API keys, and start the job saving when they were last used and picking up
+    // expired keys
+    let api_keys = {
+        let mut conn = pool.get().expect("Could not obtain database connection.");
+        if let Some(key) = auth::ensure_admin_key(&mut conn).expect("Unable to create admin API key") {
+            println!("No admin API key found, created one. Store it now, it won't be shown again:\n{}", key);
+        }
+        Data::new(ApiKeys::load(&mut conn).expect("Unable to load API keys"))
+    };
+    {
+        let pool = pool.clone();
+        let api_keys = api_keys.clone();
+        task::spawn(async move {
+            let mut interval = time::interval(Duration::from_secs(60));
+
+            loop {
+                interval.tick().await;
+                let pool = pool.clone();
+                let api_keys = api_keys.clone();
+                let result = task::spawn_blocking(move || {
+                    let mut conn = pool.get().expect("Could not obtain database connection.");
+                    api_keys.save_last_used(&mut conn)?;
+                    api_keys.reload(&mut conn)
+                }).await;
+
+                match result {
+                    Ok(Ok(())) => {}
+                    Ok(Err(e)) => println!("Error refreshing API keys: {}", e),
+                    Err(e) => println!("API key refresh job panicked: {}", e)
+                }
+            }
+        });
+    }
+
+    // Load the registered workers, and start the job saving their heartbeats and counters and
+    // removing the workers offline for too long
+    let fleet = {
+        let mut conn = pool.get().expect("Could not obtain database connection.");
+        Data::new(Fleet::load(&mut conn).expect("Unable to load registered workers"))
+    };
+    {
+        let pool = pool.clone();
+        let fleet = fleet.clone();
+        let retention_days = config.worker_retention_days;
+        let retention = Some(retention_days)
+            .filter(|days| *days > 0)
+            .map(|days| Duration::from_secs(days.saturating_mul(24 * 60 * 60)));
+        task::spawn(async move {
+            let mut interval = time::interval(Duration::from_secs(60));
+
+            loop {
+                interval.tick().await;
+                let pool = pool.clone();
+                let fleet = fleet.clone();
+                let result = task::spawn_blocking(move || {
+                    let mut conn = pool.get().expect("Could not obtain database connection.");
+                    fleet.save(&mut conn)?;
+                    match retention {
+                        Some(retention) => fleet.prune(retention, &mut conn),
+                        None => Ok(0)
+                    }
+                }).await;
+
+                match result {
+                    Ok(Ok(0)) => {}
+                    Ok(Ok(removed)) => println!("Removed {} workers offline for more than {} days", removed, retention_days),
+                    Ok(Err(e)) => println!("Error saving workers: {}", e),
+                    Err(e) => println!("Worker save job panicked: {}", e)
+                }
+            }
+        });
+    }
+
+    // Global send-rate budget of the scouts
+    let send_budget = Data::new(SendBudget::new(config.budget_config().expect("Invalid send-rate budget")));
+    if let Some(budget) = send_budget.config.budget {
+        println!("Splitting a budget of {} packets per second between scouts.", budget);
+    }
 
-                outstanding.retain(|job| {
-                    match SystemTime::now().duration_since(job.creation_time) {
-                        Ok(d) => {
-                            if d > Duration::from_secs(60) {
-                                // Task is older than 1 minute, remove from outstanding, add back to valid_ips
-                                println!("Removing job: {} - {}", job.id, job.ip);
-                                let ip = job.ip;
-                                valid_ips.push_front(ip);
-                                return false;  // Remove from list
-                            }
-                            true  // Not too old yet, keep in list
-                        },
-
-                        Err(_) => true  // Error means creation_time more recent than system time, ignore error and keep job
+    // Start worker config job, reloading the settings pushed to the workers when their file changes
+    let worker_config = Data::new(WorkerConfig::load(config.worker_config.clone()).expect("Invalid worker config"));
+    if worker_config.is_configured() {
+        let worker_config = worker_config.clone();
+        task::spawn(async move {
+            let mut interval = time::interval(Duration::from_secs(60));
+
+            loop {
+                interval.tick().await;
+                match worker_config.reload_if_changed() {
+                    Ok(true) => println!("Reloaded worker config."),
+                    Ok(false) => {}
+                    Err(e) => println!("Error reloading worker config, keeping the previous one: {}", e)
+                }
+            }
+        });
+    }
+
+    // Load the coverage of the current scan cycle
+    let coverage = Data::new(Coverage::load_or_new(&config.coverage_file).expect("Unable to read coverage file"));
+
+    // Start coverage job, expiring scout jobs that never reported back and saving the coverage
+    {
+        let server_state = server_state.clone();
+        let coverage = coverage.clone();
+        let coverage_file = config.coverage_file.clone();
+        task::spawn(async move {
+            let mut interval = time::interval(Duration::from_secs(60));
+            let mut ticks: u64 = 0;
+
+            loop {
+                interval.tick().await;
+                let mut expired = Vec::new();
+                server_state.outstanding_scout_jobs.lock().unwrap().retain(|id, lease| {
+                    match SystemTime::now().duration_since(lease.creation_time) {
+                        Ok(d) if d > Duration::from_secs(60 * 60) => {
+                            println!("Removing expired scout job: {}", id);
+                            expired.push((lease.cycle, mem::take(&mut lease.ips)));
+                            false
+                        }
+                        _ => true
                     }
                 });
-                println!("Finished cleaning outstanding client jobs.")
+                {
+                    // Their IPs are scanned again, unless a new cycle started since
+                    let cycle = coverage.cycle();
+                    let mut lanes = server_state.target_lanes.lock().unwrap();
+                    for (_, ips) in expired.iter().filter(|(c, _)| *c == cycle) {
+                        lanes.retry(ips);
+                    }
+                }
+                coverage.sample();
+
+                // Saving is expensive late in a cycle, only do it every 10 minutes
+                ticks += 1;
+                if ticks.is_multiple_of(10) {
+                    let coverage = coverage.clone();
+                    let coverage_file = coverage_file.clone();
+                    match task::spawn_blocking(move || coverage.save(&coverage_file)).await {
+                        Ok(Ok(())) => {}
+                        Ok(Err(e)) => println!("Error saving coverage: {}", e),
+                        Err(e) => println!("Coverage save panicked: {}", e)
+                    }
+                }
+            }
+        });
+    }
+
+    // Classify scans saved before software identification was added
+    {
+        let pool = pool.clone();
+        task::spawn_blocking(move || {
+            let mut conn = pool.get().expect("Could not obtain database connection.");
+            match software::classify_unclassified(&mut conn) {
+                Ok(0) => {}
+                Ok(count) => println!("Identified the server software of {} older scans.", count),
+                Err(e) => println!("Error identifying the server software of older scans: {}", e)
             }
         });
     }
 
+    // Start honeypot detection job, the filter is empty until its first run
+    let honeypots = Data::new(HoneypotFilter::default());
+    {
+        let pool = pool.clone();
+        let honeypots = honeypots.clone();
+        task::spawn(async move {
+            let mut interval = time::interval(Duration::from_secs(15 * 60));
+
+            loop {
+                interval.tick().await;
+                println!("Looking for honeypots.");
+                let pool = pool.clone();
+                let honeypots = honeypots.clone();
+                let result = task::spawn_blocking(move || {
+                    let mut conn = pool.get().expect("Could not obtain database connection.");
+                    let count = honeypot::run_detection(&mut conn)?;
+                    honeypots.reload(&mut conn)?;
+                    Ok::<usize, diesel::result::Error>(count)
+                }).await;
+
+                match result {
+                    Ok(Ok(count)) => println!("Finished looking for honeypots, flagged {} networks.", count),
+                    Ok(Err(e)) => println!("Error looking for honeypots: {}", e),
+                    Err(e) => println!("Honeypot detection job panicked: {}", e)
+                }
+            }
+        });
+    }
+
+    // Start player base estimation job
+    {
+        let pool = pool.clone();
+        task::spawn(async move {
+            let mut interval = time::interval(Duration::from_secs(60 * 60));
+
+            loop {
+                interval.tick().await;
+                println!("Estimating server player bases.");
+                let pool = pool.clone();
+                let result = task::spawn_blocking(move || {
+                    let mut conn = pool.get().expect("Could not obtain database connection.");
+                    player_base::run_estimation(&mut conn)
+                }).await;
+
+                match result {
+                    Ok(Ok(count)) => println!("Finished estimating the player base of {} servers.", count),
+                    Ok(Err(e)) => println!("Error estimating player bases: {}", e),
+                    Err(e) => println!("Player base estimation job panicked: {}", e)
+                }
+            }
+        });
+    }
+
+    // Start co-occurrence graph job
+    {
+        let pool = pool.clone();
+        task::spawn(async move {
+            let mut interval = time::interval(Duration::from_secs(60 * 60));
+
+            loop {
+                interval.tick().await;
+                println!("Building player co-occurrence graph.");
+                let pool = pool.clone();
+                let result = task::spawn_blocking(move || {
+                    let mut conn = pool.get().expect("Could not obtain database connection.");
+                    graph::rebuild_graph(&mut conn)
+                }).await;
+
+                match result {
+                    Ok(Ok((servers, players))) => println!("Finished building co-occurrence graph, {} server links and {} player links.", servers, players),
+                    Ok(Err(e)) => println!("Error building co-occurrence graph: {}", e),
+                    Err(e) => println!("Co-occurrence graph job panicked: {}", e)
+                }
+            }
+        });
+    }
+
+    // Start network clustering job
+    {
+        let pool = pool.clone();
+        task::spawn(async move {
+            let mut interval = time::interval(Duration::from_secs(60 * 60));
+
+            loop {
+                interval.tick().await;
+                println!("Clustering servers into networks.");
+                let pool = pool.clone();
+                let result = task::spawn_blocking(move || {
+                    let mut conn = pool.get().expect("Could not obtain database connection.");
+                    clustering::run_clustering(&mut conn)
+                }).await;
+
+                match result {
+                    Ok(Ok(count)) => println!("Finished clustering servers, found {} networks.", count),
+                    Ok(Err(e)) => println!("Error clustering servers: {}", e),
+                    Err(e) => println!("Clustering job panicked: {}", e)
+                }
+            }
+        });
+    }
+
+    // Start GeoIP enrichment job, which also reloads the databases when their files change
+    let geoip = Data::new(GeoIp::new(&config.geoip));
+    if geoip.is_configured() {
+        let pool = pool.clone();
+        let geoip = geoip.clone();
+        task::spawn(async move {
+            let mut interval = time::interval(Duration::from_secs(60));
+
+            loop {
+                interval.tick().await;
+                let pool = pool.clone();
+                let geoip = geoip.clone();
+                let result = task::spawn_blocking(move || {
+                    // Every server is looked up again when the databases change
+                    let reloaded = geoip.reload_if_changed();
+                    let mut conn = pool.get().expect("Could not obtain database connection.");
+                    geoip::run_enrichment(&geoip, reloaded, &mut conn)
+                }).await;
+
+                match result {
+                    Ok(Ok(0)) => {}
+                    Ok(Ok(count)) => println!("Looked up the location of {} servers.", count),
+                    Ok(Err(e)) => println!("Error looking up server locations: {}", e),
+                    Err(e) => println!("GeoIP enrichment job panicked: {}", e)
+                }
+            }
+        });
+    } else {
+        println!("No GeoIP database configured, server locations won't be looked up.");
+    }
+
+    // Start block scoring job, used to prioritize productive blocks in adaptive target mode
+    let targeting = Data::new(Targeting::new(config.target_mode));
+    {
+        let pool = pool.clone();
+        let geoip = geoip.clone();
+        let targeting = targeting.clone();
+        task::spawn(async move {
+            let mut interval = time::interval(Duration::from_secs(15 * 60));
+
+            loop {
+                interval.tick().await;
+                println!("Scoring netblocks.");
+                let pool = pool.clone();
+                let geoip = geoip.clone();
+                let targeting = targeting.clone();
+                let result = task::spawn_blocking(move || {
+                    let mut conn = pool.get().expect("Could not obtain database connection.");
+                    let scores = targeting::compute_scores(&geoip, &mut conn)?;
+                    targeting.update(&scores);
+                    Ok::<usize, diesel::result::Error>(scores.len())
+                }).await;
+
+                match result {
+                    Ok(Ok(count)) => println!("Finished scoring netblocks, {} blocks above average.", count),
+                    Ok(Err(e)) => println!("Error scoring netblocks: {}", e),
+                    Err(e) => println!("Netblock scoring job panicked: {}", e)
+                }
+            }
+        });
+    }
+
+    // Start statistics refresh job
+    {
+        let pool = pool.clone();
+        task::spawn(async move {
+            let mut interval = time::interval(Duration::from_secs(10 * 60));
+
+            loop {
+                interval.tick().await;
+                println!("Refreshing statistics.");
+                let pool = pool.clone();
+                let result = task::spawn_blocking(move || {
+                    let mut conn = pool.get().expect("Could not obtain database connection.");
+                    stats::refresh_views(&mut conn)
+                }).await;
+
+                match result {
+                    Ok(Ok(())) => println!("Finished refreshing statistics."),
+                    Ok(Err(e)) => println!("Error refreshing statistics: {}", e),
+                    Err(e) => println!("Statistics refresh job panicked: {}", e)
+                }
+            }
+        });
+    }
+
+    // TLS settings, the server uses plain HTTP without them
+    let tls_config = config.tls_config().expect("Invalid TLS config");
+
     // Start web server
-    env_logger::init();
+    env_logger::Builder::new().filter_level(config.log_level()).init();
     let state_copy = server_state.clone();
-    HttpServer::new(move || {
+    let coverage_copy = coverage.clone();
+    let fleet_copy = fleet.clone();
+    let pool_copy = pool.clone();
+    let server = HttpServer::new(move || {
         App::new()
+            // Registered before the logger, so that rejected requests are logged
+            .wrap_fn(|req, srv| {
+                // Rejections are turned into responses here, so that the outer middlewares see them
+                let call = match auth::authorize(&req).and_then(|()| routes::check_protocol_version(&req)) {
+                    Ok(()) => Ok(srv.call(req)),
+                    Err(e) => Err(req.error_response(e))
+                };
+                async move {
+                    match call {
+                        Ok(call) => call.await,
+                        Err(rejection) => Ok(rejection)
+                    }
+                }
+            })
             .wrap(Logger::default())
+            // Lets workers detect a dispatcher speaking another protocol version, even when
+            // their request is rejected
+            .wrap(DefaultHeaders::new().add((VERSION_HEADER, PROTOCOL_VERSION.to_string())))
             .app_data(state_copy.clone())
             .app_data(Data::new(pool.clone()))
-            .service(get_client_scope())
-            .service(get_scout_scope())
-            .service(get_info_scope())
-    }).bind(("0.0.0.0", 8000))?.run().await.expect("HttpServer panicked!");
+            .app_data(honeypots.clone())
+            .app_data(targeting.clone())
+            .app_data(coverage_copy.clone())
+            .app_data(send_budget.clone())
+            .app_data(worker_config.clone())
+            .app_data(fleet_copy.clone())
+            .app_data(api_keys.clone())
+            .configure(routes::configure_app)
+    }).on_connect(tls::on_connect);
+    let server = match tls_config {
+        Some(tls_config) => {
+            let server_config = tls_config.server_config().expect("Unable to load TLS certificates");
+            if tls_config.verifies_clients() {
+                println!("Serving HTTPS, verifying client certificates.");
+            } else {
+                println!("Serving HTTPS.");
+            }
+            server.bind_rustls((config.bind_address.as_str(), config.port), server_config)?
+        }
+        None => server.bind((config.bind_address.as_str(), config.port))?
+    };
+    println!("Listening on {}:{}", config.bind_address, config.port);
+    server.run().await.expect("HttpServer panicked!");
 
     // Save to server state to disk
     println!("Saving current state to disk.");
     // Start by adding outstanding jobs back to valid_ips pool
     {
-        let mut outstanding = server_state.outstanding_client_jobs.lock().unwrap();
+        let outstanding = server_state.outstanding_client_jobs.lock().unwrap().take_all();
         let mut valid_ips = server_state.valid_ips.lock().unwrap();
         outstanding.iter().for_each(|x| valid_ips.push_front(x.ip));
-        outstanding.clear();
     }
+    coverage.save(&config.coverage_file).expect("Unable to save coverage to disk.");
+    fleet.save(&mut pool_copy.get().expect("Could not obtain database connection."))
+        .expect("Unable to save workers.");
     // Serialize to json, save to disk
     let json_val = serde_json::to_string(&server_state.into_inner())
         .expect("Error serializing server state");
-    fs::write("./.dispatcher", json_val).expect("Unable to save state to disk.");
+    fs::write(&config.state_file, json_val).expect("Unable to save state to disk.");
     println!("Success! Shutting down...");
     Ok(())
 }
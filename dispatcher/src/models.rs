@@ -1,11 +1,20 @@
 use std::error::Error;
+use std::time::SystemTime;
 use custom_error::custom_error;
+use diesel::dsl::{count_star, sql};
+use diesel::expression::SqlLiteral;
 use diesel::prelude::*;
+use diesel::sql_query;
+use diesel::sql_types::{Bool, Nullable, Text};
 use ipnet::IpNet;
 use serde_json::Value;
 use uuid::Uuid;
 use crate::DbConnection;
-use crate::schema::{scan, player, player_scan};
+use crate::favicon::{favicon_hash, perceptual_hash, to_rgba};
+use crate::mods::ModInfo;
+use crate::schema::{api_key, cluster, favicon, honeypot, mod_, mod_scan, scan, player, player_base, player_name_history, player_scan, server_cluster, server_geo, worker};
+
+sql_function!(fn lower(x: diesel::sql_types::Text) -> diesel::sql_types::Text);
 
 // ERRORS
 custom_error! {pub DBError
@@ -24,7 +33,21 @@ pub struct Scan {
     pub online_count: Option<i32>,
     pub max_count: Option<i32>,
     pub description: Option<String>,
-    pub favicon: Option<String>
+    pub favicon_id: Option<i32>,
+    pub protocol: Option<i32>,
+    // Identified server software (see [`crate::software`]), None if not classified yet
+    pub software: Option<String>,
+    pub is_proxy: Option<bool>,
+    pub is_modded: Option<bool>,
+    pub game_version_min: Option<String>,
+    pub game_version_max: Option<String>,
+    // Decorative entries of the player sample, see [`crate::samples`]
+    pub sample_text: Vec<Option<String>>,
+    pub player_count_spoofed: bool,
+    // None for scans saved before scan times were recorded
+    pub scanned_at: Option<SystemTime>,
+    // Client that produced the scan, None if it didn't register
+    pub worker_id: Option<Uuid>
 }
 
 #[derive(Insertable)]
@@ -35,15 +58,222 @@ pub struct NewScan {
     pub online_count: Option<i32>,
     pub max_count: Option<i32>,
     pub description: Option<String>,
-    pub favicon: Option<String>
+    pub favicon_id: Option<i32>,
+    pub protocol: Option<i32>,
+    pub software: Option<String>,
+    pub is_proxy: Option<bool>,
+    pub is_modded: Option<bool>,
+    pub game_version_min: Option<String>,
+    pub game_version_max: Option<String>,
+    pub sample_text: Vec<Option<String>>,
+    pub player_count_spoofed: bool,
+    pub worker_id: Option<Uuid>
+}
+
+/// SQL condition only matching the most recent scan of each server
+pub fn latest_scan_filter() -> SqlLiteral<Bool> {
+    sql("NOT EXISTS (SELECT 1 FROM scan newer WHERE newer.ip = scan.ip AND newer.scan_id > scan.scan_id)")
+}
+
+/// SQL condition excluding the scans of servers flagged as honeypots (see [`crate::honeypot`])
+pub fn not_honeypot_filter() -> SqlLiteral<Bool> {
+    sql("NOT EXISTS (SELECT 1 FROM honeypot WHERE honeypot.network >>= scan.ip)")
+}
+
+impl Scan {
+    /// Returns the most recent scan of the server at `addr`, if it was ever found to be up
+    pub fn latest_for_ip(addr: IpNet, conn: &mut DbConnection) -> QueryResult<Option<Scan>> {
+        use crate::schema::scan::dsl::*;
+        scan.filter(ip.eq(addr)).order(scan_id.desc()).first::<Scan>(conn).optional()
+    }
 }
 
 impl NewScan {
-    pub fn save_to_db(&self, conn: &mut DbConnection) -> QueryResult<Scan> {
+    pub fn save_to_db(&self, conn: &mut PgConnection) -> QueryResult<Scan> {
         diesel::insert_into(scan::table).values(self).get_result::<Scan>(conn)
     }
 }
 
+//
+// FAVICON
+//
+
+#[derive(Queryable, Identifiable)]
+#[diesel(table_name = favicon)]
+pub struct Favicon {
+    pub id: i32,
+    // Hex-encoded SHA-256 hash of the PNG data
+    pub hash: String,
+    pub data: Vec<u8>,
+    // Perceptual hash of the image (see [`crate::favicon::perceptual_hash`])
+    pub phash: Option<i64>
+}
+
+impl Favicon {
+    /// Returns the favicon with the given PNG data, creating it if this is the first time it is seen.
+    /// The data should already be validated (see [`crate::favicon::decode_favicon`]).
+    pub fn get_or_create(png: Vec<u8>, conn: &mut PgConnection) -> QueryResult<Favicon> {
+        let new_favicon = NewFavicon {
+            hash: favicon_hash(&png),
+            phash: to_rgba(&png).map(|rgba| perceptual_hash(&rgba) as i64),
+            data: png
+        };
+        diesel::insert_into(favicon::table)
+            .values(&new_favicon)
+            .on_conflict(favicon::hash)
+            .do_nothing()
+            .execute(conn)?;
+        Favicon::find_by_hash(&new_favicon.hash, conn)?.ok_or(diesel::NotFound)
+    }
+
+    pub fn find(favicon_id: i32, conn: &mut DbConnection) -> QueryResult<Option<Favicon>> {
+        favicon::table.find(favicon_id).first::<Favicon>(conn).optional()
+    }
+
+    pub fn find_by_hash(favicon_hash: &str, conn: &mut PgConnection) -> QueryResult<Option<Favicon>> {
+        favicon::table.filter(favicon::hash.eq(favicon_hash)).first::<Favicon>(conn).optional()
+    }
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = favicon)]
+pub struct NewFavicon {
+    pub hash: String,
+    pub data: Vec<u8>,
+    pub phash: Option<i64>
+}
+
+//
+// CLUSTER
+//
+
+#[derive(Queryable, Identifiable)]
+#[diesel(table_name = cluster)]
+pub struct Cluster {
+    pub id: i32,
+    pub size: i32,
+    // Most common favicon and MOTD among the servers of the cluster
+    pub favicon_id: Option<i32>,
+    pub motd: Option<String>,
+    pub updated_at: SystemTime
+}
+
+impl Cluster {
+    /// Returns the IPs of all the servers that are part of the cluster
+    pub fn server_ips(&self, conn: &mut DbConnection) -> QueryResult<Vec<IpNet>> {
+        server_cluster::table
+            .filter(server_cluster::cluster_id.eq(self.id))
+            .select(server_cluster::ip)
+            .order(server_cluster::ip)
+            .load(conn)
+    }
+
+    /// Returns the cluster the server at `addr` is part of, if any
+    pub fn for_ip(addr: IpNet, conn: &mut DbConnection) -> QueryResult<Option<Cluster>> {
+        server_cluster::table
+            .inner_join(cluster::table)
+            .filter(server_cluster::ip.eq(addr))
+            .select(cluster::all_columns)
+            .first::<Cluster>(conn)
+            .optional()
+    }
+}
+
+#[derive(Insertable, AsChangeset)]
+#[diesel(table_name = cluster)]
+#[diesel(treat_none_as_null = true)]
+pub struct NewCluster {
+    pub size: i32,
+    pub favicon_id: Option<i32>,
+    pub motd: Option<String>,
+    pub updated_at: SystemTime
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = server_cluster)]
+pub struct NewServerCluster {
+    pub ip: IpNet,
+    pub cluster_id: i32
+}
+
+//
+// HONEYPOT
+//
+
+#[derive(Queryable)]
+#[diesel(table_name = honeypot)]
+pub struct Honeypot {
+    // Single IP (/32) or netblock (/24)
+    pub network: IpNet,
+    pub score: f32,
+    pub reasons: Vec<Option<String>>,
+    pub updated_at: SystemTime
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = honeypot)]
+pub struct NewHoneypot {
+    pub network: IpNet,
+    pub score: f32,
+    pub reasons: Vec<Option<String>>,
+    pub updated_at: SystemTime
+}
+
+//
+// MOD
+//
+
+#[derive(Queryable, QueryableByName, Identifiable)]
+#[diesel(table_name = mod_)]
+#[diesel(primary_key(mod_id))]
+pub struct Mod {
+    #[diesel(column_name = mod_id)]
+    pub id: i32,
+    // Mod id as advertised by the mod loader (i.e. `jei`)
+    pub modid: String,
+    pub version: Option<String>
+}
+
+impl Mod {
+    /// Returns the mod with the given id and version, creating it if this is the first time it is seen
+    pub fn get_or_create(info: &ModInfo, conn: &mut PgConnection) -> QueryResult<Mod> {
+        // The conflict target is the expression of the `mod_modid_version_idx` index, which diesel
+        // can't express. The no-op update returns the existing row.
+        sql_query("INSERT INTO mod (modid, version) VALUES ($1, $2)
+                   ON CONFLICT (modid, coalesce(version, '')) DO UPDATE SET modid = excluded.modid
+                   RETURNING mod_id, modid, version")
+            .bind::<Text, _>(&info.modid)
+            .bind::<Nullable<Text>, _>(&info.version)
+            .get_result(conn)
+    }
+
+    /// Returns the mods found during a scan, sorted by mod id
+    pub fn for_scan(scan_id: i32, conn: &mut DbConnection) -> QueryResult<Vec<Mod>> {
+        mod_scan::table
+            .inner_join(mod_::table)
+            .filter(mod_scan::scan_id.eq(scan_id))
+            .select(mod_::all_columns)
+            .order(mod_::modid)
+            .load(conn)
+    }
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = mod_scan)]
+pub struct NewModScan {
+    pub mod_id: i32,
+    pub scan_id: i32
+}
+
+impl NewModScan {
+    pub fn save_all(rows: &[NewModScan], conn: &mut PgConnection) -> QueryResult<usize> {
+        diesel::insert_into(mod_scan::table)
+            .values(rows)
+            .on_conflict_do_nothing()
+            .execute(conn)
+    }
+}
+
 //
 // PLAYER
 //
@@ -86,6 +316,10 @@ impl Player {
         }
         let new_username = result["data"]["player"]["username"].as_str().expect("Bad JSON response from PlayerDB");
         match diesel::update(self).set(username.eq(new_username)).execute(conn) {
+            Ok(_) => {}
+            Err(_) => return None
+        }
+        match PlayerNameHistory::record(self.id, new_username, conn) {
             Ok(_) => Some(String::from(new_username)),
             Err(_) => None
         }
@@ -100,7 +334,7 @@ impl Player {
     /// If no UUID is provided (this means this is a fake account that doesn't exist), the database
     /// is queried with the provided player username. If no row exists with that name, a new entry
     /// will be created, and the UUID will be set to null.
-    pub fn create_if_not_exist(name: String, uuid: Option<Uuid>, conn: &mut DbConnection) -> Result<Player, DBError> {
+    pub fn create_if_not_exist(name: String, uuid: Option<Uuid>, conn: &mut PgConnection) -> Result<Player, DBError> {
         use crate::schema::player::dsl::*;
 
         // Get player w/ UUID if present, otherwise get w/ username
@@ -115,7 +349,7 @@ impl Player {
             Err(_) => todo!()  // Error querying database
         };
 
-        match result {
+        let result = match result {
             Some(p) => {
                 // Player already exists, update username if needed
                 if p.username != name {
@@ -135,9 +369,42 @@ impl Player {
                     Err(_) => Err(DBError::PlayerCreationError)
                 }
             }
+        };
+
+        // Keep track of the name, even if it changes later
+        let p = result?;
+        match PlayerNameHistory::record(p.id, &p.username, conn) {
+            Ok(_) => Ok(p),
+            Err(_) => Err(DBError::PlayerCreationError)
         }
     }
 
+    /// Returns the players currently using, or that used to use, the given name (case-insensitive)
+    pub fn find_by_any_name(name: &str, conn: &mut DbConnection) -> QueryResult<Vec<Player>> {
+        let name = name.to_lowercase();
+        player::table
+            .filter(player::player_id.eq_any(player_name_history::table
+                .filter(lower(player_name_history::username).eq(name))
+                .select(player_name_history::player_id)))
+            .order(player::player_id)
+            .load(conn)
+    }
+
+    /// Returns the players seen on the server at `addr`, along with the number of scans they
+    /// were seen in, most seen first
+    pub fn ranking_for_ip(addr: IpNet, limit: i64, offset: i64, conn: &mut DbConnection) -> QueryResult<Vec<(Player, i64)>> {
+        player_scan::table
+            .inner_join(player::table)
+            .inner_join(scan::table)
+            .filter(scan::ip.eq(addr))
+            .group_by(player::player_id)
+            .select((player::all_columns, count_star()))
+            .order((count_star().desc(), player::username))
+            .limit(limit)
+            .offset(offset)
+            .load(conn)
+    }
+
     pub fn query_playerdb(username: &str) -> Result<Option<Uuid>, Box<dyn Error>> {
         let url = format!("https://playerdb.co/api/player/minecraft/{}", username);
 
@@ -167,11 +434,246 @@ pub struct NewPlayer {
 }
 
 impl NewPlayer {
-    pub fn save_to_db(&self, conn: &mut DbConnection) -> QueryResult<Player> {
+    pub fn save_to_db(&self, conn: &mut PgConnection) -> QueryResult<Player> {
         diesel::insert_into(player::table).values(self).get_result::<Player>(conn)
     }
 }
 
+//
+// PLAYER NAME HISTORY
+//
+
+#[derive(Queryable, Identifiable, Associations)]
+#[diesel(table_name = player_name_history)]
+#[diesel(primary_key(player_id, username))]
+#[diesel(belongs_to(Player))]
+pub struct PlayerNameHistory {
+    pub player_id: i32,
+    pub username: String,
+    pub first_seen: SystemTime,
+    pub last_seen: SystemTime
+}
+
+impl PlayerNameHistory {
+    /// Records that the player was seen with this name now
+    pub fn record(player_id: i32, name: &str, conn: &mut PgConnection) -> QueryResult<usize> {
+        let now = SystemTime::now();
+        diesel::insert_into(player_name_history::table)
+            .values((
+                player_name_history::player_id.eq(player_id),
+                player_name_history::username.eq(name),
+                player_name_history::first_seen.eq(now),
+                player_name_history::last_seen.eq(now)
+            ))
+            .on_conflict((player_name_history::player_id, player_name_history::username))
+            .do_update()
+            .set(player_name_history::last_seen.eq(now))
+            .execute(conn)
+    }
+
+    /// Returns the names used by the player, most recent first
+    pub fn for_player(player_id: i32, conn: &mut DbConnection) -> QueryResult<Vec<PlayerNameHistory>> {
+        player_name_history::table
+            .filter(player_name_history::player_id.eq(player_id))
+            .order(player_name_history::last_seen.desc())
+            .load(conn)
+    }
+}
+
+//
+// PLAYER BASE
+//
+
+#[derive(Queryable, Identifiable)]
+#[diesel(table_name = player_base)]
+#[diesel(primary_key(ip))]
+pub struct PlayerBase {
+    pub ip: IpNet,
+    // Number of scans with a player sample
+    pub sampled_scans: i32,
+    pub observed_players: i32,
+    pub singletons: i32,
+    pub doubletons: i32,
+    // See [`crate::player_base::chao2`]
+    pub estimated_players: f32,
+    pub updated_at: SystemTime
+}
+
+impl PlayerBase {
+    pub fn for_ip(addr: IpNet, conn: &mut DbConnection) -> QueryResult<Option<PlayerBase>> {
+        player_base::table.find(addr).first::<PlayerBase>(conn).optional()
+    }
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = player_base)]
+pub struct NewPlayerBase {
+    pub ip: IpNet,
+    pub sampled_scans: i32,
+    pub observed_players: i32,
+    pub singletons: i32,
+    pub doubletons: i32,
+    pub estimated_players: f32,
+    pub updated_at: SystemTime
+}
+
+//
+// SERVER GEO
+//
+
+#[derive(Queryable, Identifiable)]
+#[diesel(table_name = server_geo)]
+#[diesel(primary_key(ip))]
+pub struct ServerGeo {
+    pub ip: IpNet,
+    // ISO 3166-1 alpha-2 code and English name
+    pub country_code: Option<String>,
+    pub country: Option<String>,
+    pub city: Option<String>,
+    pub asn: Option<i32>,
+    pub as_org: Option<String>,
+    pub updated_at: SystemTime
+}
+
+impl ServerGeo {
+    pub fn for_ip(addr: IpNet, conn: &mut DbConnection) -> QueryResult<Option<ServerGeo>> {
+        server_geo::table.find(addr).first::<ServerGeo>(conn).optional()
+    }
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = server_geo)]
+pub struct NewServerGeo {
+    pub ip: IpNet,
+    pub country_code: Option<String>,
+    pub country: Option<String>,
+    pub city: Option<String>,
+    pub asn: Option<i32>,
+    pub as_org: Option<String>,
+    pub updated_at: SystemTime
+}
+
+//
+// WORKER
+//
+
+#[derive(Queryable, Identifiable)]
+#[diesel(table_name = worker)]
+pub struct Worker {
+    pub id: Uuid,
+    // scout or client
+    pub kind: String,
+    // Name and group of the worker config (see [`crate::worker_config`])
+    pub name: Option<String>,
+    pub worker_group: Option<String>,
+    pub hostname: Option<String>,
+    pub version: Option<String>,
+    pub adapter: Option<String>,
+    pub send_rate: Option<i64>,
+    pub registered_at: SystemTime,
+    pub last_seen: SystemTime,
+    pub jobs_completed: i64,
+    // IPs probed by scouts, servers scanned by clients
+    pub items_processed: i64,
+    pub errors: i64,
+    // Fingerprint of the TLS client certificate of the worker (see [`crate::tls`])
+    pub cert_fingerprint: Option<String>,
+    // API key that registered the worker, the first of its rotations (see [`crate::auth`])
+    pub api_key_id: Option<i32>
+}
+
+impl Worker {
+    pub fn all(conn: &mut DbConnection) -> QueryResult<Vec<Worker>> {
+        worker::table.load::<Worker>(conn)
+    }
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = worker)]
+pub struct NewWorker {
+    pub id: Uuid,
+    pub kind: String,
+    pub name: Option<String>,
+    pub worker_group: Option<String>,
+    pub hostname: Option<String>,
+    pub version: Option<String>,
+    pub adapter: Option<String>,
+    pub cert_fingerprint: Option<String>,
+    pub api_key_id: Option<i32>
+}
+
+impl NewWorker {
+    pub fn save_to_db(&self, conn: &mut DbConnection) -> QueryResult<Worker> {
+        diesel::insert_into(worker::table).values(self).get_result::<Worker>(conn)
+    }
+}
+
+/// Heartbeat fields and counters of a worker
+#[derive(AsChangeset)]
+#[diesel(table_name = worker)]
+pub struct WorkerUpdate {
+    pub hostname: Option<String>,
+    pub version: Option<String>,
+    pub adapter: Option<String>,
+    pub send_rate: Option<i64>,
+    pub last_seen: SystemTime,
+    pub jobs_completed: i64,
+    pub items_processed: i64,
+    pub errors: i64
+}
+
+//
+// API KEY
+//
+
+#[derive(Queryable, Identifiable)]
+#[diesel(table_name = api_key)]
+pub struct ApiKey {
+    pub id: i32,
+    pub name: String,
+    // scout, client, read_only or admin (see [`crate::auth::Role`])
+    pub role: String,
+    // Hex-encoded SHA-256 hash of the key
+    pub key_hash: String,
+    // Start of the key, to tell keys apart
+    pub prefix: String,
+    pub created_at: SystemTime,
+    // Set on the previous key when a key is rotated
+    pub expires_at: Option<SystemTime>,
+    pub revoked_at: Option<SystemTime>,
+    pub last_used: Option<SystemTime>,
+    // First key of the rotations of this key, None if it wasn't rotated from another
+    pub origin_id: Option<i32>
+}
+
+impl ApiKey {
+    /// Id shared by the key and the keys rotated from, or into, it
+    pub fn lineage(&self) -> i32 {
+        self.origin_id.unwrap_or(self.id)
+    }
+}
+
+impl ApiKey {
+    pub fn all(conn: &mut DbConnection) -> QueryResult<Vec<ApiKey>> {
+        api_key::table.order(api_key::id).load::<ApiKey>(conn)
+    }
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = api_key)]
+pub struct NewApiKey {
+    pub name: String,
+    pub role: String,
+    pub key_hash: String,
+    pub prefix: String,
+    pub origin_id: Option<i32>
+}
+
+impl NewApiKey {
+    pub fn save_to_db(&self, conn: &mut DbConnection) -> QueryResult<ApiKey> {
+        diesel::insert_into(api_key::table).values(self).get_result::<ApiKey>(conn)
+    }
+}
 
 //
 // PLAYER-SCAN RELATION
@@ -197,7 +699,7 @@ pub struct NewPlayerScan {
 }
 
 impl NewPlayerScan {
-    pub fn save_to_db(&self, conn: &mut DbConnection) -> QueryResult<PlayerScan> {
+    pub fn save_to_db(&self, conn: &mut PgConnection) -> QueryResult<PlayerScan> {
         diesel::insert_into(player_scan::table)
             .values(self).get_result::<PlayerScan>(conn)
     }
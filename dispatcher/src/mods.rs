@@ -0,0 +1,229 @@
+// Extraction of the mod list advertised by modded servers. Forge servers send it in the
+// `forgeData` field (1.13+) or the legacy `modinfo` field (1.7 to 1.12). Since Forge 1.18.2, the
+// list is usually sent in the compressed `forgeData.d` field instead of `forgeData.mods`.
+
+use std::collections::HashSet;
+use serde_json::Value;
+
+/// Hard limit on the number of mods read from a response, protects against malicious servers
+const MAX_MODS: usize = 2048;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ModInfo {
+    pub modid: String,
+    /// Not sent for mods that don't need to be installed on the client
+    pub version: Option<String>,
+}
+
+/// Returns the mods advertised in a status response, without duplicates. Empty for vanilla
+/// servers or if the mod data is invalid.
+pub fn parse_mods(response: &Value) -> Vec<ModInfo> {
+    let mut mods = Vec::new();
+
+    if let Some(forge_data) = response.get("forgeData") {
+        // Mods (and their versions) with the same format as the legacy field, only the keys differ
+        mods.extend(parse_mod_list(&forge_data["mods"], "modId", "modmarker"));
+        if let Some(d) = forge_data["d"].as_str() {
+            mods.extend(decode_forge_data(d).unwrap_or_default());
+        }
+    }
+    if let Some(mod_info) = response.get("modinfo") {
+        mods.extend(parse_mod_list(&mod_info["modList"], "modid", "version"));
+    }
+
+    let mut seen = HashSet::new();
+    mods.retain(|m| seen.insert(m.clone()));
+    mods.truncate(MAX_MODS);
+    mods
+}
+
+fn parse_mod_list(list: &Value, id_key: &str, version_key: &str) -> Vec<ModInfo> {
+    list.as_array()
+        .map(|list| list.iter()
+            .filter_map(|m| {
+                let modid = m[id_key].as_str().filter(|id| !id.is_empty())?;
+                Some(ModInfo { modid: String::from(modid), version: m[version_key].as_str().map(String::from) })
+            })
+            .collect())
+        .unwrap_or_default()
+}
+
+/// Decodes the `forgeData.d` field. It contains binary data, stored 15 bits per character
+/// (so that the string stays valid UTF-16), the first 2 characters giving the length in bytes.
+/// Returns `None` if the data is invalid.
+fn decode_forge_data(d: &str) -> Option<Vec<ModInfo>> {
+    let chars: Vec<u32> = d.encode_utf16().map(|c| c as u32 & 0x7FFF).collect();
+    if chars.len() < 2 {
+        return None;
+    }
+    let size = (chars[0] | (chars[1] << 15)) as usize;
+
+    let mut bytes = Vec::with_capacity(size.min(chars.len() * 2));
+    let (mut buffer, mut bits) = (0u32, 0);
+    for &c in &chars[2..] {
+        buffer |= c << bits;
+        bits += 15;
+        while bits >= 8 && bytes.len() < size {
+            bytes.push(buffer as u8);
+            buffer >>= 8;
+            bits -= 8;
+        }
+    }
+    // Last byte, only partially filled by the last character
+    if bits > 0 && bytes.len() < size {
+        bytes.push(buffer as u8);
+    }
+
+    let mut reader = ByteReader { data: &bytes, pos: 0 };
+    let _truncated = reader.read_byte()? != 0;
+    let mod_count = u16::from_be_bytes([reader.read_byte()?, reader.read_byte()?]) as usize;
+
+    let mut mods = Vec::with_capacity(mod_count.min(MAX_MODS));
+    for _ in 0..mod_count.min(MAX_MODS) {
+        // Number of network channels of the mod, and whether the mod is server-side only
+        let flags = reader.read_var_int()?;
+        let channel_count = flags >> 1;
+        let server_only = flags & 1 == 1;
+
+        let modid = reader.read_string()?;
+        let version = if server_only { None } else { Some(reader.read_string()?) };
+        for _ in 0..channel_count {
+            reader.read_string()?;  // Channel name
+            reader.read_string()?;  // Channel version
+            reader.read_byte()?;  // Required on the client
+        }
+        mods.push(ModInfo { modid, version });
+    }
+    // The list of channels that don't belong to a mod follows, we don't need it
+    Some(mods)
+}
+
+struct ByteReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl ByteReader<'_> {
+    fn read_byte(&mut self) -> Option<u8> {
+        let byte = *self.data.get(self.pos)?;
+        self.pos += 1;
+        Some(byte)
+    }
+
+    fn read_var_int(&mut self) -> Option<u32> {
+        let mut value = 0;
+        for i in 0..5 {
+            let byte = self.read_byte()?;
+            value |= ((byte & 0x7F) as u32) << (7 * i);
+            if byte & 0x80 == 0 {
+                return Some(value);
+            }
+        }
+        None
+    }
+
+    /// Strings are prefixed with their length in bytes, as a VarInt
+    fn read_string(&mut self) -> Option<String> {
+        let len = self.read_var_int()? as usize;
+        let bytes = self.data.get(self.pos..self.pos.checked_add(len)?)?;
+        self.pos += len;
+        Some(String::from_utf8_lossy(bytes).into_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use super::*;
+
+    /// Packs `bytes` 15 bits per character like Forge does, after 2 characters of length
+    fn encode_forge_data(bytes: &[u8]) -> String {
+        let mut chars = vec![bytes.len() as u32 & 0x7FFF, bytes.len() as u32 >> 15];
+        let (mut buffer, mut bits) = (0u32, 0);
+        for &byte in bytes {
+            buffer |= (byte as u32) << bits;
+            bits += 8;
+            if bits >= 15 {
+                chars.push(buffer & 0x7FFF);
+                buffer >>= 15;
+                bits -= 15;
+            }
+        }
+        if bits > 0 {
+            chars.push(buffer);
+        }
+        chars.into_iter().map(|c| char::from_u32(c).unwrap()).collect()
+    }
+
+    fn push_string(bytes: &mut Vec<u8>, text: &str) {
+        bytes.push(text.len() as u8);
+        bytes.extend_from_slice(text.as_bytes());
+    }
+
+    fn mod_info(modid: &str, version: Option<&str>) -> ModInfo {
+        ModInfo { modid: String::from(modid), version: version.map(String::from) }
+    }
+
+    /// Mod list with `jei` (one channel), and the server-only `spark`
+    fn mod_list() -> Vec<u8> {
+        let mut bytes = vec![0, 0, 2];
+        bytes.push(1 << 1);
+        push_string(&mut bytes, "jei");
+        push_string(&mut bytes, "11.6.0");
+        push_string(&mut bytes, "jei:channel");
+        push_string(&mut bytes, "1");
+        bytes.push(1);
+        bytes.push(1);
+        push_string(&mut bytes, "spark");
+        bytes
+    }
+
+    #[test]
+    fn decodes_forge_data() {
+        let mods = decode_forge_data(&encode_forge_data(&mod_list())).unwrap();
+        assert_eq!(mods, vec![mod_info("jei", Some("11.6.0")), mod_info("spark", None)]);
+    }
+
+    #[test]
+    fn decodes_every_length() {
+        // The last character is only partially used, whatever the length of the data
+        for extra in 0..16 {
+            let mut bytes = mod_list();
+            bytes.resize(bytes.len() + extra, 0xFF);
+            assert_eq!(decode_forge_data(&encode_forge_data(&bytes)).unwrap().len(), 2, "{} extra bytes", extra);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_forge_data() {
+        assert_eq!(decode_forge_data(""), None);
+        assert_eq!(decode_forge_data("a"), None);
+        // Truncated in the middle of a mod
+        let bytes = mod_list();
+        assert_eq!(decode_forge_data(&encode_forge_data(&bytes[..bytes.len() - 2])), None);
+        // String longer than the data
+        assert_eq!(decode_forge_data(&encode_forge_data(&[0, 0, 1, 0, 200, b'a'])), None);
+        // VarInt longer than 5 bytes
+        assert_eq!(decode_forge_data(&encode_forge_data(&[0, 0, 1, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x01])), None);
+    }
+
+    #[test]
+    fn parses_every_field_without_duplicates() {
+        let response = json!({
+            "forgeData": {
+                "mods": [{"modId": "forge", "modmarker": "47.1.0"}, {"modId": ""}, {"modmarker": "1.0"}],
+                "d": encode_forge_data(&mod_list())
+            },
+            "modinfo": {"type": "FML", "modList": [{"modid": "jei", "version": "11.6.0"}, {"modid": "mcp"}]}
+        });
+        assert_eq!(parse_mods(&response), vec![
+            mod_info("forge", Some("47.1.0")),
+            mod_info("jei", Some("11.6.0")),
+            mod_info("spark", None),
+            mod_info("mcp", None),
+        ]);
+        assert!(parse_mods(&json!({"version": {"name": "1.20.1"}})).is_empty());
+        // Invalid compressed data is ignored
+        assert_eq!(parse_mods(&json!({"forgeData": {"d": "x"}})), vec![]);
+    }
+}
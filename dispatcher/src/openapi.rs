@@ -0,0 +1,172 @@
+// OpenAPI 3 document of the dispatcher, served at `/api/v1/openapi.json` along with a Swagger UI
+// at `/api/v1/docs/`. Each route module describes its routes in its own `OpenApi` struct, merged
+// here. Only the versioned paths are listed, the unversioned aliases of the legacy routes behave
+// the same. The tests at the bottom check that the routes of the document are the ones served by
+// the dispatcher.
+
+use actix_web::{HttpResponse, Resource, web};
+use actix_web::http::header::LOCATION;
+use utoipa::{Modify, OpenApi};
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityRequirement, SecurityScheme};
+use utoipa_swagger_ui::SwaggerUi;
+use crate::routes::{admin_routes, client_routes, cluster_routes, config_routes, favicon_routes, graph_routes,
+                    honeypot_routes, info_routes, mod_routes, player_routes, scout_routes, server_routes,
+                    stats_routes, worker_routes};
+
+const SPEC_PATH: &str = "/api/v1/openapi.json";
+const DOCS_PATH: &str = "/api/v1/docs";
+
+/// API keys, see `crate::auth`
+struct ApiKeyAuth;
+
+impl Modify for ApiKeyAuth {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme("api_key", SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new("X-Api-Key"))));
+        components.add_security_scheme("bearer", SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()));
+        // Either of them
+        openapi.security = Some(vec![
+            SecurityRequirement::new("api_key", Vec::<String>::new()),
+            SecurityRequirement::new("bearer", Vec::<String>::new()),
+        ]);
+    }
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "msearch dispatcher", description = "Hands out scan jobs to the scouts and clients, and serves the servers and players they found."),
+    modifiers(&ApiKeyAuth)
+)]
+struct ApiDoc;
+
+/// OpenAPI document of every route
+pub fn document() -> utoipa::openapi::OpenApi {
+    let mut document = ApiDoc::openapi();
+    let modules = [
+        scout_routes::ScoutApi::openapi(),
+        client_routes::ClientApi::openapi(),
+        worker_routes::WorkerApi::openapi(),
+        config_routes::ConfigApi::openapi(),
+        server_routes::ServerApi::openapi(),
+        player_routes::PlayerApi::openapi(),
+        cluster_routes::ClusterApi::openapi(),
+        mod_routes::ModApi::openapi(),
+        favicon_routes::FaviconApi::openapi(),
+        honeypot_routes::HoneypotApi::openapi(),
+        graph_routes::GraphApi::openapi(),
+        stats_routes::StatsApi::openapi(),
+        info_routes::InfoApi::openapi(),
+        admin_routes::AdminApi::openapi(),
+    ];
+    for module in modules {
+        document.merge(module);
+    }
+    document
+}
+
+/// Serves the document and the Swagger UI
+pub fn swagger_ui() -> SwaggerUi {
+    SwaggerUi::new(format!("{}/{{_:.*}}", DOCS_PATH)).url(SPEC_PATH, document())
+}
+
+/// The Swagger UI is served under `/api/v1/docs/`
+pub fn docs_redirect() -> Resource {
+    web::resource(DOCS_PATH).to(|| async {
+        HttpResponse::PermanentRedirect().insert_header((LOCATION, format!("{}/", DOCS_PATH))).finish()
+    })
+}
+
+/// The documentation can be read without an API key
+pub fn is_public(path: &str) -> bool {
+    path == SPEC_PATH || path.strip_prefix(DOCS_PATH).is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+    use actix_web::{App, HttpResponse, test, web};
+    use actix_web::http::{Method, StatusCode};
+    use actix_web::http::header::LOCATION;
+    use serde_json::Value;
+    use crate::routes::{configure_app, unversioned_path};
+    use super::{DOCS_PATH, SPEC_PATH, document};
+
+    /// Status of the requests that no route matched, no route answers with it
+    const UNMATCHED: StatusCode = StatusCode::IM_A_TEAPOT;
+    /// Routes that existed before the routes were versioned, also served at the root
+    const LEGACY_ROUTES: [&str; 3] = ["/scout/", "/client/job", "/info/"];
+
+    /// Routes of the document, i.e. `GET /api/v1/servers/{ip}`
+    fn documented_routes() -> BTreeSet<(String, String)> {
+        document().paths.paths.into_iter()
+            .flat_map(|(path, item)| item.operations.into_keys()
+                .map(move |method| {
+                    // Serialized in lowercase, i.e. `get`
+                    let method = serde_json::to_value(method).expect("Unable to serialize a method");
+                    (method.as_str().unwrap_or_default().to_uppercase(), path.clone())
+                }))
+            .collect()
+    }
+
+    /// Path of a route with its parameters filled in, i.e. `/api/v1/favicons/0.png`. Handlers reject
+    /// invalid parameters, but only once the route matched.
+    fn concrete_path(path: &str) -> String {
+        let mut concrete = String::new();
+        let mut rest = path;
+        while let Some((before, after)) = rest.split_once('{') {
+            concrete.push_str(before);
+            concrete.push('0');
+            rest = after.split_once('}').map_or("", |(_, after)| after);
+        }
+        concrete.push_str(rest);
+        concrete
+    }
+
+    /// Every route served is documented, since the route modules declare both from the same list
+    /// of handlers. Checks the other way around, with the paths and methods of the document.
+    #[actix_web::test]
+    async fn documented_routes_are_served() {
+        // Without the data of the handlers, their requests fail but not with `UNMATCHED`
+        let app = test::init_service(App::new()
+            .configure(configure_app)
+            .default_service(web::to(|| async { HttpResponse::new(UNMATCHED) }))).await;
+        let documented = documented_routes();
+        assert!(!documented.is_empty(), "No route in the OpenAPI document");
+        let mut unserved = Vec::new();
+        let mut aliased = Vec::new();
+        for (method, path) in documented {
+            let method = Method::from_bytes(method.as_bytes()).expect("Invalid method");
+            let path = concrete_path(&path);
+            let req = test::TestRequest::default().method(method.clone()).uri(&path).to_request();
+            if test::call_service(&app, req).await.status() == UNMATCHED {
+                unserved.push(format!("{} {}", method, path));
+            }
+
+            // Only the legacy routes have an unversioned alias
+            let alias = unversioned_path(&path);
+            let req = test::TestRequest::default().method(method.clone()).uri(alias).to_request();
+            let served = test::call_service(&app, req).await.status() != UNMATCHED;
+            if served != LEGACY_ROUTES.iter().any(|prefix| alias.starts_with(prefix)) {
+                aliased.push(format!("{} {}", method, alias));
+            }
+        }
+        assert!(unserved.is_empty(), "OpenAPI document out of date, documented routes that aren't served: {:?}", unserved);
+        assert!(aliased.is_empty(), "Unversioned aliases of the wrong routes: {:?}", aliased);
+    }
+
+    #[actix_web::test]
+    async fn documentation_is_served() {
+        let app = test::init_service(App::new()
+            .configure(configure_app)
+            .default_service(web::to(|| async { HttpResponse::new(UNMATCHED) }))).await;
+        let res = test::call_service(&app, test::TestRequest::get().uri(DOCS_PATH).to_request()).await;
+        assert_eq!(res.status(), StatusCode::PERMANENT_REDIRECT);
+        assert_eq!(res.headers().get(LOCATION).and_then(|l| l.to_str().ok()), Some(format!("{}/", DOCS_PATH).as_str()));
+
+        let res = test::call_service(&app, test::TestRequest::get().uri(&format!("{}/", DOCS_PATH)).to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let spec: Value = test::call_and_read_body_json(&app, test::TestRequest::get().uri(SPEC_PATH).to_request()).await;
+        assert_eq!(spec, serde_json::to_value(document()).expect("Unable to serialize the document"));
+    }
+}
@@ -0,0 +1,123 @@
+// Estimation of the number of distinct players of each server. A status response only contains
+// a random sample of at most 12 online players, so every scan of a server is treated as a
+// sampling occasion and the samples are merged with a capture-recapture estimator (Chao2): the
+// more players are only ever seen once, the more players have likely never been seen at all.
+
+use std::collections::HashMap;
+use std::time::SystemTime;
+use diesel::dsl::{count_distinct, count_star};
+use diesel::prelude::*;
+use ipnet::IpNet;
+use crate::DbConnection;
+use crate::models::NewPlayerBase;
+use crate::schema::{player_base, player_scan, scan};
+
+diesel::allow_columns_to_appear_in_same_group_by_clause!(scan::ip, player_scan::player_id);
+
+/// Bias-corrected Chao2 estimator of the number of distinct players
+///
+/// # Arguments
+///
+/// * `observed`: Number of distinct players seen
+/// * `singletons`: Number of players seen in exactly one scan
+/// * `doubletons`: Number of players seen in exactly two scans
+/// * `scans`: Number of scans with a player sample
+pub fn chao2(observed: i64, singletons: i64, doubletons: i64, scans: i64) -> f64 {
+    if scans == 0 {
+        return 0.0;
+    }
+    let m = scans as f64;
+    let (q1, q2) = (singletons as f64, doubletons as f64);
+    observed as f64 + ((m - 1.0) / m) * q1 * (q1 - 1.0).max(0.0) / (2.0 * (q2 + 1.0))
+}
+
+/// Recomputes the player base estimate of every server with at least one player sample.
+/// Returns the number of servers estimated.
+pub fn run_estimation(conn: &mut DbConnection) -> QueryResult<usize> {
+    let sampled_scans: HashMap<IpNet, i64> = player_scan::table
+        .inner_join(scan::table)
+        .group_by(scan::ip)
+        .select((scan::ip, count_distinct(player_scan::scan_id)))
+        .load::<(IpNet, i64)>(conn)?
+        .into_iter()
+        .collect();
+
+    // Number of scans each player was seen in, for each server
+    let sightings = player_scan::table
+        .inner_join(scan::table)
+        .group_by((scan::ip, player_scan::player_id))
+        .select((scan::ip, count_star()))
+        .load::<(IpNet, i64)>(conn)?;
+
+    // Observed players, singletons and doubletons
+    let mut counts: HashMap<IpNet, (i64, i64, i64)> = HashMap::new();
+    for (ip, seen) in sightings {
+        let entry = counts.entry(ip).or_default();
+        entry.0 += 1;
+        match seen {
+            1 => entry.1 += 1,
+            2 => entry.2 += 1,
+            _ => {}
+        }
+    }
+
+    let now = SystemTime::now();
+    let rows: Vec<NewPlayerBase> = counts.into_iter()
+        .map(|(ip, (observed, singletons, doubletons))| {
+            let scans = sampled_scans.get(&ip).copied().unwrap_or(0);
+            NewPlayerBase {
+                ip,
+                sampled_scans: scans as i32,
+                observed_players: observed as i32,
+                singletons: singletons as i32,
+                doubletons: doubletons as i32,
+                estimated_players: chao2(observed, singletons, doubletons, scans) as f32,
+                updated_at: now
+            }
+        })
+        .collect();
+
+    conn.transaction(|conn| {
+        diesel::delete(player_base::table).execute(conn)?;
+        // Stay under the bind parameter limit of postgres
+        for chunk in rows.chunks(5000) {
+            diesel::insert_into(player_base::table).values(chunk).execute(conn)?;
+        }
+        Ok(rows.len())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-9, "expected {}, got {}", expected, actual);
+    }
+
+    #[test]
+    fn estimates_unseen_players() {
+        // 10 + (4/5) * 4 * 3 / (2 * 3)
+        assert_close(chao2(10, 4, 2, 5), 11.6);
+        // Without doubletons, the bias correction keeps the estimate finite
+        assert_close(chao2(10, 4, 0, 5), 10.0 + 0.8 * 4.0 * 3.0 / 2.0);
+    }
+
+    #[test]
+    fn estimates_more_with_more_singletons() {
+        let few = chao2(20, 2, 5, 10);
+        let many = chao2(20, 10, 5, 10);
+        assert!(many > few, "{} should be above {}", many, few);
+        // Doubletons mean the players come back, fewer are unseen
+        assert!(chao2(20, 10, 8, 10) < many);
+    }
+
+    #[test]
+    fn never_estimates_below_observed() {
+        // Every player was seen more than once, or a single scan can't tell anything
+        assert_close(chao2(7, 0, 3, 4), 7.0);
+        assert_close(chao2(7, 1, 0, 4), 7.0);
+        assert_close(chao2(7, 7, 0, 1), 7.0);
+        assert_close(chao2(0, 0, 0, 0), 0.0);
+    }
+}
@@ -0,0 +1,289 @@
+// Renders a server list entry the same way the Minecraft client displays it: favicon on the left,
+// server name and player count on the first line, MOTD on the two following lines and the
+// version at the bottom. Text is drawn with the 8x8 bitmap font from the `font8x8` crate.
+
+use font8x8::{BASIC_FONTS, BLOCK_FONTS, BOX_FONTS, GREEK_FONTS, LATIN_FONTS, UnicodeFonts};
+use protocol::motd::{Color, Span, Style, parse_component};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use crate::favicon::{FAVICON_SIZE, to_rgba};
+use crate::models::Scan;
+
+const WIDTH: u32 = 480;
+const HEIGHT: u32 = 72;
+const PADDING: i32 = 4;
+const TEXT_X: i32 = PADDING + FAVICON_SIZE as i32 + 6;
+const LINE_HEIGHT: i32 = 12;
+const BACKGROUND: Color = Color::new(0x1E, 0x1E, 0x1E);
+const PLACEHOLDER: Color = Color::new(0x3C, 0x3C, 0x3C);
+
+/// Renders the latest scan of a server and its favicon (PNG data) into a PNG image, returned
+/// as encoded bytes.
+pub fn render_preview(scan: &Scan, favicon: Option<&[u8]>) -> Result<Vec<u8>, png::EncodingError> {
+    let mut canvas = Canvas::new(WIDTH, HEIGHT, BACKGROUND);
+
+    // Favicon, or a placeholder square if the server doesn't have a (valid) one
+    match favicon.and_then(to_rgba) {
+        Some(rgba) => canvas.draw_image(PADDING, PADDING, FAVICON_SIZE, FAVICON_SIZE, &rgba),
+        None => canvas.fill_rect(PADDING, PADDING, FAVICON_SIZE, FAVICON_SIZE, PLACEHOLDER)
+    }
+
+    // Server name, we don't have any so the IP is used instead
+    let name = plain_spans(&scan.ip.addr().to_string(), Color::WHITE);
+    canvas.draw_text(TEXT_X, PADDING + 2, &name);
+
+    // Player count, right-aligned on the first line
+    let players = player_count_spans(scan.online_count, scan.max_count);
+    let players_x = WIDTH as i32 - PADDING - text_width(&players);
+    canvas.draw_text(players_x, PADDING + 2, &players);
+
+    // MOTD, at most 2 lines are displayed
+    let motd = scan.description.as_deref()
+        .map(|d| serde_json::from_str(d).unwrap_or_else(|_| Value::String(String::from(d))))
+        .map(|d| parse_component(&d))
+        .unwrap_or_default();
+    for (i, line) in split_lines(&motd).iter().take(2).enumerate() {
+        canvas.draw_text(TEXT_X, PADDING + 2 + LINE_HEIGHT * (i as i32 + 1), line);
+    }
+
+    // Version, which can also contain formatting codes
+    if let Some(version) = &scan.version {
+        let version = with_default_color(parse_component(&Value::String(version.clone())), Color::DARK_GRAY);
+        canvas.draw_text(TEXT_X, HEIGHT as i32 - PADDING - 10, &version);
+    }
+
+    canvas.encode_png()
+}
+
+/// Character drawn in place of the character at `position` of obfuscated `text`. The client
+/// constantly cycles through random characters, the preview picks them from the text so that the
+/// same scan always renders the same image.
+fn obfuscated_char(text: &str, position: usize) -> char {
+    let digest = Sha256::new()
+        .chain_update(text.as_bytes())
+        .chain_update((position as u64).to_le_bytes())
+        .finalize();
+    char::from(b'!' + digest[0] % (b'~' - b'!' + 1))
+}
+
+fn plain_spans(text: &str, color: Color) -> Vec<Span> {
+    vec![Span { text: String::from(text), style: Style { color: Some(color), ..Style::default() } }]
+}
+
+fn player_count_spans(online: Option<i32>, max: Option<i32>) -> Vec<Span> {
+    let format = |count: Option<i32>| count.map_or_else(|| String::from("???"), |c| c.to_string());
+    let mut spans = plain_spans(&format(online), Color::GRAY);
+    spans.extend(plain_spans("/", Color::DARK_GRAY));
+    spans.extend(plain_spans(&format(max), Color::GRAY));
+    spans
+}
+
+/// Sets the color of the spans that don't specify one
+fn with_default_color(spans: Vec<Span>, color: Color) -> Vec<Span> {
+    spans.into_iter().map(|mut s| {
+        s.style.color = s.style.color.or(Some(color));
+        s
+    }).collect()
+}
+
+/// Splits a list of spans into lines, on `\n` characters
+fn split_lines(spans: &[Span]) -> Vec<Vec<Span>> {
+    let mut lines = vec![Vec::new()];
+    for span in spans {
+        for (i, part) in span.text.split('\n').enumerate() {
+            if i > 0 {
+                lines.push(Vec::new());
+            }
+            if !part.is_empty() {
+                lines.last_mut().unwrap().push(Span { text: String::from(part), style: span.style });
+            }
+        }
+    }
+    lines
+}
+
+fn glyph(c: char) -> [u8; 8] {
+    BASIC_FONTS.get(c)
+        .or_else(|| LATIN_FONTS.get(c))
+        .or_else(|| GREEK_FONTS.get(c))
+        .or_else(|| BLOCK_FONTS.get(c))
+        .or_else(|| BOX_FONTS.get(c))
+        .or_else(|| BASIC_FONTS.get('?'))
+        .unwrap()
+}
+
+/// Width of the glyph in pixels, the font is monospaced so we trim the empty columns on the
+/// right to get something closer to the proportional font used by Minecraft
+fn glyph_width(glyph: &[u8; 8]) -> i32 {
+    let columns = glyph.iter().fold(0u8, |acc, row| acc | row);
+    if columns == 0 {
+        return 3;  // Whitespace
+    }
+    8 - columns.leading_zeros() as i32
+}
+
+/// Horizontal space taken by a character, including spacing
+fn char_advance(c: char, style: &Style) -> i32 {
+    glyph_width(&glyph(c)) + 1 + style.bold as i32
+}
+
+fn text_width(spans: &[Span]) -> i32 {
+    spans.iter()
+        .flat_map(|s| s.text.chars().map(move |c| char_advance(c, &s.style)))
+        .sum()
+}
+
+struct Canvas {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+}
+
+impl Canvas {
+    fn new(width: u32, height: u32, background: Color) -> Self {
+        let pixels = (0..width * height)
+            .flat_map(|_| [background.r, background.g, background.b, 0xFF])
+            .collect();
+        Canvas { width, height, pixels }
+    }
+
+    /// Alpha-blends an RGBA pixel onto the canvas, ignoring coordinates outside of the canvas
+    fn blend_pixel(&mut self, x: i32, y: i32, rgba: [u8; 4]) {
+        if x < 0 || y < 0 || x >= self.width as i32 || y >= self.height as i32 {
+            return;
+        }
+
+        let idx = ((y as u32 * self.width + x as u32) * 4) as usize;
+        let alpha = rgba[3] as u32;
+        for (channel, &value) in self.pixels[idx..idx + 3].iter_mut().zip(&rgba[..3]) {
+            *channel = ((value as u32 * alpha + *channel as u32 * (255 - alpha)) / 255) as u8;
+        }
+    }
+
+    fn put_pixel(&mut self, x: i32, y: i32, color: Color) {
+        self.blend_pixel(x, y, [color.r, color.g, color.b, 0xFF]);
+    }
+
+    fn fill_rect(&mut self, x: i32, y: i32, width: u32, height: u32, color: Color) {
+        for dy in 0..height as i32 {
+            for dx in 0..width as i32 {
+                self.put_pixel(x + dx, y + dy, color);
+            }
+        }
+    }
+
+    fn draw_image(&mut self, x: i32, y: i32, width: u32, height: u32, rgba: &[u8]) {
+        for dy in 0..height {
+            for dx in 0..width {
+                let idx = ((dy * width + dx) * 4) as usize;
+                let pixel = [rgba[idx], rgba[idx + 1], rgba[idx + 2], rgba[idx + 3]];
+                self.blend_pixel(x + dx as i32, y + dy as i32, pixel);
+            }
+        }
+    }
+
+    /// Draws styled text with its drop shadow, `(x, y)` being the top-left corner of the first
+    /// character. Text without a color is drawn in white.
+    fn draw_text(&mut self, x: i32, y: i32, spans: &[Span]) {
+        let mut x = x;
+
+        for span in spans {
+            let style = &span.style;
+            let color = style.color.unwrap_or(Color::WHITE);
+
+            for (i, c) in span.text.chars().enumerate() {
+                let c = if style.obfuscated && !c.is_whitespace() { obfuscated_char(&span.text, i) } else { c };
+                let advance = char_advance(c, style);
+
+                self.draw_glyph(x + 1, y + 1, c, style, color.shadow());
+                self.draw_glyph(x, y, c, style, color);
+                x += advance;
+            }
+        }
+    }
+
+    fn draw_glyph(&mut self, x: i32, y: i32, c: char, style: &Style, color: Color) {
+        let glyph = glyph(c);
+        let advance = char_advance(c, style);
+
+        for (row, bits) in glyph.iter().enumerate() {
+            // Italic text is slanted by 1px every 3 rows
+            let offset = if style.italic { (7 - row as i32) / 3 } else { 0 };
+            for col in 0..8 {
+                if bits & (1 << col) == 0 {
+                    continue;
+                }
+                self.put_pixel(x + col + offset, y + row as i32, color);
+                if style.bold {
+                    self.put_pixel(x + col + offset + 1, y + row as i32, color);
+                }
+            }
+        }
+
+        if style.underlined {
+            (-1..advance).for_each(|dx| self.put_pixel(x + dx, y + 8, color));
+        }
+        if style.strikethrough {
+            (-1..advance).for_each(|dx| self.put_pixel(x + dx, y + 3, color));
+        }
+    }
+
+    fn encode_png(&self) -> Result<Vec<u8>, png::EncodingError> {
+        let mut out = Vec::new();
+        {
+            let mut encoder = png::Encoder::new(&mut out, self.width, self.height);
+            encoder.set_color(png::ColorType::Rgba);
+            encoder.set_depth(png::BitDepth::Eight);
+            let mut writer = encoder.write_header()?;
+            writer.write_image_data(&self.pixels)?;
+            writer.finish()?;
+        }
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ipnet::IpNet;
+    use super::*;
+
+    fn scan(description: &str) -> Scan {
+        Scan {
+            id: 1,
+            ip: "10.0.0.1/32".parse::<IpNet>().unwrap(),
+            version: Some(String::from("§kPaper 1.20.1")),
+            online_count: Some(3),
+            max_count: Some(20),
+            description: Some(String::from(description)),
+            favicon_id: None,
+            protocol: Some(763),
+            software: None,
+            is_proxy: None,
+            is_modded: None,
+            game_version_min: None,
+            game_version_max: None,
+            sample_text: vec![],
+            player_count_spoofed: false,
+            scanned_at: None,
+            worker_id: None,
+        }
+    }
+
+    #[test]
+    fn obfuscated_text_renders_the_same_image() {
+        let scan = scan(r#"{"text": "secret", "obfuscated": true, "extra": ["§kmore§r text"]}"#);
+        let first = render_preview(&scan, None).unwrap();
+        assert_eq!(render_preview(&scan, None).unwrap(), first);
+        // The text is still hidden
+        assert_ne!(render_preview(&self::scan(r#"{"text": "secret"}"#), None).unwrap(), first);
+    }
+
+    #[test]
+    fn obfuscated_chars_are_printable() {
+        let chars: String = (0..64).map(|i| obfuscated_char("secret", i)).collect();
+        assert!(chars.chars().all(|c| ('!'..='~').contains(&c)));
+        assert_eq!(chars, (0..64).map(|i| obfuscated_char("secret", i)).collect::<String>());
+        // Not a single character repeated
+        assert!(chars.chars().any(|c| c != chars.chars().next().unwrap()));
+    }
+}
@@ -1,3 +1,148 @@
+use actix_web::{Error, HttpMessage, HttpRequest, error};
+use actix_web::dev::ServiceRequest;
+use actix_web::web::{ServiceConfig, scope};
+use protocol::{API_PREFIX, VERSION_HEADER};
+use serde::Deserialize;
+use utoipa::IntoParams;
+use uuid::Uuid;
+use crate::auth::AuthenticatedKey;
+use crate::openapi;
+use crate::fleet::Fleet;
+use crate::routes::admin_routes::get_admin_scope;
+use crate::routes::client_routes::{get_client_scope, get_legacy_client_scope};
+use crate::routes::cluster_routes::get_cluster_scope;
+use crate::routes::config_routes::get_config_scope;
+use crate::routes::favicon_routes::get_favicon_scope;
+use crate::routes::graph_routes::get_graph_scope;
+use crate::routes::honeypot_routes::get_honeypot_scope;
+use crate::routes::info_routes::get_info_scope;
+use crate::routes::mod_routes::get_mod_scope;
+use crate::routes::player_routes::get_player_scope;
+use crate::routes::scout_routes::get_scout_scope;
+use crate::routes::server_routes::get_server_scope;
+use crate::routes::stats_routes::get_stats_scope;
+use crate::routes::worker_routes::get_worker_scope;
+use crate::tls::ClientCertificate;
+
+/// Declares the scope of a route module along with its OpenAPI document (see `crate::openapi`) from
+/// a single list of handlers, in the order the routes are matched, so that every route served is
+/// documented
+macro_rules! route_module {
+    ($api:ident, $get_scope:ident, $path:literal, [$($handler:ident),+ $(,)?] $(, $components:meta)?) => {
+        #[derive(utoipa::OpenApi)]
+        #[openapi(paths($($handler),+) $(, $components)?)]
+        pub struct $api;
+
+        pub fn $get_scope() -> actix_web::Scope {
+            actix_web::web::scope($path)
+                $(.service($handler))+
+        }
+    };
+}
+
+pub mod admin_routes;
 pub mod client_routes;
+pub mod cluster_routes;
+pub mod config_routes;
+pub mod favicon_routes;
+pub mod graph_routes;
+pub mod honeypot_routes;
 pub mod info_routes;
+pub mod mod_routes;
+pub mod player_routes;
 pub mod scout_routes;
+pub mod server_routes;
+pub mod stats_routes;
+pub mod worker_routes;
+
+/// Registers the documentation (see `crate::openapi`) and the routes, which are served under
+/// [`API_PREFIX`]. The routes of the workers released before the routes were versioned are also
+/// served at the root, see [`configure_legacy`].
+pub fn configure_app(cfg: &mut ServiceConfig) {
+    // Registered before the versioned routes, whose scope would take their requests
+    cfg.service(openapi::swagger_ui())
+        .service(openapi::docs_redirect())
+        .service(scope(API_PREFIX).configure(configure))
+        .configure(configure_legacy);
+}
+
+/// Registers the unversioned aliases of the routes that existed before the routes were versioned:
+/// the scout and info routes, and the jobs of the clients. The workers using them don't send
+/// their protocol version either, see [`is_legacy_worker`].
+fn configure_legacy(cfg: &mut ServiceConfig) {
+    cfg.service(get_legacy_client_scope())
+        .service(get_scout_scope())
+        .service(get_info_scope());
+}
+
+/// Registers the routes of every module
+fn configure(cfg: &mut ServiceConfig) {
+    cfg.service(get_client_scope())
+        .service(get_scout_scope())
+        .service(get_info_scope())
+        .service(get_server_scope())
+        .service(get_favicon_scope())
+        .service(get_cluster_scope())
+        .service(get_mod_scope())
+        .service(get_honeypot_scope())
+        .service(get_player_scope())
+        .service(get_graph_scope())
+        .service(get_stats_scope())
+        .service(get_config_scope())
+        .service(get_worker_scope())
+        .service(get_admin_scope());
+}
+
+/// Path of a request without the version prefix, so that both paths of a route are handled alike
+pub fn unversioned_path(path: &str) -> &str {
+    path.strip_prefix(API_PREFIX)
+        .filter(|rest| rest.is_empty() || rest.starts_with('/'))
+        .unwrap_or(path)
+}
+
+/// Registered worker a request of a scout or client acts for, `claimed` being the id it sent. The
+/// worker must have registered with the client certificate and API key of the request, see
+/// [`Fleet::identify`]. Rejected with 403 otherwise.
+pub fn request_worker(req: &HttpRequest, fleet: &Fleet, claimed: Option<Uuid>) -> Result<Option<Uuid>, Error> {
+    let fingerprint = req.conn_data::<ClientCertificate>().map(|c| c.fingerprint.as_str());
+    let lineage = req.extensions().get::<AuthenticatedKey>().map(|k| k.lineage);
+    fleet.identify(claimed, fingerprint, lineage).map_err(error::ErrorForbidden)
+}
+
+/// Query parameters of paginated routes
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PageQuery {
+    page: Option<i64>,
+    per_page: Option<i64>,
+}
+
+impl PageQuery {
+    /// Returns the (limit, offset) of the requested page. Pages start at 0, and contain
+    /// 50 items by default.
+    pub fn limit_offset(&self) -> (i64, i64) {
+        let per_page = self.per_page.unwrap_or(50).clamp(1, 500);
+        let page = self.page.unwrap_or(0).max(0);
+        (per_page, page.saturating_mul(per_page))
+    }
+}
+
+/// Rejects scouts and clients speaking another version of the protocol than the dispatcher, see
+/// the `protocol` crate. Workers sending no version speak the legacy version, see
+/// [`is_legacy_worker`].
+pub fn check_protocol_version(req: &ServiceRequest) -> Result<(), Error> {
+    let path = unversioned_path(req.path());
+    let worker_route = ["/scout", "/client", "/workers", "/config"].iter()
+        .any(|scope| path.strip_prefix(scope).is_some_and(|rest| rest.is_empty() || rest.starts_with('/')));
+    let version = match req.headers().get(VERSION_HEADER) {
+        Some(version) if worker_route => version.to_str().unwrap_or_default(),
+        _ => return Ok(())
+    };
+    protocol::check_version(Some(version)).map_err(|e| error::ErrorBadRequest(format!("Incompatible worker: {}", e)))
+}
+
+/// Whether a worker was released before the protocol was versioned, and speaks
+/// [`protocol::LEGACY_PROTOCOL_VERSION`]
+pub fn is_legacy_worker(req: &HttpRequest) -> bool {
+    !req.headers().contains_key(VERSION_HEADER)
+}
@@ -0,0 +1,184 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use actix_web::{HttpResponse, Responder, Result, delete, error, get, post, web};
+use actix_web::web::{Data, Path};
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use crate::DbPool;
+use crate::auth::{ApiKeys, Role, generate_key};
+use crate::models::ApiKey;
+use crate::schema::api_key;
+
+/// Default time a rotated key stays valid, to update the workers using it
+const DEFAULT_GRACE_SECS: u64 = 24 * 60 * 60;
+/// Longest time a rotated key can stay valid
+const MAX_GRACE_SECS: u64 = 365 * 24 * 60 * 60;
+
+#[derive(Serialize, ToSchema)]
+struct KeyInfo {
+    id: i32,
+    name: String,
+    role: String,
+    prefix: String,
+    created_at: u64,
+    expires_at: Option<u64>,
+    revoked_at: Option<u64>,
+    last_used: Option<u64>,
+}
+
+/// Response of the routes creating a key, the only time the key is shown
+#[derive(Serialize, ToSchema)]
+struct NewKeyInfo {
+    key: String,
+    #[serde(flatten)]
+    info: KeyInfo,
+}
+
+#[derive(Deserialize, ToSchema)]
+struct KeyRequest {
+    name: String,
+    role: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+struct RotateRequest {
+    // Seconds the previous key stays valid, 1 day by default
+    grace_secs: Option<u64>,
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+impl From<ApiKey> for KeyInfo {
+    fn from(key: ApiKey) -> Self {
+        KeyInfo {
+            id: key.id,
+            name: key.name,
+            role: key.role,
+            prefix: key.prefix,
+            created_at: unix_secs(key.created_at),
+            expires_at: key.expires_at.map(unix_secs),
+            revoked_at: key.revoked_at.map(unix_secs),
+            last_used: key.last_used.map(unix_secs),
+        }
+    }
+}
+
+route_module!(AdminApi, get_admin_scope, "/admin", [get_keys, post_key, rotate_key, revoke_key],
+              components(schemas(KeyInfo, NewKeyInfo, KeyRequest, RotateRequest)));
+
+/// API keys, including the expired and revoked ones. Keys themselves aren't stored, only their
+/// prefix is listed.
+#[utoipa::path(get, path = "/api/v1/admin/keys", tag = "admin", responses((status = 200, body = [KeyInfo])))]
+#[get("/keys")]
+async fn get_keys(pool: Data<DbPool>) -> Result<impl Responder> {
+    let keys = web::block(move || {
+        let mut conn = pool.get()
+            .expect("Could not obtain database connection.");
+        ApiKey::all(&mut conn)
+    }).await
+        .map_err(|e| error::ErrorInternalServerError(e.to_string()))?
+        .map_err(|e| error::ErrorInternalServerError(e.to_string()))?;
+
+    Ok(HttpResponse::Ok().json(keys.into_iter().map(KeyInfo::from).collect::<Vec<_>>()))
+}
+
+/// Creates a key, body format: `{ "name": "scout-fra-1", "role": "scout" }`. The role is one of
+/// `scout`, `client`, `read_only` or `admin`. The response contains the key, it can't be
+/// retrieved later.
+#[utoipa::path(post, path = "/api/v1/admin/keys", tag = "admin", request_body = KeyRequest,
+    responses((status = 200, body = NewKeyInfo), (status = 400, description = "Invalid body or role")))]
+#[post("/keys")]
+async fn post_key(json: String, keys: Data<ApiKeys>, pool: Data<DbPool>) -> Result<impl Responder> {
+    let request: KeyRequest = serde_json::from_str(&json).map_err(|e| error::ErrorBadRequest(e.to_string()))?;
+    let role = Role::parse(&request.role)
+        .ok_or_else(|| error::ErrorBadRequest("`role` must be one of scout, client, read_only or admin"))?;
+
+    let (key, new_key) = generate_key(request.name, role);
+    let created = web::block(move || {
+        let mut conn = pool.get()
+            .expect("Could not obtain database connection.");
+        let created = new_key.save_to_db(&mut conn)?;
+        keys.reload(&mut conn)?;
+        Ok::<ApiKey, diesel::result::Error>(created)
+    }).await
+        .map_err(|e| error::ErrorInternalServerError(e.to_string()))?
+        .map_err(|e| error::ErrorInternalServerError(e.to_string()))?;
+
+    Ok(HttpResponse::Ok().json(NewKeyInfo { key, info: created.into() }))
+}
+
+/// Replaces a key with a new one with the same name and role. The previous key stays valid for
+/// `grace_secs` seconds (optional body `{ "grace_secs": 3600 }`, 1 day by default and 1 year at
+/// most). The new key can act for the workers registered with the previous one.
+#[utoipa::path(post, path = "/api/v1/admin/keys/{id}/rotate", tag = "admin",
+    params(("id" = i32, Path, description = "Id of the key")), request_body(content = Option<RotateRequest>),
+    responses((status = 200, body = NewKeyInfo), (status = 400, description = "Invalid body or grace period"),
+              (status = 404, description = "Key not found or revoked")))]
+#[post("/keys/{id}/rotate")]
+async fn rotate_key(path: Path<i32>, json: String, keys: Data<ApiKeys>, pool: Data<DbPool>) -> Result<impl Responder> {
+    let id = path.into_inner();
+    let request: RotateRequest = if json.trim().is_empty() {
+        RotateRequest { grace_secs: None }
+    } else {
+        serde_json::from_str(&json).map_err(|e| error::ErrorBadRequest(e.to_string()))?
+    };
+    let expires_at = Some(request.grace_secs.unwrap_or(DEFAULT_GRACE_SECS))
+        .filter(|grace_secs| *grace_secs <= MAX_GRACE_SECS)
+        .and_then(|grace_secs| SystemTime::now().checked_add(Duration::from_secs(grace_secs)))
+        .ok_or_else(|| error::ErrorBadRequest(format!("`grace_secs` must be at most {}", MAX_GRACE_SECS)))?;
+
+    let rotated = web::block(move || {
+        let mut conn = pool.get()
+            .expect("Could not obtain database connection.");
+        let rotated = conn.build_transaction().run(|conn| {
+            let previous = match api_key::table.find(id).first::<ApiKey>(conn).optional()? {
+                Some(k) if k.revoked_at.is_none() => k,
+                _ => return Ok(None)
+            };
+            let role = Role::parse(&previous.role).unwrap_or(Role::ReadOnly);
+            let (key, mut new_key) = generate_key(previous.name.clone(), role);
+            // The new key acts for the workers of the previous one
+            new_key.origin_id = Some(previous.lineage());
+            let created = diesel::insert_into(api_key::table).values(&new_key).get_result::<ApiKey>(conn)?;
+            // A key that already expires sooner keeps its expiry
+            let expires_at = previous.expires_at.map_or(expires_at, |e| e.min(expires_at));
+            diesel::update(api_key::table.find(id)).set(api_key::expires_at.eq(expires_at)).execute(conn)?;
+            Ok::<Option<(String, ApiKey)>, diesel::result::Error>(Some((key, created)))
+        })?;
+        keys.reload(&mut conn)?;
+        Ok::<Option<(String, ApiKey)>, diesel::result::Error>(rotated)
+    }).await
+        .map_err(|e| error::ErrorInternalServerError(e.to_string()))?
+        .map_err(|e| error::ErrorInternalServerError(e.to_string()))?
+        .ok_or_else(|| error::ErrorNotFound("Key not found or revoked"))?;
+
+    let (key, created) = rotated;
+    Ok(HttpResponse::Ok().json(NewKeyInfo { key, info: created.into() }))
+}
+
+/// Revokes a key, it is rejected right away
+#[utoipa::path(delete, path = "/api/v1/admin/keys/{id}", tag = "admin",
+    params(("id" = i32, Path, description = "Id of the key")),
+    responses((status = 200), (status = 404, description = "Key not found or already revoked")))]
+#[delete("/keys/{id}")]
+async fn revoke_key(path: Path<i32>, keys: Data<ApiKeys>, pool: Data<DbPool>) -> Result<impl Responder> {
+    let id = path.into_inner();
+    let updated = web::block(move || {
+        let mut conn = pool.get()
+            .expect("Could not obtain database connection.");
+        let updated = diesel::update(api_key::table.find(id).filter(api_key::revoked_at.is_null()))
+            .set(api_key::revoked_at.eq(SystemTime::now()))
+            .execute(&mut conn)?;
+        keys.reload(&mut conn)?;
+        Ok::<usize, diesel::result::Error>(updated)
+    }).await
+        .map_err(|e| error::ErrorInternalServerError(e.to_string()))?
+        .map_err(|e| error::ErrorInternalServerError(e.to_string()))?;
+
+    if updated == 0 {
+        return Err(error::ErrorNotFound("Key not found or already revoked"));
+    }
+    Ok(HttpResponse::Ok().finish())
+}
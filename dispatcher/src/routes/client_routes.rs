@@ -1,70 +1,209 @@
-use std::net::{AddrParseError, Ipv4Addr};
+use std::collections::HashMap;
+use std::iter;
+use std::net::Ipv4Addr;
 use std::sync::atomic::{AtomicU32, Ordering};
-use std::time::SystemTime;
-use actix_web::{HttpResponse, Scope, Result, Responder, error, get, post, web};
-use actix_web::web::{Data, Path, scope};
+use std::time::{Duration, SystemTime};
+use actix_web::{HttpRequest, HttpResponse, Result, Responder, error, get, post, rt, web};
+use actix_web::web::{Data, Path, Query};
 use ipnet::{IpNet, Ipv4Net};
-use serde::{Deserialize, Serialize, Serializer};
-use serde::ser::SerializeStruct;
+use protocol::{BatchQuery, ClientBatch, ClientResult, JobResult, ServerStatus, WorkerQuery};
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use uuid::Uuid;
-use crate::{DbPool, ServerState};
-use crate::models::{NewPlayerScan, Player};
+use crate::{DbConnection, DbPool, ServerState};
+use crate::favicon::decode_favicon;
+use crate::fleet::Fleet;
+use crate::honeypot::HoneypotFilter;
+use crate::models::{Favicon, Mod, NewModScan, NewPlayerScan, Player};
 use crate::models::NewScan;
+use crate::mods::parse_mods;
+use crate::routes::{is_legacy_worker, request_worker};
+use crate::samples::check_players;
+use crate::software::classify_response;
+use crate::targeting::Targeting;
+use crate::worker_config::WorkerConfig;
 
 static JOB_ID: AtomicU32 = AtomicU32::new(0);
+/// Most jobs leased at once by a client
+const MAX_BATCH_SIZE: u32 = 4096;
 
-#[derive(Deserialize, Copy, Clone)]
+/// Client job waiting for its result, given to another client once it expires
+#[derive(Serialize, Deserialize, Copy, Clone)]
 pub struct ClientJob {
     pub id: u32,
     pub ip: Ipv4Addr,
     pub creation_time: SystemTime,
+    // Registered client the job was given to
+    #[serde(skip)]
+    pub worker: Option<Uuid>,
 }
 
-// Default used when deserializing w/ missing fields
-impl Default for ClientJob {
-    fn default() -> Self {
-        ClientJob {
-            id: 0,
-            ip: Ipv4Addr::new(0, 0, 0, 0),
-            creation_time: SystemTime::now(),
+impl From<&ClientJob> for protocol::ClientJob {
+    fn from(job: &ClientJob) -> Self {
+        protocol::ClientJob { id: job.id, ip: job.ip, config_version: None }
+    }
+}
+
+/// Client jobs waiting for their result, by id. Saved in the state file as a list.
+#[derive(Default, Clone, Serialize, Deserialize)]
+#[serde(from = "Vec<ClientJob>", into = "Vec<ClientJob>")]
+pub struct OutstandingJobs(HashMap<u32, ClientJob>);
+
+impl From<Vec<ClientJob>> for OutstandingJobs {
+    fn from(jobs: Vec<ClientJob>) -> Self {
+        OutstandingJobs(jobs.into_iter().map(|job| (job.id, job)).collect())
+    }
+}
+
+impl From<OutstandingJobs> for Vec<ClientJob> {
+    fn from(jobs: OutstandingJobs) -> Self {
+        jobs.0.into_values().collect()
+    }
+}
+
+impl OutstandingJobs {
+    /// Removes a job, None if it isn't outstanding: its result was already received, or it
+    /// expired and was given to another client
+    pub fn take(&mut self, id: u32, ip: Ipv4Addr) -> Option<ClientJob> {
+        match self.0.get(&id) {
+            Some(job) if job.ip == ip => self.0.remove(&id),
+            _ => None
         }
     }
+
+    /// Removes the jobs older than `expiry`
+    pub fn remove_expired(&mut self, expiry: Duration) -> Vec<ClientJob> {
+        let now = SystemTime::now();
+        let expired: Vec<ClientJob> = self.0.values()
+            // Jobs created after now, if the clock went back, aren't expired
+            .filter(|job| now.duration_since(job.creation_time).is_ok_and(|age| age > expiry))
+            .copied()
+            .collect();
+        for job in &expired {
+            self.0.remove(&job.id);
+        }
+        expired
+    }
+
+    pub fn take_all(&mut self) -> Vec<ClientJob> {
+        self.0.drain().map(|(_, job)| job).collect()
+    }
 }
 
-impl Serialize for ClientJob {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
-        let mut state = serializer.serialize_struct("ClientJob", 2)?;
-        state.serialize_field("id", &self.id.to_string())?;
-        state.serialize_field("ip", &self.ip.to_string())?;
-        state.end()
+/// Takes up to `count` IPs to scan, and adds their jobs to the outstanding list
+fn lease_jobs(state: &ServerState, honeypots: &HoneypotFilter, count: usize, worker: Option<Uuid>) -> Vec<ClientJob> {
+    let ips: Vec<Ipv4Addr> = {
+        let mut valid_ips = state.valid_ips.lock().unwrap();
+        // Skip servers flagged as honeypots since they were queued
+        iter::from_fn(|| valid_ips.pop_front())
+            .filter(|ip| !honeypots.contains(*ip))
+            .take(count)
+            .collect()
+    };
+    let creation_time = SystemTime::now();
+    let jobs: Vec<ClientJob> = ips.into_iter()
+        .map(|ip| ClientJob { id: JOB_ID.fetch_add(1, Ordering::Relaxed), ip, creation_time, worker })
+        .collect();
+    state.outstanding_client_jobs.lock().unwrap().0.extend(jobs.iter().map(|job| (job.id, *job)));
+    jobs
+}
+
+/// Status response of a result, None if the server is down
+fn result_response(result: ClientResult) -> Result<Option<Value>> {
+    match (result.status, result.response) {
+        (ServerStatus::Down, _) => Ok(None),
+        (ServerStatus::Up, Some(response)) if !response.is_null() => Ok(Some(response)),
+        // Empty response when status is up, invalid state
+        (ServerStatus::Up, _) => Err(error::ErrorBadRequest(format!("No response provided for {} while status is 'up'", result.ip)))
+    }
+}
+
+/// Removes a completed job from the outstanding list, None if it isn't outstanding anymore (see
+/// [`OutstandingJobs::take`]). The job is credited to the registered client that sent the result
+/// (`worker_id`, see [`request_worker`]), or else to the one it was given to.
+fn complete_job(state: &ServerState, targeting: &Targeting, fleet: &Fleet, id: u32, ip: Ipv4Addr, up: bool,
+                worker_id: Option<Uuid>) -> Option<ClientJob> {
+    let job = state.outstanding_client_jobs.lock().unwrap().take(id, ip)?;
+    // Servers are often next to other servers, scan the rest of the /24
+    if up && targeting.is_adaptive() {
+        state.target_lanes.lock().unwrap().add_neighbours(ip);
+    }
+    let worker = worker_id.or(job.worker).filter(|w| fleet.contains(*w));
+    Some(ClientJob { worker, ..job })
+}
+
+/// Saves the scans of completed jobs in the background, since looking up their players on
+/// PlayerDB takes a while. The servers whose scan couldn't be saved are scanned again.
+fn save_scans(state: Data<ServerState>, pool: Data<DbPool>, scans: Vec<(Ipv4Addr, Value, Option<Uuid>)>) {
+    if scans.is_empty() {
+        return;
     }
+    rt::spawn(async move {
+        let failed = web::block(move || {
+            let mut conn = pool.get()
+                .expect("Could not obtain database connection.");
+            scans.into_iter()
+                .filter(|(ip, response, worker_id)| !save_scan(*ip, response, *worker_id, &mut conn))
+                .map(|(ip, _, _)| ip)
+                .collect::<Vec<_>>()
+        }).await;
+        match failed {
+            Ok(failed) if failed.is_empty() => {}
+            Ok(failed) => {
+                println!("Could not save {} scans to database, scanning them again", failed.len());
+                state.valid_ips.lock().unwrap().extend(failed);
+            }
+            Err(e) => println!("Scan saving job panicked: {}", e)
+        }
+    });
 }
 
-pub fn get_client_scope() -> Scope {
-    scope("/client")
+route_module!(ClientApi, get_client_scope, "/client", [get_job, post_job, post_results],
+              components(schemas(protocol::ClientJob, ClientBatch, ClientResult, JobResult, ServerStatus)));
+
+/// Routes of the clients released before the routes were versioned, see
+/// [`crate::routes::configure_app`]
+pub fn get_legacy_client_scope() -> actix_web::Scope {
+    web::scope("/client")
         .service(get_job)
         .service(post_job)
 }
 
+/// Leases jobs to a client. Without `count`, returns a single job. With it, returns a batch of up
+/// to `count` jobs (at most 4096), which share the version of the client's settings. Clients that
+/// don't send their protocol version get the id of the job as a string.
+#[utoipa::path(get, path = "/api/v1/client/job", tag = "workers",
+    params(WorkerQuery, BatchQuery, ("X-Msearch-Protocol" = Option<u32>, Header, description = "Protocol version of the worker, see the `protocol` crate")),
+    // Named after the schema of `protocol::ClientJob`, not the `ClientJob` of this module
+    responses((status = 200, description = "A `ClientJob`, or a `ClientBatch` with `count`", body = ClientJob),
+              (status = 400, description = "`count` is 0"), (status = 403, description = "Client registered with another certificate or API key"),
+              (status = 404, description = "No job available")))]
 #[get("/job")]
-async fn get_job(state: Data<ServerState>) -> Result<impl Responder> {
-    let mut valid_ips = state.valid_ips.lock().unwrap();
-
-    match valid_ips.pop_front() {
-        Some(ip) => {
-            let job = ClientJob {
-                id: JOB_ID.fetch_add(1, Ordering::Relaxed),
-                ip,
-                creation_time: SystemTime::now(),
-            };
-            // Add job to outstanding list
-            let mut outstanding = state.outstanding_client_jobs.lock().unwrap();
-            outstanding.push_back(job);
-
-            Ok(HttpResponse::Ok().json(job))
-        }
-        None => Err(error::ErrorNotFound("No job available"))
+async fn get_job(req: HttpRequest, query: Query<WorkerQuery>, batch: Query<BatchQuery>, state: Data<ServerState>, honeypots: Data<HoneypotFilter>,
+                 fleet: Data<Fleet>, worker_config: Data<WorkerConfig>) -> Result<impl Responder> {
+    let count = match batch.count {
+        Some(0) => return Err(error::ErrorBadRequest("`count` must be above 0")),
+        Some(count) => count.min(MAX_BATCH_SIZE),
+        None => 1
+    };
+    let worker_id = request_worker(&req, &fleet, query.worker_id)?;
+    let jobs = lease_jobs(&state, &honeypots, count as usize, worker_id);
+    if jobs.is_empty() {
+        return Err(error::ErrorNotFound("No job available"));
+    }
+
+    let config_version = worker_config.resolve(query.worker.as_deref(), query.group.as_deref())
+        .map(|c| c.version);
+    let job = protocol::ClientJob { config_version: config_version.clone(), ..protocol::ClientJob::from(&jobs[0]) };
+    match batch.count {
+        // Clients released before the protocol was versioned
+        None if is_legacy_worker(&req) => Ok(HttpResponse::Ok().json(job.to_legacy())),
+        // Clients released before batches
+        None => Ok(HttpResponse::Ok().json(job)),
+        Some(_) => Ok(HttpResponse::Ok().json(ClientBatch {
+            jobs: jobs.iter().map(protocol::ClientJob::from).collect(),
+            config_version,
+        }))
     }
 }
 
@@ -78,7 +217,7 @@ async fn get_job(state: Data<ServerState>) -> Result<impl Responder> {
 ///   }
 /// }
 /// ```
-/// `status` can take a value of `up`, or any other value meaning `down`<br>
+/// `status` is either `up` or `down`<br>
 /// `response` is an optional field and does not have to be provided if `status` is not `up`.
 ///  Must be included otherwise.
 ///
@@ -87,134 +226,170 @@ async fn get_job(state: Data<ServerState>) -> Result<impl Responder> {
 /// `description`, `favicon` (optional, b64 png format)
 /// `players.sample` is also an optional field, and will be omitted from the response
 /// if there are no online players (`players.online==0`)
+///
+/// Registered clients send their id as `worker_id` in the query, to record the client that
+/// produced the scan. Clients with a certificate are identified by it.
+///
+/// The scan is saved after the response, results of jobs that aren't outstanding anymore (already
+/// received, or expired) are ignored. Sending a result again is harmless.
+#[utoipa::path(post, path = "/api/v1/client/job/{id}", tag = "workers",
+    params(("id" = u32, Path, description = "Id of the job"), WorkerQuery, ("X-Msearch-Protocol" = Option<u32>, Header, description = "Protocol version of the worker, see the `protocol` crate")),
+    request_body = ClientResult, responses((status = 200), (status = 400, description = "Invalid body, or incompatible worker"),
+        (status = 403, description = "Client registered with another certificate or API key")))]
 #[post("/job/{id}")]
-async fn post_job(path: Path<u32>, json: String, state: Data<ServerState>, pool: Data<DbPool>) -> Result<impl Responder> {
-    // This function is truly horrific, rewrite when possible (refactor validation into separate function?)
-
+#[allow(clippy::too_many_arguments)]
+async fn post_job(req: HttpRequest, path: Path<u32>, query: Query<WorkerQuery>, json: String, state: Data<ServerState>, targeting: Data<Targeting>,
+                  fleet: Data<Fleet>, pool: Data<DbPool>) -> Result<impl Responder> {
     let id = path.into_inner();
+    let worker_id = request_worker(&req, &fleet, query.worker_id)?;
 
     if json.is_empty() {
         return Ok(HttpResponse::BadRequest().body("Invalid JSON data received (empty)"));
     }
+    let result: ClientResult = serde_json::from_str(&json)
+        .map_err(|e| error::ErrorBadRequest(e.to_string()))?;
+    let ip = result.ip;
+    let response = result_response(result)?;
 
-    // Parse json
-    let json: Value = serde_json::from_str(&json).map_err(|e| error::ErrorBadRequest(e.to_string()))?;
-    let status = json["status"].as_str()
-        .ok_or_else(|| error::ErrorBadRequest("Missing 'status' field"))?;
-    let ip: Ipv4Addr = json["ip"].as_str()
-        .ok_or_else(|| error::ErrorBadRequest("Missing `ip` field"))?
-        .parse().map_err(|e: AddrParseError| error::ErrorBadRequest(e.to_string()))?;
-    let response = json["response"].clone();
-
-    // If server isn't up, no need to save to db, just remove from outstanding job list
-    if status.to_lowercase() != "up" {
-        // Remove job from outstanding list (if already in list)
-        let mut outstanding = state.outstanding_client_jobs.lock().unwrap();
-        if let Some(idx) = outstanding.iter().position(|x| x.id == id && x.ip == ip) {
-            outstanding.remove(idx);
-        };
-        return Ok(HttpResponse::Ok().finish());
+    let job = match complete_job(&state, &targeting, &fleet, id, ip, response.is_some(), worker_id) {
+        Some(job) => job,
+        None => return Ok(HttpResponse::Ok().finish())
+    };
+    if let Some(worker) = job.worker {
+        fleet.record_job(worker, 1);
     }
-
-    // Server is up, expect response
-    if response.is_null() {
-        // Empty response when status is up, invalid state
-        return Err(error::ErrorBadRequest("No response provided while status is 'up'"));
+    // If server isn't up, no need to save to db
+    if let Some(response) = response {
+        save_scans(state, pool, vec![(ip, response, job.worker)]);
     }
+    Ok(HttpResponse::Ok().finish())
+}
 
-    // Remove from outstanding job list
-    {
-        let mut outstanding = state.outstanding_client_jobs.lock().unwrap();
-        if let Some(idx) = outstanding.iter().position(|x| x.id == id && x.ip == ip) {
-            outstanding.remove(idx);
+/// Results of a batch of jobs (see `GET /client/job?count=N`), body format:
+/// ```json
+/// [
+///   { "id": 42, "status": "up", "ip": "0.0.0.0", "response": { ... } },
+///   { "id": 43, "status": "down", "ip": "0.0.0.1" }
+/// ]
+/// ```
+/// Each result has the format of `POST /client/job/{id}` along with the id of its job. The batch
+/// may only contain part of the jobs, the others are given to another client once they expire.
+/// The whole batch is rejected if a result is invalid. Like single results, the scans are saved
+/// after the response and sending a batch again is harmless.
+#[utoipa::path(post, path = "/api/v1/client/results", tag = "workers",
+    params(WorkerQuery, ("X-Msearch-Protocol" = Option<u32>, Header, description = "Protocol version of the worker, see the `protocol` crate")),
+    request_body = Vec<JobResult>, responses((status = 200), (status = 400, description = "Invalid body, or incompatible worker"),
+        (status = 403, description = "Client registered with another certificate or API key")))]
+#[post("/results")]
+async fn post_results(req: HttpRequest, query: Query<WorkerQuery>, json: String, state: Data<ServerState>, targeting: Data<Targeting>,
+                      fleet: Data<Fleet>, pool: Data<DbPool>) -> Result<impl Responder> {
+    let worker_id = request_worker(&req, &fleet, query.worker_id)?;
+    let results: Vec<JobResult> = serde_json::from_str(&json)
+        .map_err(|e| error::ErrorBadRequest(e.to_string()))?;
+    let results = results.into_iter()
+        .map(|JobResult { id, result }| Ok((id, result.ip, result_response(result)?)))
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut completed: HashMap<Uuid, u64> = HashMap::new();
+    let mut scans = Vec::new();
+    for (id, ip, response) in results {
+        let job = match complete_job(&state, &targeting, &fleet, id, ip, response.is_some(), worker_id) {
+            Some(job) => job,
+            None => continue
         };
+        if let Some(worker) = job.worker {
+            *completed.entry(worker).or_default() += 1;
+        }
+        if let Some(response) = response {
+            scans.push((ip, response, job.worker));
+        }
+    }
+    // A batch counts as a single job of its client
+    for (worker, items) in completed {
+        fleet.record_job(worker, items);
     }
 
-    // Save retrieved values into DB
-    // DB isn't async, run in block
-    let success = web::block(move || {
-        let mut conn = pool.get()
-            .expect("Could not obtain database connection.");
+    save_scans(state, pool, scans);
+    Ok(HttpResponse::Ok().finish())
+}
 
+/// Saves the scan of a server that is up, along with its favicon, mods and players. Returns false
+/// if it couldn't be saved, in which case nothing was saved.
+fn save_scan(ip: Ipv4Addr, response: &Value, worker_id: Option<Uuid>, conn: &mut DbConnection) -> bool {
+    // Decorative sample entries are only kept as text, they aren't players
+    let sample = check_players(&response["players"]);
 
-        // Create Scan struct instance
-        // Description is a JSON object, serialize to string before saving to db
-        let desc = response.get("description")
-            .map(|x| x.to_string());
+    // Players are looked up on PlayerDB before writing anything, to keep the transaction short:
+    // If PlayerDB UUID matches server UUID:
+    //   Valid online player, save in DB, updating username of entry if needed
+    // If username isn't a valid MC account name:
+    //   Create DB entry with username and null UUID
+    // If username exists with different UUID:
+    //   Create DB entry w/ real name and UUID, assoc with server UUID (wrong/offline UUID)
+    let mut players = Vec::new();
+    for (player_name, u) in sample.players {
+        match Player::query_playerdb(&player_name) {
+            Ok(db_uuid) => players.push((player_name, db_uuid, u)),
+            Err(_) => return false
+        }
+    }
+
+    // Description is a JSON object, serialize to string before saving to db
+    let desc = response.get("description")
+        .map(|x| x.to_string());
+    let png = match response["favicon"].as_str().map(decode_favicon) {
+        Some(Ok(png)) => Some(png),
+        Some(Err(e)) => {
+            println!("Discarding favicon of {}: {}", ip, e);
+            None
+        }
+        None => None
+    };
+    let software = classify_response(response);
+
+    conn.build_transaction().run(|conn| {
+        // Favicons are deduplicated, only keep a reference to the stored image
+        let favicon_id = match png {
+            Some(png) => Some(Favicon::get_or_create(png, conn)?.id),
+            None => None
+        };
         let new_scan = NewScan {
             ip: IpNet::V4(Ipv4Net::from(ip)),
             version: response["version"]["name"].as_str().map(String::from),
             online_count: response["players"]["online"].as_u64().map(|x| x as i32),
             max_count: response["players"]["max"].as_u64().map(|x| x as i32),
             description: desc,
-            favicon: response["favicon"].as_str().map(String::from),
-        };
-        let new_scan = match new_scan.save_to_db(&mut conn) {
-            Ok(s) => s,
-            Err(_) => return false
-        };
-
-        let players = response["players"]["sample"].as_array();
+            favicon_id,
+            protocol: response["version"]["protocol"].as_i64().map(|x| x as i32),
+            software: Some(software.family),
+            is_proxy: Some(software.is_proxy),
+            is_modded: Some(software.is_modded),
+            game_version_min: software.version_min,
+            game_version_max: software.version_max,
+            sample_text: sample.sample_text.into_iter().map(Some).collect(),
+            player_count_spoofed: sample.count_spoofed,
+            worker_id,
+        }.save_to_db(conn)?;
 
-        if players.is_none() {
-            return true;
+        // Mods advertised by Forge servers
+        let mut mod_scans = Vec::new();
+        for info in parse_mods(response) {
+            let m = Mod::get_or_create(&info, conn)?;
+            mod_scans.push(NewModScan { mod_id: m.id, scan_id: new_scan.id });
         }
+        NewModScan::save_all(&mod_scans, conn)?;
 
-        for u in players.unwrap() {
-            let player_name = u["name"].as_str().unwrap();
-            match Uuid::parse_str(u["id"].as_str().unwrap()) {
-                Ok(u) => {
-                    // Workflow:
-                    // Query PlayerDB with username to get UUID
-                    // If PlayerDB UUID matches server UUID:
-                    //   Valid online player, save in DB, updating username of entry if needed
-                    // If username isn't a valid MC account name:
-                    //   Create DB entry with username and null UUID
-                    // If username exists with different UUID:
-                    //   Create DB entry w/ real name and UUID, assoc with server UUID (wrong/offline UUID)
-
-                    let db_uuid = match Player::query_playerdb(player_name) {
-                        Ok(x) => x,
-                        Err(_) => return false
-                    };
-
-                    let db_player = match db_uuid {
-                        Some(db_uuid) => {
-                            // Username is real, either UUIDs match (online) or not (offline)
-                            // In both cases, create user in db if needed
-                            match Player::create_if_not_exist(String::from(player_name), Some(db_uuid), &mut conn) {
-                                Ok(p) => p,
-                                Err(_) => return false
-                            }
-                        }
-                        None => {
-                            // Fake username, create user in db with no UUID (if needed)
-                            match Player::create_if_not_exist(String::from(player_name), None, &mut conn) {
-                                Ok(p) => p,
-                                Err(_) => return false
-                            }
-                        }
-                    };
-
-                    let new_playerscan = NewPlayerScan {
-                        player_id: db_player.id,
-                        scan_id: new_scan.id,
-                        player_scan_uuid: u
-                    };
-                    match new_playerscan.save_to_db(&mut conn) {
-                        Ok(_) => {}
-                        Err(_) => return false
-                    }
-                }
-                Err(_) => return false
-            }
+        for (player_name, db_uuid, u) in players {
+            // Username is real, either UUIDs match (online) or not (offline). In both cases,
+            // create user in db if needed. Fake usernames are created with no UUID.
+            let db_player = Player::create_if_not_exist(player_name, db_uuid, conn)
+                .map_err(|_| diesel::result::Error::RollbackTransaction)?;
+            NewPlayerScan {
+                player_id: db_player.id,
+                scan_id: new_scan.id,
+                player_scan_uuid: u
+            }.save_to_db(conn)?;
         }
-        true
-    }).await.map_err(|e| error::ErrorInternalServerError(e.to_string()))?;
-
-    if success {
-        Ok(HttpResponse::Ok().finish())
-    } else {
-        Err(error::ErrorInternalServerError("Could not successfully save scan to database"))
-    }
+        Ok::<(), diesel::result::Error>(())
+    }).is_ok()
 }
@@ -0,0 +1,102 @@
+use std::net::IpAddr;
+use std::time::UNIX_EPOCH;
+use actix_web::{HttpResponse, Responder, Result, error, get, web};
+use actix_web::web::{Data, Path, Query};
+use diesel::prelude::*;
+use ipnet::IpNet;
+use serde::Serialize;
+use utoipa::ToSchema;
+use crate::DbPool;
+use crate::models::Cluster;
+use crate::routes::PageQuery;
+use crate::schema::{cluster, favicon};
+
+#[derive(Serialize, ToSchema)]
+pub struct ClusterInfo {
+    pub id: i32,
+    size: i32,
+    // Hash of the most common favicon, see `/favicons/{hash}.png`
+    favicon: Option<String>,
+    motd: Option<String>,
+    updated_at: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<Vec<String>>)]
+    servers: Option<Vec<IpAddr>>,
+}
+
+impl ClusterInfo {
+    fn new(cluster: Cluster, favicon: Option<String>, servers: Option<Vec<IpNet>>) -> Self {
+        ClusterInfo {
+            id: cluster.id,
+            size: cluster.size,
+            favicon,
+            motd: cluster.motd,
+            updated_at: cluster.updated_at.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs()),
+            servers: servers.map(|s| s.iter().map(IpNet::addr).collect()),
+        }
+    }
+}
+
+route_module!(ClusterApi, get_cluster_scope, "/clusters", [get_clusters, get_cluster],
+              components(schemas(ClusterInfo)));
+
+/// Lists the detected server networks, largest first
+#[utoipa::path(get, path = "/api/v1/clusters", tag = "clusters", params(PageQuery),
+    responses((status = 200, body = [ClusterInfo])))]
+#[get("")]
+async fn get_clusters(query: Query<PageQuery>, pool: Data<DbPool>) -> Result<impl Responder> {
+    let (limit, offset) = query.limit_offset();
+
+    let clusters = web::block(move || {
+        let mut conn = pool.get()
+            .expect("Could not obtain database connection.");
+
+        cluster::table
+            .left_join(favicon::table)
+            .select((cluster::all_columns, favicon::hash.nullable()))
+            .order((cluster::size.desc(), cluster::cluster_id))
+            .limit(limit)
+            .offset(offset)
+            .load::<(Cluster, Option<String>)>(&mut conn)
+            .map_err(|e| e.to_string())
+    }).await
+        .map_err(|e| error::ErrorInternalServerError(e.to_string()))?
+        .map_err(error::ErrorInternalServerError)?;
+
+    let clusters: Vec<ClusterInfo> = clusters.into_iter()
+        .map(|(c, favicon)| ClusterInfo::new(c, favicon, None))
+        .collect();
+    Ok(HttpResponse::Ok().json(clusters))
+}
+
+/// Returns a network along with the IPs of all of its servers
+#[utoipa::path(get, path = "/api/v1/clusters/{id}", tag = "clusters", params(("id" = i32, Path, description = "Id of the network")),
+    responses((status = 200, body = ClusterInfo), (status = 404, description = "Cluster not found")))]
+#[get("/{id}")]
+async fn get_cluster(path: Path<i32>, pool: Data<DbPool>) -> Result<impl Responder> {
+    let id = path.into_inner();
+
+    let cluster = web::block(move || {
+        let mut conn = pool.get()
+            .expect("Could not obtain database connection.");
+
+        let result = cluster::table
+            .left_join(favicon::table)
+            .filter(cluster::cluster_id.eq(id))
+            .select((cluster::all_columns, favicon::hash.nullable()))
+            .first::<(Cluster, Option<String>)>(&mut conn)
+            .optional()?;
+        match result {
+            Some((cluster, favicon)) => {
+                let servers = cluster.server_ips(&mut conn)?;
+                Ok(Some(ClusterInfo::new(cluster, favicon, Some(servers))))
+            }
+            None => Ok(None)
+        }
+    }).await
+        .map_err(|e| error::ErrorInternalServerError(e.to_string()))?
+        .map_err(|e: diesel::result::Error| error::ErrorInternalServerError(e.to_string()))?
+        .ok_or_else(|| error::ErrorNotFound("Cluster not found"))?;
+
+    Ok(HttpResponse::Ok().json(cluster))
+}
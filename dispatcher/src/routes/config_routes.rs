@@ -0,0 +1,21 @@
+use actix_web::{HttpResponse, Responder, Result, error, get};
+use actix_web::web::{Data, Query};
+use protocol::{ResolvedConfig, WorkerQuery, WorkerSettings};
+use crate::worker_config::WorkerConfig;
+
+route_module!(ConfigApi, get_config_scope, "/config", [get_worker_config],
+              components(schemas(ResolvedConfig, WorkerSettings)));
+
+/// Settings of a worker, see [`crate::worker_config`]. Only the settings managed by the
+/// dispatcher are included, e.g.
+/// ```json
+/// { "version": "2f1c0a4b9d3e5f60", "send_rate": 500, "stop_timeout": 10 }
+/// ```
+#[utoipa::path(get, path = "/api/v1/config/worker", tag = "workers", params(WorkerQuery, ("X-Msearch-Protocol" = Option<u32>, Header, description = "Protocol version of the worker, see the `protocol` crate")),
+    responses((status = 200, body = ResolvedConfig), (status = 404, description = "No worker config")))]
+#[get("/worker")]
+async fn get_worker_config(query: Query<WorkerQuery>, worker_config: Data<WorkerConfig>) -> Result<impl Responder> {
+    let config = worker_config.resolve(query.worker.as_deref(), query.group.as_deref())
+        .ok_or_else(|| error::ErrorNotFound("No worker config"))?;
+    Ok(HttpResponse::Ok().json(config))
+}
@@ -0,0 +1,53 @@
+use actix_web::{HttpMessage, HttpRequest, HttpResponse, Responder, Result, error, get, web};
+use actix_web::http::header::{CacheControl, CacheDirective, ETag, EntityTag, IfNoneMatch};
+use actix_web::web::{Data, Path};
+use crate::DbPool;
+use crate::models::Favicon;
+
+route_module!(FaviconApi, get_favicon_scope, "/favicons", [get_favicon]);
+
+/// Serves a stored favicon as a PNG image. Favicons are addressed by the hash of their content,
+/// so they never change and can be cached indefinitely.
+#[utoipa::path(get, path = "/api/v1/favicons/{hash}.png", tag = "servers",
+    params(("hash" = String, Path, description = "SHA-256 of the favicon, in hexadecimal")),
+    responses((status = 200, description = "PNG image", content_type = "image/png"), (status = 304, description = "Not modified"),
+              (status = 404, description = "Favicon not found")))]
+#[get("/{hash}.png")]
+async fn get_favicon(path: Path<String>, req: HttpRequest, pool: Data<DbPool>) -> Result<impl Responder> {
+    let hash = path.into_inner().to_lowercase();
+    if hash.len() != 64 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(error::ErrorNotFound("Invalid favicon hash"));
+    }
+
+    let etag = EntityTag::new_strong(hash.clone());
+    let cache_control = CacheControl(vec![
+        CacheDirective::Public,
+        CacheDirective::MaxAge(31_536_000),
+        CacheDirective::Extension(String::from("immutable"), None),
+    ]);
+
+    // Content is immutable, no need to query the database if the client already has it
+    if let Some(IfNoneMatch::Items(tags)) = req.get_header::<IfNoneMatch>() {
+        if tags.iter().any(|t| t.weak_eq(&etag)) {
+            return Ok(HttpResponse::NotModified()
+                .insert_header(ETag(etag))
+                .insert_header(cache_control)
+                .finish());
+        }
+    }
+
+    let favicon = web::block(move || {
+        let mut conn = pool.get()
+            .expect("Could not obtain database connection.");
+        Favicon::find_by_hash(&hash, &mut conn).map_err(|e| e.to_string())
+    }).await
+        .map_err(|e| error::ErrorInternalServerError(e.to_string()))?
+        .map_err(error::ErrorInternalServerError)?
+        .ok_or_else(|| error::ErrorNotFound("Favicon not found"))?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("image/png")
+        .insert_header(ETag(etag))
+        .insert_header(cache_control)
+        .body(favicon.data))
+}
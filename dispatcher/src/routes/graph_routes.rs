@@ -0,0 +1,58 @@
+use actix_web::{HttpResponse, Responder, Result, error, get, web};
+use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType};
+use actix_web::web::{Data, Path, Query};
+use serde::Deserialize;
+use utoipa::IntoParams;
+use crate::DbPool;
+use crate::graph::{GraphFormat, GraphKind, export_graph};
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ExportQuery {
+    // Edges with a lower weight are left out, defaults to 1 (whole graph)
+    min_weight: Option<i32>,
+}
+
+route_module!(GraphApi, get_graph_scope, "/graph", [get_graph]);
+
+/// Exports the server (`servers.graphml`, `servers.gexf`) or player (`players.graphml`,
+/// `players.gexf`) co-occurrence graph
+#[utoipa::path(get, path = "/api/v1/graph/{file}", tag = "players",
+    params(("file" = String, Path, description = "`servers` or `players`, followed by `.graphml` or `.gexf`"), ExportQuery),
+    responses((status = 200, description = "GraphML or GEXF document", content_type = "application/xml"),
+              (status = 404, description = "Unknown graph or format")))]
+#[get("/{file}")]
+async fn get_graph(path: Path<String>, query: Query<ExportQuery>, pool: Data<DbPool>) -> Result<impl Responder> {
+    let file = path.into_inner();
+    let (kind, format) = match file.split_once('.') {
+        Some((kind, format)) => (kind, format),
+        None => return Err(error::ErrorNotFound("Unknown graph"))
+    };
+    let kind = match kind {
+        "servers" => GraphKind::Servers,
+        "players" => GraphKind::Players,
+        _ => return Err(error::ErrorNotFound("Unknown graph, expected `servers` or `players`"))
+    };
+    let (format, content_type) = match format {
+        "graphml" => (GraphFormat::GraphMl, "application/graphml+xml"),
+        "gexf" => (GraphFormat::Gexf, "application/gexf+xml"),
+        _ => return Err(error::ErrorNotFound("Unknown format, expected `graphml` or `gexf`"))
+    };
+    let min_weight = query.min_weight.unwrap_or(1);
+
+    let graph = web::block(move || {
+        let mut conn = pool.get()
+            .expect("Could not obtain database connection.");
+        export_graph(kind, format, min_weight, &mut conn)
+    }).await
+        .map_err(|e| error::ErrorInternalServerError(e.to_string()))?
+        .map_err(|e| error::ErrorInternalServerError(e.to_string()))?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(content_type)
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(file)],
+        })
+        .body(graph))
+}
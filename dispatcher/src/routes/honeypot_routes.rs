@@ -0,0 +1,53 @@
+use std::time::UNIX_EPOCH;
+use actix_web::{HttpResponse, Responder, Result, error, get, web};
+use actix_web::web::{Data, Query};
+use diesel::prelude::*;
+use serde::Serialize;
+use utoipa::ToSchema;
+use crate::DbPool;
+use crate::models::Honeypot;
+use crate::routes::PageQuery;
+use crate::schema::honeypot;
+
+#[derive(Serialize, ToSchema)]
+struct HoneypotInfo {
+    // Single IP (`1.2.3.4/32`) or netblock (`1.2.3.0/24`)
+    network: String,
+    score: f32,
+    reasons: Vec<String>,
+    updated_at: u64,
+}
+
+route_module!(HoneypotApi, get_honeypot_scope, "/honeypots", [get_honeypots],
+              components(schemas(HoneypotInfo)));
+
+/// Lists the IPs and netblocks flagged as likely honeypots, highest score first
+#[utoipa::path(get, path = "/api/v1/honeypots", tag = "honeypots", params(PageQuery),
+    responses((status = 200, body = [HoneypotInfo])))]
+#[get("")]
+async fn get_honeypots(query: Query<PageQuery>, pool: Data<DbPool>) -> Result<impl Responder> {
+    let (limit, offset) = query.limit_offset();
+
+    let honeypots = web::block(move || {
+        let mut conn = pool.get()
+            .expect("Could not obtain database connection.");
+
+        honeypot::table
+            .order((honeypot::score.desc(), honeypot::network))
+            .limit(limit)
+            .offset(offset)
+            .load::<Honeypot>(&mut conn)
+    }).await
+        .map_err(|e| error::ErrorInternalServerError(e.to_string()))?
+        .map_err(|e| error::ErrorInternalServerError(e.to_string()))?;
+
+    let honeypots: Vec<HoneypotInfo> = honeypots.into_iter()
+        .map(|h| HoneypotInfo {
+            network: h.network.to_string(),
+            score: h.score,
+            reasons: h.reasons.into_iter().flatten().collect(),
+            updated_at: h.updated_at.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs()),
+        })
+        .collect();
+    Ok(HttpResponse::Ok().json(honeypots))
+}
@@ -1,15 +1,69 @@
 use std::ops::Deref;
-use actix_web::{get, HttpResponse, Responder, Scope};
-use actix_web::web::{Data, scope};
+use actix_web::{get, HttpResponse, Responder};
+use actix_web::web::{Data, Query};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
 use crate::ServerState;
+use crate::coverage::{Coverage, Progress, RangeProgress};
+use crate::fleet::{Fleet, WorkerInfo};
+use crate::targeting::{BlockScore, TargetMode, Targeting};
 
-pub fn get_info_scope() -> Scope {
-    scope("/info")
-        .service(get_valid_ips)
-}
+route_module!(InfoApi, get_info_scope, "/info", [get_valid_ips, get_blocks, get_progress, get_workers],
+              components(schemas(BlocksInfo, BlockScore, Progress, RangeProgress, WorkerInfo)));
 
+#[utoipa::path(get, path = "/api/v1/info/ips", tag = "info",
+    responses((status = 200, description = "IPs waiting to be scanned by a client", body = [String])))]
 #[get("/ips")]
 async fn get_valid_ips(state: Data<ServerState>) -> impl Responder {
     let ips = state.valid_ips.lock().unwrap();
     HttpResponse::Ok().json(ips.deref())
-}
\ No newline at end of file
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct BlocksQuery {
+    // Number of /16s, 50 by default
+    count: Option<usize>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct BlocksInfo {
+    // `uniform` or `adaptive`
+    mode: &'static str,
+    blocks: Vec<BlockScore>,
+    queued_neighbours: usize,
+}
+
+/// Most productive /16s, with their hit rates (see `crate::targeting`), and the number of /24s
+/// waiting in the neighbours lane
+#[utoipa::path(get, path = "/api/v1/info/blocks", tag = "info", params(BlocksQuery),
+    responses((status = 200, body = BlocksInfo)))]
+#[get("/blocks")]
+async fn get_blocks(query: Query<BlocksQuery>, state: Data<ServerState>, targeting: Data<Targeting>) -> impl Responder {
+    let blocks = targeting.best_blocks(query.count.unwrap_or(50));
+    let queued_neighbours = state.target_lanes.lock().unwrap().queued_neighbours();
+    HttpResponse::Ok().json(BlocksInfo {
+        mode: match targeting.mode {
+            TargetMode::Uniform => "uniform",
+            TargetMode::Adaptive => "adaptive"
+        },
+        blocks,
+        queued_neighbours,
+    })
+}
+
+/// Coverage of the current scan cycle: share of the public IPv4 space probed, estimated time
+/// left at the current rate, and coverage of each /8
+#[utoipa::path(get, path = "/api/v1/info/progress", tag = "info", responses((status = 200, body = Progress)))]
+#[get("/progress")]
+async fn get_progress(coverage: Data<Coverage>) -> impl Responder {
+    HttpResponse::Ok().json(coverage.progress())
+}
+
+/// Registered scouts and clients (see `crate::fleet`), with their last heartbeat, jobs completed,
+/// throughput (IPs probed or servers scanned per second) and errors, most recently seen first
+#[utoipa::path(get, path = "/api/v1/info/workers", tag = "info", responses((status = 200, body = [WorkerInfo])))]
+#[get("/workers")]
+async fn get_workers(fleet: Data<Fleet>) -> impl Responder {
+    HttpResponse::Ok().json(fleet.list())
+}
@@ -0,0 +1,66 @@
+use std::net::IpAddr;
+use actix_web::{HttpResponse, Responder, Result, error, get, web};
+use actix_web::web::{Data, Path, Query};
+use diesel::prelude::*;
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+use crate::DbPool;
+use crate::models::latest_scan_filter;
+use crate::routes::PageQuery;
+use crate::schema::{mod_, mod_scan, scan};
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ModQuery {
+    // Only return servers running this exact version of the mod
+    version: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct ModServer {
+    #[schema(value_type = String)]
+    ip: IpAddr,
+    // Version of the mod installed on the server
+    version: Option<String>,
+}
+
+route_module!(ModApi, get_mod_scope, "/mods", [get_mod_servers],
+              components(schemas(ModServer)));
+
+/// Lists the servers that advertised the mod during their latest scan
+#[utoipa::path(get, path = "/api/v1/mods/{modid}/servers", tag = "mods",
+    params(("modid" = String, Path, description = "Id of the mod, i.e. `jei`"), ModQuery, PageQuery),
+    responses((status = 200, body = [ModServer])))]
+#[get("/{modid}/servers")]
+async fn get_mod_servers(path: Path<String>, query: Query<ModQuery>, page: Query<PageQuery>, pool: Data<DbPool>) -> Result<impl Responder> {
+    let modid = path.into_inner();
+    let (limit, offset) = page.limit_offset();
+
+    let servers = web::block(move || {
+        let mut conn = pool.get()
+            .expect("Could not obtain database connection.");
+
+        let mut servers = mod_scan::table
+            .inner_join(mod_::table)
+            .inner_join(scan::table)
+            .filter(mod_::modid.eq(modid))
+            .filter(latest_scan_filter())
+            .select((scan::ip, mod_::version))
+            .order(scan::ip)
+            .limit(limit)
+            .offset(offset)
+            .into_boxed();
+        if let Some(version) = &query.version {
+            servers = servers.filter(mod_::version.eq(version));
+        }
+        servers.load::<(IpNet, Option<String>)>(&mut conn)
+    }).await
+        .map_err(|e| error::ErrorInternalServerError(e.to_string()))?
+        .map_err(|e| error::ErrorInternalServerError(e.to_string()))?;
+
+    let servers: Vec<ModServer> = servers.into_iter()
+        .map(|(ip, version)| ModServer { ip: ip.addr(), version })
+        .collect();
+    Ok(HttpResponse::Ok().json(servers))
+}
@@ -0,0 +1,139 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+use actix_web::{HttpResponse, Responder, Result, error, get, web};
+use actix_web::web::{Data, Path, Query};
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+use crate::{DbConnection, DbPool};
+use crate::models::{Player, PlayerNameHistory};
+use crate::routes::PageQuery;
+use crate::schema::{player, player_link};
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PlayerQuery {
+    // Current or previous name of the player, case-insensitive
+    name: String,
+}
+
+#[derive(Serialize, ToSchema)]
+struct PlayerName {
+    username: String,
+    first_seen: u64,
+    last_seen: u64,
+}
+
+#[derive(Serialize, ToSchema)]
+struct PlayerInfo {
+    id: i32,
+    username: String,
+    uuid: Option<String>,
+    // Every name seen for the player, most recent first
+    names: Vec<PlayerName>,
+}
+
+impl PlayerInfo {
+    fn load(player: Player, conn: &mut DbConnection) -> QueryResult<Self> {
+        let timestamp = |t: SystemTime| t.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+        let names = PlayerNameHistory::for_player(player.id, conn)?
+            .into_iter()
+            .map(|n| PlayerName { username: n.username, first_seen: timestamp(n.first_seen), last_seen: timestamp(n.last_seen) })
+            .collect();
+        Ok(PlayerInfo {
+            id: player.id,
+            username: player.username,
+            uuid: player.player_uuid.map(|u| u.to_string()),
+            names,
+        })
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+struct LinkedPlayer {
+    id: i32,
+    username: String,
+    // Number of scans both players were seen in
+    shared_scans: i32,
+}
+
+route_module!(PlayerApi, get_player_scope, "/players", [search_players, get_player, get_neighbours],
+              components(schemas(PlayerInfo, PlayerName, LinkedPlayer)));
+
+/// Finds the players currently using, or that used to use, a name
+#[utoipa::path(get, path = "/api/v1/players", tag = "players", params(PlayerQuery),
+    responses((status = 200, body = [PlayerInfo])))]
+#[get("")]
+async fn search_players(query: Query<PlayerQuery>, pool: Data<DbPool>) -> Result<impl Responder> {
+    let players = web::block(move || {
+        let mut conn = pool.get()
+            .expect("Could not obtain database connection.");
+
+        Player::find_by_any_name(&query.name, &mut conn)?
+            .into_iter()
+            .map(|p| PlayerInfo::load(p, &mut conn))
+            .collect::<QueryResult<Vec<PlayerInfo>>>()
+    }).await
+        .map_err(|e| error::ErrorInternalServerError(e.to_string()))?
+        .map_err(|e| error::ErrorInternalServerError(e.to_string()))?;
+
+    Ok(HttpResponse::Ok().json(players))
+}
+
+/// Returns a player along with their name history
+#[utoipa::path(get, path = "/api/v1/players/{id}", tag = "players", params(("id" = i32, Path, description = "Id of the player")),
+    responses((status = 200, body = PlayerInfo), (status = 404, description = "Player not found")))]
+#[get("/{id}")]
+async fn get_player(path: Path<i32>, pool: Data<DbPool>) -> Result<impl Responder> {
+    let id = path.into_inner();
+
+    let player = web::block(move || {
+        let mut conn = pool.get()
+            .expect("Could not obtain database connection.");
+
+        match player::table.find(id).first::<Player>(&mut conn).optional()? {
+            Some(p) => PlayerInfo::load(p, &mut conn).map(Some),
+            None => Ok(None)
+        }
+    }).await
+        .map_err(|e| error::ErrorInternalServerError(e.to_string()))?
+        .map_err(|e: diesel::result::Error| error::ErrorInternalServerError(e.to_string()))?
+        .ok_or_else(|| error::ErrorNotFound("Player not found"))?;
+
+    Ok(HttpResponse::Ok().json(player))
+}
+
+/// Lists the players most often seen together with this player
+#[utoipa::path(get, path = "/api/v1/players/{id}/neighbours", tag = "players", params(("id" = i32, Path, description = "Id of the player"), PageQuery),
+    responses((status = 200, body = [LinkedPlayer]), (status = 404, description = "Player not found")))]
+#[get("/{id}/neighbours")]
+async fn get_neighbours(path: Path<i32>, page: Query<PageQuery>, pool: Data<DbPool>) -> Result<impl Responder> {
+    let id = path.into_inner();
+    let (limit, offset) = page.limit_offset();
+
+    let neighbours = web::block(move || {
+        let mut conn = pool.get()
+            .expect("Could not obtain database connection.");
+
+        let exists = player::table.find(id).select(player::player_id).first::<i32>(&mut conn).optional()?;
+        if exists.is_none() {
+            return Ok(None);
+        }
+        player_link::table
+            .inner_join(player::table.on(player::player_id.eq(player_link::linked_player_id)))
+            .filter(player_link::player_id.eq(id))
+            .select((player::player_id, player::username, player_link::shared_scans))
+            .order((player_link::shared_scans.desc(), player::player_id))
+            .limit(limit)
+            .offset(offset)
+            .load::<(i32, String, i32)>(&mut conn)
+            .map(Some)
+    }).await
+        .map_err(|e| error::ErrorInternalServerError(e.to_string()))?
+        .map_err(|e: diesel::result::Error| error::ErrorInternalServerError(e.to_string()))?
+        .ok_or_else(|| error::ErrorNotFound("Player not found"))?;
+
+    let neighbours: Vec<LinkedPlayer> = neighbours.into_iter()
+        .map(|(id, username, shared_scans)| LinkedPlayer { id, username, shared_scans })
+        .collect();
+    Ok(HttpResponse::Ok().json(neighbours))
+}
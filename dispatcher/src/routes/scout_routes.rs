@@ -1,72 +1,228 @@
-use std::collections::VecDeque;
+use std::collections::{HashSet, VecDeque};
+use std::mem;
 use std::net::Ipv4Addr;
-use std::sync::atomic::{AtomicU32, Ordering};
-use actix_web::{HttpResponse, Responder, Scope, Result, post, get, error};
-use actix_web::web::{Data, Path, scope};
+use std::sync::atomic::Ordering;
+use std::time::SystemTime;
+use actix_web::{HttpRequest, HttpResponse, Responder, Result, post, get, error, web};
+use actix_web::web::{Data, Path, Query};
 use itertools::Itertools;
-use serde::{Serialize, Serializer};
-use serde::ser::SerializeStruct;
-use crate::ServerState;
+use protocol::{IpsQuery, ProbeResult, ScoutJob, ScoutJobQuery};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use crate::{DbPool, ServerState};
+use crate::coverage::Coverage;
+use crate::fleet::Fleet;
+use crate::routes::{is_legacy_worker, request_worker};
+use crate::honeypot::{HoneypotFilter, record_netblock_stats, record_port_probes};
+use crate::send_budget::SendBudget;
+use crate::targeting::{Targeting, build_job};
+use crate::worker_config::WorkerConfig;
 
-static JOB_ID: AtomicU32 = AtomicU32::new(0);
+/// Max number of servers probed on a random port per job
+const MAX_PROBES_PER_JOB: usize = 1000;
+/// Servers waiting for a probe are dropped past this, probes are only a heuristic
+const MAX_PROBE_QUEUE: usize = 100000;
 
-#[derive(Clone)]
-pub struct ScoutJob {
-    id: u32,
-    ips: Vec<Ipv4Addr>
+/// Scout job waiting for its results, its IPs are covered once they come back. Saved with the
+/// server state, the IPs of jobs that never come back are scanned again (see
+/// [`crate::targeting::TargetLanes::retry`]).
+#[derive(Serialize, Deserialize)]
+pub struct ScoutLease {
+    pub ips: Vec<Ipv4Addr>,
+    // Servers the job probes on a random port, the lease is kept until their results come back
+    pub probes: Vec<Ipv4Addr>,
+    // Cycle of the coverage the job was created in
+    pub cycle: u32,
+    pub creation_time: SystemTime,
+    // Registered scout the job was given to
+    pub worker: Option<Uuid>
 }
 
-impl Serialize for ScoutJob {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
-        let mut state = serializer.serialize_struct("IpRange", 2)?;
-        state.serialize_field("ips", &self.ips)?;
-        state.serialize_field("id", &self.id)?;
-        state.end()
+impl ScoutLease {
+    /// Whether `worker` may report the results of the job, jobs of unregistered scouts can be
+    /// reported by anyone
+    fn leased_to(&self, worker: Option<Uuid>) -> bool {
+        self.worker.is_none() || self.worker == worker
     }
 }
 
-pub fn get_scout_scope() -> Scope {
-    scope("/scout")
-        .service(get_job)
-        .service(post_ips)
-}
+route_module!(ScoutApi, get_scout_scope, "/scout", [get_job, post_ips, post_probes],
+              components(schemas(ScoutJob, ProbeResult)));
 
 
 // ROUTES
 
+/// Returns a job of `size` IPs. With a send-rate budget (see [`crate::send_budget`]), the job
+/// contains the rate the scout should send at and its size follows from that rate, except for
+/// legacy scouts which keep their own rate and size. The job also
+/// contains the version of the scout's settings if there is a worker config.
+#[utoipa::path(get, path = "/api/v1/scout/job/{size}", tag = "workers",
+    params(("size" = usize, Path, description = "Number of IPs, ignored with a send-rate budget"), ScoutJobQuery, ("X-Msearch-Protocol" = Option<u32>, Header, description = "Protocol version of the worker, see the `protocol` crate")),
+    responses((status = 200, body = ScoutJob), (status = 403, description = "Scout registered with another certificate or API key"),
+              (status = 503, description = "No IP left to scan, or scanning is paused")))]
 #[get("/job/{size}")]
-async fn get_job(path: Path<usize>, state: Data<ServerState>) -> impl Responder {
-    let size = path.into_inner();
+#[allow(clippy::too_many_arguments)]
+async fn get_job(path: Path<usize>, query: Query<ScoutJobQuery>, req: HttpRequest, state: Data<ServerState>, targeting: Data<Targeting>,
+                 coverage: Data<Coverage>, budget: Data<SendBudget>, worker_config: Data<WorkerConfig>, fleet: Data<Fleet>,
+                 pool: Data<DbPool>) -> Result<HttpResponse> {
+    let mut size = path.into_inner();
 
-    let mut ip_iterator = state.ip_range.lock().unwrap();
-    let mut ips: Vec<Ipv4Addr> = Vec::new();
+    let ScoutJobQuery { scout, group, worker_id } = query.into_inner();
+    let worker_id = request_worker(&req, &fleet, worker_id)?;
+    let config_version = worker_config.resolve(scout.as_deref(), group.as_deref()).map(|c| c.version);
+    let legacy = is_legacy_worker(&req);
+    // Legacy scouts ignore the rate of their job, see `crate::send_budget`
+    let share = if legacy {
+        None
+    } else {
+        let scout = worker_id.map(|id| id.to_string())
+            .or(scout)
+            .or_else(|| req.peer_addr().map(|a| a.ip().to_string()))
+            .unwrap_or_default();
+        budget.share(&scout)
+    };
+    if share.is_some_and(|s| s.send_rate == 0) || (share.is_none() && budget.is_paused()) {
+        // Blackout window, scouts retry later
+        return Ok(HttpResponse::ServiceUnavailable()
+            .insert_header(("Retry-After", "60"))
+            .body("Scanning is paused"));
+    }
+    if let Some(share) = share {
+        size = share.job_size;
+    }
 
-    while ips.len() < size {
-        match ip_iterator.next() {
-            Some(ip) => ips.push(ip),
-            None => ip_iterator.regenerate()  // Iterator is empty, refill it
+    let ips: Vec<Ipv4Addr> = {
+        let mut ip_iterator = state.ip_range.lock().unwrap();
+        let mut lanes = state.target_lanes.lock().unwrap();
+        let mut regenerated = false;
+        let ips = build_job(size, &targeting, &mut lanes, || loop {
+            match ip_iterator.next() {
+                Some(ip) => break ip,
+                None => {
+                    // Iterator is empty, refill it
+                    ip_iterator.regenerate();
+                    regenerated = true;
+                }
+            }
+        });
+        // Productive blocks are scanned again in the next pass over the whole space
+        if regenerated {
+            lanes.reset_blocks();
+            coverage.start_cycle();
         }
+        ips
+    };
+
+    // Legacy scouts don't report probes
+    let probes: Vec<Ipv4Addr> = if legacy {
+        Vec::new()
+    } else {
+        let mut probe_queue = state.probe_queue.lock().unwrap();
+        let count = probe_queue.len().min(MAX_PROBES_PER_JOB);
+        probe_queue.drain(..count).collect()
+    };
+
+    // Keep track of the number of probed IPs in each /24, for honeypot detection
+    {
+        let ips = ips.clone();
+        let result = web::block(move || {
+            let mut conn = pool.get()
+                .expect("Could not obtain database connection.");
+            record_netblock_stats(&ips, false, &mut conn)
+        }).await;
+        if let Ok(Err(e)) = result {
+            println!("Error saving netblock stats: {}", e);
+        }
+    }
+
+    let id = state.next_scout_job.fetch_add(1, Ordering::SeqCst);
+    if legacy {
+        // Legacy scouts don't send the job their IPs were found in, see `crate::coverage`
+        coverage.mark(coverage.cycle(), &ips);
+    } else {
+        state.outstanding_scout_jobs.lock().unwrap().insert(id, ScoutLease {
+            ips: ips.clone(),
+            probes: probes.clone(),
+            cycle: coverage.cycle(),
+            creation_time: SystemTime::now(),
+            worker: worker_id
+        });
     }
 
     let new_job = ScoutJob {
         ips,
-        id: JOB_ID.fetch_add(1, Ordering::SeqCst)
+        id,
+        probes,
+        // High port that shouldn't be open on a real server
+        probe_port: rand::thread_rng().gen_range(40000..60000),
+        send_rate: share.map(|s| s.send_rate),
+        config_version
     };
 
-    HttpResponse::Ok().json(new_job)
+    Ok(HttpResponse::Ok().json(new_job))
 }
 
+/// Body format: `["0.0.0.0", ...]`, the IPs that answered with a SYN-ACK during job `job`.
+/// The IPs of the job are then marked as covered, see [`crate::coverage`]. Only the scout the job
+/// was given to can report it.
+#[utoipa::path(post, path = "/api/v1/scout/ips", tag = "workers", params(IpsQuery, ("X-Msearch-Protocol" = Option<u32>, Header, description = "Protocol version of the worker, see the `protocol` crate")),
+    request_body = Vec<String>, responses((status = 200), (status = 400, description = "Invalid body, or incompatible worker"),
+                                          (status = 403, description = "Job given to another scout")))]
 #[post("/ips")]
-async fn post_ips(json: String, state: Data<ServerState>) -> Result<impl Responder> {
-    let ips: Vec<String> = serde_json::from_str(&json).map_err(|e| error::ErrorBadRequest(e.to_string()))?;
-    let ips: VecDeque<Ipv4Addr> = ips.iter().map(|x| x.parse().unwrap()).unique().collect();
+#[allow(clippy::too_many_arguments)]
+async fn post_ips(query: Query<IpsQuery>, json: String, req: HttpRequest, state: Data<ServerState>, honeypots: Data<HoneypotFilter>,
+                  coverage: Data<Coverage>, fleet: Data<Fleet>, pool: Data<DbPool>) -> Result<impl Responder> {
+    let worker = request_worker(&req, &fleet, query.worker_id)?;
+    let ips: Vec<Ipv4Addr> = serde_json::from_str(&json).map_err(|e| error::ErrorBadRequest(e.to_string()))?;
+    let ips: VecDeque<Ipv4Addr> = ips.into_iter().unique().collect();
     println!("Received the following ips: {:?}", ips);
 
+    if let Some(id) = query.job {
+        let lease = {
+            let mut outstanding = state.outstanding_scout_jobs.lock().unwrap();
+            match outstanding.get_mut(&id) {
+                Some(lease) if !lease.leased_to(worker) => return Err(error::ErrorForbidden("Job given to another scout")),
+                // Already reported
+                Some(lease) if lease.ips.is_empty() => None,
+                Some(lease) if !lease.probes.is_empty() => Some((lease.cycle, mem::take(&mut lease.ips), lease.worker)),
+                Some(_) => outstanding.remove(&id).map(|lease| (lease.cycle, lease.ips, lease.worker)),
+                None => None
+            }
+        };
+        if let Some((cycle, ips, leased_to)) = lease {
+            coverage.mark(cycle, &ips);
+            if let Some(worker) = leased_to {
+                fleet.record_job(worker, ips.len() as u64);
+            }
+        }
+    }
+
     if ips.is_empty() {
         // Return before trying to gain mutex lock
         return Ok(HttpResponse::Ok().finish());
     }
 
+    {
+        let ips: Vec<Ipv4Addr> = ips.iter().copied().collect();
+        web::block(move || {
+            let mut conn = pool.get()
+                .expect("Could not obtain database connection.");
+            record_netblock_stats(&ips, true, &mut conn)
+        }).await
+            .map_err(|e| error::ErrorInternalServerError(e.to_string()))?
+            .map_err(|e| error::ErrorInternalServerError(e.to_string()))?;
+    }
+
+    // Servers in flagged networks aren't worth a client's time
+    let ips: Vec<Ipv4Addr> = ips.into_iter().filter(|ip| !honeypots.contains(*ip)).collect();
+    {
+        let mut probe_queue = state.probe_queue.lock().unwrap();
+        probe_queue.extend(ips.iter().copied());
+        let excess = probe_queue.len().saturating_sub(MAX_PROBE_QUEUE);
+        probe_queue.drain(..excess);
+    }
+
     let mut valid_ips = state.valid_ips.lock().unwrap();
     // Costly iteration, could get out of hand if ip backlog is too large?
     // Consider using faster lookup data type, like hash list
@@ -78,3 +234,55 @@ async fn post_ips(json: String, state: Data<ServerState>) -> Result<impl Respond
 
     Ok(HttpResponse::Ok().finish())
 }
+
+/// Body format:
+/// ```json
+/// {
+///   "ips": ["0.0.0.0", ...],
+///   "hits": ["0.0.0.0", ...]
+/// }
+/// ```
+/// `ips` contains the IPs probed on the random port of job `job`, `hits` the ones that answered
+/// with a SYN-ACK. IPs the job didn't ask to probe are ignored, and only the scout the job was
+/// given to can report it.
+#[utoipa::path(post, path = "/api/v1/scout/probes", tag = "workers", params(IpsQuery, ("X-Msearch-Protocol" = Option<u32>, Header, description = "Protocol version of the worker, see the `protocol` crate")),
+    request_body = ProbeResult, responses((status = 200), (status = 400, description = "Invalid body, or incompatible worker"),
+                                          (status = 403, description = "Job given to another scout")))]
+#[post("/probes")]
+async fn post_probes(query: Query<IpsQuery>, json: String, req: HttpRequest, state: Data<ServerState>, fleet: Data<Fleet>,
+                     pool: Data<DbPool>) -> Result<impl Responder> {
+    let worker = request_worker(&req, &fleet, query.worker_id)?;
+    let result: ProbeResult = serde_json::from_str(&json).map_err(|e| error::ErrorBadRequest(e.to_string()))?;
+
+    let probes: HashSet<Ipv4Addr> = {
+        let mut outstanding = state.outstanding_scout_jobs.lock().unwrap();
+        match query.job.and_then(|id| Some((id, outstanding.get_mut(&id)?))) {
+            Some((_, lease)) if !lease.leased_to(worker) => return Err(error::ErrorForbidden("Job given to another scout")),
+            Some((id, lease)) => {
+                let probes = mem::take(&mut lease.probes);
+                if lease.ips.is_empty() {
+                    outstanding.remove(&id);
+                }
+                probes.into_iter().collect()
+            }
+            None => HashSet::new()
+        }
+    };
+    let ips: Vec<Ipv4Addr> = result.ips.into_iter().filter(|ip| probes.contains(ip)).collect();
+    if ips.is_empty() {
+        return Ok(HttpResponse::Ok().finish());
+    }
+    if !result.hits.is_empty() {
+        println!("Servers answering on a random port: {:?}", result.hits);
+    }
+
+    web::block(move || {
+        let mut conn = pool.get()
+            .expect("Could not obtain database connection.");
+        record_port_probes(&ips, &result.hits, &mut conn)
+    }).await
+        .map_err(|e| error::ErrorInternalServerError(e.to_string()))?
+        .map_err(|e| error::ErrorInternalServerError(e.to_string()))?;
+
+    Ok(HttpResponse::Ok().finish())
+}
@@ -0,0 +1,700 @@
+use std::net::{IpAddr, Ipv4Addr};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use actix_web::{HttpResponse, Responder, Result, error, get, web};
+use actix_web::web::{Data, Path, Query};
+use diesel::dsl::{count_star, sql};
+use diesel::helper_types::LeftJoinQuerySource;
+use diesel::pg::Pg;
+use diesel::prelude::*;
+use diesel::sql_types::{Array, Bool, Integer};
+use ipnet::{IpNet, Ipv4Net};
+use protocol::motd::{parse_component, plain_text};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use utoipa::{IntoParams, ToSchema};
+use crate::DbPool;
+use crate::history::{Change, CountPoint, MAX_POINTS, Resolution, load_changes, load_counts};
+use crate::models::{Cluster, Favicon, Mod, Player, PlayerBase, Scan, ServerGeo, latest_scan_filter, not_honeypot_filter};
+use crate::preview::render_preview;
+use crate::routes::PageQuery;
+use crate::schema::{favicon, scan, server_geo, server_link};
+use crate::software::{VersionRange, version_sql};
+
+type ServerSource = LeftJoinQuerySource<scan::table, favicon::table>;
+type ServerPredicate = Box<dyn BoxableExpression<ServerSource, Pg, SqlType = Bool>>;
+
+/// Filters of the server search routes. They are applied to the latest scan of each server.
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ServerFilter {
+    software: Option<String>,
+    proxy: Option<bool>,
+    modded: Option<bool>,
+    // Supported game version, i.e. `1.19.2`, or `1.19.x` for any 1.19 release
+    #[param(value_type = Option<String>)]
+    version: Option<VersionRange>,
+    protocol: Option<i32>,
+    // Include servers flagged as honeypots, excluded by default
+    honeypots: Option<bool>,
+    // ISO country code (i.e. `FR`) and AS number, see `crate::geoip`
+    country: Option<String>,
+    asn: Option<i32>,
+}
+
+impl ServerFilter {
+    fn predicate(&self) -> ServerPredicate {
+        let mut predicate: ServerPredicate = Box::new(latest_scan_filter());
+        if let Some(software) = &self.software {
+            predicate = Box::new(predicate.and(scan::software.is_not_distinct_from(software.to_lowercase())));
+        }
+        if let Some(proxy) = self.proxy {
+            predicate = Box::new(predicate.and(scan::is_proxy.is_not_distinct_from(proxy)));
+        }
+        if let Some(modded) = self.modded {
+            predicate = Box::new(predicate.and(scan::is_modded.is_not_distinct_from(modded)));
+        }
+        if let Some(version) = self.version {
+            // The range of versions of the server overlaps the versions of the filter
+            let matches = sql::<Bool>("coalesce(")
+                .sql(&version_sql("scan.game_version_min", false))
+                .sql(" <= ").bind::<Array<Integer>, _>(version.highest.to_vec())
+                .sql(" AND ").bind::<Array<Integer>, _>(version.lowest.to_vec())
+                .sql(" <= ").sql(&version_sql("scan.game_version_max", true))
+                .sql(", false)");
+            predicate = Box::new(predicate.and(matches));
+        }
+        if let Some(protocol) = self.protocol {
+            predicate = Box::new(predicate.and(scan::protocol.is_not_distinct_from(protocol)));
+        }
+        if let Some(country) = &self.country {
+            let ips = server_geo::table
+                .filter(server_geo::country_code.eq(country.to_uppercase()))
+                .select(server_geo::ip);
+            predicate = Box::new(predicate.and(scan::ip.eq_any(ips)));
+        }
+        if let Some(asn) = self.asn {
+            let ips = server_geo::table
+                .filter(server_geo::asn.eq(asn))
+                .select(server_geo::ip);
+            predicate = Box::new(predicate.and(scan::ip.eq_any(ips)));
+        }
+        if !self.honeypots.unwrap_or(false) {
+            predicate = Box::new(predicate.and(not_honeypot_filter()));
+        }
+        predicate
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ServerSummary {
+    #[schema(value_type = String)]
+    ip: IpAddr,
+    version: Option<String>,
+    protocol: Option<i32>,
+    software: Option<String>,
+    proxy: Option<bool>,
+    modded: Option<bool>,
+    game_version_min: Option<String>,
+    game_version_max: Option<String>,
+    online_count: Option<i32>,
+    max_count: Option<i32>,
+    // The player counts can't be real, see `crate::samples`
+    player_count_spoofed: bool,
+    // Plain text of the MOTD, without formatting
+    motd: Option<String>,
+    // Hash of the favicon, see `/favicons/{hash}.png`
+    favicon: Option<String>,
+}
+
+impl ServerSummary {
+    fn new(scan: Scan, favicon: Option<String>) -> Self {
+        let motd = scan.description.map(motd_text);
+        ServerSummary {
+            ip: scan.ip.addr(),
+            version: scan.version,
+            protocol: scan.protocol,
+            software: scan.software,
+            proxy: scan.is_proxy,
+            modded: scan.is_modded,
+            game_version_min: scan.game_version_min,
+            game_version_max: scan.game_version_max,
+            online_count: scan.online_count,
+            max_count: scan.max_count,
+            player_count_spoofed: scan.player_count_spoofed,
+            motd,
+            favicon,
+        }
+    }
+}
+
+/// Plain text of a description (MOTD) as saved in a scan
+fn motd_text(description: String) -> String {
+    let value = serde_json::from_str(&description).unwrap_or(Value::String(description));
+    plain_text(&parse_component(&value))
+}
+
+#[derive(Serialize, ToSchema)]
+struct PlayerBaseInfo {
+    // Number of scans with a player sample
+    sampled_scans: i32,
+    observed_players: i32,
+    // Players seen in exactly one and two scans, the estimate is unreliable when most players
+    // were only seen once
+    singletons: i32,
+    doubletons: i32,
+    // Estimated number of distinct players, including the ones never seen in a sample
+    estimated_players: f32,
+    updated_at: u64,
+}
+
+#[derive(Serialize, ToSchema)]
+struct LocationInfo {
+    country_code: Option<String>,
+    country: Option<String>,
+    city: Option<String>,
+    asn: Option<i32>,
+    as_org: Option<String>,
+    updated_at: u64,
+}
+
+#[derive(Serialize, ToSchema)]
+struct RankedPlayer {
+    username: String,
+    uuid: Option<String>,
+    // Number of scans the player was seen in, and share of the sampled scans
+    seen: i64,
+    share: f64,
+}
+
+#[derive(Serialize, ToSchema)]
+struct ServerDetail {
+    #[serde(flatten)]
+    server: ServerSummary,
+    player_base: Option<PlayerBaseInfo>,
+    // None until the server is looked up in the GeoIP databases
+    location: Option<LocationInfo>,
+    // Players seen on the server, most seen first
+    players: Vec<RankedPlayer>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct ServerCount {
+    count: i64,
+}
+
+#[derive(Serialize, ToSchema)]
+struct SoftwareCount {
+    software: Option<String>,
+    proxy: Option<bool>,
+    modded: Option<bool>,
+    count: i64,
+}
+
+route_module!(ServerApi, get_server_scope, "/servers", [search_servers, count_servers, get_software_breakdown,
+                                                        get_changes, get_preview, get_related, get_mods,
+                                                        get_neighbours, get_timeseries, get_server],
+              components(schemas(ServerSummary, ServerCount, SoftwareCount, ServerDetail, PlayerBaseInfo, LocationInfo,
+                                 RankedPlayer, RelatedServers, ModEntry, LinkedServer, Timeseries, CountEntry,
+                                 TimedChange, FieldChange, ServerChange)));
+
+/// Searches servers based on their latest scan, see [`ServerFilter`] for the available filters
+#[utoipa::path(get, path = "/api/v1/servers", tag = "servers", params(ServerFilter, PageQuery),
+    responses((status = 200, body = [ServerSummary])))]
+#[get("")]
+async fn search_servers(filter: Query<ServerFilter>, page: Query<PageQuery>, pool: Data<DbPool>) -> Result<impl Responder> {
+    let (limit, offset) = page.limit_offset();
+
+    let servers = web::block(move || {
+        let mut conn = pool.get()
+            .expect("Could not obtain database connection.");
+
+        scan::table
+            .left_join(favicon::table)
+            .select((scan::all_columns, favicon::hash.nullable()))
+            .into_boxed()
+            .filter(filter.predicate())
+            .order(scan::ip)
+            .limit(limit)
+            .offset(offset)
+            .load::<(Scan, Option<String>)>(&mut conn)
+    }).await
+        .map_err(|e| error::ErrorInternalServerError(e.to_string()))?
+        .map_err(|e| error::ErrorInternalServerError(e.to_string()))?;
+
+    let servers: Vec<ServerSummary> = servers.into_iter()
+        .map(|(scan, favicon)| ServerSummary::new(scan, favicon))
+        .collect();
+    Ok(HttpResponse::Ok().json(servers))
+}
+
+/// Counts the servers matching the filters, see [`ServerFilter`]
+#[utoipa::path(get, path = "/api/v1/servers/count", tag = "servers", params(ServerFilter),
+    responses((status = 200, body = ServerCount)))]
+#[get("/count")]
+async fn count_servers(filter: Query<ServerFilter>, pool: Data<DbPool>) -> Result<impl Responder> {
+    let count = web::block(move || {
+        let mut conn = pool.get()
+            .expect("Could not obtain database connection.");
+
+        scan::table
+            .left_join(favicon::table)
+            .select(count_star())
+            .into_boxed()
+            .filter(filter.predicate())
+            .get_result::<i64>(&mut conn)
+    }).await
+        .map_err(|e| error::ErrorInternalServerError(e.to_string()))?
+        .map_err(|e| error::ErrorInternalServerError(e.to_string()))?;
+
+    Ok(HttpResponse::Ok().json(ServerCount { count }))
+}
+
+/// Number of servers matching the filters for each software family, split by proxy and
+/// modded status
+#[utoipa::path(get, path = "/api/v1/servers/software", tag = "servers", params(ServerFilter),
+    responses((status = 200, body = [SoftwareCount])))]
+#[get("/software")]
+async fn get_software_breakdown(filter: Query<ServerFilter>, pool: Data<DbPool>) -> Result<impl Responder> {
+    let counts = web::block(move || {
+        let mut conn = pool.get()
+            .expect("Could not obtain database connection.");
+
+        scan::table
+            .left_join(favicon::table)
+            .group_by((scan::software, scan::is_proxy, scan::is_modded))
+            .select((scan::software, scan::is_proxy, scan::is_modded, count_star()))
+            .into_boxed()
+            .filter(filter.predicate())
+            .order(count_star().desc())
+            .load::<(Option<String>, Option<bool>, Option<bool>, i64)>(&mut conn)
+    }).await
+        .map_err(|e| error::ErrorInternalServerError(e.to_string()))?
+        .map_err(|e| error::ErrorInternalServerError(e.to_string()))?;
+
+    let counts: Vec<SoftwareCount> = counts.into_iter()
+        .map(|(software, proxy, modded, count)| SoftwareCount { software, proxy, modded, count })
+        .collect();
+    Ok(HttpResponse::Ok().json(counts))
+}
+
+#[derive(Serialize, ToSchema)]
+struct RelatedServers {
+    // Network the server is part of, None if no related server was found
+    cluster: Option<i32>,
+    #[schema(value_type = Vec<String>)]
+    servers: Vec<IpAddr>,
+}
+
+/// Renders the latest scan of the server as it would appear in the Minecraft server list
+#[utoipa::path(get, path = "/api/v1/servers/{ip}/preview.png", tag = "servers", params(("ip" = String, Path, description = "IPv4 address of the server")),
+    responses((status = 200, description = "PNG image", content_type = "image/png"), (status = 404, description = "No scan found for this server")))]
+#[get("/{ip}/preview.png")]
+async fn get_preview(path: Path<Ipv4Addr>, pool: Data<DbPool>) -> Result<impl Responder> {
+    let ip = IpNet::V4(Ipv4Net::from(path.into_inner()));
+
+    // Rendering is done in the blocking thread as well, since it's CPU bound
+    let png = web::block(move || {
+        let mut conn = pool.get()
+            .expect("Could not obtain database connection.");
+
+        let scan = match Scan::latest_for_ip(ip, &mut conn).map_err(|e| e.to_string())? {
+            Some(scan) => scan,
+            None => return Ok(None)
+        };
+        let favicon = match scan.favicon_id {
+            Some(id) => Favicon::find(id, &mut conn).map_err(|e| e.to_string())?,
+            None => None
+        };
+        render_preview(&scan, favicon.as_ref().map(|f| f.data.as_slice()))
+            .map(Some).map_err(|e| e.to_string())
+    }).await
+        .map_err(|e| error::ErrorInternalServerError(e.to_string()))?
+        .map_err(error::ErrorInternalServerError)?
+        .ok_or_else(|| error::ErrorNotFound("No scan found for this server"))?;
+
+    Ok(HttpResponse::Ok().content_type("image/png").body(png))
+}
+
+/// Lists the other servers that are part of the same network as this server
+#[utoipa::path(get, path = "/api/v1/servers/{ip}/related", tag = "servers", params(("ip" = String, Path, description = "IPv4 address of the server")),
+    responses((status = 200, body = RelatedServers), (status = 404, description = "No scan found for this server")))]
+#[get("/{ip}/related")]
+async fn get_related(path: Path<Ipv4Addr>, pool: Data<DbPool>) -> Result<impl Responder> {
+    let ip = IpNet::V4(Ipv4Net::from(path.into_inner()));
+
+    let related = web::block(move || {
+        let mut conn = pool.get()
+            .expect("Could not obtain database connection.");
+
+        if Scan::latest_for_ip(ip, &mut conn)?.is_none() {
+            return Ok(None);
+        }
+        let related = match Cluster::for_ip(ip, &mut conn)? {
+            Some(cluster) => RelatedServers {
+                cluster: Some(cluster.id),
+                servers: cluster.server_ips(&mut conn)?.iter().filter(|x| **x != ip).map(IpNet::addr).collect()
+            },
+            None => RelatedServers { cluster: None, servers: Vec::new() }
+        };
+        Ok(Some(related))
+    }).await
+        .map_err(|e| error::ErrorInternalServerError(e.to_string()))?
+        .map_err(|e: diesel::result::Error| error::ErrorInternalServerError(e.to_string()))?
+        .ok_or_else(|| error::ErrorNotFound("No scan found for this server"))?;
+
+    Ok(HttpResponse::Ok().json(related))
+}
+
+#[derive(Serialize, ToSchema)]
+struct ModEntry {
+    modid: String,
+    version: Option<String>,
+}
+
+/// Lists the mods advertised by the server during its latest scan
+#[utoipa::path(get, path = "/api/v1/servers/{ip}/mods", tag = "servers", params(("ip" = String, Path, description = "IPv4 address of the server")),
+    responses((status = 200, body = [ModEntry]), (status = 404, description = "No scan found for this server")))]
+#[get("/{ip}/mods")]
+async fn get_mods(path: Path<Ipv4Addr>, pool: Data<DbPool>) -> Result<impl Responder> {
+    let ip = IpNet::V4(Ipv4Net::from(path.into_inner()));
+
+    let mods = web::block(move || {
+        let mut conn = pool.get()
+            .expect("Could not obtain database connection.");
+
+        match Scan::latest_for_ip(ip, &mut conn)? {
+            Some(scan) => Mod::for_scan(scan.id, &mut conn).map(Some),
+            None => Ok(None)
+        }
+    }).await
+        .map_err(|e| error::ErrorInternalServerError(e.to_string()))?
+        .map_err(|e: diesel::result::Error| error::ErrorInternalServerError(e.to_string()))?
+        .ok_or_else(|| error::ErrorNotFound("No scan found for this server"))?;
+
+    let mods: Vec<ModEntry> = mods.into_iter()
+        .map(|m| ModEntry { modid: m.modid, version: m.version })
+        .collect();
+    Ok(HttpResponse::Ok().json(mods))
+}
+
+/// Returns the latest scan of the server, the estimate of its player base and the players seen
+/// on it. The players are paginated.
+#[utoipa::path(get, path = "/api/v1/servers/{ip}", tag = "servers", params(("ip" = String, Path, description = "IPv4 address of the server"), PageQuery),
+    responses((status = 200, body = ServerDetail), (status = 404, description = "No scan found for this server")))]
+#[get("/{ip}")]
+async fn get_server(path: Path<Ipv4Addr>, page: Query<PageQuery>, pool: Data<DbPool>) -> Result<impl Responder> {
+    let ip = IpNet::V4(Ipv4Net::from(path.into_inner()));
+    let (limit, offset) = page.limit_offset();
+
+    let detail = web::block(move || {
+        let mut conn = pool.get()
+            .expect("Could not obtain database connection.");
+
+        let latest = scan::table
+            .left_join(favicon::table)
+            .filter(scan::ip.eq(ip))
+            .select((scan::all_columns, favicon::hash.nullable()))
+            .order(scan::scan_id.desc())
+            .first::<(Scan, Option<String>)>(&mut conn)
+            .optional()?;
+        let (scan, favicon) = match latest {
+            Some(latest) => latest,
+            None => return Ok(None)
+        };
+
+        let player_base = PlayerBase::for_ip(ip, &mut conn)?;
+        let location = ServerGeo::for_ip(ip, &mut conn)?;
+        let sampled_scans = player_base.as_ref().map_or(0, |p| p.sampled_scans);
+        let players = Player::ranking_for_ip(ip, limit, offset, &mut conn)?
+            .into_iter()
+            .map(|(player, seen)| RankedPlayer {
+                username: player.username,
+                uuid: player.player_uuid.map(|u| u.to_string()),
+                seen,
+                share: if sampled_scans > 0 { (seen as f64 / sampled_scans as f64).min(1.0) } else { 0.0 },
+            })
+            .collect();
+
+        Ok(Some(ServerDetail {
+            server: ServerSummary::new(scan, favicon),
+            player_base: player_base.map(|p| PlayerBaseInfo {
+                sampled_scans: p.sampled_scans,
+                observed_players: p.observed_players,
+                singletons: p.singletons,
+                doubletons: p.doubletons,
+                estimated_players: p.estimated_players,
+                updated_at: p.updated_at.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs()),
+            }),
+            location: location.map(|l| LocationInfo {
+                country_code: l.country_code,
+                country: l.country,
+                city: l.city,
+                asn: l.asn,
+                as_org: l.as_org,
+                updated_at: l.updated_at.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs()),
+            }),
+            players,
+        }))
+    }).await
+        .map_err(|e| error::ErrorInternalServerError(e.to_string()))?
+        .map_err(|e: diesel::result::Error| error::ErrorInternalServerError(e.to_string()))?
+        .ok_or_else(|| error::ErrorNotFound("No scan found for this server"))?;
+
+    Ok(HttpResponse::Ok().json(detail))
+}
+
+#[derive(Serialize, ToSchema)]
+struct LinkedServer {
+    #[schema(value_type = String)]
+    ip: IpAddr,
+    // Number of distinct players seen on both servers
+    shared_players: i32,
+}
+
+/// Lists the servers sharing the most players with this server
+#[utoipa::path(get, path = "/api/v1/servers/{ip}/neighbours", tag = "servers", params(("ip" = String, Path, description = "IPv4 address of the server"), PageQuery),
+    responses((status = 200, body = [LinkedServer]), (status = 404, description = "No scan found for this server")))]
+#[get("/{ip}/neighbours")]
+async fn get_neighbours(path: Path<Ipv4Addr>, page: Query<PageQuery>, pool: Data<DbPool>) -> Result<impl Responder> {
+    let ip = IpNet::V4(Ipv4Net::from(path.into_inner()));
+    let (limit, offset) = page.limit_offset();
+
+    let neighbours = web::block(move || {
+        let mut conn = pool.get()
+            .expect("Could not obtain database connection.");
+
+        if Scan::latest_for_ip(ip, &mut conn)?.is_none() {
+            return Ok(None);
+        }
+        server_link::table
+            .filter(server_link::ip.eq(ip))
+            .select((server_link::linked_ip, server_link::shared_players))
+            .order((server_link::shared_players.desc(), server_link::linked_ip))
+            .limit(limit)
+            .offset(offset)
+            .load::<(IpNet, i32)>(&mut conn)
+            .map(Some)
+    }).await
+        .map_err(|e| error::ErrorInternalServerError(e.to_string()))?
+        .map_err(|e: diesel::result::Error| error::ErrorInternalServerError(e.to_string()))?
+        .ok_or_else(|| error::ErrorNotFound("No scan found for this server"))?;
+
+    let neighbours: Vec<LinkedServer> = neighbours.into_iter()
+        .map(|(ip, shared_players)| LinkedServer { ip: ip.addr(), shared_players })
+        .collect();
+    Ok(HttpResponse::Ok().json(neighbours))
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct TimeRange {
+    // Unix timestamps (seconds), see the routes for the defaults
+    from: Option<u64>,
+    to: Option<u64>,
+}
+
+impl TimeRange {
+    /// Returns the requested range, `default` being the length of the range when `from` is
+    /// not set
+    fn range(&self, default: Duration) -> Result<(SystemTime, SystemTime)> {
+        let to = match self.to {
+            Some(t) => timestamp(t, "to")?,
+            None => SystemTime::now()
+        };
+        let from = match self.from {
+            Some(f) => timestamp(f, "from")?,
+            None => to.checked_sub(default).unwrap_or(UNIX_EPOCH)
+        };
+        if from > to {
+            return Err(error::ErrorBadRequest("`from` must not be after `to`"));
+        }
+        Ok((from, to))
+    }
+}
+
+/// Time of a Unix timestamp of the query, up to the end of year 9999
+fn timestamp(secs: u64, name: &str) -> Result<SystemTime> {
+    const MAX_TIMESTAMP: u64 = 253_402_300_799;
+    Some(secs)
+        .filter(|secs| *secs <= MAX_TIMESTAMP)
+        .and_then(|secs| UNIX_EPOCH.checked_add(Duration::from_secs(secs)))
+        .ok_or_else(|| error::ErrorBadRequest(format!("`{}` is out of range", name)))
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct TimeseriesQuery {
+    // raw, hourly or daily, hourly by default
+    resolution: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct CountEntry {
+    time: u64,
+    // Number of scans in the bucket
+    scans: i64,
+    online_avg: Option<f64>,
+    online_min: Option<i32>,
+    online_max: Option<i32>,
+    max_count: Option<i32>,
+}
+
+impl From<CountPoint> for CountEntry {
+    fn from(point: CountPoint) -> Self {
+        CountEntry {
+            time: unix_secs(point.time),
+            scans: point.scans,
+            online_avg: point.online_avg,
+            online_min: point.online_min,
+            online_max: point.online_max,
+            max_count: point.max_count,
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+struct FieldChange {
+    previous: Option<String>,
+    current: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct Timeseries {
+    resolution: String,
+    from: u64,
+    to: u64,
+    // Oldest first, only the latest `MAX_POINTS` are returned
+    counts: Vec<CountEntry>,
+    version_changes: Vec<TimedChange>,
+    motd_changes: Vec<TimedChange>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct TimedChange {
+    time: u64,
+    #[serde(flatten)]
+    change: FieldChange,
+}
+
+/// Player counts of the server over time, downsampled to the requested resolution, with the
+/// version and MOTD changes over the same range. By default, the last 7 days are returned for
+/// the raw resolution, 30 days for hourly and a year for daily.
+#[utoipa::path(get, path = "/api/v1/servers/{ip}/timeseries", tag = "servers", params(("ip" = String, Path, description = "IPv4 address of the server"), TimeseriesQuery, TimeRange),
+    responses((status = 200, body = Timeseries), (status = 400, description = "Unknown resolution, or invalid range"), (status = 404, description = "No scan found for this server")))]
+#[get("/{ip}/timeseries")]
+async fn get_timeseries(path: Path<Ipv4Addr>, query: Query<TimeseriesQuery>, range: Query<TimeRange>, pool: Data<DbPool>) -> Result<impl Responder> {
+    let ip = IpNet::V4(Ipv4Net::from(path.into_inner()));
+    let resolution_name = query.into_inner().resolution.unwrap_or_else(|| String::from("hourly"));
+    let resolution = Resolution::from_name(&resolution_name)
+        .ok_or_else(|| error::ErrorBadRequest("Resolution must be raw, hourly or daily"))?;
+    let default_days = match resolution {
+        Resolution::Raw => 7,
+        Resolution::Hourly => 30,
+        Resolution::Daily => 365
+    };
+    let (from, to) = range.range(Duration::from_secs(default_days * 24 * 60 * 60))?;
+
+    let (counts, changes) = web::block(move || {
+        let mut conn = pool.get()
+            .expect("Could not obtain database connection.");
+
+        if Scan::latest_for_ip(ip, &mut conn)?.is_none() {
+            return Ok(None);
+        }
+        let counts = load_counts(ip, resolution, from, to, &mut conn)?;
+        let changes = load_changes(Some(ip), from, to, MAX_POINTS, 0, &mut conn)?;
+        Ok(Some((counts, changes)))
+    }).await
+        .map_err(|e| error::ErrorInternalServerError(e.to_string()))?
+        .map_err(|e: diesel::result::Error| error::ErrorInternalServerError(e.to_string()))?
+        .ok_or_else(|| error::ErrorNotFound("No scan found for this server"))?;
+
+    let mut version_changes = Vec::new();
+    let mut motd_changes = Vec::new();
+    for change in changes {
+        let time = unix_secs(change.time);
+        if change.version_changed {
+            version_changes.push(TimedChange {
+                time,
+                change: FieldChange { previous: change.previous_version, current: change.version },
+            });
+        }
+        if change.motd_changed {
+            motd_changes.push(TimedChange {
+                time,
+                change: FieldChange {
+                    previous: change.previous_description.map(motd_text),
+                    current: change.description.map(motd_text),
+                },
+            });
+        }
+    }
+
+    Ok(HttpResponse::Ok().json(Timeseries {
+        resolution: resolution_name,
+        from: unix_secs(from),
+        to: unix_secs(to),
+        counts: counts.into_iter().map(CountEntry::from).collect(),
+        version_changes,
+        motd_changes,
+    }))
+}
+
+#[derive(Serialize, ToSchema)]
+struct ServerChange {
+    #[schema(value_type = String)]
+    ip: IpAddr,
+    time: u64,
+    // Only set for the fields that changed since the previous scan of the server
+    version: Option<FieldChange>,
+    motd: Option<FieldChange>,
+    // Favicon hashes, see `/favicons/{hash}.png`
+    favicon: Option<FieldChange>,
+}
+
+impl From<Change> for ServerChange {
+    fn from(change: Change) -> Self {
+        ServerChange {
+            ip: change.ip.addr(),
+            time: unix_secs(change.time),
+            version: change.version_changed.then_some(FieldChange {
+                previous: change.previous_version,
+                current: change.version,
+            }),
+            motd: change.motd_changed.then(|| FieldChange {
+                previous: change.previous_description.map(motd_text),
+                current: change.description.map(motd_text),
+            }),
+            favicon: change.favicon_changed.then_some(FieldChange {
+                previous: change.previous_favicon,
+                current: change.favicon,
+            }),
+        }
+    }
+}
+
+/// Lists the scans where the version, MOTD or favicon of a server changed, most recent first.
+/// Covers the last 24 hours by default.
+#[utoipa::path(get, path = "/api/v1/servers/changes", tag = "servers", params(TimeRange, PageQuery),
+    responses((status = 200, body = [ServerChange]), (status = 400, description = "Invalid range")))]
+#[get("/changes")]
+async fn get_changes(range: Query<TimeRange>, page: Query<PageQuery>, pool: Data<DbPool>) -> Result<impl Responder> {
+    let (from, to) = range.range(Duration::from_secs(24 * 60 * 60))?;
+    let (limit, offset) = page.limit_offset();
+
+    let changes = web::block(move || {
+        let mut conn = pool.get()
+            .expect("Could not obtain database connection.");
+
+        load_changes(None, from, to, limit, offset, &mut conn)
+    }).await
+        .map_err(|e| error::ErrorInternalServerError(e.to_string()))?
+        .map_err(|e| error::ErrorInternalServerError(e.to_string()))?;
+
+    let changes: Vec<ServerChange> = changes.into_iter().map(ServerChange::from).collect();
+    Ok(HttpResponse::Ok().json(changes))
+}
@@ -0,0 +1,133 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+use actix_web::{HttpResponse, Responder, Result, error, get, web};
+use actix_web::web::Data;
+use serde::Serialize;
+use utoipa::ToSchema;
+use crate::DbPool;
+use crate::stats::load_stats;
+
+#[derive(Serialize, ToSchema)]
+struct StatsInfo {
+    // Servers ever found, and servers scanned in the last day
+    total_servers: i64,
+    online_servers: i64,
+    // Players online on the online servers, spoofed player counts are left out
+    total_players: i64,
+    average_players: Option<f64>,
+    versions: Vec<VersionEntry>,
+    protocols: Vec<ProtocolEntry>,
+    software: Vec<SoftwareEntry>,
+    // Online servers in online mode, offline mode, or without a player sample to tell
+    auth_modes: Vec<AuthModeEntry>,
+    // New servers found each day, oldest first
+    discovery: Vec<DiscoveryEntry>,
+    // Online servers by location and network, servers not found in the GeoIP databases are
+    // counted with null fields. Only the 100 largest ASNs are listed.
+    countries: Vec<CountryEntry>,
+    asns: Vec<AsnEntry>,
+    updated_at: u64,
+}
+
+#[derive(Serialize, ToSchema)]
+struct VersionEntry {
+    game_version: Option<String>,
+    servers: i64,
+}
+
+#[derive(Serialize, ToSchema)]
+struct ProtocolEntry {
+    protocol: Option<i32>,
+    servers: i64,
+}
+
+#[derive(Serialize, ToSchema)]
+struct SoftwareEntry {
+    software: Option<String>,
+    proxy: Option<bool>,
+    modded: Option<bool>,
+    servers: i64,
+}
+
+#[derive(Serialize, ToSchema)]
+struct AuthModeEntry {
+    mode: String,
+    servers: i64,
+    share: f64,
+}
+
+#[derive(Serialize, ToSchema)]
+struct DiscoveryEntry {
+    day: u64,
+    servers: i64,
+}
+
+#[derive(Serialize, ToSchema)]
+struct CountryEntry {
+    country_code: Option<String>,
+    country: Option<String>,
+    servers: i64,
+}
+
+#[derive(Serialize, ToSchema)]
+struct AsnEntry {
+    asn: Option<i32>,
+    as_org: Option<String>,
+    servers: i64,
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+route_module!(StatsApi, get_stats_scope, "/stats", [get_stats],
+              components(schemas(StatsInfo, VersionEntry, ProtocolEntry, SoftwareEntry, AuthModeEntry, DiscoveryEntry,
+                                 CountryEntry, AsnEntry)));
+
+/// Overview of the dataset, as of the last refresh of the statistics (see `updated_at`).
+/// Distributions only include the online servers.
+#[utoipa::path(get, path = "/api/v1/stats", tag = "stats", responses((status = 200, body = StatsInfo)))]
+#[get("")]
+async fn get_stats(pool: Data<DbPool>) -> Result<impl Responder> {
+    let stats = web::block(move || {
+        let mut conn = pool.get()
+            .expect("Could not obtain database connection.");
+
+        load_stats(&mut conn)
+    }).await
+        .map_err(|e| error::ErrorInternalServerError(e.to_string()))?
+        .map_err(|e| error::ErrorInternalServerError(e.to_string()))?;
+
+    let online_servers = stats.overview.online_servers;
+    Ok(HttpResponse::Ok().json(StatsInfo {
+        total_servers: stats.overview.total_servers,
+        online_servers,
+        total_players: stats.overview.total_players,
+        average_players: stats.overview.average_players,
+        versions: stats.versions.into_iter()
+            .map(|v| VersionEntry { game_version: v.game_version, servers: v.servers })
+            .collect(),
+        protocols: stats.protocols.into_iter()
+            .map(|p| ProtocolEntry { protocol: p.protocol, servers: p.servers })
+            .collect(),
+        software: stats.software.into_iter()
+            .map(|s| SoftwareEntry { software: s.software, proxy: s.is_proxy, modded: s.is_modded, servers: s.servers })
+            .collect(),
+        auth_modes: stats.auth_modes.into_iter()
+            .map(|a| AuthModeEntry {
+                share: a.servers as f64 / online_servers.max(1) as f64,
+                mode: a.mode,
+                servers: a.servers,
+            })
+            .collect(),
+        discovery: stats.discovery.into_iter()
+            .map(|d| DiscoveryEntry { day: unix_secs(d.day), servers: d.servers })
+            .collect(),
+        countries: stats.countries.into_iter()
+            .map(|c| CountryEntry { country_code: c.country_code, country: c.country, servers: c.servers })
+            .collect(),
+        asns: stats.asns.into_iter()
+            .map(|a| AsnEntry { asn: a.asn, as_org: a.as_org, servers: a.servers })
+            .collect(),
+        updated_at: unix_secs(stats.overview.updated_at),
+    }))
+}
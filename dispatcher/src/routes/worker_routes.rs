@@ -0,0 +1,112 @@
+use actix_web::{HttpMessage, HttpRequest, HttpResponse, Responder, Result, error, post, web};
+use actix_web::web::{Data, Path};
+use protocol::{Heartbeat, Registration, RegistrationResponse, WorkerKind};
+use uuid::Uuid;
+use crate::DbPool;
+use crate::auth::AuthenticatedKey;
+use crate::fleet::Fleet;
+use crate::models::NewWorker;
+use crate::routes::request_worker;
+use crate::tls::ClientCertificate;
+
+route_module!(WorkerApi, get_worker_scope, "/workers", [register, post_heartbeat],
+              components(schemas(Registration, RegistrationResponse, Heartbeat, WorkerKind)));
+
+/// Registers a worker, and returns its id. Body format:
+/// ```json
+/// {
+///   "kind": "scout",
+///   "name": "scout-1",
+///   "group": "eu",
+///   "hostname": "scanner-fra-1",
+///   "version": "0.1.0",
+///   "adapter": "eth0"
+/// }
+/// ```
+/// `kind` is either `scout` or `client`, other fields are optional. `name` and `group` are the
+/// ones of the worker config (see [`crate::worker_config`]). A worker connecting with a client
+/// certificate gets the id it was given the first time it registered with that certificate.
+/// Without a certificate, a worker with the same kind, name and hostname as a previous one from
+/// the same API key gets the id of that worker, so workers sharing a host need distinct names. The
+/// worker belongs to the API key it registered with, see [`crate::auth`].
+/// Response format: `{ "id": "<uuid>" }`
+#[utoipa::path(post, path = "/api/v1/workers/register", tag = "workers", params(("X-Msearch-Protocol" = Option<u32>, Header, description = "Protocol version of the worker, see the `protocol` crate")),
+    request_body = Registration, responses((status = 200, body = RegistrationResponse), (status = 400, description = "Invalid body, or incompatible worker")))]
+#[post("/register")]
+async fn register(req: HttpRequest, json: String, fleet: Data<Fleet>, pool: Data<DbPool>) -> Result<impl Responder> {
+    let registration: Registration = serde_json::from_str(&json).map_err(|e| error::ErrorBadRequest(e.to_string()))?;
+
+    let lineage = req.extensions().get::<AuthenticatedKey>().map(|k| k.lineage);
+    let fingerprint = req.conn_data::<ClientCertificate>().map(|c| c.fingerprint.clone());
+    let kind = registration.kind.as_str();
+    let previous = match fingerprint.as_deref() {
+        Some(fingerprint) => fleet.find_by_certificate(fingerprint),
+        None => fleet.find_by_identity(kind, registration.name.as_deref(), registration.hostname.as_deref(), lineage)
+    };
+    if let Some(id) = previous {
+        if !fleet.key_matches(id, lineage) {
+            return Err(error::ErrorForbidden("Worker registered with another API key"));
+        }
+        let heartbeat = Heartbeat {
+            hostname: registration.hostname,
+            version: registration.version,
+            adapter: registration.adapter,
+            ..Heartbeat::default()
+        };
+        fleet.heartbeat(id, heartbeat);
+        println!("Worker {} registered again", id);
+        return Ok(HttpResponse::Ok().json(RegistrationResponse { id }));
+    }
+
+    let new_worker = NewWorker {
+        id: Uuid::new_v4(),
+        kind: String::from(kind),
+        name: registration.name,
+        worker_group: registration.group,
+        hostname: registration.hostname,
+        version: registration.version,
+        adapter: registration.adapter,
+        cert_fingerprint: fingerprint,
+        api_key_id: lineage,
+    };
+    let worker = web::block(move || {
+        let mut conn = pool.get()
+            .expect("Could not obtain database connection.");
+        new_worker.save_to_db(&mut conn)
+    }).await
+        .map_err(|e| error::ErrorInternalServerError(e.to_string()))?
+        .map_err(|e| error::ErrorInternalServerError(e.to_string()))?;
+
+    let id = worker.id;
+    println!("Registered {} {} ({})", worker.kind, id, worker.hostname.as_deref().unwrap_or("unknown host"));
+    fleet.add(worker);
+    Ok(HttpResponse::Ok().json(RegistrationResponse { id }))
+}
+
+/// Heartbeat of a worker, sent every 30 seconds. Body format:
+/// ```json
+/// {
+///   "hostname": "scanner-fra-1",
+///   "version": "0.1.0",
+///   "adapter": "eth0",
+///   "send_rate": 1000,
+///   "errors": 0
+/// }
+/// ```
+/// `errors` is the number of errors since the previous heartbeat, all fields are optional.
+/// Returns 404 if the worker isn't registered, and 403 if it registered with another client
+/// certificate or API key.
+#[utoipa::path(post, path = "/api/v1/workers/{id}/heartbeat", tag = "workers",
+    params(("id" = Uuid, Path, description = "Id of the worker"), ("X-Msearch-Protocol" = Option<u32>, Header, description = "Protocol version of the worker, see the `protocol` crate")),
+    request_body = Heartbeat, responses((status = 200), (status = 400, description = "Invalid body, or incompatible worker"),
+        (status = 403, description = "Worker registered with another certificate or API key"), (status = 404, description = "Unknown worker")))]
+#[post("/{id}/heartbeat")]
+async fn post_heartbeat(req: HttpRequest, path: Path<Uuid>, json: String, fleet: Data<Fleet>) -> Result<impl Responder> {
+    let heartbeat: Heartbeat = serde_json::from_str(&json).map_err(|e| error::ErrorBadRequest(e.to_string()))?;
+    let id = path.into_inner();
+    request_worker(&req, &fleet, Some(id))?;
+    if !fleet.heartbeat(id, heartbeat) {
+        return Err(error::ErrorNotFound("Unknown worker"));
+    }
+    Ok(HttpResponse::Ok().finish())
+}
@@ -0,0 +1,125 @@
+// Detection of decorative player samples. Many servers use `players.sample` (the list shown when
+// hovering the player count) to display text lines or ads instead of the online players, usually
+// with the nil UUID, and advertise fake player counts (i.e. 999999/1000000).
+
+use std::collections::HashMap;
+use serde_json::Value;
+use uuid::Uuid;
+
+/// Servers with more online players than this don't exist, the largest ones peak at about 200k
+const MAX_PLAUSIBLE_ONLINE: i64 = 250_000;
+
+#[derive(Debug, Default)]
+pub struct SampleCheck {
+    /// Entries that look like real players: username and UUID sent by the server
+    pub players: Vec<(String, Uuid)>,
+    /// Text of the decorative entries, in the order they were sent
+    pub sample_text: Vec<String>,
+    /// True if the online or max player counts can't be real
+    pub count_spoofed: bool,
+}
+
+/// Splits the player sample of a status response (the `players` field) into real players and
+/// decorative entries, and checks whether the player counts are plausible.
+pub fn check_players(players: &Value) -> SampleCheck {
+    let sample = players["sample"].as_array().map(Vec::as_slice).unwrap_or_default();
+    let entries: Vec<(String, Option<Uuid>)> = sample.iter()
+        .map(|entry| {
+            let name = entry["name"].as_str().map(String::from).unwrap_or_default();
+            let id = entry["id"].as_str().and_then(|id| Uuid::parse_str(id).ok());
+            (name, id)
+        })
+        .collect();
+
+    // Decorative lines are often all sent with the same UUID
+    let mut uuid_counts: HashMap<Uuid, usize> = HashMap::new();
+    entries.iter().filter_map(|(_, id)| *id).for_each(|id| *uuid_counts.entry(id).or_default() += 1);
+
+    let mut check = SampleCheck::default();
+    for (name, id) in entries {
+        match id {
+            Some(id) if !id.is_nil() && uuid_counts[&id] == 1 && is_valid_username(&name) => {
+                check.players.push((name, id))
+            }
+            _ => check.sample_text.push(name)
+        }
+    }
+
+    let online = players["online"].as_i64();
+    let max = players["max"].as_i64();
+    check.count_spoofed = online.is_some_and(|o| !(0..=MAX_PLAUSIBLE_ONLINE).contains(&o))
+        || max.is_some_and(|m| m < 0)
+        // The sample only contains online players, so it can't be larger than the online count
+        || online.is_some_and(|o| (check.players.len() as i64) > o);
+    check
+}
+
+/// Minecraft usernames are 3 to 16 characters long, and only contain letters, digits and
+/// underscores. This also rules out names with formatting codes or spaces.
+pub fn is_valid_username(name: &str) -> bool {
+    (3..=16).contains(&name.len()) && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use super::*;
+
+    const NOTCH: &str = "069a79f4-44e9-4726-a5be-fca90e38aaf5";
+    const JEB: &str = "853c80ef-3c37-49fd-aa49-938b674adae6";
+    const NIL: &str = "00000000-0000-0000-0000-000000000000";
+
+    #[test]
+    fn keeps_real_players() {
+        let check = check_players(&json!({"online": 2, "max": 20, "sample": [
+            {"name": "Notch", "id": NOTCH},
+            {"name": "jeb_", "id": JEB}
+        ]}));
+        let names: Vec<&str> = check.players.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, vec!["Notch", "jeb_"]);
+        assert_eq!(check.players[0].1, Uuid::parse_str(NOTCH).unwrap());
+        assert!(check.sample_text.is_empty());
+        assert!(!check.count_spoofed);
+    }
+
+    #[test]
+    fn keeps_decorative_entries_as_text() {
+        let check = check_players(&json!({"online": 5, "max": 100, "sample": [
+            {"name": "§6Welcome to the server", "id": NIL},
+            {"name": "Notch", "id": NOTCH},
+            {"name": "line_a", "id": JEB},
+            {"name": "line_b", "id": JEB},
+            {"name": "no_id"},
+            {"name": "ab", "id": "1ad7a2a4-bc3c-4bfb-9b54-9ce52a8d22b3"}
+        ]}));
+        assert_eq!(check.players.len(), 1);
+        // Shared UUIDs, missing UUIDs and invalid names are text, in the order they were sent
+        assert_eq!(check.sample_text, vec!["§6Welcome to the server", "line_a", "line_b", "no_id", "ab"]);
+        assert!(!check.count_spoofed);
+    }
+
+    #[test]
+    fn flags_spoofed_counts() {
+        let spoofed = |players: Value| check_players(&players).count_spoofed;
+        assert!(spoofed(json!({"online": 999_999, "max": 1_000_000})));
+        assert!(spoofed(json!({"online": -1, "max": 20})));
+        assert!(spoofed(json!({"online": 0, "max": -5})));
+        // More players in the sample than online
+        assert!(spoofed(json!({"online": 0, "max": 20, "sample": [{"name": "Notch", "id": NOTCH}]})));
+        assert!(!spoofed(json!({"online": 250_000, "max": 300_000})));
+        assert!(!spoofed(json!({"max": 20})));
+        assert!(!spoofed(Value::Null));
+    }
+
+    #[test]
+    fn validates_usernames() {
+        assert!(is_valid_username("Notch"));
+        assert!(is_valid_username("a_b"));
+        assert!(is_valid_username("sixteen_chars_16"));
+        assert!(!is_valid_username("ab"));
+        assert!(!is_valid_username("seventeen_chars17"));
+        assert!(!is_valid_username("two words"));
+        assert!(!is_valid_username("§aGreen"));
+        assert!(!is_valid_username("émile"));
+    }
+}
@@ -1,5 +1,72 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    api_key (id) {
+        id -> Int4,
+        name -> Text,
+        role -> Text,
+        key_hash -> Text,
+        prefix -> Text,
+        created_at -> Timestamp,
+        expires_at -> Nullable<Timestamp>,
+        revoked_at -> Nullable<Timestamp>,
+        last_used -> Nullable<Timestamp>,
+        origin_id -> Nullable<Int4>,
+    }
+}
+
+diesel::table! {
+    cluster (cluster_id) {
+        cluster_id -> Int4,
+        size -> Int4,
+        favicon_id -> Nullable<Int4>,
+        motd -> Nullable<Text>,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    favicon (favicon_id) {
+        favicon_id -> Int4,
+        hash -> Text,
+        data -> Bytea,
+        phash -> Nullable<Int8>,
+    }
+}
+
+diesel::table! {
+    honeypot (network) {
+        network -> Cidr,
+        score -> Float4,
+        reasons -> Array<Nullable<Text>>,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    #[sql_name = "mod"]
+    mod_ (mod_id) {
+        mod_id -> Int4,
+        modid -> Text,
+        version -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    mod_scan (mod_id, scan_id) {
+        mod_id -> Int4,
+        scan_id -> Int4,
+    }
+}
+
+diesel::table! {
+    netblock_stats (network) {
+        network -> Cidr,
+        probed -> Int4,
+        responded -> Int4,
+    }
+}
+
 diesel::table! {
     player (player_id) {
         player_id -> Int4,
@@ -8,6 +75,35 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    player_base (ip) {
+        ip -> Inet,
+        sampled_scans -> Int4,
+        observed_players -> Int4,
+        singletons -> Int4,
+        doubletons -> Int4,
+        estimated_players -> Float4,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    player_link (player_id, linked_player_id) {
+        player_id -> Int4,
+        linked_player_id -> Int4,
+        shared_scans -> Int4,
+    }
+}
+
+diesel::table! {
+    player_name_history (player_id, username) {
+        player_id -> Int4,
+        username -> Text,
+        first_seen -> Timestamp,
+        last_seen -> Timestamp,
+    }
+}
+
 diesel::table! {
     player_scan (player_id, scan_id) {
         player_scan_uuid -> Uuid,
@@ -16,6 +112,21 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    port_probe (ip) {
+        ip -> Inet,
+        probes -> Int4,
+        hits -> Int4,
+    }
+}
+
+diesel::table! {
+    rejected_favicon (scan_id) {
+        scan_id -> Int4,
+        favicon -> Text,
+    }
+}
+
 diesel::table! {
     scan (scan_id) {
         scan_id -> Int4,
@@ -24,15 +135,96 @@ diesel::table! {
         online_count -> Nullable<Int4>,
         max_count -> Nullable<Int4>,
         description -> Nullable<Text>,
-        favicon -> Nullable<Text>,
+        favicon_id -> Nullable<Int4>,
+        protocol -> Nullable<Int4>,
+        software -> Nullable<Text>,
+        is_proxy -> Nullable<Bool>,
+        is_modded -> Nullable<Bool>,
+        game_version_min -> Nullable<Text>,
+        game_version_max -> Nullable<Text>,
+        sample_text -> Array<Nullable<Text>>,
+        player_count_spoofed -> Bool,
+        scanned_at -> Nullable<Timestamp>,
+        worker_id -> Nullable<Uuid>,
+    }
+}
+
+diesel::table! {
+    server_cluster (ip) {
+        ip -> Inet,
+        cluster_id -> Int4,
+    }
+}
+
+diesel::table! {
+    server_geo (ip) {
+        ip -> Inet,
+        country_code -> Nullable<Text>,
+        country -> Nullable<Text>,
+        city -> Nullable<Text>,
+        asn -> Nullable<Int4>,
+        as_org -> Nullable<Text>,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    server_link (ip, linked_ip) {
+        ip -> Inet,
+        linked_ip -> Inet,
+        shared_players -> Int4,
+    }
+}
+
+diesel::table! {
+    worker (id) {
+        id -> Uuid,
+        kind -> Text,
+        name -> Nullable<Text>,
+        worker_group -> Nullable<Text>,
+        hostname -> Nullable<Text>,
+        version -> Nullable<Text>,
+        adapter -> Nullable<Text>,
+        send_rate -> Nullable<Int8>,
+        registered_at -> Timestamp,
+        last_seen -> Timestamp,
+        jobs_completed -> Int8,
+        items_processed -> Int8,
+        errors -> Int8,
+        cert_fingerprint -> Nullable<Text>,
+        api_key_id -> Nullable<Int4>,
     }
 }
 
+diesel::joinable!(cluster -> favicon (favicon_id));
+diesel::joinable!(mod_scan -> mod_ (mod_id));
+diesel::joinable!(mod_scan -> scan (scan_id));
+diesel::joinable!(player_name_history -> player (player_id));
 diesel::joinable!(player_scan -> player (player_id));
 diesel::joinable!(player_scan -> scan (scan_id));
+diesel::joinable!(rejected_favicon -> scan (scan_id));
+diesel::joinable!(scan -> favicon (favicon_id));
+diesel::joinable!(scan -> worker (worker_id));
+diesel::joinable!(server_cluster -> cluster (cluster_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
+    api_key,
+    cluster,
+    favicon,
+    honeypot,
+    mod_,
+    mod_scan,
+    netblock_stats,
     player,
+    player_base,
+    player_link,
+    player_name_history,
     player_scan,
+    port_probe,
+    rejected_favicon,
     scan,
+    server_cluster,
+    server_geo,
+    server_link,
+    worker,
 );
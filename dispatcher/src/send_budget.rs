@@ -0,0 +1,253 @@
+// Global send-rate budget of the scouts. Without it, every scout sends at the rate of its own
+// config file and adding scouts multiplies the total outbound rate. When `send_rate.budget`
+// (`SEND_RATE_BUDGET`) is set, the budget (in packets per second) is split evenly between the
+// active scouts, and the job size follows from each scout's rate so that every job lasts about
+// `send_rate.job_duration_secs` (`JOB_DURATION_SECS`).
+//
+// The budget can vary with the time of day (UTC) with `send_rate.schedule`
+// (`SEND_RATE_SCHEDULE`), i.e. `00:00-08:00=50000,20:00-00:00=30000`, the first matching window
+// applies and the base budget is used outside of them. Scanning is paused during the windows of
+// `send_rate.blackouts` (`SEND_BLACKOUTS`), i.e. `12:00-13:30,18:00-18:15`. Windows can wrap
+// around midnight.
+//
+// Scouts are told apart by their registered id, then by their name, then by their address, so
+// unregistered scouts without a name behind the same address (i.e. a NAT) share a single part of
+// the budget. Scouts released before the protocol was versioned ignore the rate of their job and
+// send at the rate of their config file: they are left out of the split and keep the job size
+// they ask for, but are paused during blackouts as well.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use crate::config::SendRateConfig;
+
+const SECS_PER_DAY: u32 = 24 * 60 * 60;
+
+/// Time of day window, in seconds since midnight. The end is excluded.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Window {
+    start: u32,
+    end: u32,
+}
+
+impl Window {
+    /// Parses a `HH:MM-HH:MM` window
+    fn parse(text: &str) -> Result<Window, String> {
+        let (start, end) = text.trim().split_once('-')
+            .ok_or_else(|| format!("Invalid window {}, expected HH:MM-HH:MM", text))?;
+        Ok(Window { start: parse_time(start)?, end: parse_time(end)? })
+    }
+
+    fn contains(&self, time: u32) -> bool {
+        if self.start <= self.end {
+            (self.start..self.end).contains(&time)
+        } else {
+            // Wraps around midnight
+            time >= self.start || time < self.end
+        }
+    }
+}
+
+/// Parses a `HH:MM` time of day into seconds since midnight
+fn parse_time(text: &str) -> Result<u32, String> {
+    let invalid = || format!("Invalid time {}, expected HH:MM", text);
+    let (hours, minutes) = text.trim().split_once(':').ok_or_else(invalid)?;
+    let hours: u32 = hours.parse().map_err(|_| invalid())?;
+    let minutes: u32 = minutes.parse().map_err(|_| invalid())?;
+    // 24:00 is allowed as the end of a window
+    if minutes >= 60 || hours * 60 + minutes > 24 * 60 {
+        return Err(invalid());
+    }
+    Ok((hours * 60 + minutes) * 60)
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct BudgetConfig {
+    // Total packets per second, None if the scouts use their own rate
+    pub budget: Option<u64>,
+    pub schedule: Vec<(Window, u64)>,
+    pub blackouts: Vec<Window>,
+    pub job_duration: Duration,
+}
+
+impl BudgetConfig {
+    /// Parses the budget settings, see the module documentation
+    pub fn from_config(config: &SendRateConfig) -> Result<Self, String> {
+        let schedule = config.schedule.iter()
+            .map(|entry| {
+                let (window, rate) = entry.split_once('=')
+                    .ok_or_else(|| format!("Invalid schedule entry {}, expected HH:MM-HH:MM=RATE", entry))?;
+                let rate = rate.trim().parse::<u64>().map_err(|_| format!("Invalid rate in schedule entry {}", entry))?;
+                Ok((Window::parse(window)?, rate))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        let blackouts = config.blackouts.iter()
+            .map(|entry| Window::parse(entry))
+            .collect::<Result<Vec<_>, String>>()?;
+        if config.job_duration_secs == 0 {
+            return Err(String::from("send_rate.job_duration_secs must be above 0"));
+        }
+
+        if config.budget.is_none() && (!schedule.is_empty() || !blackouts.is_empty()) {
+            return Err(String::from("send_rate.schedule and send_rate.blackouts require send_rate.budget"));
+        }
+        Ok(BudgetConfig {
+            budget: config.budget,
+            schedule,
+            blackouts,
+            job_duration: Duration::from_secs(config.job_duration_secs)
+        })
+    }
+
+    /// Total budget at `time` (seconds since midnight), 0 during blackouts. None if there is no
+    /// budget.
+    pub fn budget_at(&self, time: u32) -> Option<u64> {
+        let budget = self.budget?;
+        if self.blackouts.iter().any(|w| w.contains(time)) {
+            return Some(0);
+        }
+        Some(self.schedule.iter().find(|(w, _)| w.contains(time)).map_or(budget, |(_, rate)| *rate))
+    }
+}
+
+/// Rate and size of a scout job
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct JobShare {
+    pub send_rate: u64,
+    pub job_size: usize,
+}
+
+/// Splits the budget between the active scouts
+pub struct SendBudget {
+    pub config: BudgetConfig,
+    // Last job request of each scout
+    scouts: Mutex<HashMap<String, SystemTime>>,
+}
+
+impl SendBudget {
+    pub fn new(config: BudgetConfig) -> Self {
+        SendBudget { config, scouts: Mutex::new(HashMap::new()) }
+    }
+
+    /// Registers a job request of `scout`, and returns its share of the current budget. Returns
+    /// None if there is no budget, and a rate of 0 during blackouts.
+    pub fn share(&self, scout: &str) -> Option<JobShare> {
+        let now = SystemTime::now();
+        let budget = self.config.budget_at(time_of_day(now))?;
+
+        // Scouts are active if they asked for a job recently, they should come back at the end of
+        // each job
+        let active = {
+            let mut scouts = self.scouts.lock().unwrap();
+            scouts.insert(String::from(scout), now);
+            let timeout = self.config.job_duration * 3;
+            scouts.retain(|_, last_seen| now.duration_since(*last_seen).map_or(true, |d| d <= timeout));
+            scouts.len() as u64
+        };
+
+        Some(split(budget, active, self.config.job_duration))
+    }
+
+    /// Whether scanning is paused by a blackout, for the scouts left out of the split
+    pub fn is_paused(&self) -> bool {
+        self.config.budget_at(time_of_day(SystemTime::now())) == Some(0)
+    }
+}
+
+/// Seconds since midnight (UTC)
+fn time_of_day(time: SystemTime) -> u32 {
+    (time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs()) % SECS_PER_DAY as u64) as u32
+}
+
+/// Share of each of the `active` scouts
+fn split(budget: u64, active: u64, job_duration: Duration) -> JobShare {
+    // A budget too small to split still gives every scout something to send
+    let send_rate = if budget == 0 { 0 } else { (budget / active.max(1)).max(1) };
+    JobShare {
+        send_rate,
+        job_size: (send_rate * job_duration.as_secs()).max(1) as usize,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: u32 = 60 * 60;
+
+    #[test]
+    fn parses_times() {
+        assert_eq!(parse_time("00:00"), Ok(0));
+        assert_eq!(parse_time(" 08:30 "), Ok(8 * HOUR + 30 * 60));
+        assert_eq!(parse_time("24:00"), Ok(SECS_PER_DAY));
+        for invalid in ["24:01", "12:60", "12", "ab:00", "-1:00", ""] {
+            assert!(parse_time(invalid).is_err(), "{} should be invalid", invalid);
+        }
+    }
+
+    #[test]
+    fn parses_windows() {
+        let window = Window::parse("08:00-12:30").unwrap();
+        assert_eq!(window, Window { start: 8 * HOUR, end: 12 * HOUR + 30 * 60 });
+        assert!(window.contains(8 * HOUR));
+        assert!(window.contains(12 * HOUR));
+        // The end is excluded
+        assert!(!window.contains(12 * HOUR + 30 * 60));
+        assert!(!window.contains(7 * HOUR));
+        assert!(Window::parse("08:00").is_err());
+        assert!(Window::parse("08:00-25:00").is_err());
+    }
+
+    #[test]
+    fn windows_wrap_around_midnight() {
+        let window = Window::parse("22:00-02:00").unwrap();
+        assert!(window.contains(23 * HOUR));
+        assert!(window.contains(0));
+        assert!(window.contains(HOUR));
+        assert!(!window.contains(2 * HOUR));
+        assert!(!window.contains(12 * HOUR));
+
+        // Up to the end of the day
+        let window = Window::parse("20:00-24:00").unwrap();
+        assert!(window.contains(SECS_PER_DAY - 1));
+        assert!(!window.contains(0));
+    }
+
+    #[test]
+    fn budget_follows_schedule_and_blackouts() {
+        let config = BudgetConfig {
+            budget: Some(1000),
+            schedule: vec![(Window::parse("00:00-08:00").unwrap(), 5000), (Window::parse("06:00-10:00").unwrap(), 2000)],
+            blackouts: vec![Window::parse("12:00-13:00").unwrap()],
+            job_duration: Duration::from_secs(60),
+        };
+        assert_eq!(config.budget_at(HOUR), Some(5000));
+        // The first matching window applies
+        assert_eq!(config.budget_at(7 * HOUR), Some(5000));
+        assert_eq!(config.budget_at(9 * HOUR), Some(2000));
+        assert_eq!(config.budget_at(12 * HOUR + 1), Some(0));
+        assert_eq!(config.budget_at(20 * HOUR), Some(1000));
+        assert_eq!(BudgetConfig::default().budget_at(HOUR), None);
+    }
+
+    #[test]
+    fn splits_budget() {
+        let minute = Duration::from_secs(60);
+        assert_eq!(split(1000, 4, minute), JobShare { send_rate: 250, job_size: 15000 });
+        assert_eq!(split(1000, 0, minute), JobShare { send_rate: 1000, job_size: 60000 });
+        // Too small to split
+        assert_eq!(split(3, 10, minute), JobShare { send_rate: 1, job_size: 60 });
+        assert_eq!(split(0, 2, minute), JobShare { send_rate: 0, job_size: 1 });
+    }
+
+    #[test]
+    fn splits_between_active_scouts() {
+        let budget = SendBudget::new(BudgetConfig { budget: Some(900), job_duration: Duration::from_secs(10), ..Default::default() });
+        assert_eq!(budget.share("a").unwrap().send_rate, 900);
+        assert_eq!(budget.share("b").unwrap().send_rate, 450);
+        assert_eq!(budget.share("c").unwrap().send_rate, 300);
+        // Asking again doesn't count twice
+        assert_eq!(budget.share("a").unwrap().send_rate, 300);
+        assert!(!budget.is_paused());
+    }
+}
@@ -0,0 +1,336 @@
+// Identification of the server software from a status response. The `version.name` field usually
+// contains the name of the software (i.e. "Paper 1.19.2", "BungeeCord 1.8.x-1.19.x"), modded
+// servers also advertise their mods through the `forgeData` or `modinfo` fields.
+
+use std::str::FromStr;
+use diesel::prelude::*;
+use protocol::motd::{parse_component, plain_text};
+use serde::Deserialize;
+use serde_json::Value;
+use crate::DbConnection;
+use crate::schema::scan;
+
+/// Keyword found in the version name, software family, is proxy, is modded.
+/// Checked in order, so more specific keywords must come first.
+const FAMILIES: &[(&str, &str, bool, bool)] = &[
+    ("velocity", "velocity", true, false),
+    ("waterfall", "waterfall", true, false),
+    ("flamecord", "flamecord", true, false),
+    ("travertine", "travertine", true, false),
+    ("bungee", "bungeecord", true, false),
+    ("neoforge", "neoforge", false, true),
+    ("mohist", "mohist", false, true),
+    ("arclight", "arclight", false, true),
+    ("catserver", "catserver", false, true),
+    ("magma", "magma", false, true),
+    ("thermos", "thermos", false, true),
+    ("cauldron", "cauldron", false, true),
+    ("spongeforge", "sponge", false, true),
+    ("sponge", "sponge", false, false),
+    ("forge", "forge", false, true),
+    ("fabric", "fabric", false, true),
+    ("quilt", "quilt", false, true),
+    ("folia", "folia", false, false),
+    ("purpur", "purpur", false, false),
+    ("pufferfish", "pufferfish", false, false),
+    ("tuinity", "tuinity", false, false),
+    ("airplane", "airplane", false, false),
+    ("paper", "paper", false, false),
+    ("spigot", "spigot", false, false),
+    ("bukkit", "craftbukkit", false, false),
+    ("glowstone", "glowstone", false, false),
+    ("cuberite", "cuberite", false, false),
+    ("minestom", "minestom", false, false),
+];
+
+/// Protocol number, first and last release using it
+const PROTOCOLS: &[(i64, &str, &str)] = &[
+    (4, "1.7.2", "1.7.5"),
+    (5, "1.7.6", "1.7.10"),
+    (47, "1.8", "1.8.9"),
+    (107, "1.9", "1.9"),
+    (108, "1.9.1", "1.9.1"),
+    (109, "1.9.2", "1.9.2"),
+    (110, "1.9.3", "1.9.4"),
+    (210, "1.10", "1.10.2"),
+    (315, "1.11", "1.11"),
+    (316, "1.11.1", "1.11.2"),
+    (335, "1.12", "1.12"),
+    (338, "1.12.1", "1.12.1"),
+    (340, "1.12.2", "1.12.2"),
+    (393, "1.13", "1.13"),
+    (401, "1.13.1", "1.13.1"),
+    (404, "1.13.2", "1.13.2"),
+    (477, "1.14", "1.14"),
+    (480, "1.14.1", "1.14.1"),
+    (485, "1.14.2", "1.14.2"),
+    (490, "1.14.3", "1.14.3"),
+    (498, "1.14.4", "1.14.4"),
+    (573, "1.15", "1.15"),
+    (575, "1.15.1", "1.15.1"),
+    (578, "1.15.2", "1.15.2"),
+    (735, "1.16", "1.16"),
+    (736, "1.16.1", "1.16.1"),
+    (751, "1.16.2", "1.16.2"),
+    (753, "1.16.3", "1.16.3"),
+    (754, "1.16.4", "1.16.5"),
+    (755, "1.17", "1.17"),
+    (756, "1.17.1", "1.17.1"),
+    (757, "1.18", "1.18.1"),
+    (758, "1.18.2", "1.18.2"),
+    (759, "1.19", "1.19"),
+    (760, "1.19.1", "1.19.2"),
+    (761, "1.19.3", "1.19.3"),
+    (762, "1.19.4", "1.19.4"),
+    (763, "1.20", "1.20.1"),
+    (764, "1.20.2", "1.20.2"),
+    (765, "1.20.3", "1.20.4"),
+    (766, "1.20.5", "1.20.6"),
+    (767, "1.21", "1.21.1"),
+    (768, "1.21.2", "1.21.3"),
+    (769, "1.21.4", "1.21.4"),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SoftwareInfo {
+    /// Lowercase name of the software (`paper`, `velocity`, ...), `vanilla` if the version name
+    /// only contains a version number, `unknown` if it couldn't be identified
+    pub family: String,
+    pub is_proxy: bool,
+    pub is_modded: bool,
+    /// Range of game versions supported by the server
+    pub version_min: Option<String>,
+    pub version_max: Option<String>,
+}
+
+/// Classifies the server based on its full status response
+pub fn classify_response(response: &Value) -> SoftwareInfo {
+    let has_mods = response.get("forgeData").is_some() || response.get("modinfo").is_some();
+    classify(response["version"]["name"].as_str(), response["version"]["protocol"].as_i64(), has_mods)
+}
+
+/// Classifies the server from its version name and protocol number, `has_mods` being true if
+/// the server sent mod data (`forgeData` or `modinfo`)
+pub fn classify(version_name: Option<&str>, protocol: Option<i64>, has_mods: bool) -> SoftwareInfo {
+    // Version names can contain formatting codes
+    let name = version_name
+        .map(|n| plain_text(&parse_component(&Value::String(String::from(n)))).to_lowercase())
+        .unwrap_or_default();
+    let versions = game_versions(&name);
+
+    let (family, is_proxy, is_modded) = match FAMILIES.iter().find(|(keyword, ..)| name.contains(keyword)) {
+        Some(&(_, family, is_proxy, is_modded)) => (family, is_proxy, is_modded || has_mods),
+        // Only mod data, assume the most common mod loader
+        None if has_mods => ("forge", false, true),
+        None if !versions.is_empty() && is_only_versions(&name) => ("vanilla", false, false),
+        None => ("unknown", false, false)
+    };
+
+    // Proxies answer with the protocol of the client pinging them, so it doesn't tell us
+    // anything about the server. Otherwise, the protocol is more reliable than the name.
+    let protocol_range = protocol
+        .filter(|_| !is_proxy)
+        .and_then(|p| PROTOCOLS.iter().find(|(number, ..)| *number == p));
+    let (version_min, version_max) = match protocol_range {
+        Some(&(_, min, max)) => (Some(String::from(min)), Some(String::from(max))),
+        None => (versions.first().cloned(), versions.last().cloned())
+    };
+
+    SoftwareInfo { family: String::from(family), is_proxy, is_modded, version_min, version_max }
+}
+
+/// Classifies scans saved before software identification was added. Only the version name and
+/// protocol are available for those, mod data wasn't saved. Returns the number of updated scans.
+pub fn classify_unclassified(conn: &mut DbConnection) -> QueryResult<usize> {
+    let mut count = 0;
+    loop {
+        let scans = scan::table
+            .filter(scan::software.is_null())
+            .select((scan::scan_id, scan::version, scan::protocol))
+            .limit(1000)
+            .load::<(i32, Option<String>, Option<i32>)>(conn)?;
+        if scans.is_empty() {
+            return Ok(count);
+        }
+
+        for (id, version, protocol) in scans {
+            let info = classify(version.as_deref(), protocol.map(i64::from), false);
+            diesel::update(scan::table.find(id))
+                .set((
+                    scan::software.eq(info.family),
+                    scan::is_proxy.eq(info.is_proxy),
+                    scan::is_modded.eq(info.is_modded),
+                    scan::game_version_min.eq(info.version_min),
+                    scan::game_version_max.eq(info.version_max),
+                ))
+                .execute(conn)?;
+            count += 1;
+        }
+    }
+}
+
+/// Extracts the game versions (`1.19.2`, `1.8.x`, ...) from a version name, sorted
+fn game_versions(name: &str) -> Vec<String> {
+    let mut versions: Vec<String> = name
+        .split(|c: char| !(c.is_ascii_digit() || c == '.' || c == 'x'))
+        .map(|token| token.trim_matches('.'))
+        .filter(|token| is_game_version(token))
+        .map(String::from)
+        .collect();
+
+    versions.sort_by_key(|v| version_key(v));
+    versions.dedup();
+    versions
+}
+
+fn is_game_version(token: &str) -> bool {
+    let parts: Vec<&str> = token.split('.').collect();
+    parts.len() >= 2
+        && parts.len() <= 3
+        && parts[0] == "1"
+        && parts[1].parse::<u32>().is_ok_and(|minor| minor <= 30)
+        && parts[2..].iter().all(|p| *p == "x" || p.parse::<u32>().is_ok())
+}
+
+/// True if the version name doesn't contain anything other than versions and separators
+fn is_only_versions(name: &str) -> bool {
+    name.split(|c: char| c.is_whitespace() || c == '-' || c == ',' || c == '/')
+        .filter(|t| !t.is_empty())
+        .all(|t| is_game_version(t.trim_matches('.')))
+}
+
+/// Sort key of a version, wildcards being considered higher than any patch version
+fn version_key(version: &str) -> Vec<u32> {
+    version.split('.').map(|p| p.parse().unwrap_or(u32::MAX)).collect()
+}
+
+/// What the `x` wildcard stands for at the top of a range of versions
+const WILDCARD_TOP: i32 = i32::MAX;
+
+/// Releases matched by a game version, as [major, minor, patch]. Missing numbers are 0 (`1.19` is
+/// `1.19.0`), and the `x` wildcard of `1.19.x` stands for every patch version of 1.19.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct VersionRange {
+    pub lowest: [i32; 3],
+    pub highest: [i32; 3],
+}
+
+impl FromStr for VersionRange {
+    type Err = String;
+
+    fn from_str(version: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid game version: {}", version);
+        let parts: Vec<&str> = version.trim().split('.').collect();
+        if parts.len() < 2 || parts.len() > 3 {
+            return Err(invalid());
+        }
+        let mut range = VersionRange { lowest: [0; 3], highest: [0; 3] };
+        for (i, part) in parts.iter().enumerate() {
+            if *part == "x" && i == parts.len() - 1 {
+                range.highest[i..].fill(WILDCARD_TOP);
+            } else if !part.is_empty() && part.len() <= 4 && part.bytes().all(|b| b.is_ascii_digit()) {
+                range.lowest[i] = part.parse().map_err(|_| invalid())?;
+                range.highest[i] = range.lowest[i];
+            } else {
+                return Err(invalid());
+            }
+        }
+        Ok(range)
+    }
+}
+
+impl TryFrom<String> for VersionRange {
+    type Error = String;
+
+    fn try_from(version: String) -> Result<Self, Self::Error> {
+        version.parse()
+    }
+}
+
+/// SQL expression of a game version column as an `int[]`, compared like the bounds of a
+/// [`VersionRange`]. The `x` wildcard is the top of its range if `highest`, and 0 otherwise. NULL
+/// if the column doesn't hold a game version.
+pub fn version_sql(column: &str, highest: bool) -> String {
+    let wildcard = if highest { WILDCARD_TOP } else { 0 };
+    format!("(CASE WHEN {column} ~ '^\\d{{1,4}}(\\.(\\d{{1,4}}|x)){{1,2}}$' \
+             THEN ((string_to_array(replace({column}, 'x', '{wildcard}'), '.') || '{{0,0}}'::text[])[1:3])::int[] END)")
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use super::*;
+
+    fn versions(info: &SoftwareInfo) -> (Option<&str>, Option<&str>) {
+        (info.version_min.as_deref(), info.version_max.as_deref())
+    }
+
+    #[test]
+    fn identifies_families() {
+        let paper = classify(Some("Paper 1.19.2"), Some(760), false);
+        assert_eq!((paper.family.as_str(), paper.is_proxy, paper.is_modded), ("paper", false, false));
+        // Specific keywords win over the ones they contain
+        assert_eq!(classify(Some("Waterfall 1.19"), None, false).family, "waterfall");
+        assert_eq!(classify(Some("SpongeForge 1.12.2"), None, false).family, "sponge");
+        assert!(classify(Some("SpongeForge 1.12.2"), None, false).is_modded);
+        assert!(!classify(Some("SpongeVanilla 1.12.2"), None, false).is_modded);
+        // Formatting codes are ignored
+        assert_eq!(classify(Some("§cVelocity §f3.2.0"), Some(47), false).family, "velocity");
+    }
+
+    #[test]
+    fn falls_back_on_version_and_mods() {
+        assert_eq!(classify(Some("1.20.1"), Some(763), false).family, "vanilla");
+        assert_eq!(classify(Some("1.8.x-1.20.x"), None, false).family, "vanilla");
+        assert_eq!(classify(Some("Requires 1.20"), None, false).family, "unknown");
+        assert_eq!(classify(None, None, false).family, "unknown");
+
+        let modded = classify(Some("1.12.2"), Some(340), true);
+        assert_eq!((modded.family.as_str(), modded.is_modded), ("forge", true));
+        let response = json!({"version": {"name": "1.18.2", "protocol": 758}, "forgeData": {}});
+        assert_eq!(classify_response(&response), classify(Some("1.18.2"), Some(758), true));
+    }
+
+    #[test]
+    fn ranges_from_protocol() {
+        // One protocol number can be shared by several releases
+        assert_eq!(versions(&classify(Some("Paper 1.16.5"), Some(754), false)), (Some("1.16.4"), Some("1.16.5")));
+        // The protocol is more reliable than the name
+        assert_eq!(versions(&classify(Some("Spigot 1.19"), Some(47), false)), (Some("1.8"), Some("1.8.9")));
+        // Unknown protocol, use the name
+        assert_eq!(versions(&classify(Some("Paper 1.19.2"), Some(9999), false)), (Some("1.19.2"), Some("1.19.2")));
+    }
+
+    #[test]
+    fn ranges_of_proxies_from_name() {
+        // Proxies answer with the protocol of the client
+        let bungee = classify(Some("BungeeCord 1.8.x-1.19.x"), Some(760), false);
+        assert!(bungee.is_proxy);
+        assert_eq!(versions(&bungee), (Some("1.8.x"), Some("1.19.x")));
+        assert_eq!(versions(&classify(Some("Velocity"), Some(760), false)), (None, None));
+    }
+
+    #[test]
+    fn extracts_game_versions() {
+        assert_eq!(game_versions("travertine 1.7.x, 1.19.2, 1.8, 1.19.2"), vec!["1.7.x", "1.8", "1.19.2"]);
+        // Wildcards are above any patch version
+        assert_eq!(game_versions("1.12.x 1.12.2"), vec!["1.12.2", "1.12.x"]);
+        // Not game versions
+        assert!(game_versions("2.0.1 1.99 1.2.3.4 v1").is_empty());
+        assert!(is_only_versions("1.8 - 1.20/1.20.1"));
+        assert!(!is_only_versions("1.8 server"));
+    }
+
+    #[test]
+    fn parses_version_ranges() {
+        let range = |v: &str| v.parse::<VersionRange>().map(|r| (r.lowest, r.highest));
+        assert_eq!(range("1.19.2"), Ok(([1, 19, 2], [1, 19, 2])));
+        assert_eq!(range("1.20"), Ok(([1, 20, 0], [1, 20, 0])));
+        assert_eq!(range("1.8.x"), Ok(([1, 8, 0], [1, 8, WILDCARD_TOP])));
+        assert_eq!(range(" 1.x "), Ok(([1, 0, 0], [1, WILDCARD_TOP, WILDCARD_TOP])));
+        for invalid in ["1", "1.19.2.1", "1.x.2", "x.1", "1..2", "1.-2", "1.+2", "1.12345", ""] {
+            assert!(range(invalid).is_err(), "{} should be invalid", invalid);
+        }
+    }
+}
@@ -0,0 +1,132 @@
+// Global statistics of the dataset. They are computed by materialized views (see the
+// `create_stats_views` migration) refreshed periodically, so `/stats` doesn't aggregate every
+// scan on each request.
+
+use std::time::SystemTime;
+use diesel::prelude::*;
+use diesel::sql_query;
+use diesel::sql_types::{BigInt, Bool, Double, Integer, Nullable, Text, Timestamp};
+use crate::DbConnection;
+
+/// Views in refresh order, `stats_server` is used by all the others
+const VIEWS: [&str; 9] = [
+    "stats_server", "stats_overview", "stats_version", "stats_protocol", "stats_software",
+    "stats_auth_mode", "stats_discovery", "stats_country", "stats_asn",
+];
+/// Number of ASNs returned, there are tens of thousands of them
+const MAX_ASNS: i64 = 100;
+
+/// Recomputes all the statistics
+pub fn refresh_views(conn: &mut DbConnection) -> QueryResult<()> {
+    conn.transaction(|conn| {
+        for view in VIEWS {
+            sql_query(format!("REFRESH MATERIALIZED VIEW {}", view)).execute(conn)?;
+        }
+        Ok(())
+    })
+}
+
+#[derive(QueryableByName)]
+pub struct Overview {
+    #[diesel(sql_type = BigInt)]
+    pub total_servers: i64,
+    #[diesel(sql_type = BigInt)]
+    pub online_servers: i64,
+    #[diesel(sql_type = BigInt)]
+    pub total_players: i64,
+    #[diesel(sql_type = Nullable<Double>)]
+    pub average_players: Option<f64>,
+    #[diesel(sql_type = Timestamp)]
+    pub updated_at: SystemTime,
+}
+
+#[derive(QueryableByName)]
+pub struct VersionCount {
+    #[diesel(sql_type = Nullable<Text>)]
+    pub game_version: Option<String>,
+    #[diesel(sql_type = BigInt)]
+    pub servers: i64,
+}
+
+#[derive(QueryableByName)]
+pub struct ProtocolCount {
+    #[diesel(sql_type = Nullable<Integer>)]
+    pub protocol: Option<i32>,
+    #[diesel(sql_type = BigInt)]
+    pub servers: i64,
+}
+
+#[derive(QueryableByName)]
+pub struct SoftwareCount {
+    #[diesel(sql_type = Nullable<Text>)]
+    pub software: Option<String>,
+    #[diesel(sql_type = Nullable<Bool>)]
+    pub is_proxy: Option<bool>,
+    #[diesel(sql_type = Nullable<Bool>)]
+    pub is_modded: Option<bool>,
+    #[diesel(sql_type = BigInt)]
+    pub servers: i64,
+}
+
+#[derive(QueryableByName)]
+pub struct AuthModeCount {
+    #[diesel(sql_type = Text)]
+    pub mode: String,
+    #[diesel(sql_type = BigInt)]
+    pub servers: i64,
+}
+
+#[derive(QueryableByName)]
+pub struct DiscoveryCount {
+    #[diesel(sql_type = Timestamp)]
+    pub day: SystemTime,
+    #[diesel(sql_type = BigInt)]
+    pub servers: i64,
+}
+
+#[derive(QueryableByName)]
+pub struct CountryCount {
+    #[diesel(sql_type = Nullable<Text>)]
+    pub country_code: Option<String>,
+    #[diesel(sql_type = Nullable<Text>)]
+    pub country: Option<String>,
+    #[diesel(sql_type = BigInt)]
+    pub servers: i64,
+}
+
+#[derive(QueryableByName)]
+pub struct AsnCount {
+    #[diesel(sql_type = Nullable<Integer>)]
+    pub asn: Option<i32>,
+    #[diesel(sql_type = Nullable<Text>)]
+    pub as_org: Option<String>,
+    #[diesel(sql_type = BigInt)]
+    pub servers: i64,
+}
+
+pub struct Stats {
+    pub overview: Overview,
+    pub versions: Vec<VersionCount>,
+    pub protocols: Vec<ProtocolCount>,
+    pub software: Vec<SoftwareCount>,
+    pub auth_modes: Vec<AuthModeCount>,
+    /// Oldest day first
+    pub discovery: Vec<DiscoveryCount>,
+    pub countries: Vec<CountryCount>,
+    /// Largest ASNs only, see [`MAX_ASNS`]
+    pub asns: Vec<AsnCount>,
+}
+
+/// Loads the statistics as of the last refresh
+pub fn load_stats(conn: &mut DbConnection) -> QueryResult<Stats> {
+    Ok(Stats {
+        overview: sql_query("SELECT * FROM stats_overview").get_result(conn)?,
+        versions: sql_query("SELECT * FROM stats_version ORDER BY servers DESC, game_version").load(conn)?,
+        protocols: sql_query("SELECT * FROM stats_protocol ORDER BY servers DESC, protocol").load(conn)?,
+        software: sql_query("SELECT * FROM stats_software ORDER BY servers DESC, software").load(conn)?,
+        auth_modes: sql_query("SELECT * FROM stats_auth_mode ORDER BY servers DESC").load(conn)?,
+        discovery: sql_query("SELECT * FROM stats_discovery ORDER BY day").load(conn)?,
+        countries: sql_query("SELECT * FROM stats_country ORDER BY servers DESC, country_code").load(conn)?,
+        asns: sql_query(format!("SELECT * FROM stats_asn ORDER BY servers DESC, asn LIMIT {}", MAX_ASNS)).load(conn)?,
+    })
+}
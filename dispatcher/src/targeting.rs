@@ -0,0 +1,356 @@
+// Prioritization of the IPs sent to scouts. By default, scouts get IPs from the random walk of
+// `IpChunkIterator`, which gives every /16 the same weight. Servers are heavily clustered in
+// hosting networks though, so in adaptive mode (`target_mode = "adaptive"` in the config, or
+// `TARGET_MODE=adaptive`) part of every job is taken from two priority lanes:
+// - Productive blocks: /16s with the highest rate of confirmed servers (and SYN-ACKs) per probed
+//   IP. Blocks with few probes borrow the rate of their ASN when a GeoIP ASN database is loaded.
+// - Neighbours: the /24 around each confirmed server, hosting providers allocate servers in
+//   contiguous ranges.
+// The rest of every job still comes from the random walk, so the whole space keeps being covered.
+// In every mode, the IPs of scout jobs that were never reported are given out again first.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::Ipv4Addr;
+use std::str::FromStr;
+use std::sync::RwLock;
+use diesel::prelude::*;
+use diesel::sql_query;
+use diesel::sql_types::{BigInt, Cidr, Integer};
+use ipnet::{IpNet, Ipv4Net};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use crate::DbConnection;
+use crate::geoip::GeoIp;
+use crate::ip_chunk_iterator::is_public;
+
+/// Shares of a job taken from the neighbours and productive blocks lanes in adaptive mode
+const NEIGHBOUR_SHARE: f64 = 0.25;
+const PRIORITY_SHARE: f64 = 0.25;
+/// Number of blocks in the productive lane
+const MAX_PRIORITY_BLOCKS: usize = 256;
+/// A SYN-ACK counts as this much of a confirmed server in the block scores
+const SYN_ACK_WEIGHT: f64 = 0.1;
+/// Weight (in probed IPs) of the prior rate of a block, the rate of its ASN or of all blocks
+const PRIOR_PROBES: f64 = 1024.0;
+/// /24s waiting in the neighbours lane are dropped past this
+const MAX_NEIGHBOUR_BLOCKS: usize = 100000;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TargetMode {
+    Uniform,
+    Adaptive,
+}
+
+impl FromStr for TargetMode {
+    type Err = ();
+
+    fn from_str(mode: &str) -> Result<Self, Self::Err> {
+        match mode {
+            "uniform" => Ok(TargetMode::Uniform),
+            "adaptive" => Ok(TargetMode::Adaptive),
+            _ => Err(())
+        }
+    }
+}
+
+/// Hit rates of a /16
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct BlockScore {
+    #[schema(value_type = String)]
+    pub block: Ipv4Net,
+    pub probed: i64,
+    pub syn_acks: i64,
+    pub servers: i64,
+    // Most probed ASN of the block, if a GeoIP ASN database is loaded
+    pub asn: Option<i32>,
+    // Smoothed number of hits per probed IP
+    pub score: f64,
+}
+
+/// Block scores used to pick the productive blocks, recomputed periodically
+pub struct Targeting {
+    pub mode: TargetMode,
+    // Best blocks first, see `MAX_PRIORITY_BLOCKS`
+    blocks: RwLock<Vec<BlockScore>>,
+}
+
+impl Targeting {
+    pub fn new(mode: TargetMode) -> Self {
+        Targeting { mode, blocks: RwLock::new(Vec::new()) }
+    }
+
+    pub fn is_adaptive(&self) -> bool {
+        self.mode == TargetMode::Adaptive
+    }
+
+    /// Replaces the blocks of the productive lane
+    pub fn update(&self, scores: &[BlockScore]) {
+        let mut best = scores.to_vec();
+        best.truncate(MAX_PRIORITY_BLOCKS);
+        *self.blocks.write().unwrap() = best;
+    }
+
+    /// Returns the best blocks
+    pub fn best_blocks(&self, count: usize) -> Vec<BlockScore> {
+        self.blocks.read().unwrap().iter().take(count).cloned().collect()
+    }
+
+    /// Picks a productive block at random, weighted by score. Blocks in `exhausted` are skipped.
+    fn pick_block<R: Rng>(&self, exhausted: &HashSet<u16>, rng: &mut R) -> Option<u16> {
+        let blocks = self.blocks.read().unwrap();
+        let candidates: Vec<(u16, f64)> = blocks.iter()
+            .map(|b| ((u32::from(b.block.network()) >> 16) as u16, b.score))
+            .filter(|(block, _)| !exhausted.contains(block))
+            .collect();
+        let total: f64 = candidates.iter().map(|(_, score)| score).sum();
+        if candidates.is_empty() || total <= 0.0 {
+            return None;
+        }
+        let mut target = rng.gen_range(0.0..total);
+        for (block, score) in &candidates {
+            if target < *score {
+                return Some(*block);
+            }
+            target -= score;
+        }
+        candidates.last().map(|(block, _)| *block)
+    }
+}
+
+/// Progress of the priority lanes, saved with the server state
+#[derive(Default, Serialize, Deserialize)]
+pub struct TargetLanes {
+    // Number of IPs taken from each productive /16 (by its first 2 bytes), a block is exhausted
+    // once all its IPs were taken
+    block_cursors: HashMap<u16, u32>,
+    // /24s waiting to be scanned, and number of IPs already taken from the first one
+    neighbour_blocks: VecDeque<Ipv4Net>,
+    neighbour_cursor: u32,
+    // /24s queued in the current cycle, they are only scanned once per cycle
+    neighbour_seen: HashSet<Ipv4Net>,
+    // IPs of expired scout jobs, see `crate::routes::scout_routes::ScoutLease`
+    #[serde(default)]
+    retry_ips: VecDeque<Ipv4Addr>,
+}
+
+impl TargetLanes {
+    /// Queues the /24 of a confirmed server in the neighbours lane, if it wasn't already in this
+    /// cycle
+    pub fn add_neighbours(&mut self, ip: Ipv4Addr) {
+        let block = Ipv4Net::new(ip, 24).unwrap().trunc();
+        if self.neighbour_blocks.len() < MAX_NEIGHBOUR_BLOCKS && self.neighbour_seen.insert(block) {
+            self.neighbour_blocks.push_back(block);
+        }
+    }
+
+    /// Queues the IPs of a scout job that expired before being reported, they are given out again
+    /// before any other IP
+    pub fn retry(&mut self, ips: &[Ipv4Addr]) {
+        self.retry_ips.extend(ips);
+    }
+
+    /// Takes up to `count` IPs from the neighbours lane
+    fn take_neighbours(&mut self, count: usize, ips: &mut Vec<Ipv4Addr>) {
+        let target = ips.len() + count;
+        while ips.len() < target {
+            let block = match self.neighbour_blocks.front() {
+                Some(block) => *block,
+                None => break
+            };
+            if self.neighbour_cursor >= 256 {
+                self.neighbour_blocks.pop_front();
+                self.neighbour_cursor = 0;
+                continue;
+            }
+            let ip = Ipv4Addr::from(u32::from(block.network()) + self.neighbour_cursor);
+            self.neighbour_cursor += 1;
+            if is_public(ip) {
+                ips.push(ip);
+            }
+        }
+    }
+
+    /// Takes up to `count` IPs from the productive blocks
+    fn take_productive<R: Rng>(&mut self, count: usize, targeting: &Targeting, rng: &mut R, ips: &mut Vec<Ipv4Addr>) {
+        let mut exhausted: HashSet<u16> = self.block_cursors.iter()
+            .filter(|(_, cursor)| **cursor >= 1 << 16)
+            .map(|(block, _)| *block)
+            .collect();
+        let target = ips.len() + count;
+        while ips.len() < target {
+            let block = match targeting.pick_block(&exhausted, rng) {
+                Some(block) => block,
+                None => break
+            };
+            let cursor = self.block_cursors.entry(block).or_default();
+            // Visit the block in a stable pseudo random order, multiplying by an odd number is a
+            // permutation modulo 2^16
+            let offset = (cursor.wrapping_mul(40503).wrapping_add(block as u32 * 7919)) & 0xFFFF;
+            *cursor += 1;
+            if *cursor >= 1 << 16 {
+                exhausted.insert(block);
+            }
+            let ip = Ipv4Addr::from(((block as u32) << 16) | offset);
+            if is_public(ip) {
+                ips.push(ip);
+            }
+        }
+    }
+
+    /// Number of /24s waiting in the neighbours lane
+    pub fn queued_neighbours(&self) -> usize {
+        self.neighbour_blocks.len()
+    }
+
+    /// Forgets the progress in the productive blocks and the neighbours already scanned, so that
+    /// they can be scanned again, and the IPs waiting to be retried, the new cycle covers them
+    /// anyway. Called when a new coverage cycle starts.
+    pub fn reset_blocks(&mut self) {
+        self.block_cursors.clear();
+        self.retry_ips.clear();
+        // The /24s still waiting aren't queued twice
+        self.neighbour_seen = self.neighbour_blocks.iter().copied().collect();
+    }
+}
+
+/// Fills a job of `size` IPs: IPs to retry come first, then in adaptive mode part of them comes
+/// from the priority lanes, and `next_ip` fills the rest.
+pub fn build_job<F: FnMut() -> Ipv4Addr>(size: usize, targeting: &Targeting, lanes: &mut TargetLanes, mut next_ip: F) -> Vec<Ipv4Addr> {
+    let mut ips = Vec::with_capacity(size);
+    let retried = lanes.retry_ips.len().min(size);
+    ips.extend(lanes.retry_ips.drain(..retried));
+    if targeting.is_adaptive() {
+        let mut rng = rand::thread_rng();
+        let remaining = (size - ips.len()) as f64;
+        lanes.take_neighbours((remaining * NEIGHBOUR_SHARE) as usize, &mut ips);
+        lanes.take_productive((remaining * PRIORITY_SHARE) as usize, targeting, &mut rng, &mut ips);
+    }
+    while ips.len() < size {
+        ips.push(next_ip());
+    }
+    ips
+}
+
+#[derive(QueryableByName)]
+struct NetblockHits {
+    #[diesel(sql_type = Cidr)]
+    network: IpNet,
+    #[diesel(sql_type = Integer)]
+    probed: i32,
+    #[diesel(sql_type = Integer)]
+    responded: i32,
+    #[diesel(sql_type = BigInt)]
+    servers: i64,
+}
+
+/// Hits of a block or ASN: probed IPs, SYN-ACKs and confirmed servers
+#[derive(Default, Copy, Clone)]
+struct Hits {
+    probed: i64,
+    syn_acks: i64,
+    servers: i64,
+}
+
+impl Hits {
+    fn add(&mut self, other: &Hits) {
+        self.probed += other.probed;
+        self.syn_acks += other.syn_acks;
+        self.servers += other.servers;
+    }
+
+    fn rate(&self) -> f64 {
+        if self.probed == 0 {
+            return 0.0;
+        }
+        (self.servers as f64 + SYN_ACK_WEIGHT * self.syn_acks as f64) / self.probed as f64
+    }
+}
+
+/// Computes the scores of every probed /16 from the /24 netblock stats (see
+/// [`crate::honeypot`]) and the saved scans. Returns the scores, best first.
+pub fn compute_scores(geoip: &GeoIp, conn: &mut DbConnection) -> QueryResult<Vec<BlockScore>> {
+    let netblocks = sql_query(
+        "SELECT n.network, n.probed, n.responded, coalesce(s.servers, 0) AS servers
+        FROM netblock_stats n
+        LEFT JOIN (
+            SELECT network(set_masklen(ip, 24)) AS network, count(DISTINCT ip) AS servers
+            FROM scan GROUP BY 1
+        ) s ON s.network = n.network
+        WHERE NOT EXISTS (SELECT 1 FROM honeypot WHERE honeypot.network >>= n.network)")
+        .load::<NetblockHits>(conn)?;
+
+    // Hits of each /16 and ASN, and probes of each ASN in each /16
+    let mut blocks: HashMap<Ipv4Net, Hits> = HashMap::new();
+    let mut asns: HashMap<i32, Hits> = HashMap::new();
+    let mut block_asns: HashMap<Ipv4Net, HashMap<i32, i64>> = HashMap::new();
+    let mut total = Hits::default();
+    for netblock in netblocks {
+        let network = match netblock.network {
+            IpNet::V4(n) => n,
+            IpNet::V6(_) => continue
+        };
+        let hits = Hits {
+            probed: netblock.probed as i64,
+            syn_acks: netblock.responded as i64,
+            servers: netblock.servers,
+        };
+        let block = Ipv4Net::new(network.network(), 16).unwrap().trunc();
+        blocks.entry(block).or_default().add(&hits);
+        total.add(&hits);
+        if let Some(asn) = geoip.lookup(network.network().into()).asn {
+            asns.entry(asn).or_default().add(&hits);
+            *block_asns.entry(block).or_default().entry(asn).or_default() += hits.probed;
+        }
+    }
+
+    let global_rate = total.rate();
+    let mut scores: Vec<BlockScore> = blocks.into_iter()
+        .map(|(block, hits)| {
+            let asn = block_asns.get(&block)
+                .and_then(|a| a.iter().max_by_key(|(_, probed)| **probed).map(|(asn, _)| *asn));
+            // Blocks with few probes are pulled towards the rate of their ASN
+            let prior = asn.and_then(|a| asns.get(&a)).map_or(global_rate, Hits::rate);
+            let hits_weight = hits.servers as f64 + SYN_ACK_WEIGHT * hits.syn_acks as f64;
+            BlockScore {
+                block,
+                probed: hits.probed,
+                syn_acks: hits.syn_acks,
+                servers: hits.servers,
+                asn,
+                score: (hits_weight + prior * PRIOR_PROBES) / (hits.probed as f64 + PRIOR_PROBES),
+            }
+        })
+        // Only blocks doing better than average are worth prioritizing
+        .filter(|b| b.score > global_rate)
+        .collect();
+    scores.sort_by(|a, b| b.score.total_cmp(&a.score));
+    Ok(scores)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn neighbours_are_scanned_once_per_cycle() {
+        let mut lanes = TargetLanes::default();
+        let (scanned, waiting) = (Ipv4Addr::new(8, 8, 8, 8), Ipv4Addr::new(9, 9, 9, 9));
+        lanes.add_neighbours(scanned);
+        let mut ips = Vec::new();
+        lanes.take_neighbours(1000, &mut ips);
+        assert_eq!(ips.len(), 256);
+        assert_eq!(lanes.queued_neighbours(), 0);
+        lanes.add_neighbours(waiting);
+        lanes.add_neighbours(scanned);
+        assert_eq!(lanes.queued_neighbours(), 1);
+
+        // Scanned again in the next cycle, but not queued twice
+        lanes.reset_blocks();
+        lanes.add_neighbours(scanned);
+        lanes.add_neighbours(waiting);
+        let blocks: Vec<Ipv4Net> = [waiting, scanned].iter().map(|ip| Ipv4Net::new(*ip, 24).unwrap().trunc()).collect();
+        assert_eq!(lanes.neighbour_blocks, blocks);
+    }
+}
@@ -0,0 +1,109 @@
+// TLS termination of the HTTP server, enabled by setting `tls.cert` and `tls.key` (`TLS_CERT` and
+// `TLS_KEY`) to the paths of the PEM certificate chain and private key. Setting `tls.client_ca`
+// (`TLS_CLIENT_CA`) to a PEM bundle also verifies the certificates of the workers against it, so
+// that each worker is identified by its certificate (see `/workers/register`).
+// `tls.client_auth` (`TLS_CLIENT_AUTH`) is `required` by default, `optional` still accepts
+// connections without a certificate, e.g. to use the read-only routes from a browser.
+
+use std::any::Any;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use actix_tls::accept::rustls::TlsStream;
+use actix_web::dev::Extensions;
+use actix_web::rt::net::TcpStream;
+use rustls::{Certificate, PrivateKey, RootCertStore, ServerConfig};
+use rustls::server::{AllowAnyAnonymousOrAuthenticatedClient, AllowAnyAuthenticatedClient};
+use sha2::{Digest, Sha256};
+use crate::config::TlsSettings;
+
+/// Certificate presented by the peer of a connection, available with `HttpRequest::conn_data`
+#[derive(Debug, Clone)]
+pub struct ClientCertificate {
+    // Hex-encoded SHA-256 hash of the DER certificate
+    pub fingerprint: String,
+}
+
+#[derive(Debug)]
+pub struct TlsConfig {
+    cert: PathBuf,
+    key: PathBuf,
+    client_ca: Option<PathBuf>,
+    client_auth_required: bool,
+}
+
+fn read_certs(path: &Path) -> Result<Vec<Certificate>, String> {
+    let file = File::open(path).map_err(|e| format!("Unable to open {}: {}", path.display(), e))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .map_err(|e| format!("Unable to read {}: {}", path.display(), e))?;
+    if certs.is_empty() {
+        return Err(format!("No certificate found in {}", path.display()));
+    }
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+fn read_key(path: &Path) -> Result<PrivateKey, String> {
+    let file = File::open(path).map_err(|e| format!("Unable to open {}: {}", path.display(), e))?;
+    let mut reader = BufReader::new(file);
+    loop {
+        match rustls_pemfile::read_one(&mut reader).map_err(|e| format!("Unable to read {}: {}", path.display(), e))? {
+            Some(rustls_pemfile::Item::PKCS8Key(key))
+            | Some(rustls_pemfile::Item::RSAKey(key))
+            | Some(rustls_pemfile::Item::ECKey(key)) => return Ok(PrivateKey(key)),
+            Some(_) => {}
+            None => return Err(format!("No private key found in {}", path.display()))
+        }
+    }
+}
+
+impl TlsConfig {
+    /// Checks the TLS settings, None if the certificate and key aren't set
+    pub fn from_config(settings: &TlsSettings) -> Result<Option<Self>, String> {
+        let (cert, key) = match (&settings.cert, &settings.key) {
+            (Some(cert), Some(key)) => (cert.clone(), key.clone()),
+            (None, None) => return Ok(None),
+            _ => return Err(String::from("tls.cert and tls.key must be set together"))
+        };
+        let client_auth_required = match settings.client_auth.as_deref() {
+            Some("required") | None => true,
+            Some("optional") => false,
+            Some(other) => return Err(format!("Invalid tls.client_auth {}, expected required or optional", other))
+        };
+        Ok(Some(TlsConfig { cert, key, client_ca: settings.client_ca.clone(), client_auth_required }))
+    }
+
+    pub fn verifies_clients(&self) -> bool {
+        self.client_ca.is_some()
+    }
+
+    /// Builds the rustls config, reading the certificates and key
+    pub fn server_config(&self) -> Result<ServerConfig, String> {
+        let builder = ServerConfig::builder().with_safe_defaults();
+        let builder = match &self.client_ca {
+            Some(path) => {
+                let mut roots = RootCertStore::empty();
+                for cert in read_certs(path)? {
+                    roots.add(&cert).map_err(|e| format!("Invalid CA certificate in {}: {}", path.display(), e))?;
+                }
+                if self.client_auth_required {
+                    builder.with_client_cert_verifier(AllowAnyAuthenticatedClient::new(roots))
+                } else {
+                    builder.with_client_cert_verifier(AllowAnyAnonymousOrAuthenticatedClient::new(roots))
+                }
+            }
+            None => builder.with_no_client_auth()
+        };
+        builder.with_single_cert(read_certs(&self.cert)?, read_key(&self.key)?)
+            .map_err(|e| format!("Invalid certificate or key: {}", e))
+    }
+}
+
+/// Stores the certificate of the peer in the connection data, used with `HttpServer::on_connect`
+pub fn on_connect(connection: &dyn Any, data: &mut Extensions) {
+    if let Some(stream) = connection.downcast_ref::<TlsStream<TcpStream>>() {
+        let (_, session) = stream.get_ref();
+        if let Some(cert) = session.peer_certificates().and_then(|certs| certs.first()) {
+            data.insert(ClientCertificate { fingerprint: format!("{:x}", Sha256::digest(&cert.0)) });
+        }
+    }
+}
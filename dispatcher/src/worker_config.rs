@@ -0,0 +1,119 @@
+// Settings pushed to the scouts and clients, so that they can be changed without editing the
+// config file of every worker and restarting it. `worker_config` (`WORKER_CONFIG`) is the path of a
+// JSON file with default settings, and overrides for groups of workers and for single workers:
+// ```json
+// {
+//   "defaults": { "stop_timeout": 10, "send_rate": 1000 },
+//   "groups": { "eu": { "send_rate": 500 } },
+//   "workers": { "scout-fra-1": { "job_size": 4096 } }
+// }
+// ```
+// Worker overrides take precedence over group overrides, which take precedence over the
+// defaults. Workers send their name and group (from their own config file) with their job
+// requests, and job responses contain the version of their resolved settings, so that they
+// fetch `/config/worker` again when it changes. Settings that aren't set here keep the value of
+// the worker's config file. The file is checked periodically and reloaded when it changes.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::RwLock;
+use std::time::SystemTime;
+use protocol::{ResolvedConfig, WorkerSettings};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct WorkerConfigFile {
+    #[serde(default)]
+    defaults: WorkerSettings,
+    #[serde(default)]
+    groups: HashMap<String, WorkerSettings>,
+    #[serde(default)]
+    workers: HashMap<String, WorkerSettings>,
+}
+
+impl WorkerConfigFile {
+    fn parse(text: &str) -> Result<Self, String> {
+        let file: WorkerConfigFile = serde_json::from_str(text).map_err(|e| e.to_string())?;
+        file.defaults.validate().map_err(|e| format!("defaults: {}", e))?;
+        for (name, settings) in file.groups.iter() {
+            settings.validate().map_err(|e| format!("group {}: {}", name, e))?;
+        }
+        for (name, settings) in file.workers.iter() {
+            settings.validate().map_err(|e| format!("worker {}: {}", name, e))?;
+        }
+        Ok(file)
+    }
+}
+
+struct LoadedFile {
+    // Modification time of the loaded file
+    modified: Option<SystemTime>,
+    file: WorkerConfigFile,
+}
+
+/// Worker settings served by the dispatcher
+pub struct WorkerConfig {
+    path: Option<PathBuf>,
+    loaded: RwLock<LoadedFile>,
+}
+
+impl WorkerConfig {
+    /// Loads the file at `path`, see the module documentation
+    pub fn load(path: Option<PathBuf>) -> Result<Self, String> {
+        let config = WorkerConfig {
+            path,
+            loaded: RwLock::new(LoadedFile { modified: None, file: WorkerConfigFile::default() }),
+        };
+        config.reload_if_changed()?;
+        Ok(config)
+    }
+
+    pub fn is_configured(&self) -> bool {
+        self.path.is_some()
+    }
+
+    /// Loads the file if it changed since it was last loaded. Returns true if it was reloaded.
+    /// The previous settings are kept if the new file is invalid.
+    pub fn reload_if_changed(&self) -> Result<bool, String> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(false)
+        };
+        let modified = fs::metadata(path).and_then(|m| m.modified())
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        let mut loaded = self.loaded.write().unwrap();
+        if loaded.modified == Some(modified) {
+            return Ok(false);
+        }
+        // Not retried until the file changes again
+        loaded.modified = Some(modified);
+        loaded.file = fs::read_to_string(path).map_err(|e| e.to_string())
+            .and_then(|text| WorkerConfigFile::parse(&text))
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(true)
+    }
+
+    /// Settings of `worker` in `group`, None if there is no worker config
+    pub fn resolve(&self, worker: Option<&str>, group: Option<&str>) -> Option<ResolvedConfig> {
+        if !self.is_configured() {
+            return None;
+        }
+        let loaded = self.loaded.read().unwrap();
+        let mut settings = loaded.file.defaults.clone();
+        if let Some(group) = group.and_then(|g| loaded.file.groups.get(g)) {
+            settings.apply(group);
+        }
+        if let Some(worker) = worker.and_then(|w| loaded.file.workers.get(w)) {
+            settings.apply(worker);
+        }
+
+        // Derived from the settings, so that workers only fetch them again when their own
+        // settings change, and versions are the same after a restart
+        let json = serde_json::to_vec(&settings).expect("Error serializing worker settings");
+        let version = Sha256::digest(json)[..8].iter().map(|b| format!("{:02x}", b)).collect();
+        Some(ResolvedConfig { version, settings })
+    }
+}
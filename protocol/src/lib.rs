@@ -0,0 +1,418 @@
+// Requests and responses exchanged between the dispatcher and the workers. Every request and
+// response carries the protocol version in the `X-Msearch-Protocol` header, which is bumped on
+// every incompatible change of these types. The dispatcher rejects workers speaking another
+// version, and workers stop when the dispatcher does, see [`check_version`]. Workers released
+// before the header existed don't send it, the dispatcher still serves them in the format of
+// [`LEGACY_PROTOCOL_VERSION`].
+
+pub mod motd;
+
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::net::Ipv4Addr;
+use serde::{Deserialize, Deserializer, Serialize, de};
+use serde_json::{Value, json};
+pub use uuid::Uuid;
+
+/// Version of the types of this crate
+pub const PROTOCOL_VERSION: u32 = 1;
+/// Version of the releases that didn't send the version header, which only differs from version 1
+/// in the id of [`ClientJob`], sent as a string
+pub const LEGACY_PROTOCOL_VERSION: u32 = 0;
+/// Header carrying the protocol version
+pub const VERSION_HEADER: &str = "X-Msearch-Protocol";
+/// Prefix of the current version of the dispatcher routes
+pub const API_PREFIX: &str = "/api/v1";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VersionError {
+    // No version header, sent by releases older than the first version
+    Missing,
+    Invalid(String),
+    Mismatch(u32),
+}
+
+impl Display for VersionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VersionError::Missing => write!(f, "no protocol version was sent, the other side predates protocol version {}", PROTOCOL_VERSION),
+            VersionError::Invalid(version) => write!(f, "invalid protocol version {}", version),
+            VersionError::Mismatch(version) => write!(f, "the other side speaks protocol version {} and this side version {}, \
+                run the same release of the dispatcher and workers", version, PROTOCOL_VERSION),
+        }
+    }
+}
+
+impl Error for VersionError {}
+
+/// Checks the value of the version header received from the other side
+pub fn check_version(header: Option<&str>) -> Result<(), VersionError> {
+    let header = header.ok_or(VersionError::Missing)?;
+    let version: u32 = header.trim().parse().map_err(|_| VersionError::Invalid(String::from(header)))?;
+    if version != PROTOCOL_VERSION {
+        return Err(VersionError::Mismatch(version));
+    }
+    Ok(())
+}
+
+//
+// WORKERS
+//
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub enum WorkerKind {
+    Scout,
+    Client,
+}
+
+impl WorkerKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WorkerKind::Scout => "scout",
+            WorkerKind::Client => "client",
+        }
+    }
+}
+
+/// Body of `POST /workers/register`. `name` and `group` are the ones of the worker's config
+/// file, used to find its settings in the worker config of the dispatcher.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Registration {
+    pub kind: WorkerKind,
+    pub name: Option<String>,
+    pub group: Option<String>,
+    pub hostname: Option<String>,
+    pub version: Option<String>,
+    // Network adapter of a scout
+    pub adapter: Option<String>,
+}
+
+/// Response of `POST /workers/register`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct RegistrationResponse {
+    pub id: Uuid,
+}
+
+/// Body of `POST /workers/{id}/heartbeat`, fields that are missing keep their previous value
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Heartbeat {
+    pub hostname: Option<String>,
+    pub version: Option<String>,
+    pub adapter: Option<String>,
+    // Packets per second of a scout
+    pub send_rate: Option<u64>,
+    // Errors since the previous heartbeat
+    #[serde(default)]
+    pub errors: u64,
+}
+
+/// Query identifying a worker, sent with its job requests and results
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams), into_params(parameter_in = Query))]
+pub struct WorkerQuery {
+    // `name` and `group` of the worker's config file
+    pub worker: Option<String>,
+    pub group: Option<String>,
+    // Id issued when the worker registered
+    pub worker_id: Option<Uuid>,
+}
+
+/// Settings the dispatcher pushes to a worker, None when the worker keeps its own value
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct WorkerSettings {
+    // Scouts: seconds to wait for answers after the last packet of a job
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop_timeout: Option<u64>,
+    // Scouts: IPs per job, and packets per second. Ignored when the dispatcher manages the send
+    // rate.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub job_size: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub send_rate: Option<u64>,
+    // Clients: connect and read timeout of a server scan, in seconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tcp_timeout: Option<u64>,
+}
+
+impl WorkerSettings {
+    /// Overrides the settings that are set in `other`
+    pub fn apply(&mut self, other: &WorkerSettings) {
+        self.stop_timeout = other.stop_timeout.or(self.stop_timeout);
+        self.job_size = other.job_size.or(self.job_size);
+        self.send_rate = other.send_rate.or(self.send_rate);
+        self.tcp_timeout = other.tcp_timeout.or(self.tcp_timeout);
+    }
+
+    /// Checks that the settings that are set are above 0
+    pub fn validate(&self) -> Result<(), String> {
+        let zero = [("stop_timeout", self.stop_timeout), ("job_size", self.job_size),
+            ("send_rate", self.send_rate), ("tcp_timeout", self.tcp_timeout)]
+            .into_iter()
+            .find(|(_, value)| *value == Some(0));
+        match zero {
+            Some((name, _)) => Err(format!("{} must be greater than 0", name)),
+            None => Ok(())
+        }
+    }
+}
+
+/// Response of `GET /config/worker`, with the version workers compare to the one of their jobs
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ResolvedConfig {
+    pub version: String,
+    #[serde(flatten)]
+    pub settings: WorkerSettings,
+}
+
+//
+// SCOUTS
+//
+
+/// Query of `GET /scout/job/{size}`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams), into_params(parameter_in = Query))]
+pub struct ScoutJobQuery {
+    // Name of the scout, or a random id picked at startup. The address of the scout is used if
+    // missing.
+    pub scout: Option<String>,
+    pub group: Option<String>,
+    pub worker_id: Option<Uuid>,
+}
+
+/// Response of `GET /scout/job/{size}`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ScoutJob {
+    pub id: u32,
+    #[cfg_attr(feature = "openapi", schema(value_type = Vec<String>))]
+    pub ips: Vec<Ipv4Addr>,
+    // Servers to probe on `probe_port`, to detect honeypots answering on every port
+    #[cfg_attr(feature = "openapi", schema(value_type = Vec<String>))]
+    pub probes: Vec<Ipv4Addr>,
+    pub probe_port: u16,
+    // Packets per second the scout should send at, None if scouts use their own rate
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub send_rate: Option<u64>,
+    // Version of the scout's settings, see `GET /config/worker`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub config_version: Option<String>,
+}
+
+/// Query of `POST /scout/ips`, whose body is the list of IPs that answered, and of
+/// `POST /scout/probes`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams), into_params(parameter_in = Query))]
+pub struct IpsQuery {
+    // Job the IPs were found in
+    pub job: Option<u32>,
+    pub worker_id: Option<Uuid>,
+}
+
+/// Body of `POST /scout/probes`, the results of the random-port probes of a job
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ProbeResult {
+    // Probed IPs, and the ones that answered with a SYN-ACK
+    #[cfg_attr(feature = "openapi", schema(value_type = Vec<String>))]
+    pub ips: Vec<Ipv4Addr>,
+    #[cfg_attr(feature = "openapi", schema(value_type = Vec<String>))]
+    pub hits: Vec<Ipv4Addr>,
+}
+
+//
+// CLIENTS
+//
+
+/// Query of `GET /client/job`, sent along with a [`WorkerQuery`]. A single [`ClientJob`] is
+/// returned without `count`, and a [`ClientBatch`] of up to `count` jobs with it.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams), into_params(parameter_in = Query))]
+pub struct BatchQuery {
+    pub count: Option<u32>,
+}
+
+/// Response of `GET /client/job`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ClientJob {
+    #[serde(deserialize_with = "job_id")]
+    pub id: u32,
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
+    pub ip: Ipv4Addr,
+    // Version of the client's settings, see `GET /config/worker`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub config_version: Option<String>,
+}
+
+impl ClientJob {
+    /// Job in the format of [`LEGACY_PROTOCOL_VERSION`]
+    pub fn to_legacy(&self) -> Value {
+        let mut job = json!({ "id": self.id.to_string(), "ip": self.ip });
+        if let Some(config_version) = &self.config_version {
+            job["config_version"] = json!(config_version);
+        }
+        job
+    }
+}
+
+/// Job ids are numbers, or strings in the format of [`LEGACY_PROTOCOL_VERSION`]
+fn job_id<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u32, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum JobId {
+        Number(u32),
+        Text(String),
+    }
+    match JobId::deserialize(deserializer)? {
+        JobId::Number(id) => Ok(id),
+        JobId::Text(id) => id.parse().map_err(de::Error::custom),
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub enum ServerStatus {
+    Up,
+    Down,
+}
+
+/// Body of `POST /client/job/{id}`. `response` is the status response of the server, see
+/// <https://wiki.vg/Server_List_Ping#Status_Response>, and is only set when it is up.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ClientResult {
+    pub status: ServerStatus,
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
+    pub ip: Ipv4Addr,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "openapi", schema(value_type = Option<Object>))]
+    pub response: Option<Value>,
+}
+
+impl ClientResult {
+    pub fn up(ip: Ipv4Addr, response: Value) -> Self {
+        ClientResult { status: ServerStatus::Up, ip, response: Some(response) }
+    }
+
+    pub fn down(ip: Ipv4Addr) -> Self {
+        ClientResult { status: ServerStatus::Down, ip, response: None }
+    }
+}
+
+/// Response of `GET /client/job?count=N`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ClientBatch {
+    pub jobs: Vec<ClientJob>,
+    // Version of the client's settings, see `GET /config/worker`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub config_version: Option<String>,
+}
+
+/// Result of a job, the body of `POST /client/results` is a list of them
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct JobResult {
+    pub id: u32,
+    #[serde(flatten)]
+    pub result: ClientResult,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip<T: Serialize + de::DeserializeOwned>(value: &T) -> T {
+        serde_json::from_value(serde_json::to_value(value).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn checks_version() {
+        assert_eq!(check_version(Some(&PROTOCOL_VERSION.to_string())), Ok(()));
+        assert_eq!(check_version(Some(" 1 ")), Ok(()));
+        assert_eq!(check_version(None), Err(VersionError::Missing));
+        assert_eq!(check_version(Some("v1")), Err(VersionError::Invalid(String::from("v1"))));
+        assert_eq!(check_version(Some("7")), Err(VersionError::Mismatch(7)));
+    }
+
+    #[test]
+    fn client_job_ids_are_numbers() {
+        let job = ClientJob { id: 42, ip: Ipv4Addr::new(10, 0, 0, 1), config_version: None };
+        assert_eq!(serde_json::to_value(&job).unwrap(), json!({"id": 42, "ip": "10.0.0.1"}));
+        let job = round_trip(&ClientJob { config_version: Some(String::from("abc")), ..job });
+        assert_eq!((job.id, job.ip, job.config_version.as_deref()), (42, Ipv4Addr::new(10, 0, 0, 1), Some("abc")));
+    }
+
+    #[test]
+    fn legacy_client_job_ids_are_strings() {
+        let job = ClientJob { id: 7, ip: Ipv4Addr::new(10, 0, 0, 2), config_version: Some(String::from("abc")) };
+        let legacy = job.to_legacy();
+        assert_eq!(legacy, json!({"id": "7", "ip": "10.0.0.2", "config_version": "abc"}));
+        let parsed: ClientJob = serde_json::from_value(legacy).unwrap();
+        assert_eq!((parsed.id, parsed.ip), (7, job.ip));
+        assert_eq!(ClientJob { config_version: None, ..job }.to_legacy(), json!({"id": "7", "ip": "10.0.0.2"}));
+
+        for invalid in [json!("seven"), json!("-1"), json!(-1), json!(null)] {
+            assert!(serde_json::from_value::<ClientJob>(json!({"id": invalid, "ip": "10.0.0.2"})).is_err(), "{} is not a job id", invalid);
+        }
+    }
+
+    #[test]
+    fn batches_and_results() {
+        let batch: ClientBatch = serde_json::from_value(json!({"jobs": [{"id": 1, "ip": "10.0.0.1"}, {"id": "2", "ip": "10.0.0.2"}]})).unwrap();
+        assert_eq!(batch.jobs.iter().map(|j| j.id).collect::<Vec<_>>(), vec![1, 2]);
+        assert_eq!(batch.config_version, None);
+
+        let up = JobResult { id: 1, result: ClientResult::up(Ipv4Addr::new(10, 0, 0, 1), json!({"version": {"name": "1.20.1"}})) };
+        let down = JobResult { id: 2, result: ClientResult::down(Ipv4Addr::new(10, 0, 0, 2)) };
+        assert_eq!(serde_json::to_value([&up, &down]).unwrap(), json!([
+            {"id": 1, "status": "up", "ip": "10.0.0.1", "response": {"version": {"name": "1.20.1"}}},
+            {"id": 2, "status": "down", "ip": "10.0.0.2"}
+        ]));
+        let down = round_trip(&down);
+        assert_eq!((down.id, down.result.status, down.result.response), (2, ServerStatus::Down, None));
+    }
+
+    #[test]
+    fn scout_jobs_omit_unset_fields() {
+        let job = ScoutJob { id: 3, ips: vec![Ipv4Addr::new(10, 0, 0, 1)], probes: vec![], probe_port: 45000, send_rate: None, config_version: None };
+        assert_eq!(serde_json::to_value(&job).unwrap(), json!({"id": 3, "ips": ["10.0.0.1"], "probes": [], "probe_port": 45000}));
+        let job = round_trip(&ScoutJob { send_rate: Some(500), ..job });
+        assert_eq!((job.send_rate, job.config_version), (Some(500), None));
+    }
+
+    #[test]
+    fn worker_settings() {
+        let resolved: ResolvedConfig = serde_json::from_value(json!({"version": "abc", "send_rate": 500})).unwrap();
+        assert_eq!(resolved.settings, WorkerSettings { send_rate: Some(500), ..WorkerSettings::default() });
+        assert_eq!(serde_json::to_value(&resolved).unwrap(), json!({"version": "abc", "send_rate": 500}));
+        assert!(serde_json::from_value::<WorkerSettings>(json!({"sendrate": 500})).is_err());
+
+        let mut settings = WorkerSettings { job_size: Some(100), send_rate: Some(500), ..WorkerSettings::default() };
+        settings.apply(&WorkerSettings { send_rate: Some(1000), tcp_timeout: Some(5), ..WorkerSettings::default() });
+        assert_eq!(settings, WorkerSettings { stop_timeout: None, job_size: Some(100), send_rate: Some(1000), tcp_timeout: Some(5) });
+        assert_eq!(settings.validate(), Ok(()));
+        assert!(WorkerSettings { job_size: Some(0), ..settings }.validate().is_err());
+    }
+
+    #[test]
+    fn registration_and_heartbeats() {
+        let registration: Registration = serde_json::from_value(json!({"kind": "scout", "name": "a", "group": null,
+            "hostname": "host", "version": "0.1.0", "adapter": null})).unwrap();
+        assert_eq!((registration.kind, registration.name.as_deref()), (WorkerKind::Scout, Some("a")));
+        assert_eq!(serde_json::to_value(WorkerKind::Client).unwrap(), json!(WorkerKind::Client.as_str()));
+
+        // Errors are optional
+        let heartbeat: Heartbeat = serde_json::from_value(json!({"send_rate": 100})).unwrap();
+        assert_eq!((heartbeat.send_rate, heartbeat.errors, heartbeat.hostname), (Some(100), 0, None));
+    }
+}
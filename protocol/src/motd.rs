@@ -0,0 +1,282 @@
+// Parsing of Minecraft chat components, which is the format used by the `description` field of
+// a status response. Format reference: https://wiki.vg/Chat
+// Shared by the dispatcher, which indexes and renders the MOTDs, and the client, which prints
+// them in the terminal.
+
+use serde_json::Value;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Color {
+    pub const WHITE: Color = Color::new(0xFF, 0xFF, 0xFF);
+    pub const GRAY: Color = Color::new(0xAA, 0xAA, 0xAA);
+    pub const DARK_GRAY: Color = Color::new(0x55, 0x55, 0x55);
+
+    pub const fn new(r: u8, g: u8, b: u8) -> Self {
+        Color { r, g, b }
+    }
+
+    /// Returns the color associated with a legacy formatting code (the character following `§`)
+    pub fn from_code(code: char) -> Option<Color> {
+        let color = match code.to_ascii_lowercase() {
+            '0' => Color::new(0x00, 0x00, 0x00),
+            '1' => Color::new(0x00, 0x00, 0xAA),
+            '2' => Color::new(0x00, 0xAA, 0x00),
+            '3' => Color::new(0x00, 0xAA, 0xAA),
+            '4' => Color::new(0xAA, 0x00, 0x00),
+            '5' => Color::new(0xAA, 0x00, 0xAA),
+            '6' => Color::new(0xFF, 0xAA, 0x00),
+            '7' => Color::GRAY,
+            '8' => Color::DARK_GRAY,
+            '9' => Color::new(0x55, 0x55, 0xFF),
+            'a' => Color::new(0x55, 0xFF, 0x55),
+            'b' => Color::new(0x55, 0xFF, 0xFF),
+            'c' => Color::new(0xFF, 0x55, 0x55),
+            'd' => Color::new(0xFF, 0x55, 0xFF),
+            'e' => Color::new(0xFF, 0xFF, 0x55),
+            'f' => Color::WHITE,
+            _ => return None
+        };
+        Some(color)
+    }
+
+    /// Parses the `color` field of a chat component, which is either a named color
+    /// (`dark_red`, `gold`, ...) or a hex value (`#FF00AA`)
+    pub fn from_name(name: &str) -> Option<Color> {
+        if let Some(hex) = name.strip_prefix('#') {
+            if hex.len() != 6 { return None; }
+            let value = u32::from_str_radix(hex, 16).ok()?;
+            return Some(Color::new((value >> 16) as u8, (value >> 8) as u8, value as u8));
+        }
+
+        let code = match name {
+            "black" => '0',
+            "dark_blue" => '1',
+            "dark_green" => '2',
+            "dark_aqua" => '3',
+            "dark_red" => '4',
+            "dark_purple" => '5',
+            "gold" => '6',
+            "gray" => '7',
+            "dark_gray" => '8',
+            "blue" => '9',
+            "green" => 'a',
+            "aqua" => 'b',
+            "red" => 'c',
+            "light_purple" => 'd',
+            "yellow" => 'e',
+            "white" => 'f',
+            _ => return None
+        };
+        Color::from_code(code)
+    }
+
+    /// Color used by the Minecraft client to draw the drop shadow of text in this color
+    pub fn shadow(&self) -> Color {
+        Color::new(self.r / 4, self.g / 4, self.b / 4)
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Style {
+    pub color: Option<Color>,
+    pub bold: bool,
+    pub italic: bool,
+    pub underlined: bool,
+    pub strikethrough: bool,
+    pub obfuscated: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Span {
+    pub text: String,
+    pub style: Style,
+}
+
+/// Flattens a chat component (string, object or array form) into a list of styled text spans.
+/// Legacy `§` formatting codes inside of text fields are also applied.
+pub fn parse_component(value: &Value) -> Vec<Span> {
+    let mut spans = Vec::new();
+    push_component(value, Style::default(), &mut spans);
+    spans
+}
+
+/// Returns the text content of a list of spans, without any formatting
+pub fn plain_text(spans: &[Span]) -> String {
+    spans.iter().map(|s| s.text.as_str()).collect()
+}
+
+fn push_component(value: &Value, parent: Style, spans: &mut Vec<Span>) {
+    match value {
+        Value::String(s) => push_legacy_text(s, parent, spans),
+        Value::Number(n) => push_legacy_text(&n.to_string(), parent, spans),
+        Value::Bool(b) => push_legacy_text(&b.to_string(), parent, spans),
+        Value::Array(parts) => {
+            // The first element of an array acts as the parent of the following elements
+            let mut iter = parts.iter();
+            if let Some(first) = iter.next() {
+                let style = component_style(first, parent);
+                push_component(first, parent, spans);
+                iter.for_each(|part| push_component(part, style, spans));
+            }
+        }
+        Value::Object(obj) => {
+            let style = component_style(value, parent);
+            if let Some(text) = obj.get("text").and_then(Value::as_str) {
+                push_legacy_text(text, style, spans);
+            } else if let Some(key) = obj.get("translate").and_then(Value::as_str) {
+                // No translation tables available, display the key itself
+                push_legacy_text(key, style, spans);
+            }
+            if let Some(Value::Array(extra)) = obj.get("extra") {
+                extra.iter().for_each(|part| push_component(part, style, spans));
+            }
+        }
+        Value::Null => {}
+    }
+}
+
+fn component_style(value: &Value, parent: Style) -> Style {
+    let mut style = parent;
+    if let Some(color) = value.get("color").and_then(Value::as_str).and_then(Color::from_name) {
+        style.color = Some(color);
+    }
+
+    let flag = |name: &str, current: bool| value.get(name).and_then(Value::as_bool).unwrap_or(current);
+    style.bold = flag("bold", style.bold);
+    style.italic = flag("italic", style.italic);
+    style.underlined = flag("underlined", style.underlined);
+    style.strikethrough = flag("strikethrough", style.strikethrough);
+    style.obfuscated = flag("obfuscated", style.obfuscated);
+    style
+}
+
+/// Splits `text` on legacy `§` formatting codes, starting from (and resetting to) the `base` style
+fn push_legacy_text(text: &str, base: Style, spans: &mut Vec<Span>) {
+    let mut style = base;
+    let mut current = String::new();
+    let mut chars = text.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '§' {
+            current.push(c);
+            continue;
+        }
+
+        let code = match chars.next() {
+            Some(code) => code,
+            None => break  // Dangling formatting character
+        };
+        let mut new_style = style;
+        match code.to_ascii_lowercase() {
+            'k' => new_style.obfuscated = true,
+            'l' => new_style.bold = true,
+            'm' => new_style.strikethrough = true,
+            'n' => new_style.underlined = true,
+            'o' => new_style.italic = true,
+            'r' => new_style = base,
+            // Color codes also reset any formatting applied before them
+            c => if let Some(color) = Color::from_code(c) {
+                new_style = Style { color: Some(color), ..Style::default() };
+            }
+        }
+
+        if new_style != style {
+            push_span(std::mem::take(&mut current), style, spans);
+            style = new_style;
+        }
+    }
+    push_span(current, style, spans);
+}
+
+fn push_span(text: String, style: Style, spans: &mut Vec<Span>) {
+    if text.is_empty() {
+        return;
+    }
+
+    // Merge with the previous span if the style didn't change
+    match spans.last_mut() {
+        Some(last) if last.style == style => last.text.push_str(&text),
+        _ => spans.push(Span { text, style })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use super::*;
+
+    fn span(text: &str, style: Style) -> Span {
+        Span { text: String::from(text), style }
+    }
+
+    fn colored(code: char) -> Style {
+        Style { color: Color::from_code(code), ..Style::default() }
+    }
+
+    #[test]
+    fn parses_colors() {
+        assert_eq!(Color::from_name("gold"), Color::from_code('6'));
+        assert_eq!(Color::from_name("#FF00aa"), Some(Color::new(0xFF, 0x00, 0xAA)));
+        assert_eq!(Color::from_name("#FF00A"), None);
+        assert_eq!(Color::from_name("#GG0000"), None);
+        assert_eq!(Color::from_name("pink"), None);
+        assert_eq!(Color::from_code('g'), None);
+        assert_eq!(Color::WHITE.shadow(), Color::new(0x3F, 0x3F, 0x3F));
+    }
+
+    #[test]
+    fn parses_legacy_codes() {
+        let spans = parse_component(&json!("§aGreen §lbold§r plain §Cred"));
+        let bold_green = Style { bold: true, ..colored('a') };
+        assert_eq!(spans, vec![
+            span("Green ", colored('a')),
+            span("bold", bold_green),
+            span(" plain ", Style::default()),
+            span("red", colored('c')),
+        ]);
+        // Color codes reset the formatting, dangling codes are dropped
+        assert_eq!(parse_component(&json!("§l§6gold§")), vec![span("gold", colored('6'))]);
+        assert_eq!(plain_text(&parse_component(&json!("§kA§r B"))), "A B");
+    }
+
+    #[test]
+    fn inherits_styles() {
+        let description = json!({
+            "text": "A ",
+            "color": "red",
+            "bold": true,
+            "extra": [
+                {"text": "B", "bold": false},
+                "C",
+                {"translate": "menu.title", "color": "#00FF00"}
+            ]
+        });
+        let red = colored('c');
+        assert_eq!(parse_component(&description), vec![
+            span("A ", Style { bold: true, ..red }),
+            span("B", red),
+            span("C", Style { bold: true, ..red }),
+            span("menu.title", Style { bold: true, color: Some(Color::new(0, 0xFF, 0)), ..Style::default() }),
+        ]);
+    }
+
+    #[test]
+    fn first_element_of_array_is_parent() {
+        let description = json!([{"text": "A", "italic": true}, "B", 1, true, null]);
+        let italic = Style { italic: true, ..Style::default() };
+        assert_eq!(parse_component(&description), vec![span("AB1true", italic)]);
+        assert_eq!(parse_component(&json!([])), vec![]);
+    }
+
+    #[test]
+    fn merges_spans_of_same_style() {
+        let spans = parse_component(&json!({"text": "§aA", "extra": [{"text": "B", "color": "green"}, "§aC"]}));
+        assert_eq!(spans, vec![span("ABC", colored('a'))]);
+    }
+}
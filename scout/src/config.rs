@@ -1,99 +1,131 @@
-extern crate yaml_rust;
+// Settings of the scout, see the `worker-config` crate for how they are loaded and reloaded. The
+// settings pushed by the dispatcher (see `/config/worker`) take precedence over the local ones.
 
-use std::error::Error;
-use std::fmt::{Debug, Display, Formatter};
-use std::fs;
-use std::sync::RwLock;
+use std::sync::{Arc, OnceLock, RwLock};
 use std::time::Duration;
-use lazy_static::lazy_static;
-use yaml_rust::YamlLoader;
-
-use crate::Result;
-
-struct Config {
-    loaded: bool,
-    receive_timeout: Duration,
-    job_size: u64,
-    send_rate: u64,
-    dispatcher_base: String,
+use protocol::ResolvedConfig;
+use reqwest::blocking::Client;
+use serde::{Deserialize, Serialize};
+use worker_config::{DispatcherSettings, Settings, SharedConfig, Source};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    #[serde(flatten)]
+    pub dispatcher: DispatcherSettings,
+    // Seconds to wait for answers after the last packet of a job
+    pub stop_timeout: u64,
+    // Targets per job, ignored if the dispatcher manages the send rate
+    pub job_size: u64,
+    // Packets per second, ignored if the dispatcher manages the send rate
+    pub send_rate: u64,
 }
 
-lazy_static! {
-    static ref CONFIG: RwLock<Config> = RwLock::new(Config {
-        loaded: false,
-        receive_timeout: Duration::ZERO,
-        job_size: 0,
-        send_rate: 0,
-        dispatcher_base: String::new()
-    });
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            dispatcher: DispatcherSettings::default(),
+            stop_timeout: 10,
+            job_size: 8192,
+            send_rate: 1000,
+        }
+    }
 }
 
-pub fn load_config(path: &str) -> Result<()> {
-    let mut config = CONFIG.write().unwrap();
-
-    let yaml = YamlLoader::load_from_str(fs::read_to_string(path)?.as_str())?[0].clone();
-
-    let receive_timeout = yaml["stop_timeout"].as_i64()
-        .ok_or_else(|| ConfigParseError::new("stop_timeout field missing or invalid."))?;
-    config.receive_timeout = Duration::from_secs(receive_timeout as u64);
-
-    config.job_size = yaml["job_size"].as_i64()
-        .ok_or_else(|| ConfigParseError::new("job_size field is missing or invalid."))? as u64;
+impl Settings for Config {
+    const RELOADABLE: &'static [&'static str] = &["api_key", "ca_cert", "client_cert", "client_key",
+        "stop_timeout", "job_size", "send_rate"];
+
+    fn validate(&self) -> Vec<String> {
+        let mut errors = self.dispatcher.validate();
+        for (name, value) in [("stop_timeout", self.stop_timeout), ("job_size", self.job_size), ("send_rate", self.send_rate)] {
+            if value == 0 {
+                errors.push(format!("{} must be above 0", name));
+            }
+        }
+        errors
+    }
 
-    config.send_rate = yaml["send_rate"].as_i64()
-        .ok_or_else(|| ConfigParseError::new("send_rate field is missing or invalid."))? as u64;
+    fn dispatcher(&self) -> &DispatcherSettings {
+        &self.dispatcher
+    }
+}
 
-    config.dispatcher_base = String::from(yaml["dispatcher_base"].as_str()
-        .ok_or_else(|| ConfigParseError::new("dispatcher_base field is missing or invalid."))?);
+/// Settings pushed by the dispatcher, they take precedence over the local settings
+#[derive(Default)]
+struct RemoteConfig {
+    version: Option<String>,
+    receive_timeout: Option<Duration>,
+    job_size: Option<u64>,
+    send_rate: Option<u64>,
+}
 
-    // Config file was valid
-    config.loaded = true;
+static CONFIG: OnceLock<SharedConfig<Config>> = OnceLock::new();
+static REMOTE: RwLock<RemoteConfig> = RwLock::new(RemoteConfig {
+    version: None,
+    receive_timeout: None,
+    job_size: None,
+    send_rate: None,
+});
+
+/// Loads the settings, and reloads them on SIGHUP
+pub fn init(source: Source) -> Result<(), Vec<String>> {
+    let config = SharedConfig::load(source)?;
+    if CONFIG.set(config).is_ok() {
+        CONFIG.get().unwrap().reload_on_sighup();
+    }
     Ok(())
 }
 
-//
-// GETTERS
-//
+/// Local settings, from the config file, the environment and the command line
+pub fn current() -> Arc<Config> {
+    CONFIG.get().expect("Config used before being loaded").get()
+}
 
-pub fn get_receive_timeout() -> Duration {
-    let config = CONFIG.read().unwrap();
-    assert!(config.loaded, "Tried to access config field before loading the file.");
-    config.receive_timeout
+/// Settings along with the HTTP client of the dispatcher, see `worker_config::registration`
+pub fn shared() -> &'static SharedConfig<Config> {
+    CONFIG.get().expect("Config used before being loaded")
 }
 
-pub fn get_job_size() -> u64 {
-    let config = CONFIG.read().unwrap();
-    assert!(config.loaded, "Tried to access config field before loading the file.");
-    config.job_size
+/// HTTP client of the dispatcher, shared by every request
+pub fn dispatcher_client() -> Client {
+    CONFIG.get().expect("Config used before being loaded").client()
 }
 
-pub fn get_send_rate() -> u64 {
-    let config = CONFIG.read().unwrap();
-    assert!(config.loaded, "Tried to access config field before loading the file.");
-    config.send_rate
+/// Applies the settings pushed by the dispatcher (see `/config/worker`), settings missing from
+/// `resolved` fall back to the local settings
+pub fn apply_remote_config(resolved: &ResolvedConfig) {
+    let settings = &resolved.settings;
+    *REMOTE.write().unwrap() = RemoteConfig {
+        version: Some(resolved.version.clone()),
+        receive_timeout: settings.stop_timeout.filter(|v| *v > 0).map(Duration::from_secs),
+        job_size: settings.job_size.filter(|v| *v > 0),
+        send_rate: settings.send_rate.filter(|v| *v > 0),
+    };
 }
 
-pub fn get_dispatcher_base() -> String {
-    let config = CONFIG.read().unwrap();
-    assert!(config.loaded, "Tried to access config field before loading the file.");
-    config.dispatcher_base.clone()
+/// Goes back to the local settings
+pub fn clear_remote_config() {
+    *REMOTE.write().unwrap() = RemoteConfig::default();
 }
 
-#[derive(Debug, Clone, )]
-struct ConfigParseError {
-    msg: String,
+/// Version of the settings pushed by the dispatcher, None if there are none
+pub fn get_remote_version() -> Option<String> {
+    REMOTE.read().unwrap().version.clone()
 }
 
-impl ConfigParseError {
-    pub fn new(msg: &str) -> ConfigParseError {
-        ConfigParseError { msg: String::from(msg) }
-    }
+//
+// SETTINGS THE DISPATCHER CAN OVERRIDE
+//
+
+pub fn get_receive_timeout() -> Duration {
+    REMOTE.read().unwrap().receive_timeout.unwrap_or(Duration::from_secs(current().stop_timeout))
 }
 
-impl Display for ConfigParseError {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Error parsing config: {}", self.msg)
-    }
+pub fn get_job_size() -> u64 {
+    REMOTE.read().unwrap().job_size.unwrap_or(current().job_size)
 }
 
-impl Error for ConfigParseError {}
\ No newline at end of file
+pub fn get_send_rate() -> u64 {
+    REMOTE.read().unwrap().send_rate.unwrap_or(current().send_rate)
+}
@@ -3,16 +3,21 @@ mod packet_handler;
 mod config;
 
 use std::net::Ipv4Addr;
+use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU16, Ordering};
 use std::{result, thread};
 use std::error::Error;
 use std::thread::sleep;
 use std::time::Duration;
 use clap::{Parser, ArgGroup};
 use pnet::datalink::{Channel, NetworkInterface};
-use serde_json::Value;
+use protocol::{IpsQuery, ProbeResult, ResolvedConfig, ScoutJob, ScoutJobQuery, WorkerKind, WorkerQuery};
+use reqwest::header;
+use worker_config::{Source, registration};
+use worker_config::dispatcher::check_protocol;
 use crate::packet_handler::generate_syn_packet;
+use crate::threads::ScanResults;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -29,7 +34,11 @@ struct Cli {
     adapter: Option<String>,
 
     #[arg(short, long="config")]
-    config_path: String,
+    config_path: PathBuf,
+
+    /// Overrides a setting of the config file, i.e. `--set send_rate=500`
+    #[arg(short, long = "set", value_name = "SETTING=VALUE", value_parser = worker_config::parse_override)]
+    overrides: Vec<(String, String)>,
 }
 
 pub type Result<T> = result::Result<T, Box<dyn Error>>;
@@ -45,9 +54,11 @@ fn main() -> Result<()> {
         return Ok(());
     }
 
-    // Load config values from config file
-    if let Err(e) = config::load_config(cli.config_path.as_str()) {
-        panic!("Error trying to parse config file: {}", e);
+    // Load the settings from the config file, the environment and the command line
+    if let Err(errors) = config::init(Source { path: cli.config_path, overrides: cli.overrides }) {
+        eprintln!("Invalid configuration:");
+        errors.iter().for_each(|e| eprintln!("  - {}", e));
+        std::process::exit(1);
     }
 
     println!("Launching");
@@ -77,37 +88,60 @@ fn main() -> Result<()> {
 
     // Start receiver thread
     let sender_finish_signal = Arc::new(AtomicBool::new(false));
-    let valid_ips_mtx = Arc::new(Mutex::new(Vec::new()));
+    let probe_port = Arc::new(AtomicU16::new(0));
+    let results_mtx = Arc::new(Mutex::new(ScanResults::default()));
     let receiver_handle;
     {
-        let valid_ips = Arc::clone(&valid_ips_mtx);
+        let results = Arc::clone(&results_mtx);
+        let probe_port = probe_port.clone();
         let stop_signal = stop_signal.clone();
         let sender_finish_signal = sender_finish_signal.clone();
         let iface = interface.clone();
         receiver_handle = thread::spawn(move || {
-            threads::receiver_thread(&iface, &valid_ips, &stop_signal, &sender_finish_signal);
+            threads::receiver_thread(&iface, &results, &probe_port, &stop_signal, &sender_finish_signal);
         });
     }
 
+    registration::register(config::shared(), WorkerKind::Scout, env!("CARGO_PKG_VERSION"), Some(&interface.name));
+    registration::start_heartbeats(config::shared(), WorkerKind::Scout, env!("CARGO_PKG_VERSION"), Some(&interface.name));
+
+    // Identifies this scout to the dispatcher, to get its share of the send rate budget and its
+    // settings in the worker config
+    let scout_id = config::current().dispatcher.name.clone()
+        .or_else(|| registration::worker_id().map(|id| id.to_string()))
+        .unwrap_or_else(|| format!("{:016x}", rand::random::<u64>()));
+
     // Send while we haven't received a stop signal
     while !stop_signal.load(Ordering::Relaxed) {
-        let (ips, job_id) = get_job();
+        let job = get_job(&scout_id);
+        probe_port.store(job.probe_port, Ordering::Relaxed);
+        let send_rate = job.send_rate.filter(|r| *r > 0).unwrap_or_else(config::get_send_rate);
+        registration::set_send_rate(send_rate);
 
         // Send packets and signal receiver thread to release mutex to list of ips
-        send_packets(interface, &ips);
+        // Probes are sent first, so that their answers have until the end of the job to arrive
+        send_packets(interface, &job.probes, job.probe_port, send_rate);
+        send_packets(interface, &job.ips, 25565, send_rate);
         sender_finish_signal.store(true, Ordering::Relaxed);
 
-        println!("Finished job #{}", job_id);
+        println!("Finished job #{}", job.id);
 
-        // Lock ip vec mutex
-        let mut valid_ips = valid_ips_mtx.lock().unwrap();
-        println!("{:?}", valid_ips);
-        while !upload_ips(&valid_ips) {
+        // Lock results mutex
+        let mut results = results_mtx.lock().unwrap();
+        println!("{:?}", results.valid_ips);
+        while !upload_ips(job.id, &results.valid_ips) {
+            registration::record_error();
             println!("Error uploading job to dispatch server, retrying in 5 seconds.");
             sleep(Duration::from_secs(5));
         }
+        if !job.probes.is_empty() && !upload_probes(job.id, &job.probes, &results.probe_hits) {
+            // Probes are only used for honeypot detection, not worth retrying
+            registration::record_error();
+            println!("Error uploading probe results to dispatch server.");
+        }
         println!("Successfully uploaded job result");
-        valid_ips.clear();
+        results.valid_ips.clear();
+        results.probe_hits.clear();
         sender_finish_signal.store(false, Ordering::Relaxed);
     }
 
@@ -115,7 +149,7 @@ fn main() -> Result<()> {
     Ok(())
 }
 
-pub fn send_packets(iface: &NetworkInterface, ips: &Vec<Ipv4Addr>) {
+pub fn send_packets(iface: &NetworkInterface, ips: &Vec<Ipv4Addr>, port: u16, send_rate: u64) {
     let (mut tx, _) = match pnet::datalink::channel(iface, Default::default()) {
         Ok(Channel::Ethernet(tx, rx)) => (tx, rx),
         Ok(_) => panic!("Wrong chanel type"),
@@ -123,11 +157,11 @@ pub fn send_packets(iface: &NetworkInterface, ips: &Vec<Ipv4Addr>) {
     };
     println!("Sending new packets");
 
-    let time_per_packet = Duration::from_micros((1000000.0 / config::get_send_rate() as f64) as u64);
+    let time_per_packet = Duration::from_micros((1000000.0 / send_rate as f64) as u64);
     println!("TPP: {} us", time_per_packet.as_micros());
     for ip in ips {
         tx.build_and_send(1, 66, &mut |packet: &mut [u8]| {
-            generate_syn_packet(iface, ip, 25565, packet);
+            generate_syn_packet(iface, ip, port, packet);
         });
         sleep(time_per_packet);
     }
@@ -137,45 +171,112 @@ pub fn send_packets(iface: &NetworkInterface, ips: &Vec<Ipv4Addr>) {
 // Utility functions
 //
 
-fn upload_ips(ips: &Vec<Ipv4Addr>) -> bool {
-    let client = reqwest::blocking::Client::new();
-    let url = format!("{}/scout/ips", config::get_dispatcher_base());
-    client.post(url).json(ips).send().is_ok()
+fn upload_ips(job_id: u32, ips: &Vec<Ipv4Addr>) -> bool {
+    // The job id lets the dispatcher mark the IPs of the job as covered
+    let url = config::current().dispatcher.url("/scout/ips");
+    let query = IpsQuery { job: Some(job_id), worker_id: registration::worker_id() };
+    config::dispatcher_client().post(url).query(&query).json(ips).send().is_ok()
+}
+
+fn upload_probes(job_id: u32, ips: &[Ipv4Addr], hits: &[Ipv4Addr]) -> bool {
+    let url = config::current().dispatcher.url("/scout/probes");
+    let query = IpsQuery { job: Some(job_id), worker_id: registration::worker_id() };
+    config::dispatcher_client().post(url).query(&query).json(&ProbeResult { ips: ips.to_vec(), hits: hits.to_vec() }).send().is_ok()
+}
+
+/// Fetches the settings pushed by the dispatcher if their version changed. The current settings
+/// are kept if the dispatcher can't be reached, and the config file is used if it has none.
+fn sync_config(version: Option<&str>) {
+    if version == config::get_remote_version().as_deref() {
+        return;
+    }
+    if version.is_none() {
+        println!("Dispatcher stopped managing the settings, using the config file.");
+        config::clear_remote_config();
+        return;
+    }
+
+    let url = config::current().dispatcher.url("/config/worker");
+    let query = WorkerQuery {
+        worker: config::current().dispatcher.name.clone(),
+        group: config::current().dispatcher.group.clone(),
+        worker_id: registration::worker_id(),
+    };
+    let res = config::dispatcher_client().get(url).query(&query).send();
+    if let Ok(r) = &res {
+        check_protocol(r);
+    }
+    match res {
+        Ok(r) if r.status() == reqwest::StatusCode::OK => match r.json::<ResolvedConfig>() {
+            Ok(resolved) => {
+                println!("Applying settings version {} from the dispatcher.", resolved.version);
+                config::apply_remote_config(&resolved);
+            }
+            Err(_) => println!("Received invalid settings from the dispatcher, keeping the current ones.")
+        },
+        Ok(r) if r.status() == reqwest::StatusCode::NOT_FOUND => config::clear_remote_config(),
+        _ => println!("Error getting settings from dispatch server, keeping the current ones.")
+    }
 }
 
-fn get_job() -> (Vec<Ipv4Addr>, u32) {
-    let url = format!("{}/scout/job/{}", config::get_dispatcher_base(), config::get_job_size());
-    let client = reqwest::blocking::Client::new();
-    let res;
+fn get_job(scout_id: &str) -> ScoutJob {
+    // The job size is ignored if the dispatcher manages the send rate
+    let url = config::current().dispatcher.url(&format!("/scout/job/{}", config::get_job_size()));
+    let query = ScoutJobQuery {
+        scout: Some(String::from(scout_id)),
+        group: config::current().dispatcher.group.clone(),
+        worker_id: registration::worker_id(),
+    };
+    let job;
     loop {
-        match client.get(&url).send() {
+        match config::dispatcher_client().get(&url).query(&query).send() {
             Ok(r) => {
+                check_protocol(&r);
                 if r.status() == reqwest::StatusCode::OK {
-                    res = r;
-                    break;
+                    match r.json::<ScoutJob>() {
+                        Ok(j) => {
+                            job = j;
+                            break;
+                        }
+                        Err(e) => {
+                            registration::record_error();
+                            println!("Received invalid job from server ({}), retrying in 5 seconds...", e);
+                            sleep(Duration::from_secs(5));
+                        }
+                    }
+                } else if r.status() == reqwest::StatusCode::SERVICE_UNAVAILABLE {
+                    // Blackout window of the send rate budget
+                    let retry_after = r.headers().get(header::RETRY_AFTER)
+                        .and_then(|v| v.to_str().ok()?.parse().ok())
+                        .unwrap_or(60);
+                    println!("Scanning paused by the dispatcher, retrying in {} seconds...", retry_after);
+                    sleep(Duration::from_secs(retry_after));
+                } else if r.status() == reqwest::StatusCode::UNAUTHORIZED || r.status() == reqwest::StatusCode::FORBIDDEN {
+                    registration::record_error();
+                    println!("Dispatcher rejected the API key, check api_key in the config file. Retrying in 60 seconds...");
+                    sleep(Duration::from_secs(60));
                 } else {
+                    registration::record_error();
                     println!("Received invalid response from server, retrying in 5 seconds...");
                     sleep(Duration::from_secs(5));
                 }
             },
             Err(_) => {
+                registration::record_error();
                 println!("Error getting job from dispatch server, retrying in 5 seconds.");
                 sleep(Duration::from_secs(5));
             }
         }
     }
 
-    let res = res.text().unwrap();
-    let json: Value = serde_json::from_str(&res).unwrap();
-    let job_id: u32 = json["id"].as_u64().unwrap() as u32;
-    let ips: Vec<Ipv4Addr> = json["ips"].as_array().unwrap().iter()
-        .map(|x| x.as_str().unwrap().parse().unwrap()).collect();
+    // Applied before sending, so that the job uses the new send rate and stop timeout
+    sync_config(job.config_version.as_deref());
 
-    (ips, job_id)
+    job
 }
 
 fn print_adapter_info(adapter: &NetworkInterface) {
-    let ip: String = match adapter.ips.get(0) {
+    let ip: String = match adapter.ips.first() {
         Some(ip) => ip.ip().to_string(),
         None => String::from("No IP")
     };
@@ -10,7 +10,7 @@ pub fn generate_syn_packet(iface: &NetworkInterface, dest_ip: &Ipv4Addr, port: u
     const L2_SIZE: usize = 14;
     const L3_SIZE: usize = 20;
 
-    let src_ip = match iface.ips.get(0).unwrap().ip() {
+    let src_ip = match iface.ips.first().unwrap().ip() {
         IpAddr::V4(addr) => addr,
         _ => panic!("Interface is ipv6")
     };
@@ -55,33 +55,25 @@ pub fn generate_syn_packet(iface: &NetworkInterface, dest_ip: &Ipv4Addr, port: u
 /// # Arguments
 ///
 /// * `packet`: The bytearray representation of the packet
-/// * `port`: The expected source port
+/// * `ports`: The expected source ports
 ///
-/// returns: Option<(Ipv4Addr, bool)>
+/// returns: Option<(Ipv4Addr, u16, bool)>
 /// * `Ipv4Addr`: The source address of the packet
+/// * `u16`: The source port of the packet
 /// * `bool`: True if the packet was a TCP packet with SYN and ACK flags
 ///
-pub fn validate_response(packet: &[u8], port: u16) -> Option<(Ipv4Addr, bool)> {
-    let ethernet = match EthernetPacket::new(packet) {
-        Some(x) => x,
-        None => return None
-    };
+pub fn validate_response(packet: &[u8], ports: &[u16]) -> Option<(Ipv4Addr, u16, bool)> {
+    let ethernet = EthernetPacket::new(packet)?;
 
-    let ipv4 = match Ipv4Packet::new(ethernet.payload()) {
-        Some(x) => x,
-        None => return None
-    };
+    let ipv4 = Ipv4Packet::new(ethernet.payload())?;
 
-    let tcp = match TcpPacket::new(ipv4.payload()) {
-        Some(x) => x,
-        None => return None
-    };
-    if tcp.get_source() != port { return None; }  // Wrong port
+    let tcp = TcpPacket::new(ipv4.payload())?;
+    if !ports.contains(&tcp.get_source()) { return None; }  // Wrong port
 
     // println!("{}:{} --> {}:{}",
     //         ipv4.get_source(), tcp.get_source(),
     //         ipv4.get_destination(), tcp.get_destination());
 
     let is_syn_ack = (tcp.get_flags() & TcpFlags::SYN) != 0 && (tcp.get_flags() & TcpFlags::ACK) != 0;
-    Some((ipv4.get_source(), is_syn_ack))
+    Some((ipv4.get_source(), tcp.get_source(), is_syn_ack))
 }
\ No newline at end of file
@@ -1,7 +1,7 @@
 use std::io::{stdout, Write};
 use std::net::Ipv4Addr;
 use std::ops::Add;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU16, Ordering};
 use std::sync::Mutex;
 use std::thread::sleep;
 use std::time::{Duration, SystemTime};
@@ -9,6 +9,13 @@ use pnet::datalink::{Channel, Config, NetworkInterface};
 use crate::config;
 use crate::packet_handler::*;
 
+/// Responses received during a job
+#[derive(Default)]
+pub struct ScanResults {
+    pub valid_ips: Vec<Ipv4Addr>,
+    pub probe_hits: Vec<Ipv4Addr>,
+}
+
 /// The receiver thread receives the answer (SYN ACK) to our packets. It tries to receive
 /// until the stop_signal is set to true, at which point it waits until a specified amount of time
 /// passes without having received an answer.
@@ -18,15 +25,18 @@ use crate::packet_handler::*;
 ///
 /// The thread only considers a packet as a valid response if it is a SYN ACK packet originating
 /// from port 25565. This value is currently hard-coded and could be variable in the future.
+/// SYN ACK packets originating from the probe port of the current job are recorded separately,
+/// they are used by the dispatcher to detect honeypots.
 ///
 /// # Arguments
 ///
 /// * `iface`: The interface to receive packets on.
-/// * `valid_ips_mtx`: IP vectors containing all IPs that sent a valid answer
+/// * `results_mtx`: IPs that sent a valid answer, and probed IPs that answered
+/// * `probe_port`: Random port probed during the current job (0 if none)
 /// * `stop_signal`: When true, start stop process described above.
 /// * `sender_finish_signal`: Should be set to true when the sender is done with its batch
 ///
-pub fn receiver_thread(iface: &NetworkInterface, valid_ips_mtx: &Mutex<Vec<Ipv4Addr>>, stop_signal: &AtomicBool, sender_finish_signal: &AtomicBool) {
+pub fn receiver_thread(iface: &NetworkInterface, results_mtx: &Mutex<ScanResults>, probe_port: &AtomicU16, stop_signal: &AtomicBool, sender_finish_signal: &AtomicBool) {
     // Create channel (get packets with a timeout of 1s)
     let pnet_config = Config {
         read_timeout: Option::from(Duration::from_secs(1)),
@@ -46,7 +56,7 @@ pub fn receiver_thread(iface: &NetworkInterface, valid_ips_mtx: &Mutex<Vec<Ipv4A
     let max_no_packet_period = config::get_receive_timeout();
     let mut last_packet_time = SystemTime::now();
 
-    let mut valid_ips = valid_ips_mtx.lock().unwrap();
+    let mut results = results_mtx.lock().unwrap();
 
     // Counters
     let mut valid_count = 0;
@@ -56,18 +66,27 @@ pub fn receiver_thread(iface: &NetworkInterface, valid_ips_mtx: &Mutex<Vec<Ipv4A
         // is there a better way?
         if sender_finish_signal.load(Ordering::Relaxed) {
             // Sender is done, release mutex
-            drop(valid_ips);
+            drop(results);
 
             // Wait until sender has new task
             while sender_finish_signal.load(Ordering::Relaxed) {
                 sleep(Duration::from_millis(10));
             }
-            valid_ips = valid_ips_mtx.lock().unwrap();
+            results = results_mtx.lock().unwrap();
         }
 
         match rx.next() {
             Ok(packet) => {
-                if let Some((ip, syn_ack)) = validate_response(packet, 25565) {
+                let probe_port = probe_port.load(Ordering::Relaxed);
+                if let Some((ip, port, syn_ack)) = validate_response(packet, &[25565, probe_port]) {
+                    if port == probe_port {
+                        // Response to a probe, nothing should be listening on that port
+                        if syn_ack {
+                            results.probe_hits.push(ip);
+                        }
+                        continue;
+                    }
+
                     // Response from server
 
                     // packet_count += 1;
@@ -76,7 +95,7 @@ pub fn receiver_thread(iface: &NetworkInterface, valid_ips_mtx: &Mutex<Vec<Ipv4A
                     last_packet_time = SystemTime::now();
                     if syn_ack {
                         // Valid response packet
-                        valid_ips.push(ip);
+                        results.valid_ips.push(ip);
                         valid_count += 1;
                     } else {
                         // Invalid response (i.e. SYN RST)
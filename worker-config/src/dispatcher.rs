@@ -0,0 +1,52 @@
+// HTTP client of the dispatcher. It is built once per generation of the settings (see
+// [`crate::SharedConfig`]), so that requests share its connections, and sends the API key and the
+// protocol version with every request.
+
+use reqwest::blocking::{Client, Response};
+use reqwest::header;
+use reqwest::header::{HeaderMap, HeaderValue};
+use protocol::{PROTOCOL_VERSION, VERSION_HEADER};
+use crate::DispatcherSettings;
+
+impl DispatcherSettings {
+    /// Builds the HTTP client, reading the certificate files. Fails if they aren't valid PEM
+    /// files.
+    pub fn build_client(&self) -> Result<Client, String> {
+        let mut api_key = HeaderValue::from_str(&format!("Bearer {}", self.api_key))
+            .map_err(|_| String::from("api_key contains invalid characters"))?;
+        api_key.set_sensitive(true);
+        let mut headers = HeaderMap::new();
+        headers.insert(header::AUTHORIZATION, api_key);
+        headers.insert(VERSION_HEADER, HeaderValue::from(PROTOCOL_VERSION));
+        let mut builder = Client::builder()
+            .use_rustls_tls()
+            .default_headers(headers);
+        let ca_cert = self.read_ca_cert().map_err(|e| format!("Unable to read ca_cert: {}", e))?;
+        if let Some(ca_cert) = ca_cert {
+            let certificate = reqwest::Certificate::from_pem(&ca_cert)
+                .map_err(|e| format!("ca_cert isn't a valid PEM certificate: {}", e))?;
+            builder = builder.add_root_certificate(certificate);
+        }
+        let identity = self.read_client_identity().map_err(|e| format!("Unable to read client_cert or client_key: {}", e))?;
+        if let Some(identity) = identity {
+            let identity = reqwest::Identity::from_pem(&identity)
+                .map_err(|e| format!("client_cert and client_key aren't a valid PEM certificate and key: {}", e))?;
+            builder = builder.identity(identity);
+        }
+        builder.build().map_err(|e| format!("Error creating HTTP client: {}", e))
+    }
+}
+
+/// Stops the worker if the dispatcher speaks another version of the protocol, see the `protocol`
+/// crate
+pub fn check_protocol(res: &Response) {
+    let version = res.headers().get(VERSION_HEADER).map(|v| v.to_str().unwrap_or_default());
+    // Errors of a proxy in front of the dispatcher
+    if version.is_none() && !res.status().is_success() {
+        return;
+    }
+    if let Err(e) = protocol::check_version(version) {
+        eprintln!("Incompatible dispatcher: {}", e);
+        std::process::exit(1);
+    }
+}
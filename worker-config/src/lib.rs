@@ -0,0 +1,434 @@
+// Config of the scouts and clients. Settings are read from the YAML config file, then from
+// `MSEARCH_<SETTING>` environment variables (i.e. `MSEARCH_SEND_RATE=500`), then from
+// `--set <setting>=<value>` command-line flags, each source overriding the previous one. Settings
+// missing from every source keep their default, and every invalid setting is reported at once.
+// On SIGHUP, the settings are read again from every source and the ones that are safe to change
+// while running (see [`Settings::RELOADABLE`]) are applied, along with a new HTTP client of the
+// dispatcher (see [`dispatcher`]). The workers register with the dispatcher through
+// [`registration`].
+
+pub mod dispatcher;
+pub mod registration;
+
+use std::{env, fs, io, thread};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use protocol::API_PREFIX;
+use reqwest::blocking::Client;
+use serde::{Deserialize, Serialize};
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+use signal_hook::consts::SIGHUP;
+use signal_hook::iterator::Signals;
+use yaml_rust::{Yaml, YamlLoader};
+
+/// Prefix of the environment variables overriding the config file
+pub const ENV_PREFIX: &str = "MSEARCH_";
+
+/// Settings of a kind of worker. They must serialize to a map, with every setting present.
+pub trait Settings: Serialize + DeserializeOwned + Default + Send + Sync + 'static {
+    /// Settings applied on SIGHUP, changing the others needs a restart
+    const RELOADABLE: &'static [&'static str];
+
+    /// Checks the values of the settings, returns the errors
+    fn validate(&self) -> Vec<String>;
+
+    /// Settings used to reach the dispatcher
+    fn dispatcher(&self) -> &DispatcherSettings;
+}
+
+/// Settings used to reach the dispatcher, shared by the scout and the client
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DispatcherSettings {
+    pub dispatcher_base: String,
+    // Sent with every request to the dispatcher
+    pub api_key: String,
+    // Identify the worker in the worker config of the dispatcher
+    pub name: Option<String>,
+    pub group: Option<String>,
+    // PEM CA certificate trusted for the dispatcher, and PEM client certificate and key sent to it
+    pub ca_cert: Option<PathBuf>,
+    pub client_cert: Option<PathBuf>,
+    pub client_key: Option<PathBuf>,
+}
+
+impl DispatcherSettings {
+    pub fn validate(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+        if self.dispatcher_base.is_empty() {
+            errors.push(String::from("dispatcher_base is required"));
+        } else if !self.dispatcher_base.starts_with("http://") && !self.dispatcher_base.starts_with("https://") {
+            errors.push(format!("dispatcher_base {} must start with http:// or https://", self.dispatcher_base));
+        }
+        if self.api_key.is_empty() {
+            errors.push(String::from("api_key is required"));
+        }
+        if self.client_cert.is_some() != self.client_key.is_some() {
+            errors.push(String::from("client_cert and client_key must be set together"));
+        }
+        for (name, path) in [("ca_cert", &self.ca_cert), ("client_cert", &self.client_cert), ("client_key", &self.client_key)] {
+            if let Some(path) = path {
+                if let Err(e) = fs::metadata(path) {
+                    errors.push(format!("Unable to read {} {}: {}", name, path.display(), e));
+                }
+            }
+        }
+        errors
+    }
+
+    /// URL of a route of the dispatcher, i.e. `/client/job`
+    pub fn url(&self, route: &str) -> String {
+        format!("{}{}{}", self.dispatcher_base.trim_end_matches('/'), API_PREFIX, route)
+    }
+
+    /// PEM CA certificate trusted for the dispatcher
+    pub fn read_ca_cert(&self) -> io::Result<Option<Vec<u8>>> {
+        self.ca_cert.as_ref().map(fs::read).transpose()
+    }
+
+    /// PEM client certificate followed by its key
+    pub fn read_client_identity(&self) -> io::Result<Option<Vec<u8>>> {
+        match (&self.client_cert, &self.client_key) {
+            (Some(cert), Some(key)) => Ok(Some([fs::read(cert)?, fs::read(key)?].concat())),
+            _ => Ok(None)
+        }
+    }
+}
+
+/// Where the settings are read from
+#[derive(Debug, Clone)]
+pub struct Source {
+    pub path: PathBuf,
+    // `--set` flags of the command line
+    pub overrides: Vec<(String, String)>,
+}
+
+/// Parses a `--set setting=value` flag, used as the `value_parser` of clap
+pub fn parse_override(text: &str) -> Result<(String, String), String> {
+    let (setting, value) = text.split_once('=')
+        .ok_or_else(|| format!("expected setting=value, got {}", text))?;
+    Ok((String::from(setting.trim()), String::from(value)))
+}
+
+fn yaml_to_json(yaml: Yaml) -> Value {
+    match yaml {
+        Yaml::Real(real) => real.parse::<f64>().map_or(Value::Null, Value::from),
+        Yaml::Integer(integer) => Value::from(integer),
+        Yaml::String(string) => Value::String(string),
+        Yaml::Boolean(boolean) => Value::Bool(boolean),
+        Yaml::Array(array) => Value::Array(array.into_iter().map(yaml_to_json).collect()),
+        Yaml::Hash(hash) => Value::Object(hash.into_iter()
+            .filter_map(|(key, value)| Some((key.into_string()?, yaml_to_json(value))))
+            .collect()),
+        Yaml::Alias(_) | Yaml::Null | Yaml::BadValue => Value::Null,
+    }
+}
+
+fn read_file(path: &Path) -> Result<Map<String, Value>, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("Unable to read {}: {}", path.display(), e))?;
+    let documents = YamlLoader::load_from_str(&text).map_err(|e| format!("Invalid YAML in {}: {}", path.display(), e))?;
+    match documents.into_iter().next().map(yaml_to_json) {
+        Some(Value::Object(settings)) => Ok(settings),
+        Some(Value::Null) | None => Ok(Map::new()),
+        Some(_) => Err(format!("{} must contain a map of settings", path.display()))
+    }
+}
+
+/// Values a setting given as text can have, i.e. `500` is either a number or a string
+fn text_values(text: &str) -> Vec<Value> {
+    let mut values = vec![Value::String(String::from(text))];
+    if let Ok(value) = serde_json::from_str::<Value>(text) {
+        values.push(value);
+    }
+    values
+}
+
+/// Reads the settings of every source, see the crate documentation
+struct Loader<T> {
+    // Default settings, which list every setting
+    defaults: Map<String, Value>,
+    settings: Map<String, Value>,
+    errors: Vec<String>,
+    _settings: PhantomData<T>,
+}
+
+impl<T: Settings> Loader<T> {
+    fn new() -> Self {
+        let defaults = match serde_json::to_value(T::default()) {
+            Ok(Value::Object(defaults)) => defaults,
+            _ => panic!("Settings must serialize to a map")
+        };
+        Loader { defaults, settings: Map::new(), errors: Vec::new(), _settings: PhantomData }
+    }
+
+    /// Checks a single setting, and keeps the first of `values` that is valid
+    fn set(&mut self, setting: String, values: Vec<Value>, origin: &str) {
+        if !self.defaults.contains_key(&setting) {
+            self.errors.push(format!("Unknown setting {} in {}", setting, origin));
+            return;
+        }
+        let mut error = None;
+        for value in values {
+            let single = Map::from_iter([(setting.clone(), value.clone())]);
+            match serde_json::from_value::<T>(Value::Object(single)) {
+                Ok(_) => {
+                    self.settings.insert(setting, value);
+                    return;
+                }
+                Err(e) => error = Some(e)
+            }
+        }
+        self.errors.push(format!("Invalid {} in {}: {}", setting, origin, error.map_or_else(String::new, |e| e.to_string())));
+    }
+
+    /// Reads the settings of the file of `source`, then of the environment `variables`, then of
+    /// the overrides of `source`
+    fn load(mut self, source: &Source, variables: impl Iterator<Item = (String, String)>) -> Result<T, Vec<String>> {
+        for (setting, value) in read_file(&source.path).map_err(|e| vec![e])? {
+            self.set(setting, vec![value], &source.path.display().to_string());
+        }
+        let mut variables: Vec<(String, String)> = variables
+            .filter(|(name, _)| name.starts_with(ENV_PREFIX))
+            .collect();
+        variables.sort();
+        for (name, value) in variables {
+            let setting = name[ENV_PREFIX.len()..].to_lowercase();
+            // Variables of the other kind of worker are ignored, both can run on the same host
+            if self.defaults.contains_key(&setting) {
+                self.set(setting, text_values(&value), &name);
+            }
+        }
+        for (setting, value) in &source.overrides {
+            self.set(setting.clone(), text_values(value), "--set");
+        }
+
+        // Invalid settings are left out, so that the values of the others are checked as well
+        let settings: T = serde_json::from_value(Value::Object(self.settings)).map_err(|e| vec![e.to_string()])?;
+        self.errors.extend(settings.validate());
+        if self.errors.is_empty() {
+            Ok(settings)
+        } else {
+            Err(self.errors)
+        }
+    }
+}
+
+/// Reads the settings from every source, and checks them. Returns every error found.
+pub fn load<T: Settings>(source: &Source) -> Result<T, Vec<String>> {
+    Loader::<T>::new().load(source, env::vars())
+}
+
+/// Settings, and the HTTP client of the dispatcher built from them
+struct Generation<T> {
+    settings: Arc<T>,
+    client: Client,
+}
+
+/// Settings that can be reloaded while the worker runs
+pub struct SharedConfig<T> {
+    source: Source,
+    current: RwLock<Generation<T>>,
+}
+
+impl<T: Settings> SharedConfig<T> {
+    pub fn load(source: Source) -> Result<Self, Vec<String>> {
+        let settings: T = load(&source)?;
+        let client = settings.dispatcher().build_client().map_err(|e| vec![e])?;
+        Ok(SharedConfig { source, current: RwLock::new(Generation { settings: Arc::new(settings), client }) })
+    }
+
+    /// Current settings
+    pub fn get(&self) -> Arc<T> {
+        self.current.read().unwrap().settings.clone()
+    }
+
+    /// HTTP client of the dispatcher, built from the current settings
+    pub fn client(&self) -> Client {
+        self.current.read().unwrap().client.clone()
+    }
+
+    /// Reads the settings again and applies the reloadable ones that changed. Returns the other
+    /// settings that changed, which need a restart. The current settings are kept if the new
+    /// ones are invalid, or if no HTTP client can be built from them.
+    pub fn reload(&self) -> Result<Vec<String>, Vec<String>> {
+        let new = load::<T>(&self.source)?;
+        let mut current = self.current.write().unwrap();
+        let (mut settings, new) = match (serde_json::to_value(&*current.settings), serde_json::to_value(new)) {
+            (Ok(Value::Object(settings)), Ok(Value::Object(new))) => (settings, new),
+            _ => panic!("Settings must serialize to a map")
+        };
+
+        let mut needs_restart = Vec::new();
+        for (setting, value) in new {
+            if settings.get(&setting) == Some(&value) {
+                continue;
+            }
+            if T::RELOADABLE.contains(&setting.as_str()) {
+                settings.insert(setting, value);
+            } else {
+                needs_restart.push(setting);
+            }
+        }
+        let settings: T = serde_json::from_value(Value::Object(settings)).map_err(|e| vec![e.to_string()])?;
+        let client = settings.dispatcher().build_client().map_err(|e| vec![e])?;
+        *current = Generation { settings: Arc::new(settings), client };
+        Ok(needs_restart)
+    }
+
+    /// Reloads the settings on every SIGHUP, in the background
+    pub fn reload_on_sighup(&'static self) {
+        let mut signals = Signals::new([SIGHUP]).expect("Unable to listen for SIGHUP");
+        thread::spawn(move || {
+            for _ in signals.forever() {
+                match self.reload() {
+                    Ok(needs_restart) => {
+                        println!("Reloaded the config file.");
+                        if !needs_restart.is_empty() {
+                            println!("Restart to apply the changes to {}.", needs_restart.join(", "));
+                        }
+                    }
+                    Err(errors) => {
+                        println!("Invalid config, keeping the current one:");
+                        errors.iter().for_each(|e| println!("  - {}", e));
+                    }
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    #[serde(default)]
+    struct TestSettings {
+        #[serde(flatten)]
+        dispatcher: DispatcherSettings,
+        send_rate: u64,
+        label: String,
+    }
+
+    impl Default for TestSettings {
+        fn default() -> Self {
+            TestSettings { dispatcher: DispatcherSettings::default(), send_rate: 1000, label: String::from("default") }
+        }
+    }
+
+    impl Settings for TestSettings {
+        const RELOADABLE: &'static [&'static str] = &["api_key", "send_rate"];
+
+        fn validate(&self) -> Vec<String> {
+            let mut errors = self.dispatcher.validate();
+            if self.send_rate == 0 {
+                errors.push(String::from("send_rate must be above 0"));
+            }
+            errors
+        }
+
+        fn dispatcher(&self) -> &DispatcherSettings {
+            &self.dispatcher
+        }
+    }
+
+    const BASE: &str = "dispatcher_base: http://localhost:8000\napi_key: msk_test\n";
+
+    /// Config file of a test, removed when dropped
+    struct TestFile(PathBuf);
+
+    impl TestFile {
+        fn new(test: &str, text: &str) -> Self {
+            let file = TestFile(env::temp_dir().join(format!("msearch-worker-config-{}-{}.yml", std::process::id(), test)));
+            file.write(text);
+            file
+        }
+
+        fn write(&self, text: &str) {
+            fs::write(&self.0, format!("{}{}", BASE, text)).unwrap();
+        }
+
+        fn source(&self, overrides: &[&str]) -> Source {
+            let overrides = overrides.iter().map(|o| parse_override(o).unwrap()).collect();
+            Source { path: self.0.clone(), overrides }
+        }
+    }
+
+    impl Drop for TestFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    fn load_with(source: &Source, variables: &[(&str, &str)]) -> Result<TestSettings, Vec<String>> {
+        let variables = variables.iter().map(|(name, value)| (name.to_string(), value.to_string()));
+        Loader::<TestSettings>::new().load(source, variables)
+    }
+
+    #[test]
+    fn sources_override_each_other() {
+        let file = TestFile::new("precedence", "send_rate: 10\nlabel: file\n");
+        let variables = [("MSEARCH_SEND_RATE", "20"), ("MSEARCH_LABEL", "env")];
+        let settings = load_with(&file.source(&[]), &variables).unwrap();
+        assert_eq!((settings.send_rate, settings.label.as_str()), (20, "env"));
+        let settings = load_with(&file.source(&["send_rate=30"]), &variables).unwrap();
+        assert_eq!((settings.send_rate, settings.label.as_str()), (30, "env"));
+        let settings = load_with(&file.source(&[]), &[]).unwrap();
+        assert_eq!((settings.send_rate, settings.label.as_str()), (10, "file"));
+        assert_eq!(settings.dispatcher.api_key, "msk_test");
+    }
+
+    #[test]
+    fn text_is_a_string_before_a_number() {
+        let file = TestFile::new("text", "");
+        let settings = load_with(&file.source(&["label=500", "send_rate=500"]), &[("MSEARCH_NAME", "true")]).unwrap();
+        assert_eq!(settings.label, "500");
+        assert_eq!(settings.send_rate, 500);
+        assert_eq!(settings.dispatcher.name.as_deref(), Some("true"));
+        assert_eq!(text_values("500"), vec![Value::from("500"), Value::from(500)]);
+        assert_eq!(text_values("fast"), vec![Value::from("fast")]);
+    }
+
+    #[test]
+    fn rejects_unknown_settings() {
+        let file = TestFile::new("unknown", "sned_rate: 10\n");
+        let errors = load_with(&file.source(&["labels=a"]), &[("MSEARCH_RECEIVE_TIMEOUT", "5")]).unwrap_err();
+        // Variables of the other kind of worker are ignored
+        assert_eq!(errors.len(), 2, "{:?}", errors);
+        assert!(errors[0].starts_with("Unknown setting sned_rate in "), "{:?}", errors);
+        assert_eq!(errors[1], "Unknown setting labels in --set");
+    }
+
+    #[test]
+    fn reports_every_error() {
+        let file = TestFile::new("errors", "label: [1]\nclient_cert: cert.pem\n");
+        let errors = load_with(&file.source(&["send_rate=fast"]), &[("MSEARCH_SEND_RATE", "0")]).unwrap_err();
+        // The invalid override keeps the value of the variable, which then fails validation
+        assert_eq!(errors.len(), 5, "{:?}", errors);
+        assert!(errors[0].starts_with("Invalid label in "), "{:?}", errors);
+        assert!(errors[1].starts_with("Invalid send_rate in --set"), "{:?}", errors);
+        assert!(errors.contains(&String::from("client_cert and client_key must be set together")), "{:?}", errors);
+        assert!(errors.iter().any(|e| e.starts_with("Unable to read client_cert cert.pem")), "{:?}", errors);
+        assert!(errors.contains(&String::from("send_rate must be above 0")), "{:?}", errors);
+    }
+
+    #[test]
+    fn reload_applies_reloadable_settings() {
+        let file = TestFile::new("reload", "send_rate: 10\nlabel: before\n");
+        let config = SharedConfig::<TestSettings>::load(file.source(&[])).unwrap();
+
+        file.write("send_rate: 20\nlabel: after\nname: scout-1\n");
+        let mut needs_restart = config.reload().unwrap();
+        needs_restart.sort();
+        assert_eq!(needs_restart, ["label", "name"]);
+        let settings = config.get();
+        assert_eq!((settings.send_rate, settings.label.as_str(), settings.dispatcher.name.as_deref()), (20, "before", None));
+
+        // Invalid settings are ignored
+        file.write("send_rate: 0\n");
+        assert!(config.reload().is_err());
+        assert_eq!(config.get().send_rate, 20);
+    }
+}
@@ -0,0 +1,118 @@
+// Registration with the dispatcher, which issues the id the worker sends with its jobs, and
+// heartbeats keeping the dispatcher's view of the worker up to date (see `/info/workers`). The
+// dispatcher gives a restarted worker the id it had before, see `/workers/register`.
+
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::thread::sleep;
+use std::time::Duration;
+use protocol::{Heartbeat, Registration, RegistrationResponse, Uuid, WorkerKind};
+use crate::{Settings, SharedConfig};
+use crate::dispatcher::check_protocol;
+
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Id issued by the dispatcher
+static WORKER_ID: OnceLock<Uuid> = OnceLock::new();
+/// Errors since the last heartbeat
+static ERRORS: AtomicU64 = AtomicU64::new(0);
+/// Packets per second of the current job, only sent by scouts
+static SEND_RATE: AtomicU64 = AtomicU64::new(0);
+
+/// Id issued by the dispatcher, None if the worker isn't registered
+pub fn worker_id() -> Option<Uuid> {
+    WORKER_ID.get().copied()
+}
+
+pub fn record_error() {
+    ERRORS.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn set_send_rate(send_rate: u64) {
+    SEND_RATE.store(send_rate, Ordering::Relaxed);
+}
+
+fn hostname() -> Option<String> {
+    gethostname::gethostname().into_string().ok()
+}
+
+/// Registers the worker, retrying until the dispatcher can be reached. The worker stays
+/// unregistered if the dispatcher doesn't support registration. `version` is the version of the
+/// worker's crate.
+pub fn register<T: Settings>(config: &SharedConfig<T>, kind: WorkerKind, version: &str, adapter: Option<&str>) {
+    let settings = config.get();
+    let url = settings.dispatcher().url("/workers/register");
+    let body = Registration {
+        kind,
+        name: settings.dispatcher().name.clone(),
+        group: settings.dispatcher().group.clone(),
+        hostname: hostname(),
+        version: Some(String::from(version)),
+        adapter: adapter.map(String::from),
+    };
+    loop {
+        let res = config.client().post(&url).json(&body).send();
+        if let Ok(r) = &res {
+            check_protocol(r);
+        }
+        match res {
+            Ok(r) if r.status() == reqwest::StatusCode::OK => {
+                match r.json::<RegistrationResponse>() {
+                    Ok(RegistrationResponse { id }) => {
+                        println!("Registered with the dispatcher as {}", id);
+                        WORKER_ID.set(id).expect("Worker registered twice");
+                    }
+                    Err(_) => println!("Received invalid registration from the dispatcher, continuing without it.")
+                }
+                return;
+            }
+            Ok(r) if r.status() == reqwest::StatusCode::UNAUTHORIZED || r.status() == reqwest::StatusCode::FORBIDDEN => {
+                panic!("Dispatcher rejected the API key, check api_key in the config file.");
+            }
+            Ok(_) => {
+                println!("Dispatcher doesn't support registration, continuing without it.");
+                return;
+            }
+            Err(_) => {
+                println!("Error registering with the dispatcher, retrying in 5 seconds.");
+                sleep(Duration::from_secs(5));
+            }
+        }
+    }
+}
+
+/// Starts sending heartbeats in the background, if the worker is registered
+pub fn start_heartbeats<T: Settings>(config: &'static SharedConfig<T>, kind: WorkerKind, version: &str, adapter: Option<&str>) {
+    let id = match worker_id() {
+        Some(id) => id,
+        None => return
+    };
+    let url = config.get().dispatcher().url(&format!("/workers/{}/heartbeat", id));
+    let version = String::from(version);
+    let adapter = adapter.map(String::from);
+    thread::spawn(move || {
+        loop {
+            sleep(HEARTBEAT_INTERVAL);
+            let errors = ERRORS.swap(0, Ordering::Relaxed);
+            let body = Heartbeat {
+                hostname: hostname(),
+                version: Some(version.clone()),
+                adapter: adapter.clone(),
+                // Not sending yet
+                send_rate: Some(SEND_RATE.load(Ordering::Relaxed)).filter(|r| *r > 0),
+                errors,
+            };
+            match config.client().post(&url).json(&body).send() {
+                Ok(r) if r.status() == reqwest::StatusCode::NOT_FOUND => {
+                    println!("Dispatcher doesn't know this {} anymore, restart it to register again.", kind.as_str());
+                }
+                Ok(_) => {}
+                Err(_) => {
+                    // Reported with the next heartbeat
+                    ERRORS.fetch_add(errors + 1, Ordering::Relaxed);
+                }
+            }
+        }
+    });
+}